@@ -0,0 +1,3 @@
+ALTER TABLE invoices
+    ADD COLUMN expiry_warning_secs BIGINT,
+    ADD COLUMN expiry_warning_sent BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+ALTER TABLE payments ADD COLUMN confirmed_at TIMESTAMPTZ;
+
+ALTER TABLE invoices ADD COLUMN paid_at TIMESTAMPTZ;
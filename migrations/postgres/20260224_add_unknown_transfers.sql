@@ -0,0 +1,18 @@
+ALTER TABLE chains ADD COLUMN record_unknown_transfers BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE unknown_transfers (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    network VARCHAR(50) NOT NULL,
+    contract VARCHAR(64) NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    "from" VARCHAR(64) NOT NULL,
+    "to" VARCHAR(64) NOT NULL,
+    amount_raw NUMERIC(78, 0) NOT NULL,
+    block_number BIGINT NOT NULL,
+    log_index BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT unique_unknown_transfer UNIQUE (network, tx_hash, log_index)
+);
+
+CREATE INDEX idx_unknown_transfers_network ON unknown_transfers (network, block_number);
@@ -0,0 +1,3 @@
+ALTER TABLE invoices
+    ADD COLUMN locale VARCHAR(35),
+    ADD COLUMN display_currency VARCHAR(10);
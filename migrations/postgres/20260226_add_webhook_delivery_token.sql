@@ -0,0 +1,2 @@
+-- generated once per job and reused on every retry, so receivers can dedupe
+ALTER TABLE webhooks ADD COLUMN delivery_token UUID NOT NULL DEFAULT gen_random_uuid();
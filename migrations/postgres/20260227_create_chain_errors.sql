@@ -0,0 +1,12 @@
+CREATE TABLE chain_errors (
+    id BIGSERIAL PRIMARY KEY,
+    network VARCHAR(50) NOT NULL,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('Rpc', 'Processing', 'Listener')),
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT chain_errors_network_foreign
+        FOREIGN KEY (network) REFERENCES chains (name) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_chain_errors_network ON chain_errors (network, id DESC);
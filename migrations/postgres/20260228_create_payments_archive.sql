@@ -0,0 +1,27 @@
+-- finalized payments are moved here by the janitor so the confirmator only scans a small table
+CREATE TABLE payments_archive (
+    id UUID PRIMARY KEY,
+    invoice_id UUID NOT NULL,
+    "from" VARCHAR(64) NOT NULL,
+    "to" VARCHAR(64) NOT NULL,
+    network VARCHAR(50) NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    amount_raw NUMERIC(78, 0) NOT NULL,
+    block_number BIGINT NOT NULL,
+    status VARCHAR(20) NOT NULL CHECK (status IN ('Confirmed')),
+    created_at TIMESTAMPTZ NOT NULL,
+    confirmed_at TIMESTAMPTZ,
+    log_index BIGINT NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT payments_archive_invoice_id_foreign
+        FOREIGN KEY (invoice_id) REFERENCES invoices (id) ON DELETE CASCADE,
+
+    CONSTRAINT unique_archived_payment_idempotency
+        UNIQUE (tx_hash, log_index, network)
+);
+
+CREATE INDEX idx_payments_archive_invoice_id ON payments_archive (invoice_id);
+
+CREATE INDEX idx_payments_finalized ON payments (confirmed_at)
+    WHERE (status = 'Confirmed');
@@ -0,0 +1,3 @@
+ALTER TABLE invoices ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_invoices_tags ON invoices USING GIN (tags);
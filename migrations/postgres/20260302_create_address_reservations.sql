@@ -0,0 +1,15 @@
+-- short-lived claims on address indexes handed out by acquire_free_slot
+-- but not yet backed by an invoice
+CREATE TABLE address_reservations (
+    network VARCHAR(50) NOT NULL,
+    address_index INTEGER NOT NULL,
+    reserved_until TIMESTAMPTZ NOT NULL,
+
+    PRIMARY KEY (network, address_index),
+
+    CONSTRAINT address_reservations_network_foreign
+        FOREIGN KEY (network) REFERENCES chains (name) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_invoices_pending_slots ON invoices (network, address_index)
+    WHERE (status = 'Pending');
@@ -0,0 +1 @@
+ALTER TABLE webhooks ADD COLUMN processing_started_at TIMESTAMPTZ;
@@ -0,0 +1,9 @@
+-- existing rows are treated as repeating so they never conflict with the new index
+ALTER TABLE webhooks
+    ADD COLUMN dedupe_key TEXT,
+    ADD COLUMN repeating BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE UNIQUE INDEX unique_webhook_one_shot ON webhooks (invoice_id, event_type, dedupe_key)
+    WHERE NOT repeating;
+
+CREATE INDEX idx_webhooks_dedupe ON webhooks (invoice_id, event_type, dedupe_key, created_at);
@@ -0,0 +1 @@
+ALTER TABLE chains ADD COLUMN maintenance_windows JSONB NOT NULL DEFAULT '[]';
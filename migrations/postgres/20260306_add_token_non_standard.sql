@@ -0,0 +1 @@
+ALTER TABLE tokens ADD COLUMN non_standard BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+CREATE TABLE deep_link_templates (
+    wallet VARCHAR(50) PRIMARY KEY,
+    template TEXT NOT NULL
+);
+
+-- keep in sync with checkout::DEFAULT_DEEP_LINK_TEMPLATES
+INSERT INTO deep_link_templates (wallet, template) VALUES
+    ('eip681', '{eip681}'),
+    ('metamask', 'https://metamask.app.link/send/{eip681_path}');
@@ -0,0 +1,2 @@
+-- NULL means every event type is delivered
+ALTER TABLE invoices ADD COLUMN webhook_events TEXT[];
@@ -0,0 +1,10 @@
+-- merchant HD branch, 0 is the flat xpub/index layout used so far
+ALTER TABLE invoices ADD COLUMN account_id INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE address_reservations ADD COLUMN account_id INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE address_reservations DROP CONSTRAINT address_reservations_pkey;
+ALTER TABLE address_reservations ADD PRIMARY KEY (network, account_id, address_index);
+
+DROP INDEX idx_invoices_pending_slots;
+CREATE INDEX idx_invoices_pending_slots ON invoices (network, account_id, address_index)
+    WHERE (status = 'Pending');
@@ -0,0 +1,5 @@
+ALTER TABLE invoices ADD COLUMN reissued_from UUID REFERENCES invoices (id) ON DELETE SET NULL;
+
+-- an expired invoice can be replaced only once
+CREATE UNIQUE INDEX unique_invoice_reissued_from ON invoices (reissued_from)
+    WHERE reissued_from IS NOT NULL;
@@ -0,0 +1,16 @@
+ALTER TABLE invoices ADD COLUMN webhook_sequence BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE webhooks ADD COLUMN sequence BIGINT;
+
+-- number what's already there in creation order
+UPDATE webhooks w SET sequence = s.seq
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY invoice_id ORDER BY created_at, id) AS seq
+    FROM webhooks
+) s
+WHERE w.id = s.id;
+
+UPDATE invoices i SET webhook_sequence = s.max_seq
+FROM (SELECT invoice_id, MAX(sequence) AS max_seq FROM webhooks GROUP BY invoice_id) s
+WHERE i.id = s.invoice_id;
+
+ALTER TABLE webhooks ALTER COLUMN sequence SET NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE tokens ADD COLUMN min_amount NUMERIC(78, 0);
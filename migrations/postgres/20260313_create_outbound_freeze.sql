@@ -0,0 +1,9 @@
+-- single row kill switch for everything that moves funds out of merchant addresses
+CREATE TABLE outbound_freeze (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    frozen BOOLEAN NOT NULL DEFAULT FALSE,
+    reason TEXT,
+    changed_at TIMESTAMPTZ
+);
+
+INSERT INTO outbound_freeze (id) VALUES (TRUE);
@@ -0,0 +1,12 @@
+-- running totals maintained by the listener, flushed together with the block checkpoint
+CREATE TABLE chain_stats (
+    network VARCHAR(50) PRIMARY KEY,
+    blocks_processed BIGINT NOT NULL DEFAULT 0,
+    events_emitted BIGINT NOT NULL DEFAULT 0,
+    processing_ms_total BIGINT NOT NULL DEFAULT 0,
+    last_event_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT chain_stats_network_foreign
+        FOREIGN KEY (network) REFERENCES chains (name) ON DELETE CASCADE
+);
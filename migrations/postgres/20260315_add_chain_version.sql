@@ -0,0 +1,2 @@
+-- bumped by every update_chain_partial, updates compare-and-set against it
+ALTER TABLE chains ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
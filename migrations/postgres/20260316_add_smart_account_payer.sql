@@ -0,0 +1,4 @@
+-- the account behind a smart-account payment, `from` is the entrypoint/bundler for native sends
+ALTER TABLE chains ADD COLUMN resolve_smart_account_payers BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE payments ADD COLUMN payer TEXT;
+ALTER TABLE payments_archive ADD COLUMN payer TEXT;
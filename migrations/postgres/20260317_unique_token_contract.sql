@@ -0,0 +1,2 @@
+-- tokens are identified by (chain, contract), the symbol is only for display and invoices
+CREATE UNIQUE INDEX unique_token_contract_per_chain ON tokens (chain_id, LOWER(contract_address));
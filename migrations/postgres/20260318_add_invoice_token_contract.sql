@@ -0,0 +1,10 @@
+-- invoices match payments by contract, the symbol is only shown to people
+ALTER TABLE invoices ADD COLUMN token_contract VARCHAR(64) NOT NULL DEFAULT '';
+
+UPDATE invoices i SET token_contract = LOWER(t.contract_address)
+FROM tokens t JOIN chains c ON c.id = t.chain_id
+WHERE c.name = i.network AND t.symbol = i.token;
+
+UPDATE invoices i SET token_contract = 'native'
+FROM chains c
+WHERE c.name = i.network AND c.native_symbol = i.token AND i.token_contract = '';
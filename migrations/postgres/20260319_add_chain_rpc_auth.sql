@@ -0,0 +1,2 @@
+-- sealed with the instance secret key, NULL when the RPC needs no credentials
+ALTER TABLE chains ADD COLUMN rpc_auth TEXT;
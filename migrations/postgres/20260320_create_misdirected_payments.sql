@@ -0,0 +1,23 @@
+CREATE TABLE misdirected_payments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    invoice_id UUID NOT NULL,
+    network VARCHAR(50) NOT NULL,
+    token VARCHAR(10) NOT NULL,
+    token_contract VARCHAR(64) NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    "from" VARCHAR(64) NOT NULL,
+    "to" VARCHAR(64) NOT NULL,
+    amount VARCHAR(100) NOT NULL,
+    amount_raw NUMERIC(78, 0) NOT NULL,
+    block_number BIGINT NOT NULL,
+    log_index BIGINT NOT NULL DEFAULT -1,
+    status VARCHAR(20) NOT NULL DEFAULT 'Open',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMPTZ,
+
+    CONSTRAINT misdirected_payments_invoice_id_foreign
+        FOREIGN KEY (invoice_id) REFERENCES invoices (id) ON DELETE CASCADE,
+    CONSTRAINT unique_misdirected_payment UNIQUE (network, tx_hash, log_index)
+);
+
+CREATE INDEX idx_misdirected_payments_invoice_id ON misdirected_payments (invoice_id);
@@ -0,0 +1,5 @@
+ALTER TABLE invoices ADD COLUMN grace_period_secs BIGINT;
+-- set once the janitor stopped watching the address of an expired invoice
+ALTER TABLE invoices ADD COLUMN watch_released BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE invoices SET watch_released = TRUE WHERE status = 'Expired';
@@ -0,0 +1,18 @@
+ALTER TABLE invoices ADD COLUMN refunded_raw NUMERIC(78, 0) NOT NULL DEFAULT 0;
+
+-- payment_id has no foreign key, finalized payments move to payments_archive
+CREATE TABLE refunds (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    invoice_id UUID NOT NULL,
+    payment_id UUID NOT NULL,
+    amount_raw NUMERIC(78, 0) NOT NULL,
+    tx_hash VARCHAR(66),
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT refunds_invoice_id_foreign
+        FOREIGN KEY (invoice_id) REFERENCES invoices (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_refunds_invoice_id ON refunds (invoice_id);
+CREATE INDEX idx_refunds_payment_id ON refunds (payment_id);
@@ -0,0 +1,6 @@
+ALTER TABLE invoices ADD COLUMN deadline_policy VARCHAR(40) NOT NULL DEFAULT 'HonorDetectedBeforeExpiry';
+
+-- Rejected = confirmed on-chain after the deadline of a MustConfirmBeforeExpiry invoice, not credited
+ALTER TABLE payments DROP CONSTRAINT payments_status_check;
+ALTER TABLE payments ADD CONSTRAINT payments_status_check
+    CHECK (status IN ('Confirming', 'Confirmed', 'Rejected'));
@@ -0,0 +1,17 @@
+ALTER TABLE payments ADD COLUMN block_timestamp TIMESTAMPTZ;
+ALTER TABLE payments_archive ADD COLUMN block_timestamp TIMESTAMPTZ;
+
+-- block timestamp of the payment that completed the invoice -> successful InvoicePaid delivery
+CREATE TABLE settlement_latencies (
+    invoice_id UUID PRIMARY KEY,
+    payment_id UUID NOT NULL,
+    network VARCHAR(50) NOT NULL,
+    token VARCHAR(10) NOT NULL,
+    latency_ms BIGINT NOT NULL,
+    delivered_at TIMESTAMPTZ NOT NULL,
+
+    CONSTRAINT settlement_latencies_invoice_id_foreign
+        FOREIGN KEY (invoice_id) REFERENCES invoices (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_settlement_latencies_delivered_at ON settlement_latencies (delivered_at);
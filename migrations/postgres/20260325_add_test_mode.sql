@@ -0,0 +1,3 @@
+-- test-mode chains are simulated (crate::chain::simulated), their invoices never see real funds
+ALTER TABLE chains ADD COLUMN test_mode BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE invoices ADD COLUMN test_mode BOOLEAN NOT NULL DEFAULT FALSE;
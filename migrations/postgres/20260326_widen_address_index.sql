@@ -0,0 +1,5 @@
+-- non-hardened derivation goes up to 2^31 - 1, INTEGER overflows on the next candidate
+ALTER TABLE invoices ALTER COLUMN address_index TYPE BIGINT;
+ALTER TABLE address_reservations ALTER COLUMN address_index TYPE BIGINT;
+
+ALTER TABLE chains ADD COLUMN max_address_index BIGINT NOT NULL DEFAULT 2147483647;
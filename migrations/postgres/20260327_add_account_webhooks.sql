@@ -0,0 +1,9 @@
+-- webhooks addressed to a merchant instead of one invoice (PeriodSummary), signed with their own secret
+ALTER TABLE webhooks
+    ALTER COLUMN invoice_id DROP NOT NULL,
+    ADD COLUMN account_id INT,
+    ADD COLUMN secret_key TEXT,
+    ADD CONSTRAINT webhooks_invoice_or_account CHECK (invoice_id IS NOT NULL OR account_id IS NOT NULL);
+
+CREATE UNIQUE INDEX unique_account_webhook ON webhooks (account_id, event_type, dedupe_key)
+    WHERE invoice_id IS NULL;
@@ -0,0 +1,4 @@
+-- polygon pos: the block a payment is in counts as final once a heimdall checkpoint covers it
+ALTER TABLE chains ADD COLUMN heimdall_url TEXT;
+
+ALTER TABLE invoices ADD COLUMN finality_mode VARCHAR(40) NOT NULL DEFAULT 'Confirmations';
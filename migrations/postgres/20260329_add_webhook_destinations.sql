@@ -0,0 +1,13 @@
+-- Suppressed = queued while its url was suspended, never sent
+ALTER TABLE webhooks DROP CONSTRAINT webhooks_status_check;
+ALTER TABLE webhooks ADD CONSTRAINT webhooks_status_check
+    CHECK (status IN ('Pending', 'Processing', 'Sent', 'Failed', 'Suppressed'));
+
+-- failure streaks per webhook url, a row is dropped on the next successful delivery
+CREATE TABLE webhook_destinations (
+    url TEXT PRIMARY KEY,
+    failure_streak INT NOT NULL,
+    failing_since TIMESTAMPTZ NOT NULL,
+    last_error TEXT NOT NULL,
+    suspended_at TIMESTAMPTZ
+);
@@ -0,0 +1,3 @@
+-- rollups: the listener follows this tag instead of latest - block_lag, payments are final once
+-- their block is tagged finalized
+ALTER TABLE chains ADD COLUMN block_tag VARCHAR(20) CHECK (block_tag IN ('Safe', 'Finalized'));
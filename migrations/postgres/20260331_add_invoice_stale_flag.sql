@@ -0,0 +1,2 @@
+ALTER TABLE invoices
+    ADD COLUMN stale_notified BOOLEAN NOT NULL DEFAULT FALSE;
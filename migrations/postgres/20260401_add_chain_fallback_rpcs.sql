@@ -0,0 +1,5 @@
+ALTER TABLE chains
+    ALTER COLUMN rpc_url TYPE TEXT[] USING ARRAY[rpc_url];
+
+ALTER TABLE chains
+    RENAME COLUMN rpc_url TO rpc_urls;
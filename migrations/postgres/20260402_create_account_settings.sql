@@ -0,0 +1,8 @@
+-- per-merchant settings (webhook host limits, ...) as JSON, see AccountSettingKind
+CREATE TABLE account_settings (
+    account_id INT NOT NULL,
+    kind TEXT NOT NULL,
+    value JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (account_id, kind)
+);
@@ -0,0 +1,2 @@
+-- EIP-155 chain id of EVM chains, goes into the EIP-681 checkout links
+ALTER TABLE chains ADD COLUMN chain_id BIGINT;
@@ -0,0 +1,6 @@
+-- API keys added at runtime, only the sha256 of the key is stored. the key AppState is created with isn't
+CREATE TABLE api_keys (
+    key_hash TEXT PRIMARY KEY,
+    role TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,2 @@
+-- xpubs of the merchants' hardened account levels by account_id, see ChainConfig::account_xpubs
+ALTER TABLE chains ADD COLUMN account_xpubs JSONB NOT NULL DEFAULT '{}';
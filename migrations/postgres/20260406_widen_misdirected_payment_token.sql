@@ -0,0 +1,2 @@
+-- the symbol of whatever token arrived, not one of ours, so it isn't bound by the symbol limit
+ALTER TABLE misdirected_payments ALTER COLUMN token TYPE TEXT;
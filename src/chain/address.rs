@@ -0,0 +1,130 @@
+use alloy::primitives::Address;
+use bech32::{ToBase32, Variant};
+use coins_bip32::ecdsa::VerifyingKey;
+use coins_bip32::prelude::{Parent, XPub};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use crate::model::MAX_NON_HARDENED_INDEX;
+
+// turns a derived secp256k1 public key into the address format of one chain family
+pub trait AddressEncoder: Send + Sync {
+    fn encode(&self, pubkey: &VerifyingKey) -> String;
+}
+
+// `xpub` is the one of the invoice's account, see ChainConfig::account_xpub. merchants never
+// share a non-hardened branch, a leaked address key plus a shared parent xpub would expose
+// every other merchant's keys
+pub fn derive_pubkey(xpub: &str, index: u32) -> anyhow::Result<VerifyingKey> {
+    if index > MAX_NON_HARDENED_INDEX {
+        anyhow::bail!("{} is out of the non-hardened derivation range", index);
+    }
+
+    let child_xpub = XPub::from_str(xpub)?.derive_child(index)?;
+    Ok(*child_xpub.as_ref())
+}
+
+pub fn xpub_fingerprint(xpub: &str) -> anyhow::Result<String> {
+    Ok(hex::encode(XPub::from_str(xpub)?.fingerprint().0))
+}
+
+// EIP-55 checksummed 0x address
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvmEncoder;
+
+impl AddressEncoder for EvmEncoder {
+    fn encode(&self, pubkey: &VerifyingKey) -> String {
+        Address::from_public_key(pubkey).to_checksum(None)
+    }
+}
+
+// native segwit v0 (bc1q...), hash160 of the compressed key
+#[derive(Debug, Clone, Copy)]
+pub struct P2wpkhEncoder {
+    hrp: &'static str,
+}
+
+impl P2wpkhEncoder {
+    pub const BITCOIN: Self = Self { hrp: "bc" };
+    pub const BITCOIN_TESTNET: Self = Self { hrp: "tb" };
+    pub const LITECOIN: Self = Self { hrp: "ltc" };
+
+    pub fn hrp(&self) -> &'static str {
+        self.hrp
+    }
+}
+
+impl AddressEncoder for P2wpkhEncoder {
+    fn encode(&self, pubkey: &VerifyingKey) -> String {
+        let compressed = pubkey.to_encoded_point(true);
+        let hash = Ripemd160::digest(Sha256::digest(compressed.as_bytes()));
+
+        let mut data = vec![bech32::u5::try_from_u8(0).unwrap()]; // witness version
+        data.extend(hash.to_base32());
+
+        bech32::encode(self.hrp, data, Variant::Bech32)
+            .expect("hrp is one of the constants above")
+    }
+}
+
+// legacy base58check (1..., L..., D...), version byte followed by hash160 of the compressed key
+#[derive(Debug, Clone, Copy)]
+pub struct P2pkhEncoder {
+    version: u8,
+}
+
+impl P2pkhEncoder {
+    pub const BITCOIN: Self = Self { version: 0x00 };
+    pub const LITECOIN: Self = Self { version: 0x30 };
+    pub const DOGECOIN: Self = Self { version: 0x1e };
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+}
+
+impl AddressEncoder for P2pkhEncoder {
+    fn encode(&self, pubkey: &VerifyingKey) -> String {
+        let compressed = pubkey.to_encoded_point(true);
+        let hash = Ripemd160::digest(Sha256::digest(compressed.as_bytes()));
+
+        let mut payload = vec![self.version];
+        payload.extend_from_slice(&hash);
+
+        bs58::encode(payload).with_check().into_string()
+    }
+}
+
+// base58check of 0x41 followed by the same 20 bytes an EVM address has (T...)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TronEncoder;
+
+const TRON_PREFIX: u8 = 0x41;
+
+impl AddressEncoder for TronEncoder {
+    fn encode(&self, pubkey: &VerifyingKey) -> String {
+        let mut payload = vec![TRON_PREFIX];
+        payload.extend_from_slice(Address::from_public_key(pubkey).as_slice());
+
+        bs58::encode(payload).with_check().into_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoders_match_reference_addresses() {
+        // the generator point, i.e. the public key of private key 1
+        let pubkey = VerifyingKey::from_sec1_bytes(&hex::decode(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap()).unwrap();
+
+        assert_eq!(EvmEncoder.encode(&pubkey), "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf");
+        // BIP173 test vector
+        assert_eq!(P2wpkhEncoder::BITCOIN.encode(&pubkey), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(TronEncoder.encode(&pubkey), "TMVQGm1qAQYVdetCeGRRkTWYYrLXuHK2HC");
+        assert_eq!(P2pkhEncoder::BITCOIN.encode(&pubkey), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(P2pkhEncoder::DOGECOIN.encode(&pubkey), "DFpN6QqFfUm3gKNaxN6tNcab1FArL9cZLE");
+    }
+}
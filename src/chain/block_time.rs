@@ -0,0 +1,75 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+// weight of the newest interval, roughly the last 20 blocks make up the average
+const SMOOTHING: f64 = 0.1;
+
+// moving average of the block interval, fed with the block timestamps the listener fetches anyway
+#[derive(Debug, Default)]
+pub struct BlockTimeEstimator {
+    state: Mutex<EstimatorState>,
+}
+
+#[derive(Debug, Default)]
+struct EstimatorState {
+    last: Option<(u64, u64)>, // (block number, timestamp in secs)
+    avg_ms: Option<f64>,
+}
+
+impl BlockTimeEstimator {
+    pub fn observe(&self, block_number: u64, timestamp: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        // timestamps only have second precision, on fast chains most samples are 0 or 1000
+        // and it evens out in the average. skipped blocks share the interval
+        if let Some((last_block, last_timestamp)) = state.last
+            && block_number > last_block
+            && timestamp >= last_timestamp
+        {
+            let sample = (timestamp - last_timestamp) as f64 * 1000.0
+                / (block_number - last_block) as f64;
+            state.avg_ms = Some(match state.avg_ms {
+                Some(avg) => avg + SMOOTHING * (sample - avg),
+                None => sample,
+            });
+        }
+
+        // a rewind (resync) just starts a new interval from here
+        state.last = Some((block_number, timestamp));
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        self.state.lock().unwrap().avg_ms.map(|ms| Duration::from_secs_f64(ms / 1000.0))
+    }
+
+    pub fn eta(&self, blocks: u64) -> Option<Duration> {
+        self.average().map(|avg| avg.mul_f64(blocks as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_time_estimate() {
+        let estimator = BlockTimeEstimator::default();
+        assert_eq!(estimator.average(), None);
+
+        estimator.observe(100, 1_000);
+        assert_eq!(estimator.average(), None); // one block is no interval yet
+
+        estimator.observe(101, 1_012);
+        assert_eq!(estimator.average(), Some(Duration::from_secs(12)));
+
+        estimator.observe(103, 1_036); // a skipped block, still 12s each
+        assert_eq!(estimator.average(), Some(Duration::from_secs(12)));
+        assert_eq!(estimator.eta(5), Some(Duration::from_secs(60)));
+
+        estimator.observe(104, 1_058);
+        assert_eq!(estimator.average(), Some(Duration::from_secs(13)));
+
+        estimator.observe(50, 500); // rewound, not a sample
+        assert_eq!(estimator.average(), Some(Duration::from_secs(13)));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::db::{Database, DatabaseAdapter};
+use crate::model::ChainStatsDelta;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use crate::runtime::{self, JoinHandle};
+
+use tracing::{debug, error, Instrument};
+
+// persists the listener checkpoint off the block processing path. checkpoints queued while a
+// write is in flight collapse into the newest one, stats are summed up until they get written.
+// a crash loses at most the unwritten tail, those blocks are processed again and recording
+// their payments is idempotent
+pub struct CheckpointFlusher {
+    block: watch::Sender<Option<u64>>,
+    stats: Arc<Mutex<ChainStatsDelta>>,
+    task: JoinHandle<()>,
+}
+
+impl CheckpointFlusher {
+    pub fn spawn(db: Arc<Database>, chain_name: &str) -> Self {
+        let (block, mut rx) = watch::channel(None);
+        let stats = Arc::new(Mutex::new(ChainStatsDelta::default()));
+
+        let pending = stats.clone();
+        let chain = chain_name.to_owned();
+        let span = tracing::info_span!("checkpoint_flusher", chain = %chain);
+
+        let task = runtime::spawn(async move {
+            // still yields the last checkpoint after the sender is gone
+            while rx.changed().await.is_ok() {
+                let Some(block_num) = *rx.borrow_and_update() else {
+                    continue;
+                };
+
+                debug!(block_num, "Saving last processed block to DB");
+                if let Err(e) = db.update_chain_block(&chain, block_num).await {
+                    error!(error = %e, "Failed to update chain block in DB");
+                }
+
+                // stats are best effort, a failed flush just loses this batch
+                let delta = std::mem::take(&mut *pending.lock().unwrap());
+                if !delta.is_empty()
+                    && let Err(e) = db.add_chain_stats(&chain, &delta).await
+                {
+                    error!(error = %e, "Failed to update chain stats in DB");
+                }
+            }
+        }.instrument(span));
+
+        Self { block, stats, task }
+    }
+
+    // never waits for the database
+    pub fn checkpoint(&self, block_num: u64, stats: ChainStatsDelta) {
+        self.stats.lock().unwrap().merge(&stats);
+        self.block.send_replace(Some(block_num));
+    }
+
+    // writes whatever is still queued and waits for it
+    pub async fn close(self) {
+        drop(self.block);
+        if let Err(e) = self.task.await {
+            error!(error = %e, "Checkpoint flusher panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::model::ChainConfig;
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    #[tokio::test]
+    async fn test_flusher_coalesces_and_writes_on_close() {
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let config = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .build()
+            .unwrap();
+        db.add_chain(&config).await.unwrap();
+
+        let flusher = CheckpointFlusher::spawn(db.clone(), "testnet");
+        for block in 1..=50 {
+            flusher.checkpoint(block, ChainStatsDelta { blocks: 1, events: 2, ..Default::default() });
+        }
+        flusher.close().await;
+
+        assert_eq!(db.get_latest_block("testnet").await.unwrap(), Some(50));
+        let stats = db.get_chain_stats("testnet").await.unwrap().unwrap();
+        assert_eq!(stats.blocks_processed, 50);
+        assert_eq!(stats.events_emitted, 100);
+    }
+}
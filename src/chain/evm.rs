@@ -1,38 +1,272 @@
+use crate::chain::address::{derive_pubkey, xpub_fingerprint, AddressEncoder, EvmEncoder};
+use crate::chain::block_time::BlockTimeEstimator;
+use crate::chain::checkpoint::CheckpointFlusher;
+use crate::chain::failover::FailoverTransport;
+use crate::chain::heimdall::Heimdall;
 use crate::chain::BlockchainAdapter;
 use crate::db::{Database, DatabaseAdapter};
-use crate::model::TokenConfig;
-use crate::model::{ChainConfig, PaymentEvent};
+use crate::runtime;
+use crate::model::{BlockTag, ChainCapabilities, ChainStatsDelta, FinalityMode, StartFrom, TokenConfig, TokenPreflightReport};
+use crate::model::{ChainConfig, ChainError, ChainErrorKind, PaymentEvent, RpcAuth, RpcEndpointHealth, TokenRef, UnknownTransfer};
 use alloy::primitives::utils::format_units;
-use alloy::primitives::{Address, BlockNumber, TxHash, B256, U256};
-use alloy::providers::fillers::{BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill,
-                                NonceFiller};
-use alloy::providers::{Identity, Provider, ProviderBuilder, RootProvider};
-use alloy::rpc::types::Filter;
+use alloy::primitives::{address, Address, BlockNumber, TxHash, B256, U256};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::types::{BlockNumberOrTag, Filter, Log};
 use alloy::sol;
-use coins_bip32::prelude::{Parent, XPub};
+use alloy::sol_types::SolEvent;
+use alloy::transports::http::reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use alloy::transports::http::{reqwest, Http};
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
 use url::Url;
 
 use tracing::{debug, error, info, instrument, warn, trace, Instrument};
 
-type EvmProvider = FillProvider<JoinFill<Identity, JoinFill<GasFiller, JoinFill<BlobGasFiller,
-    JoinFill<NonceFiller, ChainIdFiller>>>>, RootProvider>;
-
 sol! {
     #[derive(Debug)]
     event Transfer(address indexed from, address indexed to, uint256 value);
 }
 
+// ERC-4337 EntryPoint (v0.6 to v0.8) and Safe (v1.3+)
+sol! {
+    #[derive(Debug)]
+    event UserOperationEvent(bytes32 indexed userOpHash, address indexed sender,
+        address indexed paymaster, uint256 nonce, bool success, uint256 actualGasCost,
+        uint256 actualGasUsed);
+
+    #[derive(Debug)]
+    event ExecutionSuccess(bytes32 txHash, uint256 payment);
+}
+
+// the canonical EntryPoint deployments, a UserOperationEvent from any other contract is ignored
+const ENTRY_POINTS: [Address; 3] = [
+    address!("0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"), // v0.6
+    address!("0x0000000071727De22E5E9d8BAf0edAc6f37da032"), // v0.7
+    address!("0x4337084D9E255Ff0702461CF8895CE9E3b5Ff108"), // v0.8
+];
+
+sol! {
+    #[sol(rpc)]
+    interface IERC20Metadata {
+        function decimals() external view returns (uint8);
+    }
+}
+
+// the smart account that actually paid, judged by the receipt logs of the transaction.
+// user operations only count when an EntryPoint emitted them, a Safe execution is emitted by the
+// account itself. `from` wins if it is one of the accounts, otherwise there has to be exactly one
+// candidate, a bundle of several user operations can't be attributed without tracing
+fn smart_account_payer(logs: &[Log], from: Address) -> Option<Address> {
+    let mut candidates = HashSet::new();
+
+    for log in logs {
+        if let Ok(op) = log.log_decode::<UserOperationEvent>() {
+            if op.inner.success && ENTRY_POINTS.contains(&log.address()) {
+                candidates.insert(op.inner.sender);
+            }
+        } else if log.log_decode::<ExecutionSuccess>().is_ok() {
+            candidates.insert(log.address());
+        }
+    }
+
+    if candidates.contains(&from) {
+        return Some(from);
+    }
+
+    if candidates.len() == 1 {
+        return candidates.into_iter().next();
+    }
+
+    None
+}
+
+// first block in 0..=head whose timestamp is at or after `target`, head if none is.
+// block timestamps never decrease, so this is a plain binary search
+pub(crate) async fn first_block_at<F, Fut>(head: u64, target: u64, mut timestamp_of: F) -> anyhow::Result<u64>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<u64>>,
+{
+    let (mut lo, mut hi) = (0, head);
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if timestamp_of(mid).await? >= target {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok(lo)
+}
+
+// proxies (USDC & co) keep the event in the implementation, so fall back to recent logs
+const PREFLIGHT_LOG_LOOKBACK: u64 = 1_000;
+
+// an invoice created while its payment's block was being processed only gets watched after
+// that block, so newly watched addresses get the last few processed blocks scanned again
+const WATCH_LOOKBACK_BLOCKS: u64 = 5;
+
+// non-standard tokens get a lenient fallback: some index `value` as well or pad/trim the data
+fn decode_transfer(log: &Log, non_standard: bool) -> Option<(Address, Address, U256)> {
+    if let Ok(transfer) = log.log_decode::<Transfer>() {
+        let event_data = transfer.inner;
+        return Some((event_data.from, event_data.to, event_data.value));
+    }
+
+    if !non_standard {
+        return None;
+    }
+
+    let topics = log.topics();
+    if topics.len() < 3 || topics[0] != Transfer::SIGNATURE_HASH {
+        return None;
+    }
+
+    let from = Address::from_word(topics[1]);
+    let to = Address::from_word(topics[2]);
+
+    let data = &log.data().data;
+    let value = match topics.get(3) {
+        Some(indexed_value) => U256::from_be_bytes(indexed_value.0),
+        None if !data.is_empty() => U256::from_be_slice(&data[..data.len().min(32)]),
+        None => return None,
+    };
+
+    trace!(contract = %log.address(), "Decoded non-standard Transfer event");
+
+    Some((from, to, value))
+}
+
+// watch sets up to this size are pushed into eth_getLogs as topic2 (recipient) filters,
+// split into chunks so each request stays under the providers' topic OR-list limits.
+// above it a single unfiltered request per block is cheaper than many filtered ones
+const TOPIC_FILTER_MAX_ADDRESSES: usize = 500;
+const TOPIC_FILTER_CHUNK: usize = 100;
+
+fn transfer_filters(
+    block_number: BlockNumber,
+    token_addresses: Vec<Address>,
+    addresses: &HashSet<Address>,
+) -> Vec<Filter> {
+    let base = Filter::new()
+        .from_block(block_number)
+        .to_block(block_number)
+        .address(token_addresses)
+        .event("Transfer(address,address,uint256)");
+
+    if addresses.is_empty() || addresses.len() > TOPIC_FILTER_MAX_ADDRESSES {
+        return vec![base];
+    }
+
+    let recipients: Vec<B256> = addresses.iter().map(|a| a.into_word()).collect();
+
+    recipients.chunks(TOPIC_FILTER_CHUNK)
+        .map(|chunk| base.clone().topic2(chunk.to_vec()))
+        .collect()
+}
+
+// any contract, so always filtered by recipient. there's no unfiltered fallback for large
+// watch sets, that would be every Transfer in the block
+fn unknown_transfer_filters(block_number: BlockNumber, addresses: &HashSet<Address>) -> Vec<Filter> {
+    let base = Filter::new()
+        .from_block(block_number)
+        .to_block(block_number)
+        .event("Transfer(address,address,uint256)");
+
+    let recipients: Vec<B256> = addresses.iter().map(|a| a.into_word()).collect();
+
+    recipients.chunks(TOPIC_FILTER_CHUNK)
+        .map(|chunk| base.clone().topic2(chunk.to_vec()))
+        .collect()
+}
+
+// what one block is processed against. taken under the config locks in one go, so a token or
+// address added mid-block shows up in the next block instead of half of this one
+struct BlockSnapshot {
+    generation: u64,
+    addresses: HashSet<Address>,
+    tokens: HashMap<Address, TokenConfig>,
+    record_unknown: bool,
+    resolve_payers: bool,
+}
+
+// events of one block, native transfers and token logs come from separate passes and logs from
+// several getLogs calls. handed over sorted by (transaction index, log index), a native transfer
+// before the logs of its own transaction
+#[derive(Default)]
+struct BlockEvents(Vec<(u64, PaymentEvent)>);
+
+impl BlockEvents {
+    fn push(&mut self, tx_index: u64, event: PaymentEvent) {
+        self.0.push((tx_index, event));
+    }
+
+    async fn send(mut self, sender: &Sender<PaymentEvent>) -> anyhow::Result<u64> {
+        self.0.sort_by_key(|(tx_index, event)| (*tx_index, event.log_index));
+
+        let count = self.0.len() as u64;
+        for (_, event) in self.0 {
+            sender.send(event).await
+                .map_err(|_| anyhow::anyhow!("payment event channel is closed"))?;
+        }
+
+        Ok(count)
+    }
+}
+
 #[derive(Clone)]
 pub struct EvmBlockchain {
     chain_name: String,
     chain_config: Arc<RwLock<ChainConfig>>,
-    provider: EvmProvider,
+    provider: DynProvider,
+    rpc: Option<FailoverTransport>, // under `provider`, None if the provider was passed in
+    block_time: Arc<BlockTimeEstimator>,
+    heimdall: Option<Heimdall>, // ChainConfig::heimdall_url
+    block_tag: Option<BlockTag>, // ChainConfig::block_tag
+}
+
+// plain HTTP JSON-RPC client sending the chain's credentials with every request
+pub fn http_rpc_client(rpc_url: Url, auth: &RpcAuth) -> anyhow::Result<RpcClient> {
+    let client = reqwest::Client::builder().default_headers(rpc_auth_headers(auth)?).build()?;
+    let is_local = alloy::transports::utils::guess_local_url(&rpc_url);
+
+    Ok(RpcClient::new(Http::with_client(client, rpc_url), is_local))
+}
+
+pub(crate) fn rpc_auth_headers(auth: &RpcAuth) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    match auth {
+        RpcAuth::None => {}
+        RpcAuth::Bearer { token } => {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        }
+        RpcAuth::Basic { username, password } => {
+            let encoded = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", username, password));
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Basic {}", encoded))?);
+        }
+        RpcAuth::Headers { headers: custom } => {
+            for (name, value) in custom {
+                headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+            }
+        }
+    }
+
+    for value in headers.values_mut() {
+        value.set_sensitive(true);
+    }
+
+    Ok(headers)
 }
 
 impl std::fmt::Debug for EvmBlockchain {
@@ -47,32 +281,40 @@ impl BlockchainAdapter for EvmBlockchain {
     #[instrument(skip(chain_config), fields(chain = %chain_config.name))]
     fn new(chain_config: ChainConfig) -> anyhow::Result<Self> {
         debug!("Initializing EVM Blockchain adapter");
-        let rpc_url = Url::parse(&chain_config.rpc_url).unwrap();
-        let provider = ProviderBuilder::new().connect_http(rpc_url);
-
-        Ok(Self {
-            chain_name: chain_config.name.clone(),
-            chain_config: Arc::new(RwLock::new(chain_config)),
-            provider,
-        })
+        let transport = FailoverTransport::http(&chain_config.rpc_urls, &chain_config.rpc_auth)?;
+        let is_local = chain_config.rpc_urls.iter()
+            .all(|url| Url::parse(url).is_ok_and(|u| alloy::transports::utils::guess_local_url(&u)));
+        let provider = ProviderBuilder::new()
+            .connect_client(RpcClient::new(transport.clone(), is_local))
+            .erased();
+
+        Ok(Self { rpc: Some(transport), ..Self::with_provider(chain_config, provider)? })
     }
 
     #[instrument(skip(self), level = "debug")]
-    async fn derive_address(&self, index: u32) -> anyhow::Result<String> {
-        trace!("Deriving address for index {}", index);
-
-        let xpub = XPub::from_str(
-            &self.chain_config.read().unwrap().xpub)?;
+    async fn derive_address(&self, account: u32, index: u32) -> anyhow::Result<String> {
+        trace!("Deriving address for account {} index {}", account, index);
 
-        let child_xpub = xpub.derive_child(index)?;
-        let verifying_key = child_xpub.as_ref();
+        let pubkey = derive_pubkey(self.chain_config.read().unwrap().account_xpub(account)?, index)?;
 
-        let addr = Address::from_public_key(&verifying_key).to_string();
+        let addr = EvmEncoder.encode(&pubkey);
         trace!(address = %addr, "Derived address");
 
         Ok(addr)
     }
 
+    fn normalize_address(&self, address: &str) -> Option<String> {
+        Address::from_str(address.trim()).ok().map(|a| a.to_string())
+    }
+
+    fn derivation_path(&self, index: u32) -> String {
+        format!("m/{}", index)
+    }
+
+    fn xpub_fingerprint(&self, account: u32) -> anyhow::Result<String> {
+        xpub_fingerprint(self.chain_config.read().unwrap().account_xpub(account)?)
+    }
+
     #[instrument(skip(self, db, sender), fields(chain = %self.chain_name, node_type = "EVM"), err)]
     async fn listen(&self, db: Arc<Database>, sender: Sender<PaymentEvent>) -> anyhow::Result<()> {
         info!("Starting blockchain listener loop");
@@ -85,28 +327,53 @@ impl BlockchainAdapter for EvmBlockchain {
                 Ok(n) => n,
                 Err(e) => {
                     warn!(error = %e, "Failed to get latest block number, retrying in 5s...");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    runtime::sleep(Duration::from_secs(5)).await;
                     self.provider.get_block_number().await?
                 }
             };
         }
 
         let block_lag = self.chain_config.read().unwrap().block_lag;
+        let mut paused = false;
+        let mut stats = ChainStatsDelta::default();
+        let mut snapshot = Arc::new(self.block_snapshot());
+        let flusher = CheckpointFlusher::spawn(db.clone(), &self.chain_name);
 
         loop {
-            let current_block_num = match self.provider.get_block_number().await {
+            // AppState::stop_listening closes the channel, leave with the checkpoint written
+            if sender.is_closed() {
+                info!("Event channel closed, stopping listener");
+                flusher.close().await;
+                return Ok(());
+            }
+
+            if self.chain_config.read().unwrap().in_maintenance(chrono::Utc::now()) {
+                if !paused {
+                    info!("Maintenance window started, pausing listener");
+                    paused = true;
+                }
+                runtime::sleep(Duration::from_secs(5)).await;
+                continue;
+            } else if paused {
+                info!("Maintenance window is over, resuming listener");
+                paused = false;
+            }
+
+            let current_block_num = match self.head_block(block_lag).await {
                 Ok(n) => n,
                 Err(e) => {
                     warn!(error = %e, "failed to get latest block number from RPC. Sleep 2s...");
-                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    self.record_error(&db, ChainErrorKind::Rpc,
+                                      format!("head block: {}", e)).await;
+                    runtime::sleep(Duration::from_secs(2)).await;
                     continue
                 }
-            }.saturating_sub(block_lag as u64);
+            };
 
             if current_block_num <= last_block_num {
                 trace!(current = current_block_num, last = last_block_num,
                     "No new blocks, sleep 1s...");
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                runtime::sleep(Duration::from_secs(1)).await;
                 continue;
             }
 
@@ -116,12 +383,34 @@ impl BlockchainAdapter for EvmBlockchain {
             };
 
             for block_num in (last_block_num + 1)..=current_block_num {
+                if sender.is_closed() {
+                    break;
+                }
+
+                if snapshot.generation != self.chain_config.read().unwrap().generation() {
+                    let fresh = Arc::new(self.block_snapshot());
+                    trace!(generation = fresh.generation, "Watch config changed, new snapshot");
+
+                    let added: HashSet<Address> = fresh.addresses
+                        .difference(&snapshot.addresses)
+                        .copied()
+                        .collect();
+                    snapshot = fresh;
+
+                    if !added.is_empty() {
+                        self.lookback(&db, &sender, &snapshot, added, last_block_num, decimals,
+                                      &native_symbol).await;
+                    }
+                }
+                let snapshot = snapshot.clone();
+
                 let span = tracing::info_span!("process_block", block_number = block_num);
 
-                async {
+                let result: anyhow::Result<()> = async {
                     debug!("Processing block...");
+                    let started = Instant::now();
 
-                    let transactions: Vec<Value> = loop {
+                    let (transactions, block_timestamp) = loop {
                         let bj: Value = match self.provider.raw_request(
                             "eth_getBlockByNumber".into(),
                             (format!("0x{:x}", block_num), true),
@@ -130,7 +419,9 @@ impl BlockchainAdapter for EvmBlockchain {
                             Err(e) => {
                                 warn!(error = %e,
                                     "RPC Error during getBlockByNumber. Retrying in 1s...");
-                                tokio::time::sleep(Duration::from_secs(1)).await;
+                                self.record_error(&db, ChainErrorKind::Rpc, format!(
+                                    "eth_getBlockByNumber({}): {}", block_num, e)).await;
+                                runtime::sleep(Duration::from_secs(1)).await;
                                 continue;
                             }
                         };
@@ -139,47 +430,81 @@ impl BlockchainAdapter for EvmBlockchain {
                             error!(rpc_error = ?bj["error"], "RPC Node returned error inside response");
                         }
 
+                        let timestamp = bj["timestamp"].as_str()
+                            .and_then(|ts| u64::from_str_radix(ts.trim_start_matches("0x"), 16).ok());
+                        if let Some(timestamp) = timestamp {
+                            self.block_time.observe(block_num, timestamp);
+                        }
+
                         match bj["transactions"].as_array() {
-                            Some(txs) => break txs.to_owned(),
+                            Some(txs) => break (txs.to_owned(), timestamp
+                                .and_then(|ts| DateTime::from_timestamp(ts as i64, 0))),
                             None => {
                                 error!("Failed to parse transactions. Retrying in 1s...");
                                 // THERE IS NO FUCKING WAY THAT THERE ARE NO TRANSACTIONS
-                                tokio::time::sleep(Duration::from_secs(1)).await;
+                                runtime::sleep(Duration::from_secs(1)).await;
                                 continue;
                             }
                         }
                     };
 
-                    let address_set: HashSet<Address> = self.chain_config.read().unwrap()
-                        .watch_addresses.read().unwrap()
-                        .iter()
-                        .map(|s| Address::from_str(&s).unwrap_or_default())
-                        .collect();
+                    // the checkpoint only moves once every event of the block was handed over,
+                    // otherwise the block is picked up again after the listener restarts
+                    let mut accepted = true;
+                    let mut events = 0;
 
-                    let tx_sender = sender.clone();
-                    if let Err(e) = self.process_transactions(
-                        &transactions, &address_set, tx_sender,
-                        decimals, &native_symbol, block_num).await
-                    {
-                        error!(error = %e, "Failed to process block transactions");
-                    }
+                    let mut block_events = BlockEvents::default();
+                    self.process_transactions(&transactions, &snapshot.addresses, &mut block_events,
+                                              decimals, &native_symbol, block_num,
+                                              block_timestamp, snapshot.resolve_payers).await;
 
-                    let logs_sender = sender.clone();
-                    if let Err(e) = self.process_logs(block_num, &transactions,
-                                                      &address_set, logs_sender).await {
+                    if let Err(e) = self.process_logs(&db, block_num, block_timestamp, &transactions,
+                                                      &snapshot, &mut block_events).await {
                         error!(error = %e, "Failed to process logs for block");
+                        self.record_error(&db, ChainErrorKind::Processing, format!(
+                            "logs of block {}: {}", block_num, e)).await;
+                        accepted = false;
+                    }
+
+                    if accepted {
+                        match block_events.send(&sender).await {
+                            Ok(n) => events = n,
+                            Err(e) => {
+                                error!(error = %e, "Failed to hand over events of block");
+                                self.record_error(&db, ChainErrorKind::Processing, format!(
+                                    "events of block {}: {}", block_num, e)).await;
+                                accepted = false;
+                            }
+                        }
+                    }
+
+                    if !accepted {
+                        flusher.checkpoint(last_block_num, std::mem::take(&mut stats));
+                        anyhow::bail!("events of block {} were not accepted, stopping at {}",
+                            block_num, last_block_num)
                     }
 
                     last_block_num = block_num;
                     self.chain_config.write().unwrap().last_processed_block = last_block_num;
 
-                    if last_block_num % 10 == 0 || last_block_num == current_block_num {
-                        debug!("Saving last processed block to DB");
-                        if let Err(e) = db.update_chain_block(&self.chain_name, last_block_num).await {
-                            error!(error = %e, "Failed to update chain block in DB");
-                        }
+                    stats.blocks += 1;
+                    stats.events += events;
+                    stats.processing_ms += started.elapsed().as_millis() as u64;
+                    if events > 0 {
+                        stats.last_event_at = Some(chrono::Utc::now());
+                    }
+
+                    if last_block_num.is_multiple_of(10) || last_block_num == current_block_num {
+                        flusher.checkpoint(last_block_num, std::mem::take(&mut stats));
                     }
+
+                    Ok(())
                 }.instrument(span).await;
+
+                if let Err(e) = result {
+                    flusher.close().await;
+                    return Err(e);
+                }
             }
         }
     }
@@ -205,30 +530,298 @@ impl BlockchainAdapter for EvmBlockchain {
         }
     }
 
+    #[instrument(skip(self), fields(chain = %self.chain_name), err)]
+    async fn preflight_token(&self, token: &TokenConfig) -> anyhow::Result<TokenPreflightReport> {
+        debug!("Running token preflight checks");
+
+        let mut report = TokenPreflightReport::default();
+
+        let Ok(contract) = Address::from_str(&token.contract) else {
+            report.issues.push(format!("'{}' is not a valid EVM address", token.contract));
+            return Ok(report);
+        };
+
+        let code = self.provider.get_code_at(contract).await?;
+        if code.is_empty() {
+            report.issues.push(format!("no contract deployed at {}", contract));
+            return Ok(report);
+        }
+        report.contract_exists = true;
+
+        report.emits_transfer = code.windows(32).any(|w| w == Transfer::SIGNATURE_HASH.as_slice());
+        if !report.emits_transfer {
+            let latest = self.provider.get_block_number().await?;
+            let filter = Filter::new()
+                .from_block(latest.saturating_sub(PREFLIGHT_LOG_LOOKBACK))
+                .to_block(latest)
+                .address(contract)
+                .event("Transfer(address,address,uint256)");
+
+            match self.provider.get_logs(&filter).await {
+                Ok(logs) => report.emits_transfer = !logs.is_empty(),
+                Err(e) => debug!(error = %e, "Failed to fetch recent Transfer logs"),
+            }
+        }
+        if !report.emits_transfer {
+            report.issues.push(format!("{} doesn't look like it emits Transfer events", contract));
+        }
+
+        match IERC20Metadata::new(contract, &self.provider).decimals().call().await {
+            Ok(decimals) => {
+                report.onchain_decimals = Some(decimals);
+                if decimals != token.decimals {
+                    report.issues.push(format!("decimals() returned {}, but {} was supplied",
+                                               decimals, token.decimals));
+                }
+            }
+            Err(e) => {
+                debug!(error = %e, "decimals() call failed");
+                report.issues.push(format!("{} doesn't implement decimals()", contract));
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn capabilities(&self) -> ChainCapabilities {
+        ChainCapabilities {
+            supports_tokens: true,
+            supports_memo: false, // payments are told apart by address only
+            supports_ws: false, // the listener polls over HTTP
+            finality_mode: match (&self.heimdall, self.block_tag) {
+                (Some(_), _) => FinalityMode::Checkpoint,
+                (None, Some(_)) => FinalityMode::FinalizedTag,
+                (None, None) => FinalityMode::Confirmations,
+            },
+            min_confirmations: self.chain_config.read().unwrap().required_confirmations,
+        }
+    }
+
     fn config(&self) -> Arc<RwLock<ChainConfig>> {
         self.chain_config.clone()
     }
+
+    fn block_time(&self) -> &BlockTimeEstimator {
+        &self.block_time
+    }
+
+    fn rpc_health(&self) -> Vec<RpcEndpointHealth> {
+        self.rpc.as_ref().map(FailoverTransport::health).unwrap_or_default()
+    }
+
+    async fn resolve_start_block(&self, start_from: StartFrom) -> anyhow::Result<u64> {
+        let head = self.provider.get_block_number().await?;
+
+        let first = match start_from {
+            StartFrom::Latest => return Ok(head),
+            StartFrom::Block(n) if n > head => {
+                anyhow::bail!("block {} is ahead of the chain head {}", n, head)
+            }
+            StartFrom::Block(n) => n,
+            StartFrom::TimeAgo(ago) => {
+                let target = (chrono::Utc::now().timestamp() as u64).saturating_sub(ago.as_secs());
+                first_block_at(head, target, |n| self.block_timestamp(n)).await?
+            }
+        };
+
+        debug!(?start_from, first, head, "Resolved start block");
+        // 0 would make the listener start at the head instead
+        Ok(first.saturating_sub(1).max(1))
+    }
+
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        Ok(self.provider.get_chain_id().await?)
+    }
+
+    async fn finalized_block(&self) -> anyhow::Result<Option<u64>> {
+        if let Some(heimdall) = &self.heimdall {
+            return Ok(Some(heimdall.checkpointed_block().await?));
+        }
+
+        match self.block_tag {
+            Some(_) => Ok(Some(self.tagged_block(BlockNumberOrTag::Finalized).await?)),
+            None => Ok(None),
+        }
+    }
 }
 
 impl EvmBlockchain {
+    // any transport works here, e.g. ProviderBuilder::connect_mocked_client for tests or
+    // fixture::RpcReplay to rerun recorded production traffic
+    pub fn with_provider(chain_config: ChainConfig, provider: DynProvider) -> anyhow::Result<Self> {
+        Ok(Self {
+            chain_name: chain_config.name.clone(),
+            heimdall: chain_config.heimdall_url.as_deref().map(Heimdall::new).transpose()?,
+            block_tag: chain_config.block_tag,
+            chain_config: Arc::new(RwLock::new(chain_config)),
+            provider,
+            rpc: None,
+            block_time: Arc::new(BlockTimeEstimator::default()),
+        })
+    }
+
+    // best effort and single-shot, what it misses is left to a resync. processing the same
+    // transfer twice is harmless, payments are recorded idempotently
+    #[allow(clippy::too_many_arguments)]
+    async fn lookback(&self, db: &Database, sender: &Sender<PaymentEvent>, snapshot: &BlockSnapshot,
+                      added: HashSet<Address>, up_to: u64, decimals: u8, native_symbol: &str) {
+        let from = up_to.saturating_sub(WATCH_LOOKBACK_BLOCKS - 1);
+        debug!(addresses = added.len(), from, to = up_to, "Looking back for newly watched addresses");
+
+        let lookback = BlockSnapshot {
+            generation: snapshot.generation,
+            addresses: added,
+            tokens: snapshot.tokens.clone(),
+            record_unknown: false, // recorded the first time around
+            resolve_payers: snapshot.resolve_payers,
+        };
+
+        for block_num in from..=up_to {
+            let result: anyhow::Result<u64> = async {
+                let (transactions, block_timestamp) = self.fetch_block(block_num).await?;
+
+                let mut block_events = BlockEvents::default();
+                self.process_transactions(&transactions, &lookback.addresses, &mut block_events,
+                    decimals, native_symbol, block_num, block_timestamp,
+                    lookback.resolve_payers).await;
+                // without the transactions there are no retries for lagging logs, these
+                // blocks are old enough
+                self.process_logs(db, block_num, block_timestamp, &[], &lookback,
+                    &mut block_events).await?;
+
+                block_events.send(sender).await
+            }.await;
+
+            match result {
+                Ok(0) => {}
+                Ok(events) => info!(block_num, events, "Lookback found transfers to newly watched addresses"),
+                Err(e) => warn!(block_num, error = %e, "Lookback failed for block"),
+            }
+        }
+    }
+
+    async fn fetch_block(&self, block_num: u64) -> anyhow::Result<(Vec<Value>, Option<DateTime<Utc>>)> {
+        let block: Value = self.provider.raw_request(
+            "eth_getBlockByNumber".into(),
+            (format!("0x{:x}", block_num), true),
+        ).await?;
+
+        let transactions = block["transactions"].as_array()
+            .ok_or_else(|| anyhow::anyhow!("block {} has no transactions array", block_num))?
+            .to_owned();
+        let timestamp = block["timestamp"].as_str()
+            .and_then(|ts| u64::from_str_radix(ts.trim_start_matches("0x"), 16).ok())
+            .and_then(|ts| DateTime::from_timestamp(ts as i64, 0));
+
+        Ok((transactions, timestamp))
+    }
+
+    fn block_snapshot(&self) -> BlockSnapshot {
+        let guard = self.chain_config.read().unwrap();
+        // read before the sets, a change racing with this lands in the next snapshot
+        let generation = guard.generation();
+
+        let addresses = guard.watch_addresses.read().unwrap()
+            .iter()
+            .map(|s| Address::from_str(s).unwrap_or_default())
+            .collect();
+
+        let tokens = guard.tokens.read().unwrap()
+            .iter()
+            .filter_map(|tc| Address::from_str(&tc.contract).ok().map(|addr| (addr, tc.clone())))
+            .collect();
+
+        BlockSnapshot {
+            generation,
+            addresses,
+            tokens,
+            record_unknown: guard.record_unknown_transfers,
+            resolve_payers: guard.resolve_smart_account_payers,
+        }
+    }
+
+    // best effort, a payment without a resolved payer is still a payment
+    async fn resolve_payer(&self, tx_hash: TxHash, from: &str) -> Option<String> {
+        let from = Address::from_str(from).ok()?;
+
+        let receipt = match self.provider.get_transaction_receipt(tx_hash).await {
+            Ok(Some(receipt)) => receipt,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!(error = %e, %tx_hash, "Failed to fetch receipt to resolve the payer");
+                return None;
+            }
+        };
+
+        let payer = smart_account_payer(receipt.inner.logs(), from)?;
+        debug!(%tx_hash, %payer, "Resolved smart account payer");
+        Some(payer.to_string())
+    }
+
+    async fn tagged_block(&self, tag: BlockNumberOrTag) -> anyhow::Result<u64> {
+        let block = self.provider.get_block_by_number(tag).await?
+            .ok_or_else(|| anyhow::anyhow!("node has no {} block", tag))?;
+
+        Ok(block.header.number)
+    }
+
+    // what the listener processes up to
+    async fn head_block(&self, block_lag: u8) -> anyhow::Result<u64> {
+        match self.block_tag {
+            Some(BlockTag::Safe) => self.tagged_block(BlockNumberOrTag::Safe).await,
+            Some(BlockTag::Finalized) => self.tagged_block(BlockNumberOrTag::Finalized).await,
+            None => Ok(self.provider.get_block_number().await?.saturating_sub(block_lag as u64)),
+        }
+    }
+
+    async fn block_timestamp(&self, number: u64) -> anyhow::Result<u64> {
+        let block = self.provider.get_block_by_number(number.into()).await?
+            .ok_or_else(|| anyhow::anyhow!("block {} not found", number))?;
+
+        Ok(block.header.timestamp)
+    }
+
+    async fn record_error(&self, db: &Database, kind: ChainErrorKind, message: String) {
+        let chain_error = ChainError {
+            network: self.chain_name.clone(),
+            kind,
+            message,
+            created_at: chrono::Utc::now(),
+        };
+
+        if let Err(e) = db.add_chain_error(&chain_error).await {
+            error!(error = %e, "Failed to record chain error");
+        }
+    }
+
+    async fn fetch_logs(&self, filters: &[Filter]) -> anyhow::Result<Vec<Log>> {
+        let mut logs = Vec::new();
+        for filter in filters {
+            logs.extend(self.provider.get_logs(filter).await?);
+        }
+
+        Ok(logs)
+    }
+
     #[instrument(skip_all, fields(block_number = %block_number))]
     async fn process_logs(
         &self,
+        db: &Database,
         block_number: BlockNumber,
+        block_timestamp: Option<DateTime<Utc>>,
         transactions: &[Value],
-        addresses: &HashSet<Address>,
-        sender: Sender<PaymentEvent>,
+        snapshot: &BlockSnapshot,
+        events: &mut BlockEvents,
     ) -> anyhow::Result<()> {
-        let token_map: HashMap<Address, TokenConfig> = {
-            let guard = self.chain_config.read().unwrap();
-            let tokens = guard.tokens.read().unwrap();
-
-            tokens.iter()
-                .filter_map(|tc| {
-                    Address::from_str(&tc.contract).ok().map(|addr| (addr, tc.clone()))
-                })
-                .collect()
-        };
+        let (addresses, token_map) = (&snapshot.addresses, &snapshot.tokens);
+        let record_unknown = snapshot.record_unknown;
+
+        if record_unknown && !addresses.is_empty()
+            && let Err(e) = self.process_unknown_transfers(db, block_number, addresses,
+                                                           token_map).await
+        {
+            error!(error = %e, "Failed to check for transfers from unknown contracts");
+        }
 
         if token_map.is_empty() {
             trace!("No tokens to watch, skipping log processing");
@@ -241,43 +834,39 @@ impl EvmBlockchain {
 
         let mut suspicious_block = false;
         for tx in transactions {
-            if let Some(to_str) = tx["to"].as_str() {
-                if let Ok(to_addr) = to_str.parse::<Address>() {
-                    if token_map.contains_key(&to_addr) {
-                        let input_data = tx["input"].as_str()
-                            .or_else(|| tx["data"].as_str())
-                            .unwrap_or("");
-
-                        // 0xa9059cbb = transfer(address,uint256)
-                        // 0x23b872dd = transferFrom(address,address,uint256)
-                        let is_transfer = input_data.starts_with("0xa9059cbb") ||
-                            input_data.starts_with("0x23b872dd");
-
-                        if is_transfer {
-                            suspicious_block = true;
-                            trace!(
-                                tx = %tx["hash"],
-                                contract = %to_addr,
-                                "Found transfer/transferFrom to watched contract. "
-                            );
-                            break;
-                        }
-                    }
+            if let Some(to_str) = tx["to"].as_str()
+                && let Ok(to_addr) = to_str.parse::<Address>()
+                && token_map.contains_key(&to_addr)
+            {
+                let input_data = tx["input"].as_str()
+                    .or_else(|| tx["data"].as_str())
+                    .unwrap_or("");
+
+                // 0xa9059cbb = transfer(address,uint256)
+                // 0x23b872dd = transferFrom(address,address,uint256)
+                let is_transfer = input_data.starts_with("0xa9059cbb") ||
+                    input_data.starts_with("0x23b872dd");
+
+                if is_transfer {
+                    suspicious_block = true;
+                    trace!(
+                        tx = %tx["hash"],
+                        contract = %to_addr,
+                        "Found transfer/transferFrom to watched contract. "
+                    );
+                    break;
                 }
             }
         }
 
-        let filter = Filter::new()
-            .from_block(block_number)
-            .to_block(block_number)
-            .address(token_addresses)
-            .event("Transfer(address,address,uint256)");
+        let filters = transfer_filters(block_number, token_addresses, addresses);
+        trace!(requests = filters.len(), "Built Transfer log filters");
 
         let mut attempt = 0;
         let max_retries = 15; // WHERE IS TRANSACTION?????????
 
         let logs = loop {
-            match self.provider.get_logs(&filter).await {
+            match self.fetch_logs(&filters).await {
                 Ok(l) => {
                     if !l.is_empty() {
                         break l;
@@ -291,7 +880,7 @@ impl EvmBlockchain {
                             "SUSPICIOUS: Transaction to contract found, but NO LOGS returned. \
                             Possibly RPC Lag or Revert. Retrying in 1s..."
                         );
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        runtime::sleep(Duration::from_secs(1)).await;
                         continue;
                     }
 
@@ -303,7 +892,9 @@ impl EvmBlockchain {
                 },
                 Err(e) => {
                     warn!(error = %e, "Failed to get logs. Retrying in 1s...");
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    self.record_error(db, ChainErrorKind::Rpc, format!(
+                        "eth_getLogs({}): {}", block_number, e)).await;
+                    runtime::sleep(Duration::from_secs(1)).await;
                 }
             }
         };
@@ -320,59 +911,125 @@ impl EvmBlockchain {
                 None => {
                     error!(contract = %contract_address,
                         "Received log from UNKNOWN contract");
+
+                    if record_unknown {
+                        self.record_unknown_transfer(db, &log).await;
+                    }
                     continue;
                 },
             };
 
-            if let Ok(transfer) = log.log_decode::<Transfer>() {
-                let event_data = transfer.inner;
-
-                if addresses.contains(&event_data.to) {
-                    let amount_human = format_units(event_data.value, token_conf.decimals)
-                        .unwrap_or_default();
-
-                    info!(
-                        token = %token_conf.symbol,
-                        amount = %amount_human,
-                        to = %event_data.to,
-                        tx_hash = ?log.transaction_hash,
-                        "Token transfer detected"
-                    );
+            if let Some((from, to, value)) = decode_transfer(&log, token_conf.non_standard)
+                && addresses.contains(&to)
+            {
+                let amount_human = format_units(value, token_conf.decimals)
+                    .unwrap_or_default();
+
+                info!(
+                    token = %token_conf.symbol,
+                    amount = %amount_human,
+                    to = %to,
+                    tx_hash = ?log.transaction_hash,
+                    "Token transfer detected"
+                );
+
+                let tx_hash = log.transaction_hash.unwrap_or_default();
+                let payer = match snapshot.resolve_payers {
+                    true => self.resolve_payer(tx_hash, &from.to_string()).await,
+                    false => None,
+                };
+
+                let event = PaymentEvent {
+                    network: self.chain_name.clone(),
+                    tx_hash,
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    payer,
+                    token: TokenRef::new(&self.chain_name, &token_conf.symbol,
+                                         &token_conf.contract),
+                    amount: amount_human,
+                    amount_raw: value,
+                    decimals: token_conf.decimals,
+                    block_number: log.block_number
+                        .unwrap_or(u64::MAX),
+                    block_timestamp,
+                    log_index: log.log_index,
+                };
+
+                events.push(log.transaction_index.unwrap_or(u64::MAX), event);
+            }
+        }
 
-                    let event = PaymentEvent {
-                        network: self.chain_name.clone(),
-                        tx_hash: log.transaction_hash.unwrap_or_default(),
-                        from: event_data.from.to_string(),
-                        to: event_data.to.to_string(),
-                        token: token_conf.symbol.clone(),
-                        amount: amount_human,
-                        amount_raw: event_data.value,
-                        decimals: token_conf.decimals,
-                        block_number: log.block_number
-                            .unwrap_or(u64::MAX),
-                        log_index: log.log_index,
-                    };
+        Ok(())
+    }
 
-                    if let Err(e) = sender.send(event).await {
-                        error!(error = %e, "Failed to send payment event via channel");
-                    }
+    // transfers to our addresses from contracts we don't know about, usually
+    // a token that got removed while invoices still referenced it
+    async fn process_unknown_transfers(
+        &self,
+        db: &Database,
+        block_number: BlockNumber,
+        addresses: &HashSet<Address>,
+        token_map: &HashMap<Address, TokenConfig>,
+    ) -> anyhow::Result<()> {
+        for filter in unknown_transfer_filters(block_number, addresses) {
+            for log in self.provider.get_logs(&filter).await? {
+                if token_map.contains_key(&log.address()) {
+                    continue;
                 }
+
+                self.record_unknown_transfer(db, &log).await;
             }
         }
 
         Ok(())
     }
 
+    async fn record_unknown_transfer(&self, db: &Database, log: &Log) {
+        let Ok(transfer) = log.log_decode::<Transfer>() else {
+            trace!(contract = %log.address(), "Log is not an ERC-20 Transfer, skipping");
+            return;
+        };
+
+        let event_data = transfer.inner;
+
+        warn!(
+            contract = %log.address(),
+            to = %event_data.to,
+            tx_hash = ?log.transaction_hash,
+            "Transfer from unknown contract to watched address, recording"
+        );
+
+        let unknown = UnknownTransfer {
+            network: self.chain_name.clone(),
+            contract: log.address().to_string(),
+            tx_hash: log.transaction_hash.unwrap_or_default().to_string(),
+            from: event_data.from.to_string(),
+            to: event_data.to.to_string(),
+            amount_raw: event_data.value,
+            block_number: log.block_number.unwrap_or(u64::MAX),
+            log_index: log.log_index.unwrap_or(u64::MAX),
+            created_at: chrono::Utc::now(),
+        };
+
+        if let Err(e) = db.add_unknown_transfer(&unknown).await {
+            error!(error = %e, "Failed to record unknown transfer");
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn process_transactions(
         &self,
         transactions: &[Value],
         addresses: &HashSet<Address>,
-        sender: Sender<PaymentEvent>,
+        events: &mut BlockEvents,
         decimals: u8,
         native_symbol: &str,
-        block_num: u64
-    ) -> anyhow::Result<()> {
-        for tx in transactions {
+        block_num: u64,
+        block_timestamp: Option<DateTime<Utc>>,
+        resolve_payers: bool,
+    ) {
+        for (position, tx) in transactions.iter().enumerate() {
             let to_str = tx["to"].as_str().unwrap_or_default();
 
             if let Ok(to_addr) = to_str.parse::<Address>() {
@@ -400,26 +1057,589 @@ impl EvmBlockchain {
                         "Native payment detected"
                     );
 
+                    let tx_hash: TxHash = tx_hash.parse().unwrap_or_default();
+                    // `from` of a user operation is the bundler, the account is in the receipt
+                    let payer = match resolve_payers {
+                        true => self.resolve_payer(tx_hash, from_str).await,
+                        false => None,
+                    };
+
                     let event = PaymentEvent {
                         network: self.chain_name.clone(),
-                        tx_hash: tx_hash.parse().unwrap_or_default(),
+                        tx_hash,
                         from: from_str.to_string(),
                         to: to_addr.to_string(),
-                        token: native_symbol.to_owned(),
+                        payer,
+                        token: TokenRef::native(&self.chain_name, native_symbol),
                         amount: amount_human,
                         amount_raw: value,
                         decimals,
                         block_number: block_num,
+                        block_timestamp,
                         log_index: None,
                     };
 
-                    if let Err(e) = sender.send(event).await {
-                        error!(error = %e, "Failed to send payment event via channel");
-                    }
+                    let tx_index = tx["transactionIndex"].as_str()
+                        .and_then(|i| u64::from_str_radix(i.trim_start_matches("0x"), 16).ok())
+                        .unwrap_or(position as u64);
+                    events.push(tx_index, event);
                 }
             }
         }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::model::{ChainType, DEFAULT_ACCOUNT, MAX_NON_HARDENED_INDEX, contract_key};
+    use coins_bip32::prelude::XPub;
+    use alloy::providers::mock::Asserter;
+    use coins_bip32::prelude::Parent;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    const WATCHED: &str = "0x1111111111111111111111111111111111111111";
+    const SENDER: &str = "0x2222222222222222222222222222222222222222";
+    const TOKEN: &str = "0x3333333333333333333333333333333333333333";
+    const TX_HASH: &str = "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    // same test vector, chain m/0'
+    const MERCHANT_XPUB: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+
+    fn mocked_chain(asserter: &Asserter) -> EvmBlockchain {
+        let config = ChainConfig {
+            name: "testnet".to_owned(),
+            rpc_urls: vec!["http://localhost:8545".to_owned()],
+            chain_type: ChainType::EVM,
+            xpub: String::new(),
+            native_symbol: "ETH".to_owned(),
+            decimals: 18,
+            last_processed_block: 0,
+            block_lag: 0,
+            required_confirmations: 1,
+            record_unknown_transfers: false,
+            resolve_smart_account_payers: false,
+            rpc_auth: RpcAuth::None,
+            maintenance_windows: vec![],
+            version: 0,
+            test_mode: false,
+            max_address_index: MAX_NON_HARDENED_INDEX,
+            heimdall_url: None,
+            block_tag: None,
+            chain_id: None,
+            account_xpubs: Default::default(),
+            watch_addresses: Default::default(),
+            tokens: Arc::new(RwLock::new(HashSet::from([TokenConfig {
+                symbol: "USDT".to_owned(),
+                contract: TOKEN.to_owned(),
+                decimals: 6,
+                non_standard: false,
+                min_amount: None,
+            }]))),
+            generation: Default::default(),
+        };
+
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone()).erased();
+        EvmBlockchain::with_provider(config, provider).unwrap()
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_derive_address_account_branches() {
+        let chain = mocked_chain(&Asserter::new());
+        chain.chain_config.write().unwrap().xpub = XPUB.to_owned();
+
+        let legacy = XPub::from_str(XPUB).unwrap();
+        let legacy_addr = Address::from_public_key(legacy.derive_child(5).unwrap().as_ref());
+
+        // account 0 must keep the addresses of invoices created before accounts existed
+        let default_addr = chain.derive_address(DEFAULT_ACCOUNT, 5).await.unwrap();
+        assert_eq!(default_addr, legacy_addr.to_string());
+
+        // other merchants need their own xpub from a hardened level, never a branch of the shared one
+        assert!(chain.derive_address(1, 5).await.is_err());
+        chain.chain_config.write().unwrap().account_xpubs.insert(1, MERCHANT_XPUB.to_owned());
+
+        let merchant = XPub::from_str(MERCHANT_XPUB).unwrap();
+        let merchant_addr = chain.derive_address(1, 5).await.unwrap();
+        assert_eq!(merchant_addr, Address::from_public_key(merchant.derive_child(5).unwrap().as_ref()).to_string());
+        assert_ne!(merchant_addr, default_addr);
+
+        assert_eq!(chain.derivation_path(5), "m/5");
+        // parent fingerprint of m/0'/1/2' in the same test vector
+        assert_eq!(chain.xpub_fingerprint(DEFAULT_ACCOUNT).unwrap(), "bef5a2f9");
+        assert_ne!(chain.xpub_fingerprint(1).unwrap(), "bef5a2f9");
+        assert!(chain.xpub_fingerprint(2).is_err());
+
+        let builder = ChainConfig::builder().name("testnet").rpc_url("http://localhost:8545")
+            .xpub(XPUB).native_symbol("ETH");
+        assert!(builder.clone().account_xpub(DEFAULT_ACCOUNT, MERCHANT_XPUB).build().is_err());
+        assert!(builder.clone().account_xpub(1, &XPUB.replace("CkwQ", "CkwR")).build().is_err());
+        assert!(builder.account_xpub(1, MERCHANT_XPUB).build().is_ok());
+    }
+
+    #[test]
+    fn test_transfer_filters_chunk_recipients() {
+        let token = vec![Address::from_str(TOKEN).unwrap()];
+        let addresses: HashSet<Address> = (0..250u64)
+            .map(|i| Address::left_padding_from(&i.to_be_bytes()))
+            .collect();
+
+        let filters = transfer_filters(42, token.clone(), &addresses);
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters.iter().map(|f| f.topics[2].len()).sum::<usize>(), 250);
+
+        let many: HashSet<Address> = (0..=TOPIC_FILTER_MAX_ADDRESSES as u64)
+            .map(|i| Address::left_padding_from(&i.to_be_bytes()))
+            .collect();
+
+        let filters = transfer_filters(42, token, &many);
+        assert_eq!(filters.len(), 1);
+        assert!(filters[0].topics[2].is_empty());
+
+        let filters = unknown_transfer_filters(42, &addresses);
+        assert_eq!(filters.len(), 3);
+        assert!(filters.iter().all(|f| f.topics[2].len() <= TOPIC_FILTER_CHUNK && f.address.is_empty()));
+        assert_eq!(filters.iter().map(|f| f.topics[2].len()).sum::<usize>(), 250);
+
+        let filters = unknown_transfer_filters(42, &many);
+        assert_eq!(filters.len(), TOPIC_FILTER_MAX_ADDRESSES / TOPIC_FILTER_CHUNK + 1);
+        assert!(filters.iter().all(|f| !f.topics[2].is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_preflight_token_reports_decimals_mismatch() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+
+        let bytecode = format!("0x6080{}00", hex::encode(Transfer::SIGNATURE_HASH));
+        asserter.push_success(&bytecode);
+        asserter.push_success(&format!("0x{:064x}", 18));
+
+        let token = TokenConfig::builder()
+            .symbol("USDT")
+            .contract(TOKEN)
+            .decimals(6)
+            .build()
+            .unwrap();
+
+        let report = chain.preflight_token(&token).await.unwrap();
+        assert!(report.contract_exists);
+        assert!(report.emits_transfer);
+        assert_eq!(report.onchain_decimals, Some(18));
+        assert_eq!(report.issues.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_token_rejects_missing_contract() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+        asserter.push_success(&"0x");
+
+        let token = TokenConfig::builder()
+            .symbol("USDT")
+            .contract(TOKEN)
+            .decimals(6)
+            .build()
+            .unwrap();
+
+        let report = chain.preflight_token(&token).await.unwrap();
+        assert!(!report.contract_exists);
+        assert!(!report.is_ok());
+    }
+
+    fn watched() -> HashSet<Address> {
+        HashSet::from([Address::from_str(WATCHED).unwrap()])
+    }
+
+    #[tokio::test]
+    async fn test_process_transactions_detects_native_payment() {
+        let chain = mocked_chain(&Asserter::new());
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let transactions = vec![
+            json!({"hash": TX_HASH, "from": SENDER, "to": WATCHED, "value": "0xde0b6b3a7640000"}),
+            json!({"hash": TX_HASH, "from": SENDER, "to": SENDER, "value": "0x1"}),
+        ];
+
+        let mut events = BlockEvents::default();
+        chain.process_transactions(&transactions, &watched(), &mut events, 18, "ETH", 42, None, false).await;
+        assert_eq!(events.send(&tx).await.unwrap(), 1);
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.token, TokenRef::native("testnet", "ETH"));
+        assert_eq!(event.amount_raw, U256::from(10).pow(U256::from(18)));
+        assert_eq!(event.block_number, 42);
+        assert!(event.log_index.is_none());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_logs_detects_token_transfer() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+        let db = Database::Mock(MockDatabase::new());
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let topic = |addr: &str| Address::from_str(addr).unwrap().into_word();
+        asserter.push_success(&json!([{
+            "address": TOKEN,
+            "topics": [Transfer::SIGNATURE_HASH, topic(SENDER), topic(WATCHED)],
+            "data": format!("0x{:064x}", 5_000_000u64),
+            "blockNumber": "0x2a",
+            "transactionHash": TX_HASH,
+            "transactionIndex": "0x0",
+            "blockHash": format!("0x{}", "bb".repeat(32)),
+            "logIndex": "0x3",
+            "removed": false
+        }]));
+
+        chain.chain_config.read().unwrap()
+            .update_watch_addresses(|addrs| addrs.insert(WATCHED.to_owned()));
+
+        let mut events = BlockEvents::default();
+        chain.process_logs(&db, 42, None, &[], &chain.block_snapshot(), &mut events).await.unwrap();
+        events.send(&tx).await.unwrap();
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.token.symbol, "USDT");
+        assert_eq!(event.token.contract, contract_key(TOKEN));
+        assert_eq!(event.amount, "5.000000");
+        assert_eq!(event.log_index, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_lookback_rescans_recent_blocks_for_added_addresses() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+        let db = Database::Mock(MockDatabase::new());
+        let (tx, mut rx) = mpsc::channel(10);
+
+        // blocks 1 to 5, the payment landed in 3 before its address was watched
+        for block in 1..=5u64 {
+            let transactions = match block {
+                3 => json!([{"hash": TX_HASH, "from": SENDER, "to": WATCHED, "value": "0x1"}]),
+                _ => json!([]),
+            };
+            asserter.push_success(&json!({"timestamp": "0x6500", "transactions": transactions}));
+            asserter.push_success(&json!([]));
+        }
+
+        chain.lookback(&db, &tx, &chain.block_snapshot(), watched(), 5, 18, "ETH").await;
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.block_number, 3);
+        assert_eq!(event.to, Address::from_str(WATCHED).unwrap().to_string());
+        assert!(rx.try_recv().is_err());
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[test]
+    fn test_smart_account_payer() {
+        let bundler = Address::from_str(SENDER).unwrap();
+        let account = Address::from_str(WATCHED).unwrap();
+        let log = |address: &str, topics: Vec<B256>, data: String| -> Log {
+            serde_json::from_value(json!({
+                "address": address,
+                "topics": topics,
+                "data": data,
+                "blockNumber": "0x2a",
+                "transactionHash": TX_HASH,
+                "transactionIndex": "0x0",
+                "blockHash": format!("0x{}", "bb".repeat(32)),
+                "logIndex": "0x0",
+                "removed": false
+            })).unwrap()
+        };
+
+        let entry_point = ENTRY_POINTS[1].to_string();
+        let user_op_from = |emitter: &str, sender: Address, success: bool| log(
+            emitter,
+            vec![UserOperationEvent::SIGNATURE_HASH, B256::repeat_byte(1), sender.into_word(),
+                 B256::ZERO],
+            format!("0x{:064x}{:064x}{:064x}{:064x}", 1, success as u8, 2, 3),
+        );
+        let user_op = |sender: Address, success: bool| user_op_from(&entry_point, sender, success);
+        let safe_exec = log(
+            WATCHED,
+            vec![ExecutionSuccess::SIGNATURE_HASH],
+            format!("0x{}{:064x}", "cc".repeat(32), 0),
+        );
+
+        assert_eq!(smart_account_payer(&[user_op(account, true)], bundler), Some(account));
+        assert_eq!(smart_account_payer(&[safe_exec], bundler), Some(account));
+        assert_eq!(smart_account_payer(&[user_op(account, false)], bundler), None);
+        assert_eq!(smart_account_payer(&[], bundler), None);
+        // anyone can emit the event, only an EntryPoint's counts
+        assert_eq!(smart_account_payer(&[user_op_from(TOKEN, account, true)], bundler), None);
+
+        // a bundle can't be attributed, unless `from` itself is one of the accounts
+        let other = Address::repeat_byte(0x44);
+        let bundle = [user_op(account, true), user_op(other, true)];
+        assert_eq!(smart_account_payer(&bundle, bundler), None);
+        assert_eq!(smart_account_payer(&bundle, other), Some(other));
+    }
+
+    #[test]
+    fn test_decode_transfer_non_standard() {
+        let (from, to) = (Address::from_str(SENDER).unwrap(), Address::from_str(WATCHED).unwrap());
+        let log = |topics: Vec<B256>, data: String| -> Log {
+            serde_json::from_value(json!({
+                "address": TOKEN,
+                "topics": topics,
+                "data": data,
+                "blockNumber": "0x2a",
+                "transactionHash": TX_HASH,
+                "transactionIndex": "0x0",
+                "blockHash": format!("0x{}", "bb".repeat(32)),
+                "logIndex": "0x0",
+                "removed": false
+            })).unwrap()
+        };
+        let topics = vec![Transfer::SIGNATURE_HASH, from.into_word(), to.into_word()];
+
+        let standard = log(topics.clone(), format!("0x{:064x}", 500));
+        assert_eq!(decode_transfer(&standard, false), Some((from, to, U256::from(500))));
+        assert_eq!(decode_transfer(&standard, true), Some((from, to, U256::from(500))));
+
+        // value indexed as a fourth topic
+        let indexed = log([topics.clone(), vec![B256::from(U256::from(700))]].concat(), "0x".to_owned());
+        assert_eq!(decode_transfer(&indexed, false), None);
+        assert_eq!(decode_transfer(&indexed, true), Some((from, to, U256::from(700))));
+
+        // short data is read as is, anything past the first word is ignored
+        let short = log(topics.clone(), "0x01f4".to_owned());
+        assert_eq!(decode_transfer(&short, false), None);
+        assert_eq!(decode_transfer(&short, true), Some((from, to, U256::from(500))));
+        let long = log(topics.clone(), format!("0x{:064x}{:064x}", 900, 1));
+        assert_eq!(decode_transfer(&long, true), Some((from, to, U256::from(900))));
+
+        // still has to look like a Transfer
+        assert_eq!(decode_transfer(&log(topics.clone(), "0x".to_owned()), true), None);
+        assert_eq!(decode_transfer(&log(topics[..2].to_vec(), format!("0x{:064x}", 500)), true), None);
+        let approval = log([vec![B256::repeat_byte(0x8c)], topics[1..].to_vec()].concat(), format!("0x{:064x}", 500));
+        assert_eq!(decode_transfer(&approval, true), None);
+    }
+
+    #[tokio::test]
+    async fn test_first_block_at() {
+        // 12s blocks starting at t=1000
+        let timestamps: Vec<u64> = (0..=100).map(|n| 1000 + n * 12).collect();
+        let search = |target| first_block_at(100, target, |n| {
+            let ts = timestamps[n as usize];
+            async move { Ok(ts) }
+        });
+
+        assert_eq!(search(0).await.unwrap(), 0);
+        assert_eq!(search(1000 + 50 * 12).await.unwrap(), 50);
+        assert_eq!(search(1000 + 50 * 12 + 1).await.unwrap(), 51);
+        assert_eq!(search(u64::MAX).await.unwrap(), 100);
+    }
+
+    #[test]
+    fn test_block_snapshot_follows_generation() {
+        let chain = mocked_chain(&Asserter::new());
+        let before = chain.block_snapshot();
+        assert!(before.addresses.is_empty());
+
+        let new_token = TokenConfig::builder()
+            .symbol("DAI")
+            .contract(SENDER)
+            .decimals(18)
+            .build()
+            .unwrap();
+        {
+            let config = chain.chain_config.read().unwrap();
+            config.update_watch_addresses(|addrs| addrs.insert(WATCHED.to_owned()));
+            config.update_tokens(|tokens| tokens.insert(new_token));
+        }
+
+        let after = chain.block_snapshot();
+        assert!(after.generation > before.generation);
+        assert!(after.addresses.contains(&Address::from_str(WATCHED).unwrap()));
+        assert_eq!(after.tokens.len(), 2);
+        // snapshots already handed out stay as they were
+        assert_eq!(before.tokens.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_block_events_fail_on_closed_channel() {
+        let chain = mocked_chain(&Asserter::new());
+        let (tx, rx) = mpsc::channel(10);
+        drop(rx);
+
+        let transactions = vec![
+            json!({"hash": TX_HASH, "from": SENDER, "to": WATCHED, "value": "0x1"}),
+        ];
+
+        let mut events = BlockEvents::default();
+        chain.process_transactions(&transactions, &watched(), &mut events, 18, "ETH", 42, None, false).await;
+        assert!(events.send(&tx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_block_events_are_sent_in_chain_order() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+        let db = Database::Mock(MockDatabase::new());
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let topic = |addr: &str| Address::from_str(addr).unwrap().into_word();
+        let log = |tx_index: u64, log_index: u64| json!({
+            "address": TOKEN,
+            "topics": [Transfer::SIGNATURE_HASH, topic(SENDER), topic(WATCHED)],
+            "data": format!("0x{:064x}", 1u64),
+            "blockNumber": "0x2a",
+            "transactionHash": TX_HASH,
+            "transactionIndex": format!("0x{:x}", tx_index),
+            "blockHash": format!("0x{}", "bb".repeat(32)),
+            "logIndex": format!("0x{:x}", log_index),
+            "removed": false
+        });
+        asserter.push_success(&json!([log(4, 9), log(1, 2), log(4, 7)]));
+
+        chain.chain_config.read().unwrap()
+            .update_watch_addresses(|addrs| addrs.insert(WATCHED.to_owned()));
+
+        // a native transfer in the same transaction as the later logs, one without an index
+        let transactions = vec![
+            json!({"hash": TX_HASH, "from": SENDER, "to": WATCHED, "value": "0x1", "transactionIndex": "0x4"}),
+            json!({"hash": TX_HASH, "from": SENDER, "to": WATCHED, "value": "0x1"}),
+        ];
+
+        let mut events = BlockEvents::default();
+        chain.process_logs(&db, 42, None, &[], &chain.block_snapshot(), &mut events).await.unwrap();
+        chain.process_transactions(&transactions, &watched(), &mut events, 18, "ETH", 42, None, false).await;
+        assert_eq!(events.send(&tx).await.unwrap(), 5);
+
+        let mut order = vec![];
+        while let Ok(event) = rx.try_recv() {
+            order.push((event.token.symbol, event.log_index));
+        }
+        assert_eq!(order, vec![
+            ("ETH".to_owned(), None), // position 1
+            ("USDT".to_owned(), Some(2)),
+            ("ETH".to_owned(), None),
+            ("USDT".to_owned(), Some(7)),
+            ("USDT".to_owned(), Some(9)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_listen_stops_on_closed_channel() {
+        let asserter = Asserter::new();
+        let chain = mocked_chain(&asserter);
+        {
+            let mut config = chain.chain_config.write().unwrap();
+            config.last_processed_block = 41;
+            config.update_watch_addresses(|addrs| addrs.insert(WATCHED.to_owned()));
+        }
+
+        let db = Database::Mock(MockDatabase::new());
+        let config = chain.chain_config.read().unwrap().clone();
+        db.add_chain(&config).await.unwrap();
+
+        let (tx, rx) = mpsc::channel(10);
+        drop(rx);
+
+        // what AppState::stop_listening does, nothing is fetched and the checkpoint stays
+        let db = Arc::new(db);
+        chain.listen(db.clone(), tx).await.unwrap();
+        assert_eq!(chain.chain_config.read().unwrap().last_processed_block, 41);
+        assert_eq!(db.get_latest_block("testnet").await.unwrap(), Some(41));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_auth_is_sent() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let block_number = ResponseTemplate::new(200)
+            .set_body_json(json!({"jsonrpc": "2.0", "id": 0, "result": "0x2a"}));
+        Mock::given(method("POST"))
+            .and(header("Authorization", "Basic dXNlcjpwYXNz"))
+            .respond_with(block_number.clone())
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header("X-Api-Key", "key"))
+            .respond_with(block_number)
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&server.uri()).unwrap();
+        let block_number = async |auth: RpcAuth| {
+            let client = http_rpc_client(url.clone(), &auth).unwrap();
+            ProviderBuilder::new().connect_client(client).get_block_number().await.ok()
+        };
+
+        let basic = RpcAuth::Basic { username: "user".to_owned(), password: "pass".to_owned() };
+        let headers = RpcAuth::Headers { headers: [("X-Api-Key".to_owned(), "key".to_owned())].into() };
+
+        assert_eq!(block_number(basic).await, Some(42));
+        assert_eq!(block_number(headers).await, Some(42));
+        assert_eq!(block_number(RpcAuth::None).await, None); // wiremock answers 404
+    }
+
+    #[tokio::test]
+    async fn test_head_block_follows_the_block_tag() {
+        use crate::model::BlockTag;
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+        let block = |number: u64| {
+            let mut block = alloy::rpc::types::Block::<alloy::rpc::types::Transaction>::default();
+            block.header.inner.number = number;
+            serde_json::to_value(block).unwrap()
+        };
+
+        // latest is 100, the safe block 90 and the finalized one 80
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let call: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                let result = match (call["method"].as_str().unwrap(), call["params"][0].as_str()) {
+                    ("eth_blockNumber", _) => json!("0x64"),
+                    ("eth_getBlockByNumber", Some("safe")) => block(90),
+                    ("eth_getBlockByNumber", Some("finalized")) => block(80),
+                    (method, tag) => panic!("unexpected RPC call {} {:?}", method, tag),
+                };
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+            })
+            .mount(&server)
+            .await;
+
+        let chain = |tag: Option<BlockTag>| {
+            let builder = ChainConfig::builder()
+                .name("rollup")
+                .rpc_url(&server.uri())
+                .xpub(XPUB)
+                .native_symbol("ETH");
+            let builder = match tag {
+                Some(tag) => builder.block_tag(tag),
+                None => builder,
+            };
+            EvmBlockchain::new(builder.build().unwrap()).unwrap()
+        };
+
+        // without a tag the head is latest minus the lag, and finality is counted in confirmations
+        let untagged = chain(None);
+        assert_eq!(untagged.head_block(5).await.unwrap(), 95);
+        assert_eq!(untagged.finalized_block().await.unwrap(), None);
+        assert_eq!(untagged.capabilities().finality_mode, FinalityMode::Confirmations);
+
+        let safe = chain(Some(BlockTag::Safe));
+        assert_eq!(safe.head_block(5).await.unwrap(), 90);
+        // payments still wait for the finalized block, safe only drives the listener
+        assert_eq!(safe.finalized_block().await.unwrap(), Some(80));
+        assert_eq!(safe.capabilities().finality_mode, FinalityMode::FinalizedTag);
+
+        let finalized = chain(Some(BlockTag::Finalized));
+        assert_eq!(finalized.head_block(5).await.unwrap(), 80);
+        assert_eq!(finalized.finalized_block().await.unwrap(), Some(80));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::chain::evm::http_rpc_client;
+use crate::model::{RpcAuth, RpcEndpointHealth};
+use alloy::rpc::json_rpc::{RequestPacket, ResponsePacket};
+use alloy::transports::{BoxTransport, TransportError, TransportErrorKind, TransportFut};
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::Service;
+use url::Url;
+
+use tracing::{debug, warn};
+
+// a failing endpoint is skipped for BACKOFF_BASE, doubling with every failure in a row
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+
+// JSON-RPC error codes providers answer with once their rate limit is hit (alchemy 429,
+// infura -32005, quicknode -32007). most send an HTTP 429 instead, that one is a transport error
+const RATE_LIMITED: [i64; 3] = [429, -32005, -32007];
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    consecutive_failures: u32,
+    backoff_until: Option<Instant>,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    last_success_at: Option<DateTime<Utc>>,
+}
+
+impl EndpointState {
+    fn backing_off(&self, now: Instant) -> bool {
+        self.backoff_until.is_some_and(|until| until > now)
+    }
+}
+
+struct Endpoint {
+    url: String,
+    host: String, // what gets logged and reported, providers put API keys in the url
+    transport: BoxTransport,
+    state: Mutex<EndpointState>,
+}
+
+// JSON-RPC over a chain's rpc_urls. every request goes to the first endpoint that isn't backing
+// off, a failed or rate limited request is retried on the next one right away. an endpoint
+// whose backoff is over gets requests again, so the primary takes over once it recovers
+#[derive(Clone)]
+pub struct FailoverTransport {
+    endpoints: Arc<[Endpoint]>,
+    active: Arc<AtomicUsize>, // last endpoint that answered
+}
+
+impl std::fmt::Debug for FailoverTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FailoverTransport")
+            .field("hosts", &self.endpoints.iter().map(|e| &e.host).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl FailoverTransport {
+    // (url, transport) by priority
+    pub fn new(endpoints: impl IntoIterator<Item = (String, BoxTransport)>) -> Self {
+        let endpoints: Arc<[Endpoint]> = endpoints.into_iter()
+            .map(|(url, transport)| Endpoint {
+                host: Url::parse(&url).ok()
+                    .and_then(|u| u.host_str().map(str::to_owned))
+                    .unwrap_or_else(|| "unknown".to_owned()),
+                url,
+                transport,
+                state: Mutex::new(EndpointState::default()),
+            })
+            .collect();
+
+        Self { endpoints, active: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn http(urls: &[String], auth: &RpcAuth) -> anyhow::Result<Self> {
+        if urls.is_empty() {
+            anyhow::bail!("at least one rpc url is required");
+        }
+
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            let client = http_rpc_client(Url::parse(url)?, auth)?;
+            endpoints.push((url.clone(), BoxTransport::new(client.transport().clone())));
+        }
+
+        Ok(Self::new(endpoints))
+    }
+
+    pub fn health(&self) -> Vec<RpcEndpointHealth> {
+        let now = Instant::now();
+        let active = self.active.load(Ordering::Relaxed);
+
+        self.endpoints.iter().enumerate()
+            .map(|(priority, endpoint)| {
+                let state = endpoint.state.lock().unwrap();
+                RpcEndpointHealth {
+                    host: endpoint.host.clone(),
+                    priority,
+                    active: priority == active,
+                    healthy: !state.backing_off(now),
+                    consecutive_failures: state.consecutive_failures,
+                    last_error: state.last_error.clone(),
+                    last_error_at: state.last_error_at,
+                    last_success_at: state.last_success_at,
+                }
+            })
+            .collect()
+    }
+
+    // endpoints not backing off by priority, then the others by when their backoff ends. a
+    // request is never refused without trying every endpoint
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut order: Vec<(Option<Instant>, usize)> = self.endpoints.iter().enumerate()
+            .map(|(i, endpoint)| {
+                let state = endpoint.state.lock().unwrap();
+                (state.backoff_until.filter(|_| state.backing_off(now)), i)
+            })
+            .collect();
+        order.sort();
+
+        order.into_iter().map(|(_, i)| i).collect()
+    }
+
+    fn record_success(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        {
+            let mut state = endpoint.state.lock().unwrap();
+            state.consecutive_failures = 0;
+            state.backoff_until = None;
+            state.last_success_at = Some(Utc::now());
+        }
+
+        let previous = self.active.swap(index, Ordering::Relaxed);
+        if previous != index {
+            warn!(from = %self.endpoints[previous].host, to = %endpoint.host, "Switched RPC endpoint");
+        }
+    }
+
+    fn record_failure(&self, index: usize, error: &str) {
+        let endpoint = &self.endpoints[index];
+        let error = error.replace(&endpoint.url, &endpoint.host);
+
+        let mut state = endpoint.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        let backoff = BACKOFF_BASE.saturating_mul(1 << (state.consecutive_failures - 1).min(16))
+            .min(BACKOFF_MAX);
+        state.backoff_until = Some(Instant::now() + backoff);
+        state.last_error_at = Some(Utc::now());
+
+        debug!(host = %endpoint.host, failures = state.consecutive_failures, ?backoff, %error,
+            "RPC endpoint failed");
+        state.last_error = Some(error);
+    }
+}
+
+fn rate_limited(response: &ResponsePacket) -> bool {
+    match response {
+        ResponsePacket::Single(res) => res.payload.as_error().is_some_and(|e| RATE_LIMITED.contains(&e.code)),
+        ResponsePacket::Batch(res) => res.iter()
+            .any(|r| r.payload.as_error().is_some_and(|e| RATE_LIMITED.contains(&e.code))),
+    }
+}
+
+fn rate_limited_http(error: &TransportError) -> bool {
+    matches!(error.as_transport_err(), Some(TransportErrorKind::HttpError(e)) if e.is_rate_limit_err())
+}
+
+impl Service<RequestPacket> for FailoverTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: RequestPacket) -> Self::Future {
+        let this = self.clone();
+
+        Box::pin(async move {
+            let mut last = None;
+
+            for index in this.order() {
+                let mut transport = this.endpoints[index].transport.clone();
+
+                match transport.call(req.clone()).await {
+                    Ok(res) if rate_limited(&res) => {
+                        this.record_failure(index, "rate limited");
+                        last = Some(Ok(res));
+                    }
+                    Ok(res) => {
+                        this.record_success(index);
+                        return Ok(res);
+                    }
+                    Err(e) if rate_limited_http(&e) => {
+                        this.record_failure(index, "rate limited");
+                        last = Some(Err(e));
+                    }
+                    Err(e) => {
+                        this.record_failure(index, &e.to_string());
+                        last = Some(Err(e));
+                    }
+                }
+            }
+
+            last.unwrap_or_else(|| Err(TransportErrorKind::custom_str("no rpc endpoints configured")))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::providers::{Provider, ProviderBuilder};
+    use alloy::rpc::client::RpcClient;
+    use alloy::rpc::json_rpc::ErrorPayload;
+    use alloy::transports::mock::{Asserter, MockTransport};
+
+    #[tokio::test]
+    async fn test_requests_fail_over_to_the_next_endpoint() {
+        let (primary, fallback) = (Asserter::new(), Asserter::new());
+        let transport = FailoverTransport::new([
+            ("https://primary.example/v3/secret".to_owned(), BoxTransport::new(MockTransport::new(primary.clone()))),
+            ("https://fallback.example".to_owned(), BoxTransport::new(MockTransport::new(fallback.clone()))),
+        ]);
+        let provider = ProviderBuilder::new()
+            .connect_client(RpcClient::new(transport.clone(), true))
+            .erased();
+
+        // nothing queued on the primary fails the request there
+        fallback.push_success(&"0x10");
+        assert_eq!(provider.get_block_number().await.unwrap(), 16);
+
+        let health = transport.health();
+        assert_eq!((health[0].host.as_str(), health[0].healthy, health[0].consecutive_failures),
+                   ("primary.example", false, 1));
+        assert!(!health[0].last_error.as_ref().unwrap().contains("secret"));
+        assert!(health[1].active && health[1].healthy && health[1].last_success_at.is_some());
+
+        // the primary is backing off and isn't asked
+        fallback.push_success(&"0x11");
+        assert_eq!(provider.get_block_number().await.unwrap(), 17);
+        assert_eq!(transport.health()[0].consecutive_failures, 1);
+
+        // backoff over, the primary is first again
+        transport.endpoints[0].state.lock().unwrap().backoff_until = Some(Instant::now());
+        primary.push_success(&"0x12");
+        assert_eq!(provider.get_block_number().await.unwrap(), 18);
+        let health = transport.health();
+        assert!(health[0].active && health[0].healthy);
+        assert_eq!(health[0].consecutive_failures, 0);
+
+        // rate limited counts as failed, once every endpoint failed the last answer is returned
+        primary.push_failure(ErrorPayload { code: -32005, message: "limit exceeded".into(), data: None });
+        assert!(provider.get_block_number().await.is_err());
+        assert!(transport.health().iter().all(|e| !e.healthy));
+    }
+
+    #[tokio::test]
+    async fn test_http_rate_limits_fail_over() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).set_body_string("Too Many Requests"))
+            .mount(&server)
+            .await;
+
+        let fallback = Asserter::new();
+        let primary = FailoverTransport::http(&[server.uri()], &RpcAuth::None).unwrap();
+        let transport = FailoverTransport::new([
+            (server.uri(), primary.endpoints[0].transport.clone()),
+            ("https://fallback.example".to_owned(), BoxTransport::new(MockTransport::new(fallback.clone()))),
+        ]);
+        let provider = ProviderBuilder::new()
+            .connect_client(RpcClient::new(transport.clone(), true))
+            .erased();
+
+        fallback.push_success(&"0x10");
+        assert_eq!(provider.get_block_number().await.unwrap(), 16);
+        assert_eq!(transport.health()[0].last_error.as_deref(), Some("rate limited"));
+    }
+
+    #[tokio::test]
+    async fn test_no_endpoints_is_an_error() {
+        assert!(FailoverTransport::http(&[], &RpcAuth::None).is_err());
+
+        let provider = ProviderBuilder::new()
+            .connect_client(RpcClient::new(FailoverTransport::new([]), true))
+            .erased();
+        assert!(provider.get_block_number().await.is_err());
+    }
+}
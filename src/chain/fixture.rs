@@ -0,0 +1,269 @@
+use crate::chain::evm::http_rpc_client;
+use crate::model::RpcAuth;
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::json_rpc::{
+    ErrorPayload, RequestPacket, Response, ResponsePacket, ResponsePayload, SerializedRequest,
+};
+use alloy::transports::{BoxTransport, TransportError, TransportErrorKind, TransportFut};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::Service;
+use url::Url;
+
+use tracing::{debug, warn};
+
+// record/replay of raw JSON-RPC traffic, so a block that broke the listener in production can
+// be fed to the exact same code in a test. fixtures are JSON lines, one exchange per line
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RpcExchange {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+impl RpcExchange {
+    // request ids differ between runs, so exchanges are keyed by method and params only.
+    // serde_json sorts object keys which keeps the key stable
+    fn key(&self) -> String {
+        format!("{}:{}", self.method, self.params)
+    }
+
+    fn into_payload(self) -> Result<ResponsePayload, TransportError> {
+        match (self.result, self.error) {
+            (_, Some(error)) => {
+                let error: ErrorPayload = serde_json::from_value(error)
+                    .map_err(TransportErrorKind::custom)?;
+                Ok(ResponsePayload::Failure(error))
+            }
+            (result, None) => {
+                let raw = RawValue::from_string(result.unwrap_or(Value::Null).to_string())
+                    .map_err(TransportErrorKind::custom)?;
+                Ok(ResponsePayload::Success(raw))
+            }
+        }
+    }
+}
+
+fn request_params(req: &SerializedRequest) -> Value {
+    req.params()
+        .and_then(|p| serde_json::from_str(p.get()).ok())
+        .unwrap_or(Value::Null)
+}
+
+fn requests(packet: &RequestPacket) -> &[SerializedRequest] {
+    match packet {
+        RequestPacket::Single(req) => std::slice::from_ref(req),
+        RequestPacket::Batch(reqs) => reqs,
+    }
+}
+
+fn responses(packet: &ResponsePacket) -> &[Response] {
+    match packet {
+        ResponsePacket::Single(res) => std::slice::from_ref(res),
+        ResponsePacket::Batch(res) => res,
+    }
+}
+
+// passes everything through to the inner transport and appends each exchange to the fixture
+#[derive(Clone)]
+pub struct RpcRecorder {
+    inner: BoxTransport,
+    file: Arc<Mutex<File>>,
+}
+
+impl RpcRecorder {
+    pub fn new(inner: BoxTransport, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { inner, file: Arc::new(Mutex::new(file)) })
+    }
+
+    // provider over plain HTTP that records into `path`
+    pub fn http_provider(rpc_url: Url, auth: &RpcAuth, path: impl AsRef<Path>) -> anyhow::Result<DynProvider> {
+        let inner = http_rpc_client(rpc_url, auth)?;
+        let is_local = inner.is_local();
+        let recorder = Self::new(BoxTransport::new(inner.transport().clone()), path)?;
+
+        Ok(ProviderBuilder::new()
+            .connect_client(RpcClient::new(recorder, is_local))
+            .erased())
+    }
+
+    fn record(&self, request: &RequestPacket, response: &ResponsePacket) {
+        let mut lines = String::new();
+
+        for res in responses(response) {
+            let Some(req) = requests(request).iter().find(|r| r.id() == &res.id) else {
+                continue;
+            };
+
+            let (result, error) = match &res.payload {
+                ResponsePayload::Success(raw) => (serde_json::from_str(raw.get()).ok(), None),
+                ResponsePayload::Failure(e) => (None, serde_json::to_value(e).ok()),
+            };
+
+            let exchange = RpcExchange {
+                method: req.method().to_owned(),
+                params: request_params(req),
+                result,
+                error,
+            };
+
+            if let Ok(line) = serde_json::to_string(&exchange) {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+        }
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(lines.as_bytes()) {
+            warn!(error = %e, "Failed to write RPC fixture");
+        }
+    }
+}
+
+impl Service<RequestPacket> for RpcRecorder {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: RequestPacket) -> Self::Future {
+        let this = self.clone();
+        let mut inner = self.inner.clone();
+
+        Box::pin(async move {
+            let res = inner.call(req.clone()).await?;
+            this.record(&req, &res);
+            Ok(res)
+        })
+    }
+}
+
+// answers from a recorded fixture. identical requests get their answers in recorded order and
+// the last one keeps repeating, so polling calls like eth_blockNumber settle on the final head
+#[derive(Clone, Debug)]
+pub struct RpcReplay {
+    exchanges: Arc<Mutex<HashMap<String, VecDeque<RpcExchange>>>>,
+}
+
+impl RpcReplay {
+    pub fn new(exchanges: impl IntoIterator<Item = RpcExchange>) -> Self {
+        let mut map: HashMap<String, VecDeque<RpcExchange>> = HashMap::new();
+        for exchange in exchanges {
+            map.entry(exchange.key()).or_default().push_back(exchange);
+        }
+
+        Self { exchanges: Arc::new(Mutex::new(map)) }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut exchanges = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            exchanges.push(serde_json::from_str(&line)?);
+        }
+
+        debug!(count = exchanges.len(), "Loaded RPC fixture");
+        Ok(Self::new(exchanges))
+    }
+
+    pub fn provider(self) -> DynProvider {
+        ProviderBuilder::new()
+            .connect_client(RpcClient::new(self, true))
+            .erased()
+    }
+
+    fn answer(&self, req: &SerializedRequest) -> Result<Response, TransportError> {
+        let key = format!("{}:{}", req.method(), request_params(req));
+
+        let exchange = {
+            let mut map = self.exchanges.lock().unwrap();
+            let queue = map.get_mut(&key).filter(|q| !q.is_empty()).ok_or_else(|| {
+                TransportErrorKind::custom_str(&format!("no recorded response for {}", key))
+            })?;
+
+            if queue.len() > 1 { queue.pop_front().unwrap() } else { queue[0].clone() }
+        };
+
+        Ok(Response { id: req.id().clone(), payload: exchange.into_payload()? })
+    }
+}
+
+impl Service<RequestPacket> for RpcReplay {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: RequestPacket) -> Self::Future {
+        let res = match &req {
+            RequestPacket::Single(r) => self.answer(r).map(ResponsePacket::Single),
+            RequestPacket::Batch(reqs) => reqs.iter()
+                .map(|r| self.answer(r))
+                .collect::<Result<Vec<_>, _>>()
+                .map(ResponsePacket::Batch),
+        };
+
+        Box::pin(async move { res })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::transports::mock::{Asserter, MockTransport};
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path = std::env::temp_dir().join(format!("rpc-fixture-{}.jsonl", uuid::Uuid::new_v4()));
+
+        let asserter = Asserter::new();
+        asserter.push_success(&"0x10");
+        asserter.push_success(&"0x11");
+        asserter.push_success(&"0x1");
+
+        let recorder = RpcRecorder::new(BoxTransport::new(MockTransport::new(asserter)), &path)
+            .unwrap();
+        let provider = ProviderBuilder::new()
+            .connect_client(RpcClient::new(recorder, true))
+            .erased();
+
+        assert_eq!(provider.get_block_number().await.unwrap(), 16);
+        assert_eq!(provider.get_block_number().await.unwrap(), 17);
+        assert_eq!(provider.get_chain_id().await.unwrap(), 1);
+
+        let replay = RpcReplay::load(&path).unwrap().provider();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.get_block_number().await.unwrap(), 16);
+        assert_eq!(replay.get_block_number().await.unwrap(), 17);
+        // the last recorded answer keeps repeating
+        assert_eq!(replay.get_block_number().await.unwrap(), 17);
+        assert_eq!(replay.get_chain_id().await.unwrap(), 1);
+        assert!(replay.get_gas_price().await.is_err());
+    }
+}
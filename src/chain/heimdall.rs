@@ -0,0 +1,79 @@
+use serde_json::Value;
+use std::time::Duration;
+
+use tracing::{instrument, trace};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// REST API of polygon pos' heimdall layer. every checkpoint commits a range of bor blocks to
+// ethereum, a block inside a committed range can't be reorged without breaking the parent chain
+#[derive(Debug, Clone)]
+pub struct Heimdall {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Heimdall {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self { client, url: url.trim_end_matches('/').to_owned() })
+    }
+
+    // last bor block covered by the latest checkpoint
+    #[instrument(skip(self), fields(url = %self.url), err)]
+    pub async fn checkpointed_block(&self) -> anyhow::Result<u64> {
+        let response = self.client.get(format!("{}/checkpoints/latest", self.url)).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("heimdall answered HTTP {}", status);
+        }
+
+        let body: Value = response.json().await?;
+        let block = end_block(&body)
+            .ok_or_else(|| anyhow::anyhow!("no end_block in the latest checkpoint: {}", body))?;
+
+        trace!(block, "Latest checkpoint");
+        Ok(block)
+    }
+}
+
+// v1 nodes wrap the checkpoint in `result` with numbers, v2 in `checkpoint` with strings
+fn end_block(body: &Value) -> Option<u64> {
+    let end_block = body.get("checkpoint").or_else(|| body.get("result"))?.get("end_block")?;
+
+    match end_block {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_checkpointed_block_of_both_api_versions() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/checkpoints/latest"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "checkpoint": {"id": "95123", "start_block": "76110400", "end_block": "76111679"}
+            })))
+            .mount(&server)
+            .await;
+
+        let heimdall = Heimdall::new(&format!("{}/", server.uri())).unwrap();
+        assert_eq!(heimdall.checkpointed_block().await.unwrap(), 76111679);
+
+        let v1 = json!({"height": "2000", "result": {"id": 12, "start_block": 100, "end_block": 355}});
+        assert_eq!(end_block(&v1), Some(355));
+        assert_eq!(end_block(&json!({"result": {}})), None);
+    }
+}
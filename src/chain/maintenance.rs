@@ -0,0 +1,173 @@
+use crate::model::{ChainConfig, MaintenanceWindow};
+use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
+use std::str::FromStr;
+
+// longest window we accept, is_active walks it minute by minute
+const MAX_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+
+// 5-field cron (minute hour day-of-month month day-of-week), always UTC.
+// supports `*`, lists `1,2`, ranges `1-5` and steps `*/15`, `0-30/5`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+            None => (part, None),
+        };
+
+        let (start, end) = match (range, range.split_once('-')) {
+            ("*", _) => (min, max),
+            (_, Some((start, end))) => (start.parse()?, end.parse()?),
+            (single, None) => {
+                let value = single.parse()?;
+                (value, if step.is_some() { max } else { value })
+            }
+        };
+
+        if start < min || end > max || start > end {
+            anyhow::bail!("'{}' is out of range {}-{}", part, min, max);
+        }
+
+        let step = step.unwrap_or(1);
+        if step == 0 {
+            anyhow::bail!("step in '{}' must be greater than 0", part);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            anyhow::bail!("cron expression '{}' must have exactly 5 fields", s);
+        };
+
+        let mut weekday_mask = parse_field(weekdays, 0, 7)?;
+        if weekday_mask & (1 << 7) != 0 {
+            weekday_mask = (weekday_mask & !(1 << 7)) | 1; // 7 is sunday too
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_mask,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl CronSchedule {
+    pub fn matches(&self, t: DateTime<Utc>) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+
+        let day_matches = bit(self.days, t.day());
+        let weekday_matches = bit(self.weekdays, t.weekday().num_days_from_sunday());
+
+        // classic cron: when both day fields are restricted either one may match
+        let day_ok = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day_matches,
+            (true, false) => weekday_matches,
+            (false, false) => day_matches || weekday_matches,
+        };
+
+        bit(self.minutes, t.minute())
+            && bit(self.hours, t.hour())
+            && bit(self.months, t.month())
+            && day_ok
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        CronSchedule::from_str(&self.cron)?;
+
+        if self.duration_secs == 0 || self.duration_secs > MAX_WINDOW_SECS {
+            anyhow::bail!("maintenance window duration must be between 1 and {} seconds",
+                MAX_WINDOW_SECS);
+        }
+
+        Ok(())
+    }
+
+    // active if the schedule fired at some minute within the last duration_secs
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let Ok(schedule) = CronSchedule::from_str(&self.cron) else {
+            return false
+        };
+
+        let duration = Duration::seconds(self.duration_secs.min(MAX_WINDOW_SECS) as i64);
+        let Ok(mut start) = now.duration_trunc(Duration::minutes(1)) else {
+            return false
+        };
+
+        while start + duration > now {
+            if schedule.matches(start) {
+                return true;
+            }
+            start -= Duration::minutes(1);
+        }
+
+        false
+    }
+}
+
+impl ChainConfig {
+    pub fn in_maintenance(&self, now: DateTime<Utc>) -> bool {
+        self.maintenance_windows.iter().any(|w| w.is_active(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_cron_parsing() {
+        assert!(CronSchedule::from_str("*/15 2 * * 0").is_ok());
+        assert!(CronSchedule::from_str("0 0-6/2 1,15 * 1-5").is_ok());
+        assert!(CronSchedule::from_str("0 24 * * *").is_err());
+        assert!(CronSchedule::from_str("0 0 * *").is_err());
+        assert!(CronSchedule::from_str("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_window_activity() {
+        // every sunday 02:00 for an hour, 2026-03-01 is a sunday
+        let window = MaintenanceWindow { cron: "0 2 * * 7".to_owned(), duration_secs: 3600 };
+
+        assert!(!window.is_active(at(2026, 3, 1, 1, 59)));
+        assert!(window.is_active(at(2026, 3, 1, 2, 0)));
+        assert!(window.is_active(at(2026, 3, 1, 2, 59)));
+        assert!(!window.is_active(at(2026, 3, 1, 3, 0)));
+        assert!(!window.is_active(at(2026, 3, 2, 2, 30)));
+    }
+}
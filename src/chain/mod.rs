@@ -1,55 +1,184 @@
+use crate::chain::block_time::BlockTimeEstimator;
 use crate::chain::evm::EvmBlockchain;
-use crate::chain::Blockchain::Evm;
+use crate::chain::simulated::SimulatedBlockchain;
+use crate::chain::utxo::UtxoBlockchain;
+use crate::chain::Blockchain::{Evm, Simulated, Utxo};
 use crate::db::Database;
-use crate::model::{ChainConfig, ChainType, PaymentEvent};
+use crate::model::{ChainCapabilities, ChainConfig, ChainType, PaymentEvent, RpcEndpointHealth, StartFrom, TokenConfig, TokenPreflightReport};
 use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::Sender;
 
+pub mod address;
+pub mod block_time;
+pub mod checkpoint;
 pub mod evm;
+pub mod failover;
+pub mod fixture;
+pub mod heimdall;
+pub mod maintenance;
+pub mod simulated;
+pub mod utxo;
 
 pub trait BlockchainAdapter: Sync + Send {
     fn new(chain_config: ChainConfig) -> anyhow::Result<Self> where Self: Sized;
-    fn derive_address(&self, index: u32) -> impl Future<Output = anyhow::Result<String>> + Send;
+    fn derive_address(&self, account: u32, index: u32)
+        -> impl Future<Output = anyhow::Result<String>> + Send;
+    // canonical form of an address as derive_address and the listener print it
+    fn normalize_address(&self, address: &str) -> Option<String>;
+    // path below the account's xpub that derive_address walks
+    fn derivation_path(&self, index: u32) -> String;
+    fn xpub_fingerprint(&self, account: u32) -> anyhow::Result<String>;
     fn listen(&self, db: Arc<Database>, sender: Sender<PaymentEvent>)
         -> impl Future<Output = anyhow::Result<()>> + Send;
     fn get_tx_block_number(&self, tx_hash: &str)
                            -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+    fn preflight_token(&self, token: &TokenConfig)
+        -> impl Future<Output = anyhow::Result<TokenPreflightReport>> + Send;
+    fn capabilities(&self) -> ChainCapabilities;
     fn config(&self) -> Arc<RwLock<ChainConfig>>;
+    // observed by the listener, empty until it has seen two blocks
+    fn block_time(&self) -> &BlockTimeEstimator;
+    // by priority, empty where requests don't fail over between endpoints
+    fn rpc_health(&self) -> Vec<RpcEndpointHealth>;
+    // last_processed_block that makes the listener begin at `start_from`
+    fn resolve_start_block(&self, start_from: StartFrom)
+        -> impl Future<Output = anyhow::Result<u64>> + Send;
+    // as reported by the RPC, what crate::token_registry is keyed by
+    fn chain_id(&self) -> impl Future<Output = anyhow::Result<u64>> + Send;
+    // highest block the chain's finality_mode counts as final, None on chains going by confirmations
+    fn finalized_block(&self) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
 }
 
 #[derive(Clone)]
 pub enum Blockchain {
     Evm(EvmBlockchain),
+    Simulated(SimulatedBlockchain), // ChainConfig::test_mode
+    Utxo(UtxoBlockchain), // litecoin, dogecoin
 }
 
 impl BlockchainAdapter for Blockchain {
     fn new(chain_config: ChainConfig) -> anyhow::Result<Self> {
         match chain_config.chain_type {
-            ChainType::EVM => Ok(Evm(EvmBlockchain::new(chain_config)?))
+            ChainType::EVM if chain_config.test_mode => {
+                Ok(Simulated(SimulatedBlockchain::new(chain_config)?))
+            }
+            ChainType::EVM => Ok(Evm(EvmBlockchain::new(chain_config)?)),
+            ChainType::LTC | ChainType::DOGE if chain_config.test_mode => {
+                anyhow::bail!("test mode is only available on EVM chains")
+            }
+            ChainType::LTC | ChainType::DOGE => Ok(Utxo(UtxoBlockchain::new(chain_config)?)),
         }
     }
 
-    async fn derive_address(&self, index: u32) -> anyhow::Result<String> {
+    async fn derive_address(&self, account: u32, index: u32) -> anyhow::Result<String> {
         match self {
-            Evm(bc) => bc.derive_address(index).await,
+            Evm(bc) => bc.derive_address(account, index).await,
+            Simulated(bc) => bc.derive_address(account, index).await,
+            Utxo(bc) => bc.derive_address(account, index).await,
+        }
+    }
+
+    fn normalize_address(&self, address: &str) -> Option<String> {
+        match self {
+            Evm(bc) => bc.normalize_address(address),
+            Simulated(bc) => bc.normalize_address(address),
+            Utxo(bc) => bc.normalize_address(address),
+        }
+    }
+
+    fn derivation_path(&self, index: u32) -> String {
+        match self {
+            Evm(bc) => bc.derivation_path(index),
+            Simulated(bc) => bc.derivation_path(index),
+            Utxo(bc) => bc.derivation_path(index),
+        }
+    }
+
+    fn xpub_fingerprint(&self, account: u32) -> anyhow::Result<String> {
+        match self {
+            Evm(bc) => bc.xpub_fingerprint(account),
+            Simulated(bc) => bc.xpub_fingerprint(account),
+            Utxo(bc) => bc.xpub_fingerprint(account),
         }
     }
 
     async fn listen(&self, db: Arc<Database>, sender: Sender<PaymentEvent>) -> anyhow::Result<()> {
         match self {
             Evm(bc) => bc.listen(db, sender).await,
+            Simulated(bc) => bc.listen(db, sender).await,
+            Utxo(bc) => bc.listen(db, sender).await,
         }
     }
 
     async fn get_tx_block_number(&self, tx_hash: &str) -> anyhow::Result<Option<u64>> {
         match self {
             Evm(bc) => bc.get_tx_block_number(tx_hash).await,
+            Simulated(bc) => bc.get_tx_block_number(tx_hash).await,
+            Utxo(bc) => bc.get_tx_block_number(tx_hash).await,
+        }
+    }
+
+    async fn preflight_token(&self, token: &TokenConfig) -> anyhow::Result<TokenPreflightReport> {
+        match self {
+            Evm(bc) => bc.preflight_token(token).await,
+            Simulated(bc) => bc.preflight_token(token).await,
+            Utxo(bc) => bc.preflight_token(token).await,
+        }
+    }
+
+    fn capabilities(&self) -> ChainCapabilities {
+        match self {
+            Evm(bc) => bc.capabilities(),
+            Simulated(bc) => bc.capabilities(),
+            Utxo(bc) => bc.capabilities(),
         }
     }
 
     fn config(&self) -> Arc<RwLock<ChainConfig>> {
         match self {
             Evm(bc) => bc.config(),
+            Simulated(bc) => bc.config(),
+            Utxo(bc) => bc.config(),
+        }
+    }
+
+    fn block_time(&self) -> &BlockTimeEstimator {
+        match self {
+            Evm(bc) => bc.block_time(),
+            Simulated(bc) => bc.block_time(),
+            Utxo(bc) => bc.block_time(),
+        }
+    }
+
+    fn rpc_health(&self) -> Vec<RpcEndpointHealth> {
+        match self {
+            Evm(bc) => bc.rpc_health(),
+            Simulated(bc) => bc.rpc_health(),
+            Utxo(bc) => bc.rpc_health(),
+        }
+    }
+
+    async fn resolve_start_block(&self, start_from: StartFrom) -> anyhow::Result<u64> {
+        match self {
+            Evm(bc) => bc.resolve_start_block(start_from).await,
+            Simulated(bc) => bc.resolve_start_block(start_from).await,
+            Utxo(bc) => bc.resolve_start_block(start_from).await,
+        }
+    }
+
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        match self {
+            Evm(bc) => bc.chain_id().await,
+            Simulated(bc) => bc.chain_id().await,
+            Utxo(bc) => bc.chain_id().await,
+        }
+    }
+
+    async fn finalized_block(&self) -> anyhow::Result<Option<u64>> {
+        match self {
+            Evm(bc) => bc.finalized_block().await,
+            Simulated(bc) => bc.finalized_block().await,
+            Utxo(bc) => bc.finalized_block().await,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,266 @@
+use crate::chain::address::{derive_pubkey, xpub_fingerprint, AddressEncoder, EvmEncoder};
+use crate::chain::block_time::BlockTimeEstimator;
+use crate::chain::checkpoint::CheckpointFlusher;
+use crate::chain::BlockchainAdapter;
+use crate::db::Database;
+use crate::model::{ChainCapabilities, ChainConfig, RpcEndpointHealth, ChainStatsDelta, FinalityMode, PaymentEvent, StartFrom, TokenConfig, TokenPreflightReport, TokenRef, NATIVE_CONTRACT};
+use crate::runtime;
+use alloy::primitives::utils::format_units;
+use alloy::primitives::{keccak256, Address, TxHash, U256};
+use dashmap::DashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+use tracing::{debug, info, instrument, trace};
+
+// a block is "mined" this often and carries every transfer queued since the last one
+const BLOCK_INTERVAL: Duration = Duration::from_secs(2);
+// `from` of every simulated transfer
+pub const SIMULATED_PAYER: &str = "0x000000000000000000000000000000000000dEaD";
+
+#[derive(Debug, Clone)]
+struct QueuedTransfer {
+    tx_hash: TxHash,
+    to: String,
+    token: TokenRef,
+    amount_raw: U256,
+    decimals: u8,
+}
+
+// stands in for an EVM chain with ChainConfig::test_mode set. addresses are derived the same
+// way, but nothing talks to an RPC: payments only happen through simulate_transfer
+#[derive(Clone)]
+pub struct SimulatedBlockchain {
+    chain_name: String,
+    chain_config: Arc<RwLock<ChainConfig>>,
+    block_time: Arc<BlockTimeEstimator>,
+    queued: Arc<Mutex<Vec<QueuedTransfer>>>,
+    mined: Arc<DashMap<String, u64>>, // tx hash -> block number, in memory only
+}
+
+impl SimulatedBlockchain {
+    // lands in the next simulated block, returns the made up tx hash
+    pub fn simulate_transfer(&self, to: &str, token: TokenRef, amount_raw: U256, decimals: u8)
+        -> anyhow::Result<String>
+    {
+        let to = self.normalize_address(to)
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not a valid address", to))?;
+        let tx_hash = keccak256(uuid::Uuid::new_v4().as_bytes());
+
+        debug!(%tx_hash, %to, %token, %amount_raw, "Queued simulated transfer");
+        self.queued.lock().unwrap().push(QueuedTransfer { tx_hash, to, token, amount_raw, decimals });
+
+        Ok(tx_hash.to_string())
+    }
+}
+
+impl BlockchainAdapter for SimulatedBlockchain {
+    fn new(chain_config: ChainConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            chain_name: chain_config.name.clone(),
+            chain_config: Arc::new(RwLock::new(chain_config)),
+            block_time: Arc::new(BlockTimeEstimator::default()),
+            queued: Arc::new(Mutex::new(Vec::new())),
+            mined: Arc::new(DashMap::new()),
+        })
+    }
+
+    async fn derive_address(&self, account: u32, index: u32) -> anyhow::Result<String> {
+        let pubkey = derive_pubkey(self.chain_config.read().unwrap().account_xpub(account)?, index)?;
+
+        Ok(EvmEncoder.encode(&pubkey))
+    }
+
+    fn normalize_address(&self, address: &str) -> Option<String> {
+        Address::from_str(address.trim()).ok().map(|a| a.to_string())
+    }
+
+    fn derivation_path(&self, index: u32) -> String {
+        format!("m/{}", index)
+    }
+
+    fn xpub_fingerprint(&self, account: u32) -> anyhow::Result<String> {
+        xpub_fingerprint(self.chain_config.read().unwrap().account_xpub(account)?)
+    }
+
+    #[instrument(skip(self, db, sender), fields(chain = %self.chain_name, node_type = "Simulated"), err)]
+    async fn listen(&self, db: Arc<Database>, sender: Sender<PaymentEvent>) -> anyhow::Result<()> {
+        info!("Starting simulated chain, no real funds move here");
+
+        let flusher = CheckpointFlusher::spawn(db, &self.chain_name);
+        let mut block_num = self.chain_config.read().unwrap().last_processed_block;
+
+        loop {
+            runtime::sleep(BLOCK_INTERVAL).await;
+
+            if sender.is_closed() {
+                info!("Event channel closed, stopping simulated chain");
+                flusher.close().await;
+                return Ok(());
+            }
+
+            block_num += 1;
+            let now = chrono::Utc::now();
+            self.block_time.observe(block_num, now.timestamp() as u64);
+
+            let transfers = std::mem::take(&mut *self.queued.lock().unwrap());
+            trace!(block_num, count = transfers.len(), "Mined simulated block");
+
+            for (i, transfer) in transfers.iter().enumerate() {
+                let native = transfer.token.contract == NATIVE_CONTRACT;
+                let event = PaymentEvent {
+                    network: self.chain_name.clone(),
+                    tx_hash: transfer.tx_hash,
+                    from: SIMULATED_PAYER.to_owned(),
+                    to: transfer.to.clone(),
+                    payer: None,
+                    token: transfer.token.clone(),
+                    amount: format_units(transfer.amount_raw, transfer.decimals).unwrap_or_default(),
+                    amount_raw: transfer.amount_raw,
+                    decimals: transfer.decimals,
+                    block_number: block_num,
+                    block_timestamp: Some(now),
+                    log_index: (!native).then_some(i as u64),
+                };
+
+                self.mined.insert(transfer.tx_hash.to_string(), block_num);
+
+                if sender.send(event).await.is_err() {
+                    flusher.close().await;
+                    anyhow::bail!("payment event channel is closed");
+                }
+            }
+
+            self.chain_config.write().unwrap().last_processed_block = block_num;
+            flusher.checkpoint(block_num, ChainStatsDelta {
+                blocks: 1,
+                events: transfers.len() as u64,
+                last_event_at: (!transfers.is_empty()).then_some(now),
+                ..Default::default()
+            });
+        }
+    }
+
+    async fn get_tx_block_number(&self, tx_hash: &str) -> anyhow::Result<Option<u64>> {
+        Ok(self.mined.get(tx_hash).map(|block| *block))
+    }
+
+    async fn preflight_token(&self, token: &TokenConfig) -> anyhow::Result<TokenPreflightReport> {
+        // whatever the merchant configured exists here
+        Ok(TokenPreflightReport {
+            contract_exists: true,
+            emits_transfer: true,
+            onchain_decimals: Some(token.decimals),
+            issues: vec![],
+        })
+    }
+
+    fn capabilities(&self) -> ChainCapabilities {
+        ChainCapabilities {
+            supports_tokens: true,
+            supports_memo: false,
+            supports_ws: false,
+            finality_mode: FinalityMode::Confirmations,
+            min_confirmations: self.chain_config.read().unwrap().required_confirmations,
+        }
+    }
+
+    fn config(&self) -> Arc<RwLock<ChainConfig>> {
+        self.chain_config.clone()
+    }
+
+    fn block_time(&self) -> &BlockTimeEstimator {
+        &self.block_time
+    }
+
+    fn rpc_health(&self) -> Vec<RpcEndpointHealth> {
+        vec![]
+    }
+
+    // there's no history to rescan, every start point is the current block
+    async fn resolve_start_block(&self, _start_from: StartFrom) -> anyhow::Result<u64> {
+        Ok(self.chain_config.read().unwrap().last_processed_block)
+    }
+
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        anyhow::bail!("chain '{}' is simulated and has no chain id", self.chain_name)
+    }
+
+    async fn finalized_block(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::DEFAULT_ACCOUNT;
+    use crate::db::mock::MockDatabase;
+    use crate::db::DatabaseAdapter;
+    use tokio::sync::mpsc;
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    #[tokio::test(start_paused = true)]
+    async fn test_simulated_transfer_is_mined() {
+        let config = ChainConfig::builder()
+            .name("sandbox")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .last_processed_block(7)
+            .test_mode(true)
+            .build()
+            .unwrap();
+        let chain = SimulatedBlockchain::new(config).unwrap();
+
+        let address = chain.derive_address(DEFAULT_ACCOUNT, 0).await.unwrap();
+        let tx_hash = chain.simulate_transfer(&address, TokenRef::native("sandbox", "ETH"),
+                                              U256::from(10).pow(U256::from(18)), 18).unwrap();
+        assert_eq!(chain.get_tx_block_number(&tx_hash).await.unwrap(), None);
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let listener = chain.clone();
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let handle = tokio::spawn(async move { listener.listen(db, tx).await });
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.to, address);
+        assert_eq!(event.amount, "1.000000000000000000");
+        assert_eq!(event.block_number, 8);
+        assert_eq!(event.log_index, None);
+        assert_eq!(chain.get_tx_block_number(&tx_hash).await.unwrap(), Some(8));
+
+        handle.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_listener_stops_on_closed_channel() {
+        let config = ChainConfig::builder()
+            .name("sandbox")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .test_mode(true)
+            .build()
+            .unwrap();
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        db.add_chain(&config).await.unwrap();
+        let chain = SimulatedBlockchain::new(config).unwrap();
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let listener = chain.clone();
+        let listener_db = db.clone();
+        let handle = tokio::spawn(async move { listener.listen(listener_db, tx).await });
+
+        tokio::time::sleep(BLOCK_INTERVAL * 3 + BLOCK_INTERVAL / 2).await;
+        rx.close();
+
+        // leaves cleanly and with its checkpoint written, not when it's aborted
+        handle.await.unwrap().unwrap();
+        assert_eq!(db.get_latest_block("sandbox").await.unwrap(), Some(3));
+    }
+}
@@ -0,0 +1,707 @@
+use crate::chain::address::{derive_pubkey, xpub_fingerprint, AddressEncoder, P2pkhEncoder, P2wpkhEncoder};
+use crate::chain::block_time::BlockTimeEstimator;
+use crate::chain::checkpoint::CheckpointFlusher;
+use crate::chain::evm::{first_block_at, rpc_auth_headers};
+use crate::chain::BlockchainAdapter;
+use crate::db::{Database, DatabaseAdapter};
+use crate::model::{ChainCapabilities, ChainConfig, RpcEndpointHealth, ChainError, ChainErrorKind, ChainStatsDelta, ChainType, FinalityMode, PaymentEvent, StartFrom, TokenConfig, TokenPreflightReport, TokenRef};
+use crate::runtime;
+use alloy::primitives::utils::format_units;
+use alloy::primitives::{TxHash, U256};
+use bech32::{FromBase32, Variant};
+use chrono::{DateTime, Utc};
+use coins_bip32::ecdsa::VerifyingKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use url::Url;
+
+use tracing::{debug, error, info, instrument, trace, warn};
+
+// blocks are minutes apart, polling the tip more often only burns RPC quota
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const RPC_RETRY: Duration = Duration::from_secs(2);
+// RPC_INVALID_ADDRESS_OR_KEY, what the nodes answer for an unknown block or transaction
+const RPC_NOT_FOUND: i64 = -5;
+
+// how receive addresses of the chain are written
+#[derive(Debug, Clone, Copy)]
+pub enum UtxoAddress {
+    P2wpkh(P2wpkhEncoder),
+    P2pkh(P2pkhEncoder),
+}
+
+impl UtxoAddress {
+    pub(crate) fn encode(&self, pubkey: &VerifyingKey) -> String {
+        match self {
+            UtxoAddress::P2wpkh(encoder) => encoder.encode(pubkey),
+            UtxoAddress::P2pkh(encoder) => encoder.encode(pubkey),
+        }
+    }
+
+    // only the kind of address this chain derives, everything else can't be an invoice's
+    pub(crate) fn normalize(&self, address: &str) -> Option<String> {
+        let address = address.trim();
+
+        match self {
+            UtxoAddress::P2wpkh(encoder) => {
+                let (hrp, data, variant) = bech32::decode(address).ok()?;
+                let (version, program) = data.split_first()?;
+                let program = Vec::<u8>::from_base32(program).ok()?;
+
+                (hrp == encoder.hrp() && variant == Variant::Bech32 && version.to_u8() == 0
+                    && program.len() == 20).then(|| address.to_lowercase())
+            }
+            UtxoAddress::P2pkh(encoder) => {
+                let payload = bs58::decode(address).with_check(None).into_vec().ok()?;
+
+                (payload.len() == 21 && payload[0] == encoder.version()).then(|| address.to_owned())
+            }
+        }
+    }
+}
+
+// what tells the bitcoin-derived chains apart, scanning and derivation are shared by UtxoBlockchain.
+// a new coin is one more of these and a ChainType mapped to it in UtxoParams::of
+#[derive(Debug, Clone, Copy)]
+pub struct UtxoParams {
+    pub address: UtxoAddress,
+    pub decimals: u8,
+    // getblock with verbosity 2 has the transactions inline. nodes without it (dogecoind) only
+    // list txids, those are fetched one by one with getrawtransaction and need -txindex
+    pub verbose_blocks: bool,
+}
+
+impl UtxoParams {
+    pub const LITECOIN: Self = Self {
+        address: UtxoAddress::P2wpkh(P2wpkhEncoder::LITECOIN),
+        decimals: 8,
+        verbose_blocks: true,
+    };
+
+    pub const DOGECOIN: Self = Self {
+        address: UtxoAddress::P2pkh(P2pkhEncoder::DOGECOIN),
+        decimals: 8,
+        verbose_blocks: false,
+    };
+
+    pub fn of(chain_type: ChainType) -> Option<Self> {
+        match chain_type {
+            ChainType::LTC => Some(Self::LITECOIN),
+            ChainType::DOGE => Some(Self::DOGECOIN),
+            ChainType::EVM => None,
+        }
+    }
+}
+
+// bitcoind-style JSON-RPC. the nodes answer in the 1.0 shape with `result` next to a null
+// `error`, which alloy's client refuses, so this is a plain POST
+#[derive(Clone)]
+struct UtxoRpc {
+    client: reqwest::Client,
+    url: Url,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<'a> {
+    #[serde(borrow)]
+    result: Option<&'a RawValue>,
+    #[serde(default)]
+    error: Value,
+}
+
+impl UtxoRpc {
+    async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        self.call_as(method, params).await
+    }
+
+    // not found answers come back as null. the result is decoded straight from the body so
+    // typed callers see the numbers as the node printed them
+    async fn call_as<T: DeserializeOwned>(&self, method: &str, params: Value) -> anyhow::Result<T> {
+        let request = json!({"jsonrpc": "1.0", "id": method, "method": method, "params": params});
+
+        // errors come with a 500 and the reason in the body
+        let response = self.client.post(self.url.clone()).json(&request).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        let body: RpcResponse = serde_json::from_str(&body)
+            .map_err(|e| anyhow::anyhow!("{}: HTTP {}: {}", method, status, e))?;
+
+        let error = &body.error;
+        if !error.is_null() {
+            if error["code"].as_i64() == Some(RPC_NOT_FOUND) {
+                return Ok(serde_json::from_str("null")?);
+            }
+            anyhow::bail!("{}: {}", method, error["message"].as_str().unwrap_or("unknown error"));
+        }
+
+        serde_json::from_str(body.result.map_or("null", RawValue::get))
+            .map_err(|e| anyhow::anyhow!("{}: {}", method, e))
+    }
+}
+
+// the parts of a getblock / getrawtransaction answer the listener reads
+#[derive(Deserialize)]
+struct UtxoBlock<T> {
+    time: Option<u64>,
+    tx: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct UtxoTransaction {
+    txid: String,
+    #[serde(default)]
+    vout: Vec<UtxoOutput>,
+}
+
+#[derive(Deserialize)]
+struct UtxoOutput {
+    n: Option<u64>,
+    // kept as printed, an f64 drops satoshis of large outputs
+    value: Option<Box<RawValue>>,
+    #[serde(rename = "scriptPubKey", default)]
+    script: Value,
+}
+
+#[derive(Clone)]
+pub struct UtxoBlockchain {
+    chain_name: String,
+    chain_config: Arc<RwLock<ChainConfig>>,
+    params: UtxoParams,
+    rpc: UtxoRpc,
+    block_time: Arc<BlockTimeEstimator>,
+}
+
+impl std::fmt::Debug for UtxoBlockchain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UtxoBlockchain")
+            .field("name", &self.chain_name)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+impl BlockchainAdapter for UtxoBlockchain {
+    #[instrument(skip(chain_config), fields(chain = %chain_config.name))]
+    fn new(chain_config: ChainConfig) -> anyhow::Result<Self> {
+        debug!("Initializing UTXO Blockchain adapter");
+
+        let Some(params) = UtxoParams::of(chain_config.chain_type) else {
+            anyhow::bail!("{} is not a UTXO chain type", chain_config.chain_type)
+        };
+        if chain_config.decimals != params.decimals {
+            anyhow::bail!("{} amounts have {} decimals, got {}",
+                chain_config.chain_type, params.decimals, chain_config.decimals);
+        }
+
+        // validate_rpc_urls keeps UTXO chains at a single url
+        let url = chain_config.rpc_urls.first()
+            .ok_or_else(|| anyhow::anyhow!("chain has no rpc url"))?;
+        let url = Url::parse(url)?;
+        let client = reqwest::Client::builder()
+            .default_headers(rpc_auth_headers(&chain_config.rpc_auth)?)
+            .build()?;
+
+        Ok(Self {
+            chain_name: chain_config.name.clone(),
+            chain_config: Arc::new(RwLock::new(chain_config)),
+            params,
+            rpc: UtxoRpc { client, url },
+            block_time: Arc::new(BlockTimeEstimator::default()),
+        })
+    }
+
+    async fn derive_address(&self, account: u32, index: u32) -> anyhow::Result<String> {
+        let pubkey = derive_pubkey(self.chain_config.read().unwrap().account_xpub(account)?, index)?;
+
+        Ok(self.params.address.encode(&pubkey))
+    }
+
+    fn normalize_address(&self, address: &str) -> Option<String> {
+        self.params.address.normalize(address)
+    }
+
+    fn derivation_path(&self, index: u32) -> String {
+        format!("m/{}", index)
+    }
+
+    fn xpub_fingerprint(&self, account: u32) -> anyhow::Result<String> {
+        xpub_fingerprint(self.chain_config.read().unwrap().account_xpub(account)?)
+    }
+
+    #[instrument(skip(self, db, sender), fields(chain = %self.chain_name, node_type = "UTXO"), err)]
+    async fn listen(&self, db: Arc<Database>, sender: Sender<PaymentEvent>) -> anyhow::Result<()> {
+        info!("Starting UTXO listener loop");
+
+        let mut last_block_num = self.chain_config.read().unwrap().last_processed_block;
+        if last_block_num == 0 {
+            debug!("No last processed block found, fetching latest from RPC");
+            last_block_num = self.block_count().await?;
+        }
+
+        let (block_lag, decimals, native_symbol) = {
+            let guard = self.chain_config.read().unwrap();
+            (guard.block_lag, guard.decimals, guard.native_symbol.clone())
+        };
+        let mut paused = false;
+        let mut stats = ChainStatsDelta::default();
+        let flusher = CheckpointFlusher::spawn(db.clone(), &self.chain_name);
+
+        loop {
+            // AppState::stop_listening closes the channel, leave with the checkpoint written
+            if sender.is_closed() {
+                info!("Event channel closed, stopping listener");
+                flusher.close().await;
+                return Ok(());
+            }
+
+            if self.chain_config.read().unwrap().in_maintenance(Utc::now()) {
+                if !paused {
+                    info!("Maintenance window started, pausing listener");
+                    paused = true;
+                }
+                runtime::sleep(Duration::from_secs(5)).await;
+                continue;
+            } else if paused {
+                info!("Maintenance window is over, resuming listener");
+                paused = false;
+            }
+
+            let current_block_num = match self.block_count().await {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!(error = %e, "Failed to get block count from RPC. Sleep 2s...");
+                    self.record_error(&db, ChainErrorKind::Rpc, format!("getblockcount: {}", e)).await;
+                    runtime::sleep(RPC_RETRY).await;
+                    continue
+                }
+            }.saturating_sub(block_lag as u64);
+
+            if current_block_num <= last_block_num {
+                trace!(current = current_block_num, last = last_block_num, "No new blocks");
+                runtime::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+
+            for block_num in (last_block_num + 1)..=current_block_num {
+                if sender.is_closed() {
+                    break;
+                }
+
+                let started = Instant::now();
+
+                // retried from the block count check
+                let (transactions, block_timestamp) = match self.fetch_block(block_num).await {
+                    Ok(block) => block,
+                    Err(e) => {
+                        warn!(block_num, error = %e, "Failed to fetch block. Retrying in 2s...");
+                        self.record_error(&db, ChainErrorKind::Rpc,
+                                          format!("block {}: {}", block_num, e)).await;
+                        runtime::sleep(RPC_RETRY).await;
+                        break;
+                    }
+                };
+
+                let addresses = self.chain_config.read().unwrap().watch_addresses.read().unwrap().clone();
+                let events = self.payment_events(&transactions, &addresses, block_num, block_timestamp,
+                                                 decimals, &native_symbol);
+                let event_count = events.len() as u64;
+
+                for event in events {
+                    // the block is picked up again after the listener restarts
+                    if sender.send(event).await.is_err() {
+                        flusher.checkpoint(last_block_num, std::mem::take(&mut stats));
+                        flusher.close().await;
+                        anyhow::bail!("events of block {} were not accepted, stopping at {}",
+                            block_num, last_block_num)
+                    }
+                }
+
+                last_block_num = block_num;
+                self.chain_config.write().unwrap().last_processed_block = last_block_num;
+
+                stats.blocks += 1;
+                stats.events += event_count;
+                stats.processing_ms += started.elapsed().as_millis() as u64;
+                if event_count > 0 {
+                    stats.last_event_at = Some(Utc::now());
+                }
+
+                if last_block_num.is_multiple_of(10) || last_block_num == current_block_num {
+                    flusher.checkpoint(last_block_num, std::mem::take(&mut stats));
+                }
+            }
+        }
+    }
+
+    // needs -txindex on the node for transactions that aren't in the mempool
+    #[instrument(skip(self), err)]
+    async fn get_tx_block_number(&self, tx_hash: &str) -> anyhow::Result<Option<u64>> {
+        let tx = self.rpc.call("getrawtransaction", json!([txid(tx_hash), true])).await?;
+
+        let Some(block_hash) = tx["blockhash"].as_str() else {
+            debug!("Transaction is unknown or still in the mempool");
+            return Ok(None);
+        };
+
+        let header = self.rpc.call("getblockheader", json!([block_hash])).await?;
+        // -1 confirmations = the block was reorged out
+        if header["confirmations"].as_i64().is_none_or(|c| c < 0) {
+            debug!(block_hash, "Transaction's block is not on the main chain");
+            return Ok(None);
+        }
+
+        Ok(header["height"].as_u64())
+    }
+
+    async fn preflight_token(&self, _token: &TokenConfig) -> anyhow::Result<TokenPreflightReport> {
+        Ok(TokenPreflightReport {
+            issues: vec![format!("{} has no tokens, only {}",
+                                 self.chain_name, self.chain_config.read().unwrap().native_symbol)],
+            ..TokenPreflightReport::default()
+        })
+    }
+
+    fn capabilities(&self) -> ChainCapabilities {
+        ChainCapabilities {
+            supports_tokens: false,
+            supports_memo: false,
+            supports_ws: false,
+            finality_mode: FinalityMode::Confirmations,
+            min_confirmations: self.chain_config.read().unwrap().required_confirmations,
+        }
+    }
+
+    fn config(&self) -> Arc<RwLock<ChainConfig>> {
+        self.chain_config.clone()
+    }
+
+    fn block_time(&self) -> &BlockTimeEstimator {
+        &self.block_time
+    }
+
+    fn rpc_health(&self) -> Vec<RpcEndpointHealth> {
+        vec![]
+    }
+
+    async fn resolve_start_block(&self, start_from: StartFrom) -> anyhow::Result<u64> {
+        let head = self.block_count().await?;
+
+        let first = match start_from {
+            StartFrom::Latest => return Ok(head),
+            StartFrom::Block(n) if n > head => {
+                anyhow::bail!("block {} is ahead of the chain head {}", n, head)
+            }
+            StartFrom::Block(n) => n,
+            // block times only roughly increase here (median time past), close enough for a start
+            StartFrom::TimeAgo(ago) => {
+                let target = (Utc::now().timestamp() as u64).saturating_sub(ago.as_secs());
+                first_block_at(head, target, |n| self.block_timestamp(n)).await?
+            }
+        };
+
+        debug!(?start_from, first, head, "Resolved start block");
+        // 0 would make the listener start at the head instead
+        Ok(first.saturating_sub(1).max(1))
+    }
+
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        anyhow::bail!("chain '{}' is a UTXO chain and has no chain id", self.chain_name)
+    }
+
+    async fn finalized_block(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+// txids go around as TxHash and pick up its 0x on the way, the nodes don't take it
+fn txid(tx_hash: &str) -> &str {
+    tx_hash.trim_start_matches("0x")
+}
+
+// nodes print amounts as coins with a fixed number of decimals, read digit by digit so
+// outputs past 2^53 base units stay exact
+fn to_base_units(value: &str, decimals: u8) -> Option<U256> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > decimals as usize {
+        return None;
+    }
+
+    let padded = format!("{}{}{}", whole, fraction, "0".repeat(decimals as usize - fraction.len()));
+    U256::from_str_radix(&padded, 10).ok()
+}
+
+impl UtxoBlockchain {
+    async fn block_count(&self) -> anyhow::Result<u64> {
+        self.rpc.call("getblockcount", json!([])).await?
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("getblockcount returned no number"))
+    }
+
+    async fn block_hash(&self, block_num: u64) -> anyhow::Result<String> {
+        self.rpc.call("getblockhash", json!([block_num])).await?
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow::anyhow!("block {} not found", block_num))
+    }
+
+    async fn block_timestamp(&self, block_num: u64) -> anyhow::Result<u64> {
+        let header = self.rpc.call("getblockheader", json!([self.block_hash(block_num).await?])).await?;
+
+        header["time"].as_u64()
+            .ok_or_else(|| anyhow::anyhow!("block {} has no time", block_num))
+    }
+
+    async fn fetch_block(&self, block_num: u64) -> anyhow::Result<(Vec<UtxoTransaction>, Option<DateTime<Utc>>)> {
+        let hash = self.block_hash(block_num).await?;
+        let missing = || anyhow::anyhow!("block {} not found", block_num);
+
+        let (transactions, timestamp) = match self.params.verbose_blocks {
+            true => {
+                let block: Option<UtxoBlock<UtxoTransaction>> = self.rpc.call_as("getblock", json!([hash, 2])).await?;
+                let block = block.ok_or_else(missing)?;
+                (block.tx, block.time)
+            }
+            false => {
+                let block: Option<UtxoBlock<String>> = self.rpc.call_as("getblock", json!([hash, true])).await?;
+                let block = block.ok_or_else(missing)?;
+
+                let mut transactions = Vec::with_capacity(block.tx.len());
+                for txid in block.tx {
+                    let tx: Option<UtxoTransaction> = self.rpc.call_as("getrawtransaction", json!([txid, true])).await?;
+                    transactions.extend(tx);
+                }
+                (transactions, block.time)
+            }
+        };
+
+        if let Some(timestamp) = timestamp {
+            self.block_time.observe(block_num, timestamp);
+        }
+
+        Ok((transactions, timestamp.and_then(|ts| DateTime::from_timestamp(ts as i64, 0))))
+    }
+
+    // one event per output paying a watched address, the output index stands in for the log
+    // index. inputs only reference earlier outputs, so there's no `from` without more lookups
+    fn payment_events(&self, transactions: &[UtxoTransaction], addresses: &HashSet<String>, block_num: u64,
+                      block_timestamp: Option<DateTime<Utc>>, decimals: u8, native_symbol: &str)
+        -> Vec<PaymentEvent>
+    {
+        let mut events = vec![];
+
+        for tx in transactions {
+            for (position, output) in tx.vout.iter().enumerate() {
+                let script = &output.script;
+                // "address" since bitcoind 22, a one element "addresses" before
+                let Some(to) = script["address"].as_str()
+                    .or_else(|| script["addresses"][0].as_str())
+                    .and_then(|a| self.normalize_address(a))
+                else {
+                    continue
+                };
+
+                if !addresses.contains(&to) {
+                    continue;
+                }
+
+                let (Ok(tx_hash), Some(amount_raw)) = (
+                    TxHash::from_str(&tx.txid),
+                    output.value.as_ref().and_then(|v| to_base_units(v.get(), decimals)),
+                ) else {
+                    error!(tx = %tx.txid, "Malformed output to a watched address");
+                    continue
+                };
+
+                trace!(%tx_hash, %to, %amount_raw, "Output to a watched address");
+
+                events.push(PaymentEvent {
+                    network: self.chain_name.clone(),
+                    tx_hash,
+                    from: String::new(),
+                    to,
+                    payer: None,
+                    token: TokenRef::native(&self.chain_name, native_symbol),
+                    amount: format_units(amount_raw, decimals).unwrap_or_default(),
+                    amount_raw,
+                    decimals,
+                    block_number: block_num,
+                    block_timestamp,
+                    log_index: Some(output.n.unwrap_or(position as u64)),
+                });
+            }
+        }
+
+        events
+    }
+
+    async fn record_error(&self, db: &Database, kind: ChainErrorKind, message: String) {
+        let chain_error = ChainError {
+            network: self.chain_name.clone(),
+            kind,
+            message,
+            created_at: Utc::now(),
+        };
+
+        if let Err(e) = db.add_chain_error(&chain_error).await {
+            error!(error = %e, "Failed to record chain error");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::DEFAULT_ACCOUNT;
+    use crate::db::mock::MockDatabase;
+    use tokio::sync::mpsc;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const TXID: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    fn chain(chain_type: ChainType, rpc_url: &str) -> UtxoBlockchain {
+        let config = ChainConfig::builder()
+            .name("litecoin")
+            .rpc_url(rpc_url)
+            .chain_type(chain_type)
+            .xpub(XPUB)
+            .native_symbol("LTC")
+            .decimals(8)
+            .last_processed_block(100)
+            .build()
+            .unwrap();
+
+        UtxoBlockchain::new(config).unwrap()
+    }
+
+    async fn respond(server: &MockServer, call: Value, result: Value) {
+        Mock::given(method("POST"))
+            .and(body_partial_json(call))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({"result": result, "error": null, "id": "necko3"})))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_addresses_follow_the_chain_params() {
+        let ltc = chain(ChainType::LTC, "http://localhost:9332");
+        let doge = chain(ChainType::DOGE, "http://localhost:22555");
+
+        let ltc_address = ltc.derive_address(DEFAULT_ACCOUNT, 0).await.unwrap();
+        let doge_address = doge.derive_address(DEFAULT_ACCOUNT, 0).await.unwrap();
+        assert!(ltc_address.starts_with("ltc1q"));
+        assert!(doge_address.starts_with('D'));
+
+        assert_eq!(ltc.normalize_address(&ltc_address.to_uppercase()), Some(ltc_address.clone()));
+        assert_eq!(doge.normalize_address(&doge_address), Some(doge_address.clone()));
+        assert_eq!(ltc.normalize_address(&doge_address), None);
+        assert_eq!(doge.normalize_address(&ltc_address), None);
+        // bitcoin's P2PKH version
+        assert_eq!(doge.normalize_address("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"), None);
+
+        let config = ChainConfig::builder()
+            .name("litecoin")
+            .rpc_url("http://localhost:9332")
+            .chain_type(ChainType::LTC)
+            .xpub(XPUB)
+            .native_symbol("LTC")
+            .decimals(18)
+            .build()
+            .unwrap();
+        assert!(UtxoBlockchain::new(config).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_outputs_to_watched_addresses_are_reported() {
+        let server = MockServer::start().await;
+        let chain = chain(ChainType::LTC, &server.uri());
+        let watched = chain.derive_address(DEFAULT_ACCOUNT, 0).await.unwrap();
+        let other = chain.derive_address(DEFAULT_ACCOUNT, 1).await.unwrap();
+        chain.config().read().unwrap().update_watch_addresses(|a| a.insert(watched.clone()));
+
+        respond(&server, json!({"method": "getblockcount"}), json!(101)).await;
+        respond(&server, json!({"method": "getblockhash", "params": [101]}), json!("hash101")).await;
+        respond(&server, json!({"method": "getblock", "params": ["hash101", 2]}), json!({
+            "time": 1_700_000_000,
+            "tx": [{
+                "txid": TXID,
+                "vout": [
+                    {"n": 0, "value": 3.0, "scriptPubKey": {"address": other}},
+                    {"n": 1, "value": 0.5, "scriptPubKey": {"address": watched}},
+                ],
+            }],
+        })).await;
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let listener = chain.clone();
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let handle = tokio::spawn(async move { listener.listen(db, tx).await });
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.to, watched);
+        assert_eq!(event.tx_hash, TxHash::from_str(TXID).unwrap());
+        assert_eq!(event.amount, "0.50000000");
+        assert_eq!(event.amount_raw, U256::from(50_000_000));
+        assert_eq!(event.block_number, 101);
+        assert_eq!(event.log_index, Some(1));
+
+        rx.close();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_amounts_are_parsed_exactly() {
+        assert_eq!(to_base_units("0.50000000", 8), Some(U256::from(50_000_000)));
+        assert_eq!(to_base_units("3.0", 8), Some(U256::from(300_000_000u64)));
+        assert_eq!(to_base_units("1.123456789", 8), None);
+        assert_eq!(to_base_units("-1.0", 8), None);
+        assert_eq!(to_base_units("1e-8", 8), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_large_doge_outputs_keep_every_unit() {
+        let server = MockServer::start().await;
+        let chain = chain(ChainType::DOGE, &server.uri());
+        let watched = chain.derive_address(DEFAULT_ACCOUNT, 0).await.unwrap();
+        chain.config().read().unwrap().update_watch_addresses(|a| a.insert(watched.clone()));
+
+        respond(&server, json!({"method": "getblockcount"}), json!(101)).await;
+        respond(&server, json!({"method": "getblockhash", "params": [101]}), json!("hash101")).await;
+        respond(&server, json!({"method": "getblock", "params": ["hash101", true]}), json!({
+            "time": 1_700_000_000,
+            "tx": [TXID],
+        })).await;
+        // more base units than an f64 holds exactly, written out the way the node prints it
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getrawtransaction", "params": [TXID, true]})))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"{{"result": {{"txid": "{}", "vout": [{{"n": 0, "value": 123456789.12345679,
+                "scriptPubKey": {{"address": "{}"}}}}]}}, "error": null, "id": "necko3"}}"#,
+                TXID, watched)))
+            .mount(&server)
+            .await;
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let listener = chain.clone();
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let handle = tokio::spawn(async move { listener.listen(db, tx).await });
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.amount_raw, U256::from(12_345_678_912_345_679u64));
+        assert_eq!(event.amount, "123456789.12345679");
+
+        rx.close();
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use crate::model::{CheckoutPayload, DeepLink, DeepLinkTemplate, Invoice};
+use url::form_urlencoded;
+
+// seeded into the template table, operators can add Trust/Rabby/TronLink/... variants there.
+// placeholders: {eip681} {eip681_path} {eip681_encoded} {address} {contract}
+//               {amount} {amount_raw} {token} {network}
+pub const DEFAULT_DEEP_LINK_TEMPLATES: &[(&str, &str)] = &[
+    ("eip681", "{eip681}"),
+    ("metamask", "https://metamask.app.link/send/{eip681_path}"),
+];
+
+// native coin when contract is None, ERC-20 transfer otherwise. without a chain id wallets
+// assume whatever network they are on
+pub fn eip681_uri(address: &str, contract: Option<&str>, chain_id: Option<u64>, amount_raw: &str) -> String {
+    let chain = chain_id.map(|id| format!("@{}", id)).unwrap_or_default();
+
+    match contract {
+        Some(contract) => format!("ethereum:{}{}/transfer?address={}&uint256={}",
+                                  contract, chain, address, amount_raw),
+        None => format!("ethereum:{}{}?value={}", address, chain, amount_raw),
+    }
+}
+
+pub fn render_template(template: &str, invoice: &Invoice, contract: Option<&str>, chain_id: Option<u64>,
+                       amount: &str, amount_raw: &str) -> String {
+    let eip681 = eip681_uri(&invoice.address, contract, chain_id, amount_raw);
+    let eip681_path = eip681.trim_start_matches("ethereum:");
+    let eip681_encoded: String = form_urlencoded::byte_serialize(eip681.as_bytes()).collect();
+
+    template
+        .replace("{eip681_encoded}", &eip681_encoded)
+        .replace("{eip681_path}", eip681_path)
+        .replace("{eip681}", &eip681)
+        .replace("{address}", &invoice.address)
+        .replace("{contract}", contract.unwrap_or_default())
+        .replace("{amount_raw}", amount_raw)
+        .replace("{amount}", amount)
+        .replace("{token}", &invoice.token)
+        .replace("{network}", &invoice.network)
+}
+
+// links always ask for what is still due, not the original amount
+pub fn build_payload(invoice: &Invoice, contract: Option<&str>, chain_id: Option<u64>,
+                     templates: &[DeepLinkTemplate]) -> anyhow::Result<CheckoutPayload> {
+    let remaining_raw = invoice.amount_raw.saturating_sub(invoice.paid_raw);
+    let remaining = alloy::primitives::utils::format_units(remaining_raw, invoice.decimals)?;
+    let remaining_raw = remaining_raw.to_string();
+
+    let deep_links = templates.iter()
+        .map(|t| DeepLink {
+            wallet: t.wallet.clone(),
+            url: render_template(&t.template, invoice, contract, chain_id, &remaining, &remaining_raw),
+        })
+        .collect();
+
+    Ok(CheckoutPayload {
+        invoice_id: invoice.id.clone(),
+        address: invoice.address.clone(),
+        network: invoice.network.clone(),
+        token: invoice.token.clone(),
+        contract: contract.map(str::to_owned),
+        amount_due: remaining,
+        amount_due_raw: remaining_raw,
+        expires_at: invoice.expires_at,
+        locale: invoice.locale.clone(),
+        display_currency: invoice.display_currency.clone(),
+        deep_links,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+    const CONTRACT: &str = "0xdAC17F958D2ee523a2206206994597C13D831ec7";
+
+    #[test]
+    fn test_eip681_uri_shapes() {
+        assert_eq!(eip681_uri(ADDRESS, None, Some(137), "1000"),
+                   format!("ethereum:{}@137?value=1000", ADDRESS));
+        assert_eq!(eip681_uri(ADDRESS, Some(CONTRACT), Some(1), "2500000"),
+                   format!("ethereum:{}@1/transfer?address={}&uint256=2500000", CONTRACT, ADDRESS));
+        assert_eq!(eip681_uri(ADDRESS, None, None, "1000"),
+                   format!("ethereum:{}?value=1000", ADDRESS));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::model::{ChainConfig, CheckoutPayload, FinalityMode, Invoice, SplitShare, TokenConfig};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use url::Url;
+use utoipa::ToSchema;
+
+// request/response bodies share the utoipa schemas with the server, so a field added to the
+// model shows up here without touching this module
+
+// path templates of the REST API relative to its base url, in the syntax of axum and
+// utoipa::path so the server registers and documents the routes the client calls
+pub mod routes {
+    pub const INVOICES: &str = "/invoices";
+    pub const INVOICE: &str = "/invoices/{id}";
+    pub const INVOICE_SETTLE: &str = "/invoices/{id}/settle";
+    pub const INVOICE_CHECKOUT: &str = "/invoices/{id}/checkout";
+    pub const CHAINS: &str = "/chains";
+    pub const CHAIN: &str = "/chains/{name}";
+    pub const CHAIN_TOKENS: &str = "/chains/{name}/tokens";
+
+    pub const ALL: [&str; 7] = [INVOICES, INVOICE, INVOICE_SETTLE, INVOICE_CHECKOUT, CHAINS, CHAIN, CHAIN_TOKENS];
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct CreateInvoiceRequest {
+    pub network: String,
+    pub token: String,
+    pub amount: String, // human readable, e.g. "10.5"
+    pub expires_in_secs: Option<u64>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_events: Option<Vec<String>>,
+    pub expiry_warning_secs: Option<u64>,
+    pub split_schedule: Option<Vec<SplitShare>>,
+    pub locale: Option<String>,
+    pub display_currency: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub finality_mode: FinalityMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct SettleInvoiceRequest {
+    pub accept_shortfall: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ApiError {
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NeckoClient {
+    client: Client,
+    base_url: Url,
+    api_key: String,
+}
+
+impl NeckoClient {
+    pub fn new(base_url: &str, api_key: &str) -> anyhow::Result<Self> {
+        let base_url = Url::parse(base_url)?;
+
+        Ok(Self {
+            client: Client::new(),
+            base_url,
+            api_key: api_key.to_owned(),
+        })
+    }
+
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub async fn create_invoice(&self, request: &CreateInvoiceRequest) -> anyhow::Result<Invoice> {
+        self.send(self.request(Method::POST, routes::INVOICES, &[])?.json(request)).await
+    }
+
+    pub async fn get_invoice(&self, id: &str) -> anyhow::Result<Invoice> {
+        self.send(self.request(Method::GET, routes::INVOICE, &[id])?).await
+    }
+
+    pub async fn get_invoices_by_tag(&self, tag: &str) -> anyhow::Result<Vec<Invoice>> {
+        let mut url = self.url(routes::INVOICES, &[])?;
+        url.query_pairs_mut().append_pair("tag", tag);
+
+        self.send(self.request_url(Method::GET, url)).await
+    }
+
+    pub async fn settle_invoice(&self, id: &str, accept_shortfall: bool) -> anyhow::Result<()> {
+        let builder = self.request(Method::POST, routes::INVOICE_SETTLE, &[id])?
+            .json(&SettleInvoiceRequest { accept_shortfall });
+        self.send_empty(builder).await
+    }
+
+    pub async fn checkout(&self, id: &str) -> anyhow::Result<CheckoutPayload> {
+        self.send(self.request(Method::GET, routes::INVOICE_CHECKOUT, &[id])?).await
+    }
+
+    pub async fn get_chains(&self) -> anyhow::Result<Vec<ChainConfig>> {
+        self.send(self.request(Method::GET, routes::CHAINS, &[])?).await
+    }
+
+    pub async fn get_chain(&self, name: &str) -> anyhow::Result<ChainConfig> {
+        self.send(self.request(Method::GET, routes::CHAIN, &[name])?).await
+    }
+
+    pub async fn get_tokens(&self, chain: &str) -> anyhow::Result<Vec<TokenConfig>> {
+        self.send(self.request(Method::GET, routes::CHAIN_TOKENS, &[chain])?).await
+    }
+
+    // fills the {params} of a routes:: template in order. ids and names are percent-encoded,
+    // a '/' or '?' in them stays inside its segment
+    fn url(&self, route: &str, params: &[&str]) -> anyhow::Result<Url> {
+        let mut params = params.iter();
+        let segments = route.split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| if s.starts_with('{') {
+                params.next().copied()
+                    .ok_or_else(|| anyhow::anyhow!("no value for {} in route '{}'", s, route))
+            } else {
+                Ok(s)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if params.next().is_some() {
+            anyhow::bail!("too many values for route '{}'", route);
+        }
+
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("base url '{}' can't have a path", self.base_url))?
+            .pop_if_empty()
+            .extend(segments);
+
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, route: &str, params: &[&str]) -> anyhow::Result<RequestBuilder> {
+        Ok(self.request_url(method, self.url(route, params)?))
+    }
+
+    fn request_url(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .bearer_auth(&self.api_key)
+            .timeout(Duration::from_secs(30))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, builder: RequestBuilder) -> anyhow::Result<T> {
+        let res = builder.send().await?;
+        let status = res.status();
+
+        if !status.is_success() {
+            anyhow::bail!("necko API returned {}: {}", status, error_message(res).await);
+        }
+
+        Ok(res.json().await?)
+    }
+
+    async fn send_empty(&self, builder: RequestBuilder) -> anyhow::Result<()> {
+        let res = builder.send().await?;
+        let status = res.status();
+
+        if !status.is_success() {
+            anyhow::bail!("necko API returned {}: {}", status, error_message(res).await);
+        }
+
+        Ok(())
+    }
+}
+
+async fn error_message(res: reqwest::Response) -> String {
+    let body = res.text().await.unwrap_or_default();
+
+    match serde_json::from_str::<ApiError>(&body) {
+        Ok(e) => e.error,
+        Err(_) => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_path_segments_are_escaped() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/chains/bsc%2Fmain%3Fnet/tokens"))
+            .and(header("authorization", "Bearer key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "symbol": "USDT", "contract": "0x55d398326f99059fF775485246999027B3197955", "decimals": 18 }
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = NeckoClient::new(&format!("{}/api", server.uri()), "key").unwrap();
+        let tokens = client.get_tokens("bsc/main?net").await.unwrap();
+        assert_eq!(tokens[0].symbol, "USDT");
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_carry_the_api_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/invoices/missing%20id/settle"))
+            .respond_with(ResponseTemplate::new(404)
+                .set_body_json(serde_json::json!({ "error": "Invoice 'missing id' does not exist" })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/chains"))
+            .respond_with(ResponseTemplate::new(502).set_body_string("bad gateway"))
+            .mount(&server)
+            .await;
+
+        let client = NeckoClient::new(&server.uri(), "key").unwrap();
+        let err = client.settle_invoice("missing id", false).await.unwrap_err();
+        assert_eq!(err.to_string(), "necko API returned 404 Not Found: Invoice 'missing id' does not exist");
+
+        // not an ApiError body, passed on as it is
+        let err = client.get_chains().await.unwrap_err();
+        assert_eq!(err.to_string(), "necko API returned 502 Bad Gateway: bad gateway");
+    }
+
+    #[test]
+    fn test_urls_follow_the_route_templates() {
+        let client = NeckoClient::new("http://localhost:8080/api/", "key").unwrap();
+        for route in routes::ALL {
+            let params = vec!["x"; route.matches('{').count()];
+            let url = client.url(route, &params).unwrap();
+            assert_eq!(url.path(), format!("/api{}", route.replace("{id}", "x").replace("{name}", "x")));
+        }
+
+        assert!(client.url(routes::INVOICE, &[]).is_err());
+        assert!(client.url(routes::CHAINS, &["x"]).is_err());
+    }
+}
@@ -1,31 +1,62 @@
 use crate::chain::{Blockchain, BlockchainAdapter};
-use crate::db::DatabaseAdapter;
-use crate::model::{ChainConfig, Invoice, InvoiceStatus, PartialChainUpdate, Payment, PaymentStatus, TokenConfig, WebhookEvent, WebhookJob, WebhookStatus};
+use crate::checkout::DEFAULT_DEEP_LINK_TEMPLATES;
+use crate::db::{validate_invoice_chain, DatabaseAdapter, CHAIN_ERRORS_CAP, SLOT_RESERVATION_TTL};
+use crate::model::{AccountSettingKind, AccountUsage, Quota, Role, AddressIndexExhausted, ChainConfig, ChainError, ChainStats, ChainStatsDelta, ChainVersionConflict, DeepLinkTemplate, OutboundFreeze, Invoice, InvoiceFilter, InvoiceStatus, Page, PageRequest, invoice_cursor, parse_invoice_cursor, InvoiceTotals, LatencyPercentiles, MisdirectedPayment, MisdirectedStatus, PartialChainUpdate, Payment, PaymentAlreadyFinalized, PaymentAnalytics, PaymentStatus, PendingWebhook, Refund, RefundExceedsPayment, TokenConfig, WebhookEvent, UnknownTransfer, WebhookDestination, WebhookJob, WebhookStatus, contract_key, NATIVE_CONTRACT, validate_max_address_index, validate_rpc_urls, validate_expiry_warning, validate_split_schedule, validate_webhook_events, MAX_NON_HARDENED_INDEX};
 use alloy::primitives::utils::format_units;
 use alloy::primitives::U256;
-use dashmap::DashMap;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use dashmap::{DashMap, DashSet};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 pub struct MockDatabase {
     chains: RwLock<HashMap<String, Arc<Blockchain>>>, // key = chain name
     invoices: DashMap<String, Invoice>, // key = id/uuid
-    token_decimals: RwLock<HashMap<String, HashMap<String, u8>>>, // (chain_name, (token_symbol, decimals))
-    payments: DashMap<String, Payment>, // key = invoice_id
+    token_decimals: RwLock<HashMap<String, HashMap<String, u8>>>, // (chain_name, (contract_key, decimals))
+    payments: DashMap<String, Payment>, // key = payment id
+    payments_archive: DashMap<String, Payment>, // key = payment id
     webhooks: DashMap<String, MockWebhook>, // key = id/uuid
+    webhook_destinations: DashMap<String, WebhookDestination>, // key = url
+    expiry_warned: DashSet<String>, // invoice ids
+    stale_notified: DashSet<String>, // invoice ids
+    watch_released: DashSet<String>, // ids of expired invoices no longer watched
+    unknown_transfers: DashMap<String, UnknownTransfer>, // key = network:tx_hash:log_index
+    misdirected_payments: DashMap<String, MisdirectedPayment>, // key = id
+    refunds: DashMap<String, Refund>, // key = id
+    settlement_latencies: DashMap<String, (String, String, u64, DateTime<Utc>)>, // key = invoice id, (network, token, latency_ms, delivered_at)
+    chain_errors: DashMap<String, VecDeque<ChainError>>, // key = chain name, newest at the back
+    chain_stats: DashMap<String, (ChainStats, u64)>, // key = chain name, (stats, processing_ms_total)
+    slot_reservations: DashMap<(String, u32), HashMap<u32, DateTime<Utc>>>, // ((chain_name, account_id), (index, reserved_until))
+    quota_lock: Mutex<()>, // what the advisory lock per account is in postgres
+    deep_link_templates: DashMap<String, String>, // (wallet, template)
+    outbound_freeze: RwLock<OutboundFreeze>,
+    account_settings: DashMap<(u32, AccountSettingKind), serde_json::Value>,
+    api_keys: DashMap<String, Role>, // key = sha256 of the key
 }
 
 struct MockWebhook {
     id: uuid::Uuid,
-    invoice_id: uuid::Uuid,
+    delivery_token: uuid::Uuid,
+    invoice_id: Option<uuid::Uuid>, // None = to a merchant, see add_account_webhook_job
+    account: Option<(u32, String)>, // account_id, secret
     url: String,
     payload: WebhookEvent,
     status: WebhookStatus,
     attempts: u32,
     max_retries: u32,
     next_retry: chrono::DateTime<Utc>,
+    processing_started_at: Option<chrono::DateTime<Utc>>,
+    dedupe_key: String,
+    sequence: u64,
+    created_at: chrono::DateTime<Utc>,
+}
+
+impl Default for MockDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MockDatabase {
@@ -35,7 +66,26 @@ impl MockDatabase {
             invoices: DashMap::new(),
             token_decimals: RwLock::new(HashMap::new()),
             payments: DashMap::new(),
+            payments_archive: DashMap::new(),
             webhooks: DashMap::new(),
+            webhook_destinations: DashMap::new(),
+            expiry_warned: DashSet::new(),
+            stale_notified: DashSet::new(),
+            watch_released: DashSet::new(),
+            unknown_transfers: DashMap::new(),
+            misdirected_payments: DashMap::new(),
+            refunds: DashMap::new(),
+            settlement_latencies: DashMap::new(),
+            chain_errors: DashMap::new(),
+            chain_stats: DashMap::new(),
+            slot_reservations: DashMap::new(),
+            quota_lock: Mutex::new(()),
+            deep_link_templates: DEFAULT_DEEP_LINK_TEMPLATES.iter()
+                .map(|(wallet, template)| (wallet.to_string(), template.to_string()))
+                .collect(),
+            outbound_freeze: RwLock::new(OutboundFreeze::default()),
+            account_settings: DashMap::new(),
+            api_keys: DashMap::new(),
         }
     }
 }
@@ -54,15 +104,14 @@ impl DatabaseAdapter for MockDatabase {
         Ok(self.chains.read().unwrap().get(chain_name).cloned())
     }
 
-    async fn get_chain_by_id(&self, id: u32) -> anyhow::Result<Option<Arc<Blockchain>>> {
+    async fn get_chain_by_id(&self, _id: u32) -> anyhow::Result<Option<Arc<Blockchain>>> {
         unimplemented!("mock database does not have ids")
     }
 
     async fn add_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<()> {
-        match self.chains.read().unwrap().contains_key(&chain_config.name) {
-            true => anyhow::bail!("chain with name {} already exists", chain_config.name),
-            false => {},
-        };
+        if self.chains.read().unwrap().contains_key(&chain_config.name) {
+            anyhow::bail!("chain with name {} already exists", chain_config.name);
+        }
 
         let blockchain = Blockchain::new(chain_config.clone())?;
 
@@ -72,6 +121,48 @@ impl DatabaseAdapter for MockDatabase {
         Ok(())
     }
 
+    async fn upsert_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<bool> {
+        let mut guard = self.chains.write().unwrap();
+
+        let Some(blockchain) = guard.get(&chain_config.name) else {
+            let blockchain = Blockchain::new(chain_config.clone())?;
+            guard.insert(chain_config.name.clone(), Arc::new(blockchain));
+
+            return Ok(true)
+        };
+
+        let config_lock = blockchain.config();
+        let mut new_config = config_lock.read().unwrap().clone();
+
+        if new_config.chain_type != chain_config.chain_type
+            || new_config.native_symbol != chain_config.native_symbol
+            || new_config.decimals != chain_config.decimals
+            || new_config.test_mode != chain_config.test_mode
+        {
+            anyhow::bail!("chain '{}' already exists with a different type, native symbol, \
+                decimals or test mode", chain_config.name);
+        }
+
+        new_config.rpc_urls = chain_config.rpc_urls.clone();
+        new_config.xpub = chain_config.xpub.clone();
+        new_config.block_lag = chain_config.block_lag;
+        new_config.required_confirmations = chain_config.required_confirmations;
+        new_config.record_unknown_transfers = chain_config.record_unknown_transfers;
+        new_config.resolve_smart_account_payers = chain_config.resolve_smart_account_payers;
+        new_config.rpc_auth = chain_config.rpc_auth.clone();
+        new_config.maintenance_windows = chain_config.maintenance_windows.clone();
+        new_config.max_address_index = chain_config.max_address_index;
+        new_config.heimdall_url = chain_config.heimdall_url.clone();
+        new_config.block_tag = chain_config.block_tag;
+        new_config.chain_id = chain_config.chain_id;
+        new_config.account_xpubs = chain_config.account_xpubs.clone();
+
+        let new_blockchain = Arc::new(Blockchain::new(new_config)?);
+        guard.insert(chain_config.name.clone(), new_blockchain);
+
+        Ok(false)
+    }
+
     async fn update_chain_block(&self, chain_name: &str, block_num: u64) -> anyhow::Result<()> {
         match self.chains.read().unwrap().get(chain_name) {
             Some(c) => c.config().write().unwrap()
@@ -87,16 +178,16 @@ impl DatabaseAdapter for MockDatabase {
             .map(|c| c.config().read().unwrap().last_processed_block))
     }
 
-    async fn get_chains_with_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
+    async fn get_chains_with_token(&self, token_contract: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
         let guard = self.chains.read().unwrap();
+        let key = contract_key(token_contract);
 
         let result = guard.values()
             .filter(|c| {
-                if c.config().read().unwrap()
-                    .native_symbol == token_symbol { return true; }
+                if key == NATIVE_CONTRACT { return true; }
                 c.config().read().unwrap()
                     .tokens.read().unwrap().iter()
-                    .any(|c| c.symbol == token_symbol)
+                    .any(|t| contract_key(&t.contract) == key)
             })
             .cloned()
             .collect();
@@ -106,10 +197,11 @@ impl DatabaseAdapter for MockDatabase {
 
     async fn remove_chain(&self, chain_name: &str) -> anyhow::Result<()> {
         self.chains.write().unwrap().remove(chain_name);
+        self.chain_stats.remove(chain_name);
         Ok(())
     }
 
-    async fn remove_chain_by_id(&self, id: u32) -> anyhow::Result<()> {
+    async fn remove_chain_by_id(&self, _id: u32) -> anyhow::Result<()> {
         unimplemented!("mock database does not have ids")
     }
 
@@ -125,12 +217,23 @@ impl DatabaseAdapter for MockDatabase {
         let config_lock = blockchain.config();
         let mut chain_config = config_lock.read().unwrap().clone();
 
+        if let Some(expected) = chain_update.expected_version
+            && expected != chain_config.version
+        {
+            return Err(ChainVersionConflict {
+                chain: chain_name.to_owned(),
+                expected,
+            }.into());
+        }
+        chain_config.version += 1;
+
         if let Some(xpub) = &chain_update.xpub {
             chain_config.xpub = xpub.to_owned();
         }
 
-        if let Some(rpc_url) = &chain_update.rpc_url {
-            chain_config.rpc_url = rpc_url.to_owned();
+        if let Some(rpc_urls) = &chain_update.rpc_urls {
+            validate_rpc_urls(rpc_urls, chain_config.chain_type)?;
+            chain_config.rpc_urls = rpc_urls.to_owned();
         }
 
         if let Some(last_processed_block) = chain_update.last_processed_block {
@@ -145,13 +248,59 @@ impl DatabaseAdapter for MockDatabase {
             chain_config.required_confirmations = required_confirmations;
         }
 
+        if let Some(record_unknown_transfers) = chain_update.record_unknown_transfers {
+            chain_config.record_unknown_transfers = record_unknown_transfers;
+        }
+
+        if let Some(resolve) = chain_update.resolve_smart_account_payers {
+            chain_config.resolve_smart_account_payers = resolve;
+        }
+
+        if let Some(rpc_auth) = &chain_update.rpc_auth {
+            chain_config.rpc_auth = rpc_auth.clone();
+        }
+
+        if let Some(maintenance_windows) = &chain_update.maintenance_windows {
+            for window in maintenance_windows {
+                window.validate()?;
+            }
+            chain_config.maintenance_windows = maintenance_windows.clone();
+        }
+
+        if let Some(max_index) = chain_update.max_address_index {
+            validate_max_address_index(max_index)?;
+            chain_config.max_address_index = max_index;
+        }
+
         let new_blockchain = Arc::new(Blockchain::new(chain_config)?);
+        // clones share the counter, so listeners still on the replaced config re-snapshot too
+        new_blockchain.config().read().unwrap().bump_generation();
 
         guard.insert(chain_name.to_owned(), new_blockchain);
 
         Ok(())
     }
 
+    async fn reload_chain(&self, chain_name: &str) -> anyhow::Result<Option<Arc<Blockchain>>> {
+        let mut guard = self.chains.write().unwrap();
+
+        let Some(blockchain) = guard.get(chain_name) else {
+            return Ok(None)
+        };
+
+        let chain_config = blockchain.config().read().unwrap().clone();
+        let new_blockchain = Arc::new(Blockchain::new(chain_config)?);
+
+        guard.insert(chain_name.to_owned(), new_blockchain.clone());
+
+        Ok(Some(new_blockchain))
+    }
+
+    async fn get_stored_chain_config(&self, chain_name: &str) -> anyhow::Result<Option<ChainConfig>> {
+        Ok(self.chains.read().unwrap().get(chain_name)
+            .map(|c| c.config().read().unwrap().clone()))
+    }
+
     async fn get_watch_addresses(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
         Ok(self.chains.read().unwrap().get(chain_name)
             .map(|c| c.config().read().unwrap()
@@ -161,10 +310,14 @@ impl DatabaseAdapter for MockDatabase {
     }
 
     async fn remove_watch_address(&self, chain_name: &str, address: &str) -> anyhow::Result<()> {
+        if self._is_address_still_needed(chain_name, address) {
+            return Ok(());
+        }
+
         match self.chains.read().unwrap().get(chain_name) {
             Some(c) => {
                 c.config().read().unwrap()
-                    .watch_addresses.write().unwrap().remove(address);
+                    .update_watch_addresses(|addrs| addrs.remove(address));
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name),
         }
@@ -180,12 +333,13 @@ impl DatabaseAdapter for MockDatabase {
         match self.chains.read().unwrap().get(chain_name) {
             Some(c) => {
                 let config_lock = c.config();
-                let guard = config_lock.read().unwrap();
-                let mut watch_addresses = guard.watch_addresses.write().unwrap();
-
-                for addr in addresses {
-                    watch_addresses.remove::<String>(addr);
-                }
+                config_lock.read().unwrap().update_watch_addresses(|watch_addresses| {
+                    for addr in addresses {
+                        if !self._is_address_still_needed(chain_name, addr) {
+                            watch_addresses.remove::<String>(addr);
+                        }
+                    }
+                });
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name)
         }
@@ -197,7 +351,7 @@ impl DatabaseAdapter for MockDatabase {
         match self.chains.read().unwrap().get(chain_name) {
             Some(c) => {
                 c.config().read().unwrap()
-                    .watch_addresses.write().unwrap().insert(address.to_owned());
+                    .update_watch_addresses(|addrs| addrs.insert(address.to_owned()));
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name),
         }
@@ -210,10 +364,10 @@ impl DatabaseAdapter for MockDatabase {
             .map(|c| c.config().read().unwrap().xpub.clone()))
     }
 
-    async fn get_rpc_url(&self, chain_name: &str) -> anyhow::Result<Option<String>> {
+    async fn get_rpc_urls(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
         Ok(self.chains.read().unwrap().get(chain_name)
             .map(|c| c.config().read().unwrap()
-                .rpc_url.clone()))
+                .rpc_urls.clone()))
     }
 
     async fn get_block_lag(&self, chain_name: &str) -> anyhow::Result<Option<u8>> {
@@ -250,7 +404,7 @@ impl DatabaseAdapter for MockDatabase {
         }
     }
 
-    async fn get_token_by_id(&self, chain_name: &str, id: u32) -> anyhow::Result<Option<TokenConfig>> {
+    async fn get_token_by_id(&self, _chain_name: &str, _id: u32) -> anyhow::Result<Option<TokenConfig>> {
         unimplemented!("mock database does not have ids")
     }
 
@@ -260,7 +414,7 @@ impl DatabaseAdapter for MockDatabase {
         match self.chains.read().unwrap().get(chain_name) {
             Some(c) => Ok(c.config().read().unwrap()
                 .tokens.read().unwrap().iter()
-                .find(|tc| tc.contract == contract_address)
+                .find(|tc| contract_key(&tc.contract) == contract_key(contract_address))
                 .cloned()),
             None => Ok(None),
         }
@@ -268,29 +422,55 @@ impl DatabaseAdapter for MockDatabase {
 
     async fn remove_token(&self, chain_name: &str, token_symbol: &str) -> anyhow::Result<()> {
         if let Some(c) = self.chains.read().unwrap().get(chain_name) {
+            let contract = c.config().read().unwrap().token_contract(token_symbol);
             c.config().read().unwrap()
-                .tokens.write().unwrap().retain(|t| t.symbol != token_symbol);
-        }
+                .update_tokens(|tokens| tokens.retain(|t| t.symbol != token_symbol));
 
-        if let Some(chain_decimals) = self.token_decimals.write().unwrap()
-            .get_mut(chain_name)
-        {
-            chain_decimals.remove(token_symbol);
+            if let Some(contract) = contract
+                && let Some(chain_decimals) = self.token_decimals.write().unwrap()
+                    .get_mut(chain_name)
+            {
+                chain_decimals.remove(&contract_key(&contract));
+            }
         }
 
         Ok(())
     }
 
-    async fn remove_token_by_id(&self, chain_name: &str, id: u32) -> anyhow::Result<()> {
+    async fn remove_token_by_id(&self, _chain_name: &str, _id: u32) -> anyhow::Result<()> {
         unimplemented!("mock database does not have ids")
     }
 
+    async fn set_token_min_amount(&self, chain_name: &str, token_symbol: &str,
+                                  min_amount: Option<U256>) -> anyhow::Result<()> {
+        let mut found = false;
+        if let Some(c) = self.chains.read().unwrap().get(chain_name) {
+            let config = c.config();
+            config.read().unwrap().update_tokens(|tokens| {
+                if let Some(mut token) = tokens.iter().find(|t| t.symbol == token_symbol).cloned() {
+                    tokens.remove(&token);
+                    token.min_amount = min_amount;
+                    tokens.insert(token);
+                    found = true;
+                }
+            });
+        }
+
+        if !found {
+            anyhow::bail!("Token {} not found on chain {}", token_symbol, chain_name)
+        }
+
+        Ok(())
+    }
+
     async fn add_token(&self, chain_name: &str, token_config: &TokenConfig) -> anyhow::Result<()> {
         if let Some(c) = self.chains.read().unwrap().get(chain_name) {
-            c.config().read().unwrap()
-                .tokens.write().unwrap().insert(token_config.clone());
+            let config = c.config();
+            let config = config.read().unwrap();
+            config.check_token_collision(token_config)?;
+            config.update_tokens(|tokens| tokens.insert(token_config.clone()));
         }
-        self._insert_token_decimals(chain_name, &token_config.symbol, token_config.decimals)?;
+        self._insert_token_decimals(chain_name, &token_config.contract, token_config.decimals)?;
 
         Ok(())
     }
@@ -308,13 +488,58 @@ impl DatabaseAdapter for MockDatabase {
             .collect())
     }
 
-    async fn get_invoices_by_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Invoice>> {
+    async fn get_invoices_by_token(&self, token_contract: &str) -> anyhow::Result<Vec<Invoice>> {
+        let key = contract_key(token_contract);
+        Ok(self.invoices.iter()
+            .map(|x| x.value().clone())
+            .filter(|inv| inv.token_contract == key)
+            .collect())
+    }
+
+    async fn get_invoices_by_tag(&self, tag: &str) -> anyhow::Result<Vec<Invoice>> {
         Ok(self.invoices.iter()
             .map(|x| x.value().clone())
-            .filter(|inv| inv.token == token_symbol)
+            .filter(|inv| inv.tags.iter().any(|t| t == tag))
             .collect())
     }
 
+    fn stream_invoices<'a>(&'a self, filter: &InvoiceFilter) -> BoxStream<'a, anyhow::Result<Invoice>> {
+        let mut invoices: Vec<Invoice> = self.invoices.iter()
+            .filter(|inv| filter.matches(inv.value()))
+            .map(|inv| inv.value().clone())
+            .collect();
+        invoices.sort_by_key(|inv| inv.created_at);
+
+        stream::iter(invoices.into_iter().map(Ok)).boxed()
+    }
+
+    async fn list_invoices(&self, filter: &InvoiceFilter, page: &PageRequest) -> anyhow::Result<Page<Invoice>> {
+        let after = page.cursor.as_deref().map(parse_invoice_cursor).transpose()?;
+
+        let mut invoices: Vec<Invoice> = self.invoices.iter()
+            .filter(|inv| filter.matches(inv.value()))
+            .map(|inv| inv.value().clone())
+            .collect();
+        invoices.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+        let total = page.with_total.then_some(invoices.len() as u64);
+
+        let limit = page.limit() as usize;
+        let mut items: Vec<Invoice> = invoices.into_iter()
+            .filter(|inv| after.as_ref().is_none_or(|(at, id)| (inv.created_at, &inv.id) < (*at, id)))
+            .take(limit + 1)
+            .collect();
+
+        let next_cursor = match items.len() > limit {
+            true => {
+                items.truncate(limit);
+                items.last().map(invoice_cursor)
+            }
+            false => None,
+        };
+
+        Ok(Page { items, next_cursor, total })
+    }
+
     async fn get_invoices_by_address(&self, address: &str) -> anyhow::Result<Vec<Invoice>> {
         Ok(self.invoices.iter()
             .map(|x| x.value().clone())
@@ -326,6 +551,12 @@ impl DatabaseAdapter for MockDatabase {
         Ok(self.invoices.get(uuid).map(|x| x.value().clone()))
     }
 
+    async fn get_invoices_by_ids(&self, uuids: &[String]) -> anyhow::Result<Vec<Invoice>> {
+        Ok(uuids.iter()
+            .filter_map(|id| self.invoices.get(id).map(|x| x.value().clone()))
+            .collect())
+    }
+
     async fn get_invoices_by_status(&self, status: InvoiceStatus) -> anyhow::Result<Vec<Invoice>> {
         Ok(self.invoices.iter()
             .map(|x| x.value().clone())
@@ -347,21 +578,101 @@ impl DatabaseAdapter for MockDatabase {
             .collect())
     }
 
-    async fn get_busy_indexes(&self, chain_name: &str) -> anyhow::Result<Vec<u32>> {
-        Ok(self.invoices.iter()
-            .filter(|i| i.status == InvoiceStatus::Pending
-                && i.network == chain_name)
-            .map(|i| i.value().address_index)
-            .collect())
+    async fn find_invoice_by_tx_hash(&self, chain_name: &str, tx_hash: &str) -> anyhow::Result<Vec<Invoice>> {
+        let invoice_ids: HashSet<String> = self.payments.iter()
+            .chain(self.payments_archive.iter())
+            .filter(|p| p.network == chain_name && p.tx_hash == tx_hash)
+            .map(|p| p.invoice_id.clone())
+            .collect();
+
+        let mut invoices: Vec<Invoice> = invoice_ids.iter()
+            .filter_map(|id| self.invoices.get(id).map(|x| x.value().clone()))
+            .collect();
+
+        invoices.sort_by_key(|inv| inv.created_at);
+
+        Ok(invoices)
+    }
+
+    async fn get_busy_indexes(&self, chain_name: &str, account_id: u32) -> anyhow::Result<Vec<u32>> {
+        Ok(self._busy_indexes(chain_name, account_id).collect())
+    }
+
+    async fn acquire_free_slot(&self, chain_name: &str, account_id: u32, quota: Option<&Quota>)
+        -> anyhow::Result<u32>
+    {
+        let _quota_guard = quota.map(|_| self.quota_lock.lock().unwrap());
+        if let Some(quota) = quota {
+            let usage = self._account_usage(account_id, Utc::now() - chrono::Duration::days(1));
+            quota.check(account_id, chain_name, &usage)?;
+        }
+
+        // holding the entry keeps concurrent acquisitions on this account serialized, nothing
+        // below awaits while it's held
+        let mut reservations = self.slot_reservations
+            .entry((chain_name.to_owned(), account_id))
+            .or_default();
+
+        let now = Utc::now();
+        reservations.retain(|_, reserved_until| *reserved_until > now);
+
+        let mut busy: HashSet<u32> = self._busy_indexes(chain_name, account_id).collect();
+        busy.extend(reservations.keys());
+
+        let max_index = self.chains.read().unwrap().get(chain_name)
+            .map(|chain| chain.config().read().unwrap().max_address_index)
+            .unwrap_or(MAX_NON_HARDENED_INDEX);
+
+        let slot = (0..=max_index).find(|i| !busy.contains(i))
+            .ok_or_else(|| AddressIndexExhausted {
+                chain: chain_name.to_owned(),
+                account_id,
+                max_index,
+            })?;
+
+        let ttl = chrono::Duration::from_std(SLOT_RESERVATION_TTL)?;
+        reservations.insert(slot, now + ttl);
+
+        Ok(slot)
     }
 
     async fn add_invoice(&self, invoice: &Invoice) -> anyhow::Result<()> {
+        if let Some(schedule) = &invoice.split_schedule {
+            validate_split_schedule(schedule)?;
+        }
+
+        if let Some(events) = &invoice.webhook_events {
+            validate_webhook_events(events)?;
+        }
+
+        if let Some(secs) = invoice.expiry_warning_secs {
+            validate_expiry_warning(secs)?;
+        }
+
+        let token_contract = match self.chains.read().unwrap().get(&invoice.network) {
+            Some(chain) => validate_invoice_chain(invoice, chain)?,
+            None => contract_key(&invoice.token_contract),
+        };
+
         if self.invoices.contains_key(&invoice.id) {
             anyhow::bail!("invoice '{}' already exists", invoice.id);
         }
 
+        if let Some(original) = &invoice.reissued_from
+            && self.invoices.iter().any(|i| i.reissued_from.as_ref() == Some(original))
+        {
+            anyhow::bail!("invoice '{}' was already reissued", original);
+        }
+
+        let invoice = &Invoice { token_contract, ..invoice.clone() };
+
         self.invoices.insert(invoice.id.clone(), invoice.clone());
 
+        let reservation_key = (invoice.network.clone(), invoice.account_id);
+        if let Some(mut reservations) = self.slot_reservations.get_mut(&reservation_key) {
+            reservations.remove(&invoice.address_index);
+        }
+
         Ok(())
     }
 
@@ -374,6 +685,43 @@ impl DatabaseAdapter for MockDatabase {
         Ok(())
     }
 
+    async fn set_invoice_address(&self, uuid: &str, address: &str) -> anyhow::Result<()> {
+        match self.invoices.get_mut(uuid) {
+            Some(mut inv) => inv.address = address.to_owned(),
+            None => anyhow::bail!("invoice '{}' does not exist", uuid),
+        }
+
+        Ok(())
+    }
+
+    async fn settle_invoice(&self, uuid: &str, paid_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        let mut inv = self.invoices.get_mut(uuid)
+            .ok_or_else(|| anyhow::anyhow!("invoice '{}' does not exist", uuid))?;
+
+        if inv.status == InvoiceStatus::Paid {
+            return Ok(false);
+        }
+
+        inv.status = InvoiceStatus::Paid;
+        inv.paid_at = Some(paid_at);
+
+        Ok(true)
+    }
+
+    async fn expire_invoice(&self, uuid: &str) -> anyhow::Result<bool> {
+        let mut inv = self.invoices.get_mut(uuid)
+            .ok_or_else(|| anyhow::anyhow!("invoice '{}' does not exist", uuid))?;
+
+        if inv.status != InvoiceStatus::Pending {
+            return Ok(false);
+        }
+
+        inv.status = InvoiceStatus::Expired;
+        self.watch_released.insert(inv.id.clone()); // the caller stops watching right away
+
+        Ok(true)
+    }
+
     // async fn add_payment(&self, uuid: &str, amount_raw: U256) -> anyhow::Result<(U256, String)> {
     //     let mut inv = match self.invoices.get_mut(uuid) {
     //         Some(inv) => inv,
@@ -412,6 +760,91 @@ impl DatabaseAdapter for MockDatabase {
         Ok(old_invoices)
     }
 
+    async fn release_expired_addresses(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let now = Utc::now();
+
+        let released: Vec<(String, String, String)> = self.invoices.iter()
+            .filter(|inv| inv.status == InvoiceStatus::Expired
+                && !self.watch_released.contains(&inv.id)
+                && inv.expires_at + Duration::from_secs(inv.grace_period_secs.unwrap_or(0)) <= now)
+            .map(|inv| (inv.id.clone(), inv.network.clone(), inv.address.clone()))
+            .collect();
+
+        Ok(released.into_iter()
+            .map(|(id, network, address)| {
+                self.watch_released.insert(id);
+                (network, address)
+            })
+            .collect())
+    }
+
+    async fn revive_invoice_in_grace(&self, chain_name: &str, address: &str) -> anyhow::Result<Option<Invoice>> {
+        let now = Utc::now();
+
+        let reissued: HashSet<String> = self.invoices.iter()
+            .filter_map(|inv| inv.reissued_from.clone())
+            .collect();
+
+        let Some(mut inv) = self.invoices.iter_mut()
+            .find(|inv| inv.network == chain_name && inv.address == address
+                && inv.status == InvoiceStatus::Expired
+                && !self.watch_released.contains(&inv.id)
+                && !reissued.contains(&inv.id)
+                && inv.grace_period_secs
+                    .is_some_and(|secs| inv.expires_at + Duration::from_secs(secs) > now))
+        else {
+            return Ok(None);
+        };
+
+        inv.status = InvoiceStatus::Pending;
+        let grace = inv.grace_period_secs.take().unwrap_or(0);
+        inv.expires_at += Duration::from_secs(grace);
+
+        Ok(Some(inv.clone()))
+    }
+
+    async fn get_expiring_invoices(&self) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        let now = Utc::now();
+
+        let expiring: Vec<(String, DateTime<Utc>)> = self.invoices.iter()
+            .filter(|inv| inv.status == InvoiceStatus::Pending
+                && inv.expires_at > now
+                && !self.expiry_warned.contains(&inv.id))
+            .filter(|inv| match inv.expiry_warning_secs {
+                Some(secs) => inv.expires_at - Duration::from_secs(secs) <= now,
+                None => false,
+            })
+            .map(|inv| (inv.id.clone(), inv.expires_at))
+            .collect();
+
+        Ok(expiring)
+    }
+
+    async fn mark_expiry_warned(&self, uuid: &str) -> anyhow::Result<()> {
+        self.expiry_warned.insert(uuid.to_owned());
+        Ok(())
+    }
+
+    async fn get_stale_invoices(&self, older_than: Duration) -> anyhow::Result<Vec<Invoice>> {
+        let cutoff = Utc::now() - older_than;
+
+        let stale: Vec<Invoice> = self.invoices.iter()
+            .filter(|inv| inv.status == InvoiceStatus::Pending
+                && inv.created_at <= cutoff
+                && inv.paid_raw.is_zero()
+                && !self.stale_notified.contains(&inv.id))
+            .filter(|inv| !self.payments.iter().any(|p| p.invoice_id == inv.id))
+            .map(|inv| inv.clone())
+            .collect();
+
+        Ok(stale)
+    }
+
+    async fn mark_invoice_stale(&self, uuid: &str) -> anyhow::Result<()> {
+        self.stale_notified.insert(uuid.to_owned());
+        Ok(())
+    }
+
     async fn is_invoice_expired(&self, uuid: &str) -> anyhow::Result<Option<bool>> {
         Ok(self.invoices.iter()
             .find(|inv| inv.id == uuid)
@@ -436,38 +869,311 @@ impl DatabaseAdapter for MockDatabase {
         Ok(())
     }
 
-    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str, tx_hash: &str,
-                                 amount_raw: U256, block_number: u64, network: &str,
-                                 log_index: Option<u64>) -> anyhow::Result<()> {
-        let mut contains = false;
+    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str,
+                                 payer: Option<&str>, tx_hash: &str, amount_raw: U256,
+                                 block_number: u64, block_timestamp: Option<DateTime<Utc>>,
+                                 network: &str, log_index: Option<u64>) -> anyhow::Result<()> {
+        let same_transfer = |p: &Payment| p.network == network && p.tx_hash == tx_hash
+            && p.log_index == log_index;
 
-        if self.payments.contains_key(invoice_id) {
-            contains = true;
+        if self.payments_archive.iter().any(|p| same_transfer(&p)) {
+            return Ok(()) // already finalized and archived
         }
 
-        if contains {
-            self.payments.get_mut(invoice_id)
-                .unwrap().block_number = block_number;
+        if let Some(mut payment) = self.payments.iter_mut().find(|p| same_transfer(p)) {
+            payment.block_number = block_number;
+            if block_timestamp.is_some() {
+                payment.block_timestamp = block_timestamp;
+            }
+            if payer.is_some() {
+                payment.payer = payer.map(str::to_owned);
+            }
             return Ok(())
         }
 
-        self.payments.insert(invoice_id.to_owned(), Payment {
-            id: uuid::Uuid::new_v4().to_string(),
+        let id = uuid::Uuid::new_v4().to_string();
+        self.payments.insert(id.clone(), Payment {
+            id,
             invoice_id: invoice_id.to_owned(),
             from: from.to_owned(),
             to: to.to_owned(),
+            payer: payer.map(str::to_owned),
             network: network.to_owned(),
             tx_hash: tx_hash.to_owned(),
             amount_raw,
             block_number,
+            block_timestamp,
             status: PaymentStatus::Confirming,
             created_at: chrono::Utc::now(),
-            log_index: log_index.unwrap_or(u64::MAX),
+            confirmed_at: None,
+            log_index,
         });
 
         Ok(())
     }
 
+    async fn get_account_usage(&self, account_id: u32, since: DateTime<Utc>) -> anyhow::Result<AccountUsage> {
+        Ok(self._account_usage(account_id, since))
+    }
+
+    async fn get_invoice_totals(&self, account_id: u32, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> anyhow::Result<Vec<InvoiceTotals>>
+    {
+        let mut totals: Vec<InvoiceTotals> = vec![];
+
+        for inv in self.invoices.iter() {
+            if inv.account_id != account_id || inv.test_mode {
+                continue;
+            }
+
+            let (at, amount_raw) = match inv.status {
+                InvoiceStatus::Paid => (inv.paid_at.unwrap_or(inv.expires_at), inv.paid_raw),
+                InvoiceStatus::Expired => (inv.expires_at, inv.amount_raw),
+                InvoiceStatus::Pending => continue,
+            };
+            if at < from || at >= to {
+                continue;
+            }
+
+            match totals.iter_mut().find(|t| t.network == inv.network && t.token == inv.token
+                && t.status == inv.status)
+            {
+                Some(total) => {
+                    total.count += 1;
+                    total.amount_raw += amount_raw;
+                }
+                None => totals.push(InvoiceTotals {
+                    network: inv.network.clone(),
+                    token: inv.token.clone(),
+                    decimals: inv.decimals,
+                    status: inv.status,
+                    count: 1,
+                    amount_raw,
+                }),
+            }
+        }
+
+        totals.sort_by(|a, b| (a.status.as_ref(), &a.network, &a.token)
+            .cmp(&(b.status.as_ref(), &b.network, &b.token)));
+        Ok(totals)
+    }
+
+    async fn get_payment_analytics(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<PaymentAnalytics>> {
+        // (network, token) -> (confirmation secs, amount deviations in %)
+        let mut buckets: HashMap<(String, String), (Vec<f64>, Vec<f64>)> = HashMap::new();
+
+        for p in self.payments.iter().chain(self.payments_archive.iter()) {
+            let Some(confirmed_at) = p.confirmed_at else { continue };
+            if p.status != PaymentStatus::Confirmed || confirmed_at < from || confirmed_at >= to {
+                continue;
+            }
+            let Some(inv) = self.invoices.get(&p.invoice_id) else { continue };
+
+            buckets.entry((inv.network.clone(), inv.token.clone()))
+                .or_default()
+                .0.push((confirmed_at - p.created_at).num_milliseconds() as f64 / 1000.0);
+        }
+
+        for inv in self.invoices.iter() {
+            if inv.status == InvoiceStatus::Pending || inv.paid_raw.is_zero() || inv.amount_raw.is_zero() {
+                continue;
+            }
+            let settled_at = inv.paid_at.unwrap_or(inv.expires_at);
+            if settled_at < from || settled_at >= to {
+                continue;
+            }
+
+            let amount: f64 = inv.amount_raw.to_string().parse()?;
+            let paid: f64 = inv.paid_raw.to_string().parse()?;
+
+            buckets.entry((inv.network.clone(), inv.token.clone()))
+                .or_default()
+                .1.push((paid - amount) / amount * 100.0);
+        }
+
+        let avg = |v: &[f64]| (!v.is_empty()).then(|| v.iter().sum::<f64>() / v.len() as f64);
+
+        let mut analytics: Vec<PaymentAnalytics> = buckets.into_iter()
+            .map(|((network, token), (confirmations, deviations))| PaymentAnalytics {
+                network,
+                token,
+                payments_count: confirmations.len() as u64,
+                avg_confirmation_secs: avg(&confirmations),
+                avg_amount_deviation_pct: avg(&deviations),
+            })
+            .collect();
+
+        analytics.sort_by(|a, b| (&a.network, &a.token).cmp(&(&b.network, &b.token)));
+
+        Ok(analytics)
+    }
+
+    async fn record_settlement_latency(&self, invoice_id: &str, delivered_at: DateTime<Utc>) -> anyhow::Result<Option<u64>> {
+        if self.settlement_latencies.contains_key(invoice_id) {
+            return Ok(None);
+        }
+        let Some(inv) = self.invoices.get(invoice_id) else { return Ok(None) };
+
+        // the payment that completed the invoice is the last one confirmed
+        let block_timestamp = self.payments.iter().chain(self.payments_archive.iter())
+            .filter(|p| p.invoice_id == invoice_id && p.status == PaymentStatus::Confirmed)
+            .max_by_key(|p| p.confirmed_at)
+            .and_then(|p| p.block_timestamp);
+        let Some(block_timestamp) = block_timestamp else { return Ok(None) };
+
+        let latency_ms = (delivered_at - block_timestamp).num_milliseconds().max(0) as u64;
+        self.settlement_latencies.insert(invoice_id.to_owned(),
+            (inv.network.clone(), inv.token.clone(), latency_ms, delivered_at));
+
+        Ok(Some(latency_ms))
+    }
+
+    async fn get_latency_percentiles(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<LatencyPercentiles>> {
+        let mut buckets: HashMap<(String, String), Vec<u64>> = HashMap::new();
+
+        for entry in self.settlement_latencies.iter() {
+            let (network, token, latency_ms, delivered_at) = entry.value();
+            if *delivered_at >= from && *delivered_at < to {
+                buckets.entry((network.clone(), token.clone())).or_default().push(*latency_ms);
+            }
+        }
+
+        // nearest rank, what percentile_disc returns
+        let percentile = |sorted: &[u64], p: f64| {
+            sorted[((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1]
+        };
+
+        let mut percentiles: Vec<LatencyPercentiles> = buckets.into_iter()
+            .map(|((network, token), mut latencies)| {
+                latencies.sort_unstable();
+                LatencyPercentiles {
+                    network,
+                    token,
+                    payments_count: latencies.len() as u64,
+                    p50_ms: percentile(&latencies, 0.5),
+                    p90_ms: percentile(&latencies, 0.9),
+                    p99_ms: percentile(&latencies, 0.99),
+                    max_ms: latencies[latencies.len() - 1],
+                }
+            })
+            .collect();
+
+        percentiles.sort_by(|a, b| (&a.network, &a.token).cmp(&(&b.network, &b.token)));
+
+        Ok(percentiles)
+    }
+
+    async fn archive_finalized_payments(&self, confirmed_before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let keys: Vec<String> = self.payments.iter()
+            .filter(|p| p.status == PaymentStatus::Confirmed
+                && p.confirmed_at.is_some_and(|t| t < confirmed_before))
+            .map(|p| p.key().clone())
+            .collect();
+
+        let mut archived = 0;
+        for key in keys {
+            if let Some((_, payment)) = self.payments.remove(&key) {
+                self.payments_archive.insert(payment.id.clone(), payment);
+                archived += 1;
+            }
+        }
+
+        Ok(archived)
+    }
+
+    async fn add_unknown_transfer(&self, transfer: &UnknownTransfer) -> anyhow::Result<()> {
+        let key = format!("{}:{}:{}", transfer.network, transfer.tx_hash, transfer.log_index);
+        self.unknown_transfers.entry(key).or_insert_with(|| transfer.clone());
+
+        Ok(())
+    }
+
+    async fn get_unknown_transfers(&self, chain_name: &str) -> anyhow::Result<Vec<UnknownTransfer>> {
+        let mut transfers: Vec<UnknownTransfer> = self.unknown_transfers.iter()
+            .filter(|t| t.network == chain_name)
+            .map(|t| t.value().clone())
+            .collect();
+
+        transfers.sort_by_key(|t| (t.block_number, t.log_index));
+
+        Ok(transfers)
+    }
+
+    async fn add_misdirected_payment(&self, payment: &MisdirectedPayment) -> anyhow::Result<bool> {
+        if self.misdirected_payments.iter().any(|p| p.token.chain == payment.token.chain
+            && p.tx_hash == payment.tx_hash && p.log_index == payment.log_index)
+        {
+            return Ok(false);
+        }
+
+        self.misdirected_payments.insert(payment.id.clone(), payment.clone());
+
+        Ok(true)
+    }
+
+    async fn get_misdirected_payments(&self, invoice_id: &str) -> anyhow::Result<Vec<MisdirectedPayment>> {
+        let mut payments: Vec<MisdirectedPayment> = self.misdirected_payments.iter()
+            .filter(|p| p.invoice_id == invoice_id)
+            .map(|p| p.value().clone())
+            .collect();
+
+        payments.sort_by_key(|p| p.created_at);
+
+        Ok(payments)
+    }
+
+    async fn set_misdirected_payment_status(&self, id: &str, status: MisdirectedStatus) -> anyhow::Result<()> {
+        let mut payment = self.misdirected_payments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("misdirected payment '{}' not found", id))?;
+
+        payment.status = status;
+        payment.resolved_at = (status != MisdirectedStatus::Open).then(Utc::now);
+
+        Ok(())
+    }
+
+    async fn add_refund(&self, refund: &Refund) -> anyhow::Result<U256> {
+        let mut invoice = self.invoices.get_mut(&refund.invoice_id)
+            .ok_or_else(|| anyhow::anyhow!("Invoice {} not found", refund.invoice_id))?;
+
+        let payment_amount = self.payments.get(&refund.payment_id)
+            .filter(|p| p.status == PaymentStatus::Confirmed)
+            .or_else(|| self.payments_archive.get(&refund.payment_id))
+            .filter(|p| p.invoice_id == refund.invoice_id)
+            .map(|p| p.amount_raw)
+            .ok_or_else(|| anyhow::anyhow!("Confirmed payment {} of invoice {} not found",
+                refund.payment_id, refund.invoice_id))?;
+
+        let already_refunded = self.refunds.iter()
+            .filter(|r| r.payment_id == refund.payment_id)
+            .fold(U256::ZERO, |sum, r| sum + r.amount_raw);
+
+        let refundable = payment_amount.saturating_sub(already_refunded);
+        if refund.amount_raw > refundable {
+            return Err(RefundExceedsPayment {
+                payment_id: refund.payment_id.clone(),
+                refundable,
+                requested: refund.amount_raw,
+            }.into());
+        }
+
+        self.refunds.insert(refund.id.clone(), refund.clone());
+        invoice.refunded_raw += refund.amount_raw;
+
+        Ok(invoice.refunded_raw)
+    }
+
+    async fn get_refunds(&self, invoice_id: &str) -> anyhow::Result<Vec<Refund>> {
+        let mut refunds: Vec<Refund> = self.refunds.iter()
+            .filter(|r| r.invoice_id == invoice_id)
+            .map(|r| r.value().clone())
+            .collect();
+
+        refunds.sort_by_key(|r| r.created_at);
+
+        Ok(refunds)
+    }
+
     async fn get_confirming_payments(&self) -> anyhow::Result<Vec<Payment>> {
         Ok(self.payments.iter()
             .filter(|p| p.status == PaymentStatus::Confirming)
@@ -475,14 +1181,27 @@ impl DatabaseAdapter for MockDatabase {
             .collect())
     }
 
-    async fn finalize_payment(&self, payment_id: &str) -> anyhow::Result<bool> {
+    async fn get_payment(&self, payment_id: &str) -> anyhow::Result<Option<Payment>> {
+        Ok(self.payments.iter()
+            .find(|p| p.id == payment_id)
+            .map(|p| p.value().clone()))
+    }
+
+    async fn finalize_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
         let (invoice_id, amount_to_add) = {
             let mut payment_ref = self.payments.iter_mut()
                 .find(|p| p.id == payment_id)
                 .ok_or_else(|| anyhow::anyhow!("Payment {} not found", payment_id))?;
 
             let p = payment_ref.value_mut();
+            if p.status != PaymentStatus::Confirming {
+                return Err(PaymentAlreadyFinalized {
+                    payment_id: payment_id.to_owned(),
+                    status: p.status,
+                }.into())
+            }
             p.status = PaymentStatus::Confirmed;
+            p.confirmed_at = Some(confirmed_at);
             (p.invoice_id.clone(), p.amount_raw)
         };
 
@@ -496,39 +1215,170 @@ impl DatabaseAdapter for MockDatabase {
 
         if inv.paid_raw >= inv.amount_raw {
             inv.status = InvoiceStatus::Paid;
+            inv.paid_at = Some(confirmed_at);
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
+    async fn reject_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        let mut payment = self.payments.get_mut(payment_id)
+            .ok_or_else(|| anyhow::anyhow!("Payment {} not found", payment_id))?;
+
+        if payment.status != PaymentStatus::Confirming {
+            return Ok(false);
+        }
+
+        payment.status = PaymentStatus::Rejected;
+        payment.confirmed_at = Some(confirmed_at);
+
+        Ok(true)
+    }
+
     async fn update_payment_block(&self, payment_id: &str, block_num: u64) -> anyhow::Result<()> {
         self.payments.get_mut(payment_id).unwrap().block_number = block_num;
 
         Ok(())
     }
 
-    async fn select_webhooks_job(&self) -> anyhow::Result<Vec<WebhookJob>> {
+    async fn add_chain_error(&self, error: &ChainError) -> anyhow::Result<()> {
+        let mut errors = self.chain_errors.entry(error.network.clone()).or_default();
+
+        errors.push_back(error.clone());
+        while errors.len() > CHAIN_ERRORS_CAP {
+            errors.pop_front();
+        }
+
+        Ok(())
+    }
+
+    async fn get_chain_errors(&self, chain_name: &str) -> anyhow::Result<Vec<ChainError>> {
+        Ok(self.chain_errors.get(chain_name)
+            .map(|errors| errors.iter().rev().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn add_chain_stats(&self, chain_name: &str, delta: &ChainStatsDelta) -> anyhow::Result<()> {
+        let mut entry = self.chain_stats.entry(chain_name.to_owned()).or_insert_with(|| (
+            ChainStats {
+                network: chain_name.to_owned(),
+                blocks_processed: 0,
+                events_emitted: 0,
+                avg_block_ms: 0.0,
+                last_event_at: None,
+                updated_at: Utc::now(),
+            },
+            0,
+        ));
+        let (stats, processing_ms_total) = entry.value_mut();
+
+        stats.blocks_processed += delta.blocks;
+        stats.events_emitted += delta.events;
+        *processing_ms_total += delta.processing_ms;
+        stats.avg_block_ms = ChainStats::avg_block_ms(*processing_ms_total, stats.blocks_processed);
+        stats.last_event_at = delta.last_event_at.or(stats.last_event_at);
+        stats.updated_at = Utc::now();
+
+        Ok(())
+    }
+
+    async fn get_chain_stats(&self, chain_name: &str) -> anyhow::Result<Option<ChainStats>> {
+        Ok(self.chain_stats.get(chain_name).map(|s| s.0.clone()))
+    }
+
+    async fn get_deep_link_templates(&self) -> anyhow::Result<Vec<DeepLinkTemplate>> {
+        let mut templates: Vec<DeepLinkTemplate> = self.deep_link_templates.iter()
+            .map(|t| DeepLinkTemplate { wallet: t.key().clone(), template: t.value().clone() })
+            .collect();
+
+        templates.sort_by(|a, b| a.wallet.cmp(&b.wallet));
+
+        Ok(templates)
+    }
+
+    async fn set_deep_link_template(&self, template: &DeepLinkTemplate) -> anyhow::Result<()> {
+        self.deep_link_templates.insert(template.wallet.clone(), template.template.clone());
+
+        Ok(())
+    }
+
+    async fn remove_deep_link_template(&self, wallet: &str) -> anyhow::Result<()> {
+        self.deep_link_templates.remove(wallet);
+
+        Ok(())
+    }
+
+    async fn get_outbound_freeze(&self) -> anyhow::Result<OutboundFreeze> {
+        Ok(self.outbound_freeze.read().unwrap().clone())
+    }
+
+    async fn set_outbound_freeze(&self, freeze: &OutboundFreeze) -> anyhow::Result<()> {
+        *self.outbound_freeze.write().unwrap() = freeze.clone();
+
+        Ok(())
+    }
+
+    async fn get_account_settings(&self, kind: AccountSettingKind) -> anyhow::Result<Vec<(u32, serde_json::Value)>> {
+        Ok(self.account_settings.iter()
+            .filter(|e| e.key().1 == kind)
+            .map(|e| (e.key().0, e.value().clone()))
+            .collect())
+    }
+
+    async fn set_account_setting(&self, account_id: u32, kind: AccountSettingKind,
+                                 value: Option<&serde_json::Value>) -> anyhow::Result<()> {
+        match value {
+            Some(v) => self.account_settings.insert((account_id, kind), v.clone()),
+            None => self.account_settings.remove(&(account_id, kind)).map(|(_, v)| v),
+        };
+
+        Ok(())
+    }
+
+    async fn get_api_keys(&self) -> anyhow::Result<Vec<(String, Role)>> {
+        Ok(self.api_keys.iter().map(|e| (e.key().clone(), *e.value())).collect())
+    }
+
+    async fn set_api_key(&self, key_hash: &str, role: Option<Role>) -> anyhow::Result<()> {
+        match role {
+            Some(r) => self.api_keys.insert(key_hash.to_owned(), r),
+            None => self.api_keys.remove(key_hash).map(|(_, r)| r),
+        };
+
+        Ok(())
+    }
+
+    async fn select_webhooks_job(&self, limit: u32) -> anyhow::Result<Vec<WebhookJob>> {
         let now = Utc::now();
         let mut jobs = Vec::new();
 
         let target_ids: Vec<String> = self.webhooks.iter()
             .filter(|r| r.status == WebhookStatus::Pending && r.next_retry <= now)
-            .take(50)
+            .take(limit as usize)
             .map(|r| r.key().clone())
             .collect();
 
         for id in target_ids {
             if let Some(mut job) = self.webhooks.get_mut(&id) {
                 job.status = WebhookStatus::Processing;
-
-                let secret = self.invoices.get(&job.invoice_id.to_string())
-                    .map(|inv| inv.webhook_secret.clone())
-                    .flatten()
-                    .unwrap_or_else(|| "default_secret".to_owned());
+                job.processing_started_at = Some(now);
+
+                let invoice = job.invoice_id.and_then(|id| self.invoices.get(&id.to_string()));
+                let (account_id, secret) = match &job.account {
+                    Some((account_id, secret)) => (*account_id, secret.clone()),
+                    None => (
+                        invoice.as_ref().map(|inv| inv.account_id).unwrap_or_default(),
+                        invoice.and_then(|inv| inv.webhook_secret.clone())
+                            .unwrap_or_else(|| "default_secret".to_owned()),
+                    ),
+                };
 
                 jobs.push(WebhookJob {
                     id: job.id,
+                    delivery_token: job.delivery_token,
+                    account_id: account_id as i32,
+                    sequence: job.sequence as i64,
                     url: job.url.clone(),
                     secret_key: secret,
                     payload: sqlx::types::Json(job.payload.clone()),
@@ -561,35 +1411,191 @@ impl DatabaseAdapter for MockDatabase {
         }
     }
 
+    async fn requeue_stuck_webhooks(&self, visibility_timeout: Duration) -> anyhow::Result<u64> {
+        let deadline = Utc::now() - chrono::Duration::from_std(visibility_timeout)?;
+        let mut requeued = 0;
+
+        for mut job in self.webhooks.iter_mut() {
+            if job.status == WebhookStatus::Processing
+                && job.processing_started_at.is_some_and(|t| t < deadline)
+            {
+                job.status = WebhookStatus::Pending;
+                requeued += 1;
+            }
+        }
+
+        Ok(requeued)
+    }
+
     async fn add_webhook_job(&self, invoice_id: &str, event: &WebhookEvent) -> anyhow::Result<()> {
         let inv_id = uuid::Uuid::parse_str(invoice_id)?;
 
         let invoice = self.invoices.get(invoice_id)
             .ok_or_else(|| anyhow::anyhow!("Invoice {} not found", invoice_id))?;
 
-        if invoice.webhook_url.is_none() {
+        if invoice.webhook_url.is_none() || !invoice.wants_webhook(event) {
             return Ok(());
         }
 
+        let dedupe_key = event.dedupe_key();
+        let window_start = match event.suppression_window() {
+            Some(window) => Some(Utc::now() - chrono::Duration::from_std(window)?),
+            None => None,
+        };
+
+        let duplicate = self.webhooks.iter().any(|w| {
+            w.invoice_id == Some(inv_id)
+                && w.payload.as_ref() == event.as_ref()
+                && w.dedupe_key == dedupe_key
+                && window_start.is_none_or(|start| w.created_at > start)
+        });
+
+        if duplicate {
+            return Ok(());
+        }
+
+        let sequence = self.webhooks.iter()
+            .filter(|w| w.invoice_id == Some(inv_id))
+            .map(|w| w.sequence)
+            .max()
+            .unwrap_or(0) + 1;
+
+        let url = invoice.webhook_url.clone().unwrap();
+        let job_id = uuid::Uuid::new_v4();
+        let job = MockWebhook {
+            id: job_id,
+            delivery_token: uuid::Uuid::new_v4(),
+            invoice_id: Some(inv_id),
+            account: None,
+            status: self.initial_webhook_status(&url),
+            url,
+            payload: event.clone(),
+            attempts: 0,
+            max_retries: 10,
+            next_retry: Utc::now(),
+            processing_started_at: None,
+            dedupe_key,
+            sequence,
+            created_at: Utc::now(),
+        };
+
+        self.webhooks.insert(job_id.to_string(), job);
+        Ok(())
+    }
+
+    async fn add_account_webhook_job(&self, account_id: u32, url: &str, secret: &str, event: &WebhookEvent)
+        -> anyhow::Result<bool>
+    {
+        let dedupe_key = event.dedupe_key();
+        let duplicate = self.webhooks.iter().any(|w| {
+            w.account.as_ref().is_some_and(|(id, _)| *id == account_id)
+                && w.payload.as_ref() == event.as_ref()
+                && w.dedupe_key == dedupe_key
+        });
+
+        if duplicate {
+            return Ok(false);
+        }
+
         let job_id = uuid::Uuid::new_v4();
         let job = MockWebhook {
             id: job_id,
-            invoice_id: inv_id,
-            url: invoice.webhook_url.clone().unwrap(),
+            delivery_token: uuid::Uuid::new_v4(),
+            invoice_id: None,
+            account: Some((account_id, secret.to_owned())),
+            url: url.to_owned(),
             payload: event.clone(),
-            status: WebhookStatus::Pending,
+            status: self.initial_webhook_status(url),
             attempts: 0,
             max_retries: 10,
             next_retry: Utc::now(),
+            processing_started_at: None,
+            dedupe_key,
+            sequence: 0,
+            created_at: Utc::now(),
         };
 
         self.webhooks.insert(job_id.to_string(), job);
+        Ok(true)
+    }
+
+    async fn get_pending_webhooks(&self) -> anyhow::Result<Vec<PendingWebhook>> {
+        let mut jobs: Vec<_> = self.webhooks.iter()
+            .filter(|w| matches!(w.status, WebhookStatus::Pending | WebhookStatus::Processing))
+            .filter_map(|w| w.invoice_id.map(|invoice_id| (w.created_at, PendingWebhook {
+                invoice_id: invoice_id.to_string(),
+                event: w.payload.clone(),
+                attempts: w.attempts,
+            })))
+            .collect();
+        jobs.sort_by_key(|(created_at, _)| *created_at);
+
+        Ok(jobs.into_iter().map(|(_, job)| job).collect())
+    }
+
+    async fn record_webhook_failure(&self, url: &str, error: &str) -> anyhow::Result<WebhookDestination> {
+        let mut destination = self.webhook_destinations.entry(url.to_owned())
+            .or_insert_with(|| WebhookDestination {
+                url: url.to_owned(),
+                failure_streak: 0,
+                failing_since: Utc::now(),
+                last_error: String::new(),
+                suspended_at: None,
+            });
+        destination.failure_streak += 1;
+        destination.last_error = error.to_owned();
+
+        Ok(destination.clone())
+    }
+
+    async fn clear_webhook_failures(&self, url: &str) -> anyhow::Result<()> {
+        self.webhook_destinations.remove_if(url, |_, d| d.suspended_at.is_none());
         Ok(())
     }
 
+    async fn suspend_webhook_destination(&self, url: &str) -> anyhow::Result<Option<u64>> {
+        match self.webhook_destinations.get_mut(url) {
+            Some(mut d) if d.suspended_at.is_none() => d.suspended_at = Some(Utc::now()),
+            _ => return Ok(None),
+        }
+
+        Ok(Some(self.move_webhooks(url, WebhookStatus::Pending, WebhookStatus::Suppressed)))
+    }
+
+    async fn resume_webhook_destination(&self, url: &str, redeliver: bool) -> anyhow::Result<Option<u64>> {
+        if self.webhook_destinations.remove_if(url, |_, d| d.suspended_at.is_some()).is_none() {
+            return Ok(None);
+        }
+
+        if !redeliver {
+            return Ok(Some(0));
+        }
+        Ok(Some(self.move_webhooks(url, WebhookStatus::Suppressed, WebhookStatus::Pending)))
+    }
+
+    async fn get_webhook_destinations(&self) -> anyhow::Result<Vec<WebhookDestination>> {
+        let mut destinations: Vec<_> = self.webhook_destinations.iter().map(|d| d.clone()).collect();
+        destinations.sort_by(|a, b| a.url.cmp(&b.url));
+
+        Ok(destinations)
+    }
+
     async fn get_token_decimals(&self, chain_name: &str, token_symbol: &str) -> anyhow::Result<Option<u8>> {
-        if let Some(decimals) = self._get_token_decimals(chain_name, token_symbol)?
-        {
+        let contract = match self.chains.read().unwrap().get(chain_name) {
+            Some(cc) => cc.config().read().unwrap().token_contract(token_symbol),
+            None => None,
+        };
+
+        match contract {
+            Some(contract) => self.get_token_decimals_by_contract(chain_name, &contract).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn get_token_decimals_by_contract(&self, chain_name: &str, contract: &str)
+        -> anyhow::Result<Option<u8>>
+    {
+        if let Some(decimals) = self._get_token_decimals(chain_name, contract)? {
             return Ok(Some(decimals))
         }
 
@@ -603,42 +1609,562 @@ impl DatabaseAdapter for MockDatabase {
             cc.config()
         };
 
-        let chain_config = chain_config_lock.read().unwrap();
-
-        if token_symbol == chain_config.native_symbol {
-            self._insert_token_decimals(chain_name, token_symbol, chain_config.decimals)?;
-
-            return Ok(Some(chain_config.decimals));
+        let decimals = chain_config_lock.read().unwrap().decimals_by_contract(contract);
+        if let Some(d) = decimals {
+            self._insert_token_decimals(chain_name, contract, d)?;
         }
 
-        match chain_config.tokens.read().unwrap().iter()
-            .find(|tc| tc.symbol == token_symbol) {
-            Some(tc) => {
-                self._insert_token_decimals(chain_name, token_symbol, tc.decimals)?;
-
-                Ok(Some(tc.decimals))
-            },
-            None => Ok(None),
-        }
+        Ok(decimals)
     }
 }
 
 impl MockDatabase {
-    fn _insert_token_decimals(&self, chain_name: &str, token_symbol: &str, decimals: u8) -> anyhow::Result<()> {
+    fn _insert_token_decimals(&self, chain_name: &str, contract: &str, decimals: u8) -> anyhow::Result<()> {
         let mut write_guard = self.token_decimals.write().unwrap();
         let inner_map = write_guard
             .entry(chain_name.to_string())
-            .or_insert_with(HashMap::new);
+            .or_default();
 
-        inner_map.insert(token_symbol.to_string(), decimals);
+        inner_map.insert(contract_key(contract), decimals);
 
         Ok(())
     }
 
-    fn _get_token_decimals(&self, chain_name: &str, token_symbol: &str) -> anyhow::Result<Option<u8>> {
+    fn _account_usage(&self, account_id: u32, since: DateTime<Utc>) -> AccountUsage {
+        let mut usage = AccountUsage::default();
+        let mut count = |network: &str, pending: bool, recent: bool| {
+            if pending {
+                usage.pending_invoices += 1;
+                if !usage.pending_networks.iter().any(|n| n == network) {
+                    usage.pending_networks.push(network.to_owned());
+                }
+            }
+            if recent {
+                usage.invoices_last_day += 1;
+            }
+        };
+
+        for inv in self.invoices.iter().filter(|inv| inv.account_id == account_id) {
+            count(&inv.network, inv.status == InvoiceStatus::Pending, inv.created_at >= since);
+        }
+
+        let now = Utc::now();
+        for entry in self.slot_reservations.iter().filter(|e| e.key().1 == account_id) {
+            for _ in entry.values().filter(|reserved_until| **reserved_until > now) {
+                count(&entry.key().0, true, true);
+            }
+        }
+
+        usage
+    }
+
+    fn _busy_indexes(&self, chain_name: &str, account_id: u32) -> impl Iterator<Item = u32> {
+        self.invoices.iter()
+            .filter(move |i| (i.status == InvoiceStatus::Pending
+                    || (i.status == InvoiceStatus::Expired && !self.watch_released.contains(&i.id)))
+                && i.network == chain_name
+                && i.account_id == account_id)
+            .map(|i| i.value().address_index)
+    }
+
+    fn _is_address_still_needed(&self, chain_name: &str, address: &str) -> bool {
+        self.invoices.iter()
+            .any(|inv| inv.network == chain_name
+                && inv.address == address
+                && inv.status == InvoiceStatus::Pending)
+    }
+
+    fn _get_token_decimals(&self, chain_name: &str, contract: &str) -> anyhow::Result<Option<u8>> {
         Ok(self.token_decimals.read().unwrap()
             .get(chain_name)
-            .and_then(|c| c.get(token_symbol)
+            .and_then(|c| c.get(&contract_key(contract))
                 .cloned()))
     }
-}
\ No newline at end of file
+
+    fn initial_webhook_status(&self, url: &str) -> WebhookStatus {
+        match self.webhook_destinations.get(url) {
+            Some(d) if d.suspended_at.is_some() => WebhookStatus::Suppressed,
+            _ => WebhookStatus::Pending,
+        }
+    }
+
+    fn move_webhooks(&self, url: &str, from: WebhookStatus, to: WebhookStatus) -> u64 {
+        let mut moved = 0;
+        for mut job in self.webhooks.iter_mut() {
+            if job.url == url && job.status == from {
+                job.status = to;
+                job.next_retry = Utc::now();
+                moved += 1;
+            }
+        }
+
+        moved
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+
+    #[tokio::test]
+    async fn test_finalize_payment_credits_once() {
+        let db = MockDatabase::new();
+
+        let invoice = Invoice::for_test();
+        db.add_invoice(&invoice).await.unwrap();
+        db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, "0xabc",
+                               U256::from(1_000_000), 42, None, "testnet", Some(0)).await.unwrap();
+        let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+
+        assert!(!db.finalize_payment(&payment_id, Utc::now()).await.unwrap());
+
+        // a second confirmator, or a retried tick, must not credit the same transfer again
+        let err = db.finalize_payment(&payment_id, Utc::now()).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<PaymentAlreadyFinalized>(), Some(&PaymentAlreadyFinalized {
+            payment_id: payment_id.clone(),
+            status: PaymentStatus::Confirmed,
+        }));
+
+        let invoice = db.get_invoice(&invoice.id).await.unwrap().unwrap();
+        assert_eq!(invoice.paid_raw, U256::from(1_000_000));
+        assert_eq!(invoice.status, InvoiceStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_settlement_latency_percentiles() {
+        let db = MockDatabase::new();
+        let mined_at = Utc::now() - chrono::Duration::minutes(10);
+
+        for (i, secs) in [10, 20, 30, 40, 100].into_iter().enumerate() {
+            let invoice = Invoice::for_test();
+            db.add_invoice(&invoice).await.unwrap();
+            db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, &format!("0x{}", i),
+                                   U256::from(2_000_000), 42, Some(mined_at), "testnet", Some(0))
+                .await.unwrap();
+            let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+            db.finalize_payment(&payment_id, Utc::now()).await.unwrap();
+
+            let delivered_at = mined_at + chrono::Duration::seconds(secs);
+            assert_eq!(db.record_settlement_latency(&invoice.id, delivered_at).await.unwrap(),
+                       Some(secs as u64 * 1000));
+            // a redelivered InvoicePaid doesn't count twice
+            assert_eq!(db.record_settlement_latency(&invoice.id, Utc::now()).await.unwrap(), None);
+        }
+
+        let percentiles = db.get_latency_percentiles(mined_at, Utc::now()).await.unwrap();
+        assert_eq!(percentiles, vec![LatencyPercentiles {
+            network: "testnet".to_owned(),
+            token: "USDT".to_owned(),
+            payments_count: 5,
+            p50_ms: 30_000,
+            p90_ms: 100_000,
+            p99_ms: 100_000,
+            max_ms: 100_000,
+        }]);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_free_slot_stops_at_max_index() {
+        let db = MockDatabase::new();
+        // BIP32 test vector 1, chain m/0'/1
+        let builder = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH");
+        assert!(builder.clone().max_address_index(MAX_NON_HARDENED_INDEX + 1).build().is_err());
+
+        db.add_chain(&builder.max_address_index(1).build().unwrap()).await.unwrap();
+
+        assert_eq!(db.acquire_free_slot("testnet", 0, None).await.unwrap(), 0);
+        assert_eq!(db.acquire_free_slot("testnet", 0, None).await.unwrap(), 1);
+
+        let err = db.acquire_free_slot("testnet", 0, None).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<AddressIndexExhausted>(), Some(&AddressIndexExhausted {
+            chain: "testnet".to_owned(),
+            account_id: 0,
+            max_index: 1,
+        }));
+
+        // other accounts have their own range
+        assert_eq!(db.acquire_free_slot("testnet", 1, None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_invoice_rejects_amounts_below_minimum() {
+        use crate::model::InvoiceAmountError;
+
+        let db = MockDatabase::new();
+        let usdt = TokenConfig::builder()
+            .symbol("USDT")
+            .contract("0x3333333333333333333333333333333333333333")
+            .decimals(6)
+            .min_amount(U256::from(1_000_000))
+            .build()
+            .unwrap();
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .token(usdt)
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        let mut dust = Invoice::for_test();
+        dust.amount_raw = U256::from(999_999);
+        let err = db.add_invoice(&dust).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<InvoiceAmountError>(), Some(InvoiceAmountError::BelowMinimum { .. })));
+
+        let mut zero = Invoice::for_test();
+        zero.token = "ETH".to_owned();
+        zero.amount_raw = U256::ZERO;
+        let err = db.add_invoice(&zero).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<InvoiceAmountError>(), Some(&InvoiceAmountError::Zero));
+
+        db.add_invoice(&Invoice::for_test()).await.unwrap();
+        assert_eq!(db.invoices.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_invoice_rejects_tokens_on_utxo_chains() {
+        use crate::model::ChainType;
+
+        let db = MockDatabase::new();
+        let chain = ChainConfig::builder()
+            .name("litecoin")
+            .chain_type(ChainType::LTC)
+            .rpc_url("http://localhost:9332")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("LTC")
+            .decimals(8)
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        let mut usdt = Invoice::for_test();
+        usdt.network = "litecoin".to_owned();
+        assert!(db.add_invoice(&usdt).await.unwrap_err().to_string().contains("doesn't support tokens"));
+
+        let mut ltc = usdt.clone();
+        ltc.id = uuid::Uuid::new_v4().to_string();
+        ltc.token = "LTC".to_owned();
+        ltc.decimals = 8;
+        db.add_invoice(&ltc).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_account_usage_is_checked_against_quota() {
+        use crate::model::{Quota, QuotaExceeded, QuotaKind};
+
+        let db = MockDatabase::new();
+        let mut invoices = vec![Invoice::for_test(), Invoice::for_test(), Invoice::for_test(), Invoice::for_test()];
+        invoices[1].network = "polygon".to_owned();
+        invoices[2].status = InvoiceStatus::Paid;
+        invoices[3].created_at = Utc::now() - chrono::Duration::days(2);
+        for inv in &invoices {
+            db.add_invoice(inv).await.unwrap();
+        }
+        let mut other = Invoice::for_test();
+        other.account_id = 1;
+        db.add_invoice(&other).await.unwrap();
+
+        let usage = db.get_account_usage(0, Utc::now() - chrono::Duration::days(1)).await.unwrap();
+        assert_eq!((usage.pending_invoices, usage.invoices_last_day), (3, 3));
+        assert_eq!(usage.pending_networks.len(), 2);
+
+        let exceeded = |kind, limit| Err(QuotaExceeded { account_id: 0, kind, limit });
+        let quota = Quota { max_pending_invoices: Some(3), ..Default::default() };
+        assert_eq!(quota.check(0, "testnet", &usage), exceeded(QuotaKind::PendingInvoices, 3));
+        let quota = Quota { max_invoices_per_day: Some(4), max_chains: Some(2), ..Default::default() };
+        assert_eq!(quota.check(0, "testnet", &usage), Ok(()));
+        // only a chain the merchant has nothing pending on counts as another one
+        assert_eq!(quota.check(0, "base", &usage), exceeded(QuotaKind::Chains, 2));
+        assert!(Quota { max_chains: Some(0), ..Default::default() }.validate().is_err());
+
+        // a reserved slot counts before its invoice exists, back to back acquisitions can't both fit
+        let quota = Quota { max_pending_invoices: Some(2), ..Default::default() };
+        assert_eq!(db.acquire_free_slot("testnet", 1, Some(&quota)).await.unwrap(), 1);
+        let err = db.acquire_free_slot("testnet", 1, Some(&quota)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<QuotaExceeded>(),
+                   Some(&QuotaExceeded { account_id: 1, kind: QuotaKind::PendingInvoices, limit: 2 }));
+    }
+
+    #[tokio::test]
+    async fn test_list_invoices_pages_newest_first() {
+        let db = MockDatabase::new();
+        let created_at = Utc::now();
+        for i in 0..5 {
+            let mut invoice = Invoice::for_test();
+            // two share a timestamp, the id breaks the tie
+            invoice.created_at = created_at + chrono::Duration::seconds(i.min(3));
+            db.add_invoice(&invoice).await.unwrap();
+        }
+
+        let mut page = PageRequest { limit: Some(2), with_total: true, ..Default::default() };
+        let mut listed = vec![];
+        loop {
+            let result = db.list_invoices(&InvoiceFilter::default(), &page).await.unwrap();
+            assert_eq!(result.total, page.with_total.then_some(5));
+            listed.extend(result.items.into_iter().map(|i| (i.created_at, i.id)));
+
+            match result.next_cursor {
+                Some(cursor) => page = PageRequest { cursor: Some(cursor), limit: Some(2), with_total: false },
+                None => break,
+            }
+        }
+
+        assert_eq!(listed.len(), 5);
+        assert!(listed.windows(2).all(|w| w[0] > w[1]));
+
+        let page = PageRequest { cursor: Some("garbage".to_owned()), ..Default::default() };
+        assert!(db.list_invoices(&InvoiceFilter::default(), &page).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_invoices_pages_until_exhausted() {
+        use futures::StreamExt;
+
+        let db = MockDatabase::new();
+        let start = Utc::now();
+        let mut ids = vec![];
+        for i in 0..5 {
+            let inv = Invoice { created_at: start + chrono::Duration::seconds(5 - i), ..Invoice::for_test() };
+            ids.push(inv.id.clone());
+            db.invoices.insert(inv.id.clone(), inv);
+        }
+        let other = Invoice { network: "othernet".to_owned(), ..Invoice::for_test() };
+        db.invoices.insert(other.id.clone(), other);
+        ids.reverse(); // oldest first
+
+        let filter = InvoiceFilter { network: Some("testnet".to_owned()), ..Default::default() };
+        let mut pages = db.stream_invoices(&filter).chunks(2);
+        let mut streamed = vec![];
+        while let Some(page) = pages.next().await {
+            assert!(!page.is_empty() && page.len() <= 2);
+            streamed.extend(page.into_iter().map(|inv| inv.unwrap().id));
+        }
+        assert_eq!(streamed, ids);
+
+        // exhausted streams stay exhausted, nothing matching ends right away
+        assert!(pages.next().await.is_none());
+        let filter = InvoiceFilter { network: Some("nonet".to_owned()), ..Default::default() };
+        assert!(db.stream_invoices(&filter).next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_invoice_rejects_expiry_warnings_out_of_range() {
+        use crate::model::MAX_EXPIRY_WARNING_SECS;
+
+        let db = MockDatabase::new();
+        for secs in [0, MAX_EXPIRY_WARNING_SECS + 1, u64::MAX] {
+            let inv = Invoice { expiry_warning_secs: Some(secs), ..Invoice::for_test() };
+            assert!(db.add_invoice(&inv).await.is_err(), "{} accepted", secs);
+        }
+        assert!(db.invoices.is_empty());
+
+        db.add_invoice(&Invoice { expiry_warning_secs: Some(MAX_EXPIRY_WARNING_SECS), ..Invoice::for_test() }).await.unwrap();
+        assert_eq!(db.get_expiring_invoices().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_invoice_validates_split_addresses() {
+        use crate::model::SplitShare;
+
+        let db = MockDatabase::new();
+        let split = |a: &str, b: &str| Some(vec![
+            SplitShare { address: a.to_owned(), bps: 9_000 },
+            SplitShare { address: b.to_owned(), bps: 1_000 },
+        ]);
+        let marketplace = "0x4444444444444444444444444444444444444444";
+
+        // unknown chain, nothing to check the addresses against yet
+        let unchecked = Invoice { split_schedule: split(ADDRESS, "bc1qnotevm"), ..Invoice::for_test() };
+        db.add_invoice(&unchecked).await.unwrap();
+
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        let bad = Invoice { token: "ETH".to_owned(), split_schedule: split(marketplace, "bc1qnotevm"), ..Invoice::for_test() };
+        let err = db.add_invoice(&bad).await.unwrap_err();
+        assert!(err.to_string().contains("bc1qnotevm"));
+
+        let good = Invoice { token: "ETH".to_owned(), split_schedule: split(marketplace, &ADDRESS.to_lowercase()), ..Invoice::for_test() };
+        db.add_invoice(&good).await.unwrap();
+        assert_eq!(db.invoices.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_keyed_by_contract() {
+        let db = MockDatabase::new();
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        let usdc = |contract: &str| TokenConfig::builder()
+            .symbol("USDC")
+            .contract(contract)
+            .decimals(6)
+            .build()
+            .unwrap();
+        let native = "0x3333333333333333333333333333333333333333";
+        let bridged = "0x4444444444444444444444444444444444444444";
+
+        // native and bridged USDC share a symbol, only the contract has to be unique
+        db.add_token("testnet", &usdc(native)).await.unwrap();
+        db.add_token("testnet", &usdc(bridged)).await.unwrap();
+        assert!(db.add_token("testnet", &usdc(&bridged.to_uppercase().replace("0X", "0x"))).await.is_err());
+
+        assert_eq!(db.get_chains_with_token(bridged).await.unwrap().len(), 1);
+        assert!(db.get_chains_with_token("0x5555555555555555555555555555555555555555").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refunds_are_capped_by_the_payment() {
+        let db = MockDatabase::new();
+
+        let invoice = Invoice::for_test();
+        db.add_invoice(&invoice).await.unwrap();
+        db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, "0xabc",
+                               U256::from(1_000_000), 42, None, "testnet", Some(0)).await.unwrap();
+        let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+        db.finalize_payment(&payment_id, Utc::now()).await.unwrap();
+
+        let refund = |amount: u64| Refund {
+            id: uuid::Uuid::new_v4().to_string(),
+            invoice_id: invoice.id.clone(),
+            payment_id: payment_id.clone(),
+            amount_raw: U256::from(amount),
+            tx_hash: None,
+            reason: None,
+            created_at: Utc::now(),
+        };
+
+        assert_eq!(db.add_refund(&refund(400_000)).await.unwrap(), U256::from(400_000));
+
+        // each refund fits on its own, together they'd exceed the payment
+        let err = db.add_refund(&refund(700_000)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<RefundExceedsPayment>(), Some(&RefundExceedsPayment {
+            payment_id: payment_id.clone(),
+            refundable: U256::from(600_000),
+            requested: U256::from(700_000),
+        }));
+
+        assert_eq!(db.add_refund(&refund(600_000)).await.unwrap(), U256::from(1_000_000));
+        assert_eq!(db.get_refunds(&invoice.id).await.unwrap().len(), 2);
+        assert_eq!(db.get_invoice(&invoice.id).await.unwrap().unwrap().refunded_raw, U256::from(1_000_000));
+
+        let unknown = Refund { payment_id: uuid::Uuid::new_v4().to_string(), ..refund(1) };
+        let err = db.add_refund(&unknown).await.unwrap_err();
+        assert!(err.downcast_ref::<RefundExceedsPayment>().is_none());
+        assert_eq!(db.get_refunds(&invoice.id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_one_shot_webhooks_are_deduped_per_transition() {
+        let db = MockDatabase::new();
+        let inv = Invoice { webhook_url: Some("https://merchant.example/hooks".to_owned()), ..Invoice::for_test() };
+        db.add_invoice(&inv).await.unwrap();
+
+        let paid_at = Utc::now();
+        db.add_webhook_job(&inv.id, &WebhookEvent::invoice_paid(&inv.id, "2", paid_at)).await.unwrap();
+        db.add_webhook_job(&inv.id, &WebhookEvent::invoice_paid(&inv.id, "2", paid_at)).await.unwrap();
+        assert_eq!(db.select_webhooks_job(10).await.unwrap().len(), 1);
+
+        // paid again after a reorg reverted the first payment
+        let repaid_at = paid_at + chrono::Duration::minutes(5);
+        db.add_webhook_job(&inv.id, &WebhookEvent::invoice_paid(&inv.id, "2", repaid_at)).await.unwrap();
+        let jobs = db.select_webhooks_job(10).await.unwrap(); // the first one is claimed already
+        assert_eq!(jobs.len(), 1);
+        assert!(matches!(jobs[0].payload.0, WebhookEvent::InvoicePaid { paid_at, .. } if paid_at == repaid_at));
+    }
+
+    #[tokio::test]
+    async fn test_min_amount_of_unknown_token_is_an_error() {
+        let db = MockDatabase::new();
+        let usdt = TokenConfig::builder().symbol("USDT").contract(ADDRESS).decimals(6).build().unwrap();
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .token(usdt)
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        db.set_token_min_amount("testnet", "USDT", Some(U256::from(1_000))).await.unwrap();
+        assert!(db.set_token_min_amount("testnet", "USDC", None).await.is_err());
+        assert!(db.set_token_min_amount("mainnet", "USDT", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_partial_chain_updates_bump_the_generation() {
+        let db = MockDatabase::new();
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+        let before = db.get_chain("testnet").await.unwrap().unwrap().config();
+
+        let update: PartialChainUpdate = serde_json::from_value(
+            serde_json::json!({ "record_unknown_transfers": true })).unwrap();
+        db.update_chain_partial("testnet", &update).await.unwrap();
+
+        let after = db.get_chain("testnet").await.unwrap().unwrap().config();
+        let generation = before.read().unwrap().generation();
+        assert!(generation > 0);
+        assert_eq!(after.read().unwrap().generation(), generation);
+        assert!(after.read().unwrap().record_unknown_transfers);
+    }
+
+    #[tokio::test]
+    async fn test_invoices_are_stored_with_their_contract() {
+        let db = MockDatabase::new();
+        let usdc = |contract: &str| TokenConfig::builder().symbol("USDC").contract(contract).decimals(6).build().unwrap();
+        let native = "0x3333333333333333333333333333333333333333";
+        let bridged = "0x4444444444444444444444444444444444444444";
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .token(usdc(native))
+            .token(usdc(bridged))
+            .build()
+            .unwrap();
+        db.add_chain(&chain).await.unwrap();
+
+        // both are "USDC", the contract decides which one the invoice is paid in
+        let paid_in_bridged = Invoice { token: "USDC".to_owned(), token_contract: bridged.to_uppercase().replace("0X", "0x"), ..Invoice::for_test() };
+        db.add_invoice(&paid_in_bridged).await.unwrap();
+        let stored = db.get_invoices_by_token(bridged).await.unwrap();
+        assert_eq!(stored.iter().map(|i| &i.id).collect::<Vec<_>>(), vec![&paid_in_bridged.id]);
+        assert!(db.get_invoices_by_token(native).await.unwrap().is_empty());
+
+        let eth = Invoice { token: "ETH".to_owned(), ..Invoice::for_test() };
+        db.add_invoice(&eth).await.unwrap();
+        assert_eq!(db.get_invoice(&eth.id).await.unwrap().unwrap().token_contract, NATIVE_CONTRACT);
+
+        let unknown = Invoice { token: "USDC".to_owned(), token_contract: ADDRESS.to_owned(), ..Invoice::for_test() };
+        assert!(db.add_invoice(&unknown).await.is_err());
+        assert!(db.add_invoice(&Invoice::for_test()).await.is_err()); // no USDT on testnet
+    }
+}
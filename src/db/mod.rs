@@ -1,15 +1,21 @@
 use crate::db::mock::MockDatabase;
 use crate::db::postgres::Postgres;
-use crate::model::{ChainConfig, TokenConfig, Invoice, InvoiceStatus, PartialChainUpdate, Payment, WebhookEvent, WebhookJob, WebhookStatus};
+use crate::secrets::SecretKey;
+use crate::model::{AccountSettingKind, AccountUsage, Quota, Role, ChainConfig, InvoiceAmountError, ChainError, FinalityMode, ChainStats, ChainStatsDelta, DeepLinkTemplate, OutboundFreeze, TokenConfig, Invoice, InvoiceFilter, InvoiceStatus, InvoiceTotals, Page, PageRequest, LatencyPercentiles, MisdirectedPayment, MisdirectedStatus, PartialChainUpdate, Payment, PaymentAnalytics, PendingWebhook, PoolMetrics, Refund, UnknownTransfer, WebhookDestination, WebhookEvent, WebhookJob, WebhookStatus, contract_key, NATIVE_CONTRACT};
 use alloy::primitives::U256;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
 use std::collections::HashMap;
 use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
-use sqlx::postgres::PgPoolOptions;
-use crate::chain::Blockchain;
+use std::time::Duration;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use crate::chain::{Blockchain, BlockchainAdapter};
 
 pub mod postgres;
 pub mod mock;
+mod trace;
 
 pub trait DatabaseAdapter: Send + Sync {
     // chain
@@ -18,14 +24,19 @@ pub trait DatabaseAdapter: Send + Sync {
     fn get_chain(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<Arc<Blockchain>>>> + Send;
     fn get_chain_by_id(&self, id: u32) -> impl Future<Output = anyhow::Result<Option<Arc<Blockchain>>>> + Send;
     fn add_chain(&self, chain_config: &ChainConfig) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn upsert_chain(&self, chain_config: &ChainConfig) -> impl Future<Output = anyhow::Result<bool>> + Send; // true if created
     fn update_chain_block(&self, chain_name: &str, block_num: u64) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn get_latest_block(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
-    fn get_chains_with_token(&self, token_symbol: &str) -> impl Future<Output = anyhow::Result<Vec<Arc<Blockchain>>>> + Send;
+    fn get_chains_with_token(&self, token_contract: &str) -> impl Future<Output = anyhow::Result<Vec<Arc<Blockchain>>>> + Send;
     fn remove_chain(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn remove_chain_by_id(&self, id: u32) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn chain_exists(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
     fn update_chain_partial(&self, chain_name: &str, chain_update: &PartialChainUpdate)
         -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn reload_chain(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<Arc<Blockchain>>>> + Send;
+    // the chain as stored right now, the cached Blockchain is left alone
+    fn get_stored_chain_config(&self, chain_name: &str)
+        -> impl Future<Output = anyhow::Result<Option<ChainConfig>>> + Send;
 
     fn get_watch_addresses(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<Vec<String>>>> + Send;
     fn remove_watch_address(&self, chain_name: &str, address: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
@@ -34,7 +45,7 @@ pub trait DatabaseAdapter: Send + Sync {
     fn add_watch_address(&self, chain_name: &str, address: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
 
     fn get_xpub(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
-    fn get_rpc_url(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
+    fn get_rpc_urls(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<Vec<String>>>> + Send;
     fn get_block_lag(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<u8>>> + Send;
 
     // token
@@ -49,77 +60,339 @@ pub trait DatabaseAdapter: Send + Sync {
     fn remove_token(&self, chain_name: &str, token_symbol: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn remove_token_by_id(&self, chain_name: &str, id: u32) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn add_token(&self, chain_name: &str, token_config: &TokenConfig) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn set_token_min_amount(&self, chain_name: &str, token_symbol: &str, min_amount: Option<U256>)
+        -> impl Future<Output = anyhow::Result<()>> + Send;
 
     // invoice
     fn get_invoices(&self) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
     fn get_invoices_by_chain(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
-    fn get_invoices_by_token(&self, token_symbol: &str) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
+    fn get_invoices_by_token(&self, token_contract: &str) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
+    fn get_invoices_by_tag(&self, tag: &str) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
+    // rows are pulled lazily, for exports that shouldn't hold the whole table in memory
+    fn stream_invoices<'a>(&'a self, filter: &InvoiceFilter) -> BoxStream<'a, anyhow::Result<Invoice>>;
+    // newest first
+    fn list_invoices(&self, filter: &InvoiceFilter, page: &PageRequest)
+        -> impl Future<Output = anyhow::Result<Page<Invoice>>> + Send;
     fn get_invoices_by_address(&self, address: &str) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
     fn get_invoice(&self, uuid: &str) -> impl Future<Output = anyhow::Result<Option<Invoice>>> + Send;
+    // ids that don't exist are left out
+    fn get_invoices_by_ids(&self, uuids: &[String]) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
     fn get_invoices_by_status(&self, status: InvoiceStatus) -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
     fn get_invoices_by_chain_and_status(&self, chain_name: &str, status: InvoiceStatus)
                                               -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
     fn get_invoices_by_address_and_status(&self, address: &str, status: InvoiceStatus)
                                               -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
-    fn get_busy_indexes(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Vec<u32>>> + Send;
+    // through payments and archived payments, one tx can pay several invoices
+    fn find_invoice_by_tx_hash(&self, chain_name: &str, tx_hash: &str)
+        -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
+    fn get_busy_indexes(&self, chain_name: &str, account_id: u32)
+        -> impl Future<Output = anyhow::Result<Vec<u32>>> + Send;
+    // reserves the lowest free address index for SLOT_RESERVATION_TTL or until add_invoice uses it.
+    // QuotaExceeded if one more invoice of the account doesn't fit `quota`, checked under the same lock
+    fn acquire_free_slot(&self, chain_name: &str, account_id: u32, quota: Option<&Quota>)
+        -> impl Future<Output = anyhow::Result<u32>> + Send;
     fn add_invoice(&self, invoice: &Invoice) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn set_invoice_status(&self, uuid: &str, status: InvoiceStatus) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn set_invoice_address(&self, uuid: &str, address: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn settle_invoice(&self, uuid: &str, paid_at: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<bool>> + Send; // false if it was already paid
+    fn expire_invoice(&self, uuid: &str)
+        -> impl Future<Output = anyhow::Result<bool>> + Send; // false if it wasn't pending
     // fn add_payment(&self, uuid: &str, amount_raw: U256) -> impl Future<Output = anyhow::Result<(U256, String)>> + Send; // (paid_raw, paid_human)
     fn get_pending_invoice_by_address(&self, chain_name: &str, address: &str)
         -> impl Future<Output = anyhow::Result<Option<Invoice>>> + Send;
     fn expire_old_invoices(&self)
         -> impl Future<Output = anyhow::Result<Vec<(String, String, String)>>> + Send; // (uuid, network, address)
+    // addresses of expired invoices whose grace period is over, each returned once
+    fn release_expired_addresses(&self)
+        -> impl Future<Output = anyhow::Result<Vec<(String, String)>>> + Send; // (network, address)
+    // back to Pending if it expired less than grace_period_secs ago, the grace is used up.
+    // never once the invoice was reissued, the payment goes unmatched instead
+    fn revive_invoice_in_grace(&self, chain_name: &str, address: &str)
+        -> impl Future<Output = anyhow::Result<Option<Invoice>>> + Send;
+    // Pending invoices inside their expiry_warning_secs window without a warning yet. marked
+    // once their InvoiceExpiringSoon is enqueued, so a failed enqueue is retried
+    fn get_expiring_invoices(&self)
+        -> impl Future<Output = anyhow::Result<Vec<(String, DateTime<Utc>)>>> + Send; // (uuid, expires_at)
+    fn mark_expiry_warned(&self, uuid: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+    // Pending invoices created at least `older_than` ago without a single detected payment and
+    // not marked yet. marked once their InvoiceStale is enqueued, so a failed enqueue is retried
+    fn get_stale_invoices(&self, older_than: Duration)
+        -> impl Future<Output = anyhow::Result<Vec<Invoice>>> + Send;
+    fn mark_invoice_stale(&self, uuid: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn is_invoice_expired(&self, uuid: &str) -> impl Future<Output = anyhow::Result<Option<bool>>> + Send;
     fn is_invoice_paid(&self, uuid: &str) -> impl Future<Output = anyhow::Result<Option<bool>>> + Send;
     fn is_invoice_pending(&self, uuid: &str) -> impl Future<Output = anyhow::Result<Option<bool>>> + Send;
     fn remove_invoice(&self, uuid: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
 
     // payments
-    fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str, tx_hash: &str,
-                           amount_raw: U256, block_number: u64, network: &str, log_index: Option<u64>)
+    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments)]
+    fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str, payer: Option<&str>,
+                           tx_hash: &str, amount_raw: U256, block_number: u64,
+                           block_timestamp: Option<DateTime<Utc>>, network: &str,
+                           log_index: Option<u64>)
         -> impl Future<Output = anyhow::Result<()>> + Send;
     fn get_confirming_payments(&self) -> impl Future<Output = anyhow::Result<Vec<Payment>>> + Send;
-    fn finalize_payment(&self, payment_id: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    fn get_payment(&self, payment_id: &str) -> impl Future<Output = anyhow::Result<Option<Payment>>> + Send;
+    // compare-and-set on PaymentStatus::Confirming, so the invoice is credited at most once
+    // (PaymentAlreadyFinalized otherwise). returns whether the invoice is now fully paid
+    fn finalize_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<bool>> + Send;
+    // PaymentStatus::Rejected, the invoice is not credited. compare-and-set on Confirming as
+    // well, returns false if the payment was already finalized
+    fn reject_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<bool>> + Send;
     fn update_payment_block(&self, payment_id: &str, block_num: u64) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn get_payment_analytics(&self, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<Vec<PaymentAnalytics>>> + Send;
+    // pending invoices of the merchant and the ones it created since `since`, for Quota
+    fn get_account_usage(&self, account_id: u32, since: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<AccountUsage>> + Send;
+    // the merchant's invoices paid or expired in [from, to), test mode invoices left out
+    fn get_invoice_totals(&self, account_id: u32, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<Vec<InvoiceTotals>>> + Send;
+    // once per invoice, the latency in ms or None if it was already recorded or the
+    // completing payment has no block timestamp
+    fn record_settlement_latency(&self, invoice_id: &str, delivered_at: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+    // over the InvoicePaid deliveries in [from, to)
+    fn get_latency_percentiles(&self, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<Vec<LatencyPercentiles>>> + Send;
+    fn archive_finalized_payments(&self, confirmed_before: DateTime<Utc>)
+        -> impl Future<Output = anyhow::Result<u64>> + Send; // number of archived payments
+
+    // unknown transfers
+    fn add_unknown_transfer(&self, transfer: &UnknownTransfer) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn get_unknown_transfers(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Vec<UnknownTransfer>>> + Send;
+
+    // misdirected payments, adding the same transfer twice is a no-op that returns false
+    fn add_misdirected_payment(&self, payment: &MisdirectedPayment) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    fn get_misdirected_payments(&self, invoice_id: &str) -> impl Future<Output = anyhow::Result<Vec<MisdirectedPayment>>> + Send;
+    fn set_misdirected_payment_status(&self, id: &str, status: MisdirectedStatus)
+        -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // refunds, only confirmed payments and never more than was received on the payment
+    // (RefundExceedsPayment). returns the new refunded_raw of the invoice
+    fn add_refund(&self, refund: &Refund) -> impl Future<Output = anyhow::Result<U256>> + Send;
+    fn get_refunds(&self, invoice_id: &str) -> impl Future<Output = anyhow::Result<Vec<Refund>>> + Send;
+
+    // chain errors, only the last CHAIN_ERRORS_CAP per chain are kept
+    fn add_chain_error(&self, error: &ChainError) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn get_chain_errors(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Vec<ChainError>>> + Send; // newest first
+
+    // chain stats
+    fn add_chain_stats(&self, chain_name: &str, delta: &ChainStatsDelta) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn get_chain_stats(&self, chain_name: &str) -> impl Future<Output = anyhow::Result<Option<ChainStats>>> + Send;
+
+    // checkout
+    fn get_deep_link_templates(&self) -> impl Future<Output = anyhow::Result<Vec<DeepLinkTemplate>>> + Send;
+    fn set_deep_link_template(&self, template: &DeepLinkTemplate) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn remove_deep_link_template(&self, wallet: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // outbound funds
+    fn get_outbound_freeze(&self) -> impl Future<Output = anyhow::Result<OutboundFreeze>> + Send;
+    fn set_outbound_freeze(&self, freeze: &OutboundFreeze) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // per-merchant settings, AppState keeps them in memory and writes through
+    fn get_account_settings(&self, kind: AccountSettingKind)
+        -> impl Future<Output = anyhow::Result<Vec<(u32, serde_json::Value)>>> + Send; // (account_id, value)
+    fn set_account_setting(&self, account_id: u32, kind: AccountSettingKind, value: Option<&serde_json::Value>)
+        -> impl Future<Output = anyhow::Result<()>> + Send; // None removes it
+
+    // API keys by sha256, see AppState::add_api_key
+    fn get_api_keys(&self) -> impl Future<Output = anyhow::Result<Vec<(String, Role)>>> + Send;
+    fn set_api_key(&self, key_hash: &str, role: Option<Role>) -> impl Future<Output = anyhow::Result<()>> + Send; // None removes it
 
     // webhooks
-    fn select_webhooks_job(&self) -> impl Future<Output = anyhow::Result<Vec<WebhookJob>>> + Send;
+    fn select_webhooks_job(&self, limit: u32) -> impl Future<Output = anyhow::Result<Vec<WebhookJob>>> + Send;
     fn set_webhook_status(&self, id: &str, status: WebhookStatus) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn schedule_webhook_retry(&self, id: &str, attempts: i32, next_retry_in_secs: f64) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn requeue_stuck_webhooks(&self, visibility_timeout: Duration) -> impl Future<Output = anyhow::Result<u64>> + Send;
     fn add_webhook_job(&self, invoice_id: &str, event: &WebhookEvent) -> impl Future<Output = anyhow::Result<()>> + Send;
+    // to the merchant instead of an invoice, false if the same event was queued before
+    fn add_account_webhook_job(&self, account_id: u32, url: &str, secret: &str, event: &WebhookEvent)
+        -> impl Future<Output = anyhow::Result<bool>> + Send;
+    fn get_pending_webhooks(&self) -> impl Future<Output = anyhow::Result<Vec<PendingWebhook>>> + Send; // pending and in flight
+    // failure streaks per url, see WebhookDestination. jobs queued for a suspended url start Suppressed
+    fn record_webhook_failure(&self, url: &str, error: &str) -> impl Future<Output = anyhow::Result<WebhookDestination>> + Send;
+    fn clear_webhook_failures(&self, url: &str) -> impl Future<Output = anyhow::Result<()>> + Send; // keeps a suspended url suspended
+    // suppresses the url's pending jobs and returns how many, None if it was suspended already
+    fn suspend_webhook_destination(&self, url: &str) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+    // None if it wasn't suspended. redeliver puts its suppressed jobs back to Pending and returns how many
+    fn resume_webhook_destination(&self, url: &str, redeliver: bool) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+    fn get_webhook_destinations(&self) -> impl Future<Output = anyhow::Result<Vec<WebhookDestination>>> + Send;
 
     // other
     fn get_token_decimals(&self, chain_name: &str, token_symbol: &str) -> impl Future<Output = anyhow::Result<Option<u8>>> + Send;
+    fn get_token_decimals_by_contract(&self, chain_name: &str, contract: &str) -> impl Future<Output = anyhow::Result<Option<u8>>> + Send; // NATIVE_CONTRACT for the native coin
 }
 
+pub const CHAIN_ERRORS_CAP: usize = 200;
+pub const SLOT_RESERVATION_TTL: Duration = Duration::from_secs(60);
+
+// what add_invoice checks against the invoice's chain, if that's known. returns the contract
+// key the invoice is stored with, so payments never have to be matched by symbol
+pub(crate) fn validate_invoice_chain(invoice: &Invoice, chain: &Blockchain) -> anyhow::Result<String> {
+    if chain.config().read().unwrap().test_mode != invoice.test_mode {
+        anyhow::bail!("invoice test_mode doesn't match chain '{}'", invoice.network);
+    }
+
+    let native_symbol = chain.config().read().unwrap().native_symbol.clone();
+    chain.capabilities().ensure_token(&invoice.token, &native_symbol)?;
+
+    // FinalizedTag applies to every invoice of its chain anyway, Checkpoint only to those asking for it
+    if invoice.finality_mode != FinalityMode::Confirmations
+        && chain.capabilities().finality_mode != invoice.finality_mode
+    {
+        anyhow::bail!("chain '{}' doesn't support {} finality", invoice.network, invoice.finality_mode);
+    }
+
+    // payouts go out on this chain, so each leg has to be an address it can send to
+    if let Some(share) = invoice.split_schedule.iter().flatten()
+        .find(|s| chain.normalize_address(&s.address).is_none())
+    {
+        anyhow::bail!("split payout address '{}' is not valid on chain '{}'", share.address, invoice.network);
+    }
+
+    // same as AppState::check_invoice_amount, the native coin has no minimum
+    if invoice.amount_raw.is_zero() {
+        return Err(InvoiceAmountError::Zero.into());
+    }
+    let contract = {
+        let config = chain.config();
+        let guard = config.read().unwrap();
+        match invoice.token_contract.is_empty() {
+            true => guard.token_contract(&invoice.token),
+            false => Some(contract_key(&invoice.token_contract))
+                .filter(|c| c == NATIVE_CONTRACT || guard.token_by_contract(c).is_some()),
+        }
+    };
+    let Some(contract) = contract else {
+        anyhow::bail!("token '{}' is not configured on chain '{}'", invoice.token, invoice.network);
+    };
+
+    let token = chain.config().read().unwrap().token_by_contract(&contract);
+    if let Some(token) = token {
+        token.check_min_amount(&invoice.network, invoice.amount_raw)?;
+    }
+
+    Ok(contract)
+}
+
+#[allow(clippy::large_enum_variant)] // constructed once per process
 pub enum Database {
     Mock(MockDatabase),
     Postgres(Postgres)
 }
 
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub statement_cache_capacity: usize,
+    pub replica_url: Option<String>, // read replica for heavy list/report queries, same pool settings
+}
+
+impl Default for PoolConfig {
+    // same as sqlx defaults
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            statement_cache_capacity: 100,
+            replica_url: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_connections == 0 {
+            anyhow::bail!("max_connections must be greater than 0");
+        }
+
+        if self.min_connections > self.max_connections {
+            anyhow::bail!("min_connections ({}) can't be greater than max_connections ({})",
+                self.min_connections, self.max_connections);
+        }
+
+        if self.acquire_timeout.is_zero() {
+            anyhow::bail!("acquire_timeout must be greater than 0");
+        }
+
+        Ok(())
+    }
+
+    fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+    }
+}
+
 impl Database {
     pub async fn init(
         database_url: &str,
-        max_connections: u32,
+        pool_config: &PoolConfig,
         db_type: &str
+    ) -> anyhow::Result<Self> {
+        Self::init_with_secret_key(database_url, pool_config, db_type, None).await
+    }
+
+    // the key encrypts stored credentials such as ChainConfig::rpc_auth
+    pub async fn init_with_secret_key(
+        database_url: &str,
+        pool_config: &PoolConfig,
+        db_type: &str,
+        secret_key: Option<SecretKey>,
     ) -> anyhow::Result<Self> {
         match db_type {
             "postgres" => {
-                let pool = PgPoolOptions::new()
-                    .max_connections(max_connections)
-                    .connect(&database_url)
+                pool_config.validate()?;
+
+                let connect_options = PgConnectOptions::from_str(database_url)?
+                    .statement_cache_capacity(pool_config.statement_cache_capacity);
+
+                let pool = pool_config.pool_options()
+                    .connect_with(connect_options)
                     .await?;
 
+                // lazy, so a replica that is down doesn't keep the primary from starting
+                let replica = pool_config.replica_url.as_deref()
+                    .map(|url| -> anyhow::Result<_> {
+                        let options = PgConnectOptions::from_str(url)?
+                            .statement_cache_capacity(pool_config.statement_cache_capacity);
+                        Ok(pool_config.pool_options().connect_lazy_with(options))
+                    })
+                    .transpose()?;
+
                 sqlx::migrate!("./migrations/postgres")
                     .run(&pool)
                     .await?;
 
-                Ok(Database::Postgres(Postgres::init(pool).await?))
+                Ok(Database::Postgres(Postgres::init(pool, secret_key).await?.with_replica(replica)))
             }
             "mock" => Ok(Database::Mock(MockDatabase::new())),
             _ => Err(anyhow::anyhow!("Unknown DB type"))
         }
     }
+
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        match self {
+            Database::Mock(_) => PoolMetrics::default(),
+            Database::Postgres(db) => db.pool_metrics(),
+        }
+    }
 }
 
 impl DatabaseAdapter for Database {
@@ -159,6 +432,13 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn upsert_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.upsert_chain(chain_config).await,
+            Database::Postgres(db) => db.upsert_chain(chain_config).await,
+        }
+    }
+
     async fn update_chain_block(&self, chain_name: &str, block_num: u64) -> anyhow::Result<()> {
         match self {
             Database::Mock(db) => db.update_chain_block(chain_name, block_num).await,
@@ -173,10 +453,10 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn get_chains_with_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
+    async fn get_chains_with_token(&self, token_contract: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
         match self {
-            Database::Mock(db) => db.get_chains_with_token(token_symbol).await,
-            Database::Postgres(db) => db.get_chains_with_token(token_symbol).await,
+            Database::Mock(db) => db.get_chains_with_token(token_contract).await,
+            Database::Postgres(db) => db.get_chains_with_token(token_contract).await,
         }
     }
 
@@ -208,6 +488,20 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn reload_chain(&self, chain_name: &str) -> anyhow::Result<Option<Arc<Blockchain>>> {
+        match self {
+            Database::Mock(db) => db.reload_chain(chain_name).await,
+            Database::Postgres(db) => db.reload_chain(chain_name).await,
+        }
+    }
+
+    async fn get_stored_chain_config(&self, chain_name: &str) -> anyhow::Result<Option<ChainConfig>> {
+        match self {
+            Database::Mock(db) => db.get_stored_chain_config(chain_name).await,
+            Database::Postgres(db) => db.get_stored_chain_config(chain_name).await,
+        }
+    }
+
     async fn get_watch_addresses(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
         match self {
             Database::Mock(db) => db.get_watch_addresses(chain_name).await,
@@ -243,10 +537,10 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn get_rpc_url(&self, chain_name: &str) -> anyhow::Result<Option<String>> {
+    async fn get_rpc_urls(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
         match self {
-            Database::Mock(db) => db.get_rpc_url(chain_name).await,
-            Database::Postgres(db) => db.get_rpc_url(chain_name).await,
+            Database::Mock(db) => db.get_rpc_urls(chain_name).await,
+            Database::Postgres(db) => db.get_rpc_urls(chain_name).await,
         }
     }
 
@@ -306,6 +600,14 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn set_token_min_amount(&self, chain_name: &str, token_symbol: &str,
+                                  min_amount: Option<U256>) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_token_min_amount(chain_name, token_symbol, min_amount).await,
+            Database::Postgres(db) => db.set_token_min_amount(chain_name, token_symbol, min_amount).await,
+        }
+    }
+
     async fn add_token(&self, chain_name: &str, token_config: &TokenConfig) -> anyhow::Result<()> {
         match self {
             Database::Mock(db) => db.add_token(chain_name, token_config).await,
@@ -327,10 +629,31 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn get_invoices_by_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Invoice>> {
+    async fn get_invoices_by_token(&self, token_contract: &str) -> anyhow::Result<Vec<Invoice>> {
+        match self {
+            Database::Mock(db) => db.get_invoices_by_token(token_contract).await,
+            Database::Postgres(db) => db.get_invoices_by_token(token_contract).await,
+        }
+    }
+
+    async fn get_invoices_by_tag(&self, tag: &str) -> anyhow::Result<Vec<Invoice>> {
+        match self {
+            Database::Mock(db) => db.get_invoices_by_tag(tag).await,
+            Database::Postgres(db) => db.get_invoices_by_tag(tag).await,
+        }
+    }
+
+    fn stream_invoices<'a>(&'a self, filter: &InvoiceFilter) -> BoxStream<'a, anyhow::Result<Invoice>> {
+        match self {
+            Database::Mock(db) => db.stream_invoices(filter),
+            Database::Postgres(db) => db.stream_invoices(filter),
+        }
+    }
+
+    async fn list_invoices(&self, filter: &InvoiceFilter, page: &PageRequest) -> anyhow::Result<Page<Invoice>> {
         match self {
-            Database::Mock(db) => db.get_invoices_by_token(token_symbol).await,
-            Database::Postgres(db) => db.get_invoices_by_token(token_symbol).await,
+            Database::Mock(db) => db.list_invoices(filter, page).await,
+            Database::Postgres(db) => db.list_invoices(filter, page).await,
         }
     }
 
@@ -348,6 +671,13 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn get_invoices_by_ids(&self, uuids: &[String]) -> anyhow::Result<Vec<Invoice>> {
+        match self {
+            Database::Mock(db) => db.get_invoices_by_ids(uuids).await,
+            Database::Postgres(db) => db.get_invoices_by_ids(uuids).await,
+        }
+    }
+
     async fn get_invoices_by_status(&self, status: InvoiceStatus) -> anyhow::Result<Vec<Invoice>> {
         match self {
             Database::Mock(db) => db.get_invoices_by_status(status).await,
@@ -369,10 +699,24 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn get_busy_indexes(&self, chain_name: &str) -> anyhow::Result<Vec<u32>> {
+    async fn find_invoice_by_tx_hash(&self, chain_name: &str, tx_hash: &str) -> anyhow::Result<Vec<Invoice>> {
         match self {
-            Database::Mock(db) => db.get_busy_indexes(chain_name).await,
-            Database::Postgres(db) => db.get_busy_indexes(chain_name).await,
+            Database::Mock(db) => db.find_invoice_by_tx_hash(chain_name, tx_hash).await,
+            Database::Postgres(db) => db.find_invoice_by_tx_hash(chain_name, tx_hash).await,
+        }
+    }
+
+    async fn get_busy_indexes(&self, chain_name: &str, account_id: u32) -> anyhow::Result<Vec<u32>> {
+        match self {
+            Database::Mock(db) => db.get_busy_indexes(chain_name, account_id).await,
+            Database::Postgres(db) => db.get_busy_indexes(chain_name, account_id).await,
+        }
+    }
+
+    async fn acquire_free_slot(&self, chain_name: &str, account_id: u32, quota: Option<&Quota>) -> anyhow::Result<u32> {
+        match self {
+            Database::Mock(db) => db.acquire_free_slot(chain_name, account_id, quota).await,
+            Database::Postgres(db) => db.acquire_free_slot(chain_name, account_id, quota).await,
         }
     }
 
@@ -390,6 +734,27 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn set_invoice_address(&self, uuid: &str, address: &str) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_invoice_address(uuid, address).await,
+            Database::Postgres(db) => db.set_invoice_address(uuid, address).await,
+        }
+    }
+
+    async fn settle_invoice(&self, uuid: &str, paid_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.settle_invoice(uuid, paid_at).await,
+            Database::Postgres(db) => db.settle_invoice(uuid, paid_at).await,
+        }
+    }
+
+    async fn expire_invoice(&self, uuid: &str) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.expire_invoice(uuid).await,
+            Database::Postgres(db) => db.expire_invoice(uuid).await,
+        }
+    }
+
     // async fn add_payment(&self, uuid: &str, amount_raw: U256) -> anyhow::Result<(U256, String)> {
     //     match self {
     //         Database::Mock(db) => db.add_payment(uuid, amount_raw).await,
@@ -411,6 +776,48 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn release_expired_addresses(&self) -> anyhow::Result<Vec<(String, String)>> {
+        match self {
+            Database::Mock(db) => db.release_expired_addresses().await,
+            Database::Postgres(db) => db.release_expired_addresses().await,
+        }
+    }
+
+    async fn revive_invoice_in_grace(&self, chain_name: &str, address: &str) -> anyhow::Result<Option<Invoice>> {
+        match self {
+            Database::Mock(db) => db.revive_invoice_in_grace(chain_name, address).await,
+            Database::Postgres(db) => db.revive_invoice_in_grace(chain_name, address).await,
+        }
+    }
+
+    async fn get_expiring_invoices(&self) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        match self {
+            Database::Mock(db) => db.get_expiring_invoices().await,
+            Database::Postgres(db) => db.get_expiring_invoices().await,
+        }
+    }
+
+    async fn mark_expiry_warned(&self, uuid: &str) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.mark_expiry_warned(uuid).await,
+            Database::Postgres(db) => db.mark_expiry_warned(uuid).await,
+        }
+    }
+
+    async fn get_stale_invoices(&self, older_than: Duration) -> anyhow::Result<Vec<Invoice>> {
+        match self {
+            Database::Mock(db) => db.get_stale_invoices(older_than).await,
+            Database::Postgres(db) => db.get_stale_invoices(older_than).await,
+        }
+    }
+
+    async fn mark_invoice_stale(&self, uuid: &str) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.mark_invoice_stale(uuid).await,
+            Database::Postgres(db) => db.mark_invoice_stale(uuid).await,
+        }
+    }
+
     async fn is_invoice_expired(&self, uuid: &str) -> anyhow::Result<Option<bool>> {
         match self {
             Database::Mock(db) => db.is_invoice_expired(uuid).await,
@@ -439,14 +846,17 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str, tx_hash: &str,
-                                 amount_raw: U256, block_number: u64, network: &str,
-                                 log_index: Option<u64>) -> anyhow::Result<()> {
+    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str,
+                                 payer: Option<&str>, tx_hash: &str, amount_raw: U256,
+                                 block_number: u64, block_timestamp: Option<DateTime<Utc>>,
+                                 network: &str, log_index: Option<u64>) -> anyhow::Result<()> {
         match self {
-            Database::Mock(db) => db.add_payment_attempt(invoice_id, from, to, tx_hash,
-                                                         amount_raw, block_number, network, log_index).await,
-            Database::Postgres(db) => db.add_payment_attempt(invoice_id, from, to, tx_hash,
-                                                             amount_raw, block_number, network, log_index).await,
+            Database::Mock(db) => db.add_payment_attempt(invoice_id, from, to, payer, tx_hash,
+                                                         amount_raw, block_number, block_timestamp,
+                                                         network, log_index).await,
+            Database::Postgres(db) => db.add_payment_attempt(invoice_id, from, to, payer, tx_hash,
+                                                             amount_raw, block_number, block_timestamp,
+                                                             network, log_index).await,
         }
     }
 
@@ -457,10 +867,24 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn finalize_payment(&self, payment_id: &str) -> anyhow::Result<bool> {
+    async fn get_payment(&self, payment_id: &str) -> anyhow::Result<Option<Payment>> {
         match self {
-            Database::Mock(db) => db.finalize_payment(payment_id).await,
-            Database::Postgres(db) => db.finalize_payment(payment_id).await,
+            Database::Mock(db) => db.get_payment(payment_id).await,
+            Database::Postgres(db) => db.get_payment(payment_id).await,
+        }
+    }
+
+    async fn finalize_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.finalize_payment(payment_id, confirmed_at).await,
+            Database::Postgres(db) => db.finalize_payment(payment_id, confirmed_at).await,
+        }
+    }
+
+    async fn reject_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.reject_payment(payment_id, confirmed_at).await,
+            Database::Postgres(db) => db.reject_payment(payment_id, confirmed_at).await,
         }
     }
 
@@ -471,10 +895,195 @@ impl DatabaseAdapter for Database {
         }
     }
 
-    async fn select_webhooks_job(&self) -> anyhow::Result<Vec<WebhookJob>> {
+    async fn get_payment_analytics(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<PaymentAnalytics>> {
+        match self {
+            Database::Mock(db) => db.get_payment_analytics(from, to).await,
+            Database::Postgres(db) => db.get_payment_analytics(from, to).await,
+        }
+    }
+
+    async fn get_account_usage(&self, account_id: u32, since: DateTime<Utc>) -> anyhow::Result<AccountUsage> {
+        match self {
+            Database::Mock(db) => db.get_account_usage(account_id, since).await,
+            Database::Postgres(db) => db.get_account_usage(account_id, since).await,
+        }
+    }
+
+    async fn get_invoice_totals(&self, account_id: u32, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> anyhow::Result<Vec<InvoiceTotals>>
+    {
+        match self {
+            Database::Mock(db) => db.get_invoice_totals(account_id, from, to).await,
+            Database::Postgres(db) => db.get_invoice_totals(account_id, from, to).await,
+        }
+    }
+
+    async fn record_settlement_latency(&self, invoice_id: &str, delivered_at: DateTime<Utc>) -> anyhow::Result<Option<u64>> {
+        match self {
+            Database::Mock(db) => db.record_settlement_latency(invoice_id, delivered_at).await,
+            Database::Postgres(db) => db.record_settlement_latency(invoice_id, delivered_at).await,
+        }
+    }
+
+    async fn get_latency_percentiles(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<LatencyPercentiles>> {
+        match self {
+            Database::Mock(db) => db.get_latency_percentiles(from, to).await,
+            Database::Postgres(db) => db.get_latency_percentiles(from, to).await,
+        }
+    }
+
+    async fn archive_finalized_payments(&self, confirmed_before: DateTime<Utc>) -> anyhow::Result<u64> {
+        match self {
+            Database::Mock(db) => db.archive_finalized_payments(confirmed_before).await,
+            Database::Postgres(db) => db.archive_finalized_payments(confirmed_before).await,
+        }
+    }
+
+    async fn add_unknown_transfer(&self, transfer: &UnknownTransfer) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.add_unknown_transfer(transfer).await,
+            Database::Postgres(db) => db.add_unknown_transfer(transfer).await,
+        }
+    }
+
+    async fn get_unknown_transfers(&self, chain_name: &str) -> anyhow::Result<Vec<UnknownTransfer>> {
+        match self {
+            Database::Mock(db) => db.get_unknown_transfers(chain_name).await,
+            Database::Postgres(db) => db.get_unknown_transfers(chain_name).await,
+        }
+    }
+
+    async fn add_misdirected_payment(&self, payment: &MisdirectedPayment) -> anyhow::Result<bool> {
+        match self {
+            Database::Mock(db) => db.add_misdirected_payment(payment).await,
+            Database::Postgres(db) => db.add_misdirected_payment(payment).await,
+        }
+    }
+
+    async fn get_misdirected_payments(&self, invoice_id: &str) -> anyhow::Result<Vec<MisdirectedPayment>> {
+        match self {
+            Database::Mock(db) => db.get_misdirected_payments(invoice_id).await,
+            Database::Postgres(db) => db.get_misdirected_payments(invoice_id).await,
+        }
+    }
+
+    async fn set_misdirected_payment_status(&self, id: &str, status: MisdirectedStatus) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_misdirected_payment_status(id, status).await,
+            Database::Postgres(db) => db.set_misdirected_payment_status(id, status).await,
+        }
+    }
+
+    async fn add_refund(&self, refund: &Refund) -> anyhow::Result<U256> {
+        match self {
+            Database::Mock(db) => db.add_refund(refund).await,
+            Database::Postgres(db) => db.add_refund(refund).await,
+        }
+    }
+
+    async fn get_refunds(&self, invoice_id: &str) -> anyhow::Result<Vec<Refund>> {
+        match self {
+            Database::Mock(db) => db.get_refunds(invoice_id).await,
+            Database::Postgres(db) => db.get_refunds(invoice_id).await,
+        }
+    }
+
+    async fn add_chain_error(&self, error: &ChainError) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.add_chain_error(error).await,
+            Database::Postgres(db) => db.add_chain_error(error).await,
+        }
+    }
+
+    async fn get_chain_errors(&self, chain_name: &str) -> anyhow::Result<Vec<ChainError>> {
+        match self {
+            Database::Mock(db) => db.get_chain_errors(chain_name).await,
+            Database::Postgres(db) => db.get_chain_errors(chain_name).await,
+        }
+    }
+
+    async fn add_chain_stats(&self, chain_name: &str, delta: &ChainStatsDelta) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.add_chain_stats(chain_name, delta).await,
+            Database::Postgres(db) => db.add_chain_stats(chain_name, delta).await,
+        }
+    }
+
+    async fn get_chain_stats(&self, chain_name: &str) -> anyhow::Result<Option<ChainStats>> {
+        match self {
+            Database::Mock(db) => db.get_chain_stats(chain_name).await,
+            Database::Postgres(db) => db.get_chain_stats(chain_name).await,
+        }
+    }
+
+    async fn get_deep_link_templates(&self) -> anyhow::Result<Vec<DeepLinkTemplate>> {
+        match self {
+            Database::Mock(db) => db.get_deep_link_templates().await,
+            Database::Postgres(db) => db.get_deep_link_templates().await,
+        }
+    }
+
+    async fn set_deep_link_template(&self, template: &DeepLinkTemplate) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_deep_link_template(template).await,
+            Database::Postgres(db) => db.set_deep_link_template(template).await,
+        }
+    }
+
+    async fn remove_deep_link_template(&self, wallet: &str) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.remove_deep_link_template(wallet).await,
+            Database::Postgres(db) => db.remove_deep_link_template(wallet).await,
+        }
+    }
+
+    async fn get_outbound_freeze(&self) -> anyhow::Result<OutboundFreeze> {
         match self {
-            Database::Mock(db) => db.select_webhooks_job().await,
-            Database::Postgres(db) => db.select_webhooks_job().await,
+            Database::Mock(db) => db.get_outbound_freeze().await,
+            Database::Postgres(db) => db.get_outbound_freeze().await,
+        }
+    }
+
+    async fn set_outbound_freeze(&self, freeze: &OutboundFreeze) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_outbound_freeze(freeze).await,
+            Database::Postgres(db) => db.set_outbound_freeze(freeze).await,
+        }
+    }
+
+    async fn get_account_settings(&self, kind: AccountSettingKind) -> anyhow::Result<Vec<(u32, serde_json::Value)>> {
+        match self {
+            Database::Mock(db) => db.get_account_settings(kind).await,
+            Database::Postgres(db) => db.get_account_settings(kind).await,
+        }
+    }
+
+    async fn set_account_setting(&self, account_id: u32, kind: AccountSettingKind,
+                                 value: Option<&serde_json::Value>) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_account_setting(account_id, kind, value).await,
+            Database::Postgres(db) => db.set_account_setting(account_id, kind, value).await,
+        }
+    }
+
+    async fn get_api_keys(&self) -> anyhow::Result<Vec<(String, Role)>> {
+        match self {
+            Database::Mock(db) => db.get_api_keys().await,
+            Database::Postgres(db) => db.get_api_keys().await,
+        }
+    }
+
+    async fn set_api_key(&self, key_hash: &str, role: Option<Role>) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.set_api_key(key_hash, role).await,
+            Database::Postgres(db) => db.set_api_key(key_hash, role).await,
+        }
+    }
+
+    async fn select_webhooks_job(&self, limit: u32) -> anyhow::Result<Vec<WebhookJob>> {
+        match self {
+            Database::Mock(db) => db.select_webhooks_job(limit).await,
+            Database::Postgres(db) => db.select_webhooks_job(limit).await,
         }
     }
 
@@ -492,6 +1101,13 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn requeue_stuck_webhooks(&self, visibility_timeout: Duration) -> anyhow::Result<u64> {
+        match self {
+            Database::Mock(db) => db.requeue_stuck_webhooks(visibility_timeout).await,
+            Database::Postgres(db) => db.requeue_stuck_webhooks(visibility_timeout).await,
+        }
+    }
+
     async fn add_webhook_job(&self, invoice_id: &str, event: &WebhookEvent) -> anyhow::Result<()> {
         match self {
             Database::Mock(db) => db.add_webhook_job(invoice_id, event).await,
@@ -499,10 +1115,68 @@ impl DatabaseAdapter for Database {
         }
     }
 
+    async fn add_account_webhook_job(&self, account_id: u32, url: &str, secret: &str, event: &WebhookEvent)
+        -> anyhow::Result<bool>
+    {
+        match self {
+            Database::Mock(db) => db.add_account_webhook_job(account_id, url, secret, event).await,
+            Database::Postgres(db) => db.add_account_webhook_job(account_id, url, secret, event).await,
+        }
+    }
+
+    async fn get_pending_webhooks(&self) -> anyhow::Result<Vec<PendingWebhook>> {
+        match self {
+            Database::Mock(db) => db.get_pending_webhooks().await,
+            Database::Postgres(db) => db.get_pending_webhooks().await,
+        }
+    }
+
+    async fn record_webhook_failure(&self, url: &str, error: &str) -> anyhow::Result<WebhookDestination> {
+        match self {
+            Database::Mock(db) => db.record_webhook_failure(url, error).await,
+            Database::Postgres(db) => db.record_webhook_failure(url, error).await,
+        }
+    }
+
+    async fn clear_webhook_failures(&self, url: &str) -> anyhow::Result<()> {
+        match self {
+            Database::Mock(db) => db.clear_webhook_failures(url).await,
+            Database::Postgres(db) => db.clear_webhook_failures(url).await,
+        }
+    }
+
+    async fn suspend_webhook_destination(&self, url: &str) -> anyhow::Result<Option<u64>> {
+        match self {
+            Database::Mock(db) => db.suspend_webhook_destination(url).await,
+            Database::Postgres(db) => db.suspend_webhook_destination(url).await,
+        }
+    }
+
+    async fn resume_webhook_destination(&self, url: &str, redeliver: bool) -> anyhow::Result<Option<u64>> {
+        match self {
+            Database::Mock(db) => db.resume_webhook_destination(url, redeliver).await,
+            Database::Postgres(db) => db.resume_webhook_destination(url, redeliver).await,
+        }
+    }
+
+    async fn get_webhook_destinations(&self) -> anyhow::Result<Vec<WebhookDestination>> {
+        match self {
+            Database::Mock(db) => db.get_webhook_destinations().await,
+            Database::Postgres(db) => db.get_webhook_destinations().await,
+        }
+    }
+
     async fn get_token_decimals(&self, chain_name: &str, token_symbol: &str) -> anyhow::Result<Option<u8>> {
         match self {
             Database::Mock(db) => db.get_token_decimals(chain_name, token_symbol).await,
             Database::Postgres(db) => db.get_token_decimals(chain_name, token_symbol).await,
         }
     }
+
+    async fn get_token_decimals_by_contract(&self, chain_name: &str, contract: &str) -> anyhow::Result<Option<u8>> {
+        match self {
+            Database::Mock(db) => db.get_token_decimals_by_contract(chain_name, contract).await,
+            Database::Postgres(db) => db.get_token_decimals_by_contract(chain_name, contract).await,
+        }
+    }
 }
\ No newline at end of file
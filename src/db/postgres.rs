@@ -1,25 +1,37 @@
 use crate::chain::{Blockchain, BlockchainAdapter};
-use crate::db::DatabaseAdapter;
-use crate::model::{ChainConfig, ChainType, Invoice, InvoiceStatus, PartialChainUpdate, Payment, PaymentStatus, TokenConfig, WebhookEvent, WebhookJob, WebhookStatus};
+use crate::db::trace::Traced;
+use crate::db::{validate_invoice_chain, DatabaseAdapter, CHAIN_ERRORS_CAP, SLOT_RESERVATION_TTL};
+use crate::model::{AccountSettingKind, AccountUsage, Quota, Role, AddressIndexExhausted, BlockTag, ChainConfig, InvoiceTotals, RpcAuth, ChainError, ChainStats, ChainStatsDelta, ChainVersionConflict, ChainErrorKind, ChainType, DeepLinkTemplate, OutboundFreeze, Invoice, InvoiceFilter, InvoiceStatus, Page, PageRequest, invoice_cursor, parse_invoice_cursor, LatencyPercentiles, MaintenanceWindow, MisdirectedPayment, MisdirectedStatus, PartialChainUpdate, Payment, PaymentAlreadyFinalized, PaymentAnalytics, PaymentStatus, PendingWebhook, PoolMetrics, Refund, RefundExceedsPayment, SplitShare, TokenConfig, TokenRef, UnknownTransfer, WebhookDestination, WebhookEvent, WebhookJob, WebhookStatus, MAX_NON_HARDENED_INDEX, NATIVE_CONTRACT, contract_key, validate_max_address_index, validate_rpc_urls, validate_expiry_warning, validate_split_schedule, validate_webhook_events};
+use crate::secrets::SecretKey;
 use alloy::primitives::utils::format_units;
 use alloy::primitives::U256;
-use sqlx::postgres::PgRow;
-use sqlx::types::BigDecimal;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::query::Query;
+use sqlx::types::{BigDecimal, Json};
 use sqlx::{PgPool, Row};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// how long the replica is skipped after it failed a query
+const REPLICA_RETRY_AFTER: Duration = Duration::from_secs(30);
 
 pub struct Postgres {
     pool: PgPool,
+    replica: Option<Replica>,
+    secret_key: Option<SecretKey>, // required once any chain has rpc_auth
 
     // cache
     chains_cache: RwLock<HashMap<String, Arc<Blockchain>>>, // key = chain name
-    token_decimals: RwLock<HashMap<String, HashMap<String, u8>>> // (chain_name, (token_symbol, decimals))
+    token_decimals: RwLock<HashMap<String, HashMap<String, u8>>> // (chain_name, (contract_key, decimals))
 }
 
 impl Postgres {
-    pub async fn init(pool: PgPool) -> anyhow::Result<Self> {
+    pub async fn init(pool: PgPool, secret_key: Option<SecretKey>) -> anyhow::Result<Self> {
         let mut chains_map: HashMap<String, Arc<Blockchain>> = HashMap::new();
         let mut decimals_map: HashMap<String, HashMap<String, u8>> = HashMap::new();
 
@@ -27,38 +39,26 @@ impl Postgres {
         let mut chain_id_to_name: HashMap<i32, String> = HashMap::new();
 
         for row in sqlx::query(
-            r#"SELECT id, name, rpc_url, chain_type, xpub, native_symbol, decimals,
-       last_processed_block, block_lag, required_confirmations FROM chains"#
+            r#"SELECT id, name, rpc_urls, chain_type, xpub, native_symbol, decimals,
+       last_processed_block, block_lag, required_confirmations, record_unknown_transfers,
+       resolve_smart_account_payers, rpc_auth, maintenance_windows, version, test_mode,
+       max_address_index, heimdall_url, block_tag, chain_id, account_xpubs
+       FROM chains"#
         )
             .fetch_all(&pool)
+            .traced("init")
             .await?
         {
             let id: i32 = row.get("id");
             let name: String = row.get("name");
 
-            let chain_str: String = row.get("chain_type");
-            let chain_type: ChainType = chain_str.parse()
-                .map_err(|e| anyhow::anyhow!("Invalid chain type: {}", e))?;
-
-            let config = ChainConfig {
-                name: name.clone(),
-                rpc_url: row.get("rpc_url"),
-                chain_type,
-                xpub: row.get("xpub"),
-                native_symbol: row.get("native_symbol"),
-                decimals: row.get::<i16, _>("decimals") as u8,
-                last_processed_block: row.get::<i64, _>("last_processed_block") as u64,
-                block_lag: row.get::<i16, _>("block_lag") as u8,
-                required_confirmations: row.get::<i64, _>("required_confirmations") as u64,
-                watch_addresses: Arc::new(RwLock::new(HashSet::new())),
-                tokens: Arc::new(RwLock::new(HashSet::new())),
-            };
+            let config = Self::map_row_to_chain_config(&row, secret_key.as_ref())?;
 
             // decimals for native token
             decimals_map
                 .entry(name.clone())
-                .or_insert_with(HashMap::new)
-                .insert(config.native_symbol.clone(), config.decimals);
+                .or_default()
+                .insert(NATIVE_CONTRACT.to_owned(), config.decimals);
 
             let blockchain = Blockchain::new(config)?;
 
@@ -67,9 +67,11 @@ impl Postgres {
         }
 
         for row in sqlx::query(
-            r#"SELECT chain_id, symbol, contract_address, decimals FROM tokens"#
+            r#"SELECT chain_id, symbol, contract_address, decimals, non_standard,
+                      min_amount::TEXT FROM tokens"#
         )
             .fetch_all(&pool)
+            .traced("init")
             .await?
         {
             let chain_id: i32 = row.get("chain_id");
@@ -82,27 +84,31 @@ impl Postgres {
             let blockchain = chains_map.get(chain_name).unwrap(); // scary!
 
             let symbol: String = row.get("symbol");
+            let contract: String = row.get("contract_address");
             let decimals = row.get::<i16, _>("decimals") as u8;
 
+            decimals_map
+                .entry(chain_name.clone())
+                .or_default()
+                .insert(contract_key(&contract), decimals);
+
             let token = TokenConfig {
-                symbol: symbol.clone(),
-                contract: row.get("contract_address"),
+                symbol,
+                contract,
                 decimals,
+                non_standard: row.get("non_standard"),
+                min_amount: Self::parse_min_amount(&row)?,
             };
 
             blockchain.config().read().unwrap()
-                .tokens.write().unwrap().insert(token);
-
-            decimals_map
-                .entry(chain_name.clone())
-                .or_insert_with(HashMap::new)
-                .insert(symbol, decimals);
+                .update_tokens(|tokens| tokens.insert(token));
         }
 
         for row in sqlx::query(
             r#"SELECT address, network FROM invoices WHERE status = 'Pending'"#
         )
             .fetch_all(&pool)
+            .traced("init")
             .await?
         {
             let network: String = row.get("network");
@@ -110,7 +116,7 @@ impl Postgres {
 
             if let Some(blockchain) = chains_map.get(&network) {
                 blockchain.config().read().unwrap()
-                    .watch_addresses.write().unwrap().insert(address);
+                    .update_watch_addresses(|addrs| addrs.insert(address));
             }
         }
         
@@ -118,15 +124,194 @@ impl Postgres {
             "UPDATE webhooks SET status = 'Pending' WHERE status = 'Processing'"
         )
             .execute(&pool)
+            .traced("init")
             .await?;
 
         Ok(Self {
             pool,
+            replica: None,
+            secret_key,
             chains_cache: RwLock::new(chains_map),
             token_decimals: RwLock::new(decimals_map)
         })
     }
 
+    fn map_row_to_chain_config(
+        row: &PgRow,
+        secret_key: Option<&SecretKey>,
+    ) -> anyhow::Result<ChainConfig> {
+        let chain_str: String = row.get("chain_type");
+        let chain_type: ChainType = chain_str.parse()
+            .map_err(|e| anyhow::anyhow!("Invalid chain type: {}", e))?;
+        let block_tag = row.get::<Option<String>, _>("block_tag")
+            .map(|s| s.parse::<BlockTag>()
+                .map_err(|e| anyhow::anyhow!("Invalid block tag: {}", e)))
+            .transpose()?;
+
+        let rpc_auth = match row.get::<Option<String>, _>("rpc_auth") {
+            Some(sealed) => {
+                let name: String = row.get("name");
+                let key = secret_key.ok_or_else(|| anyhow::anyhow!(
+                    "chain '{}' has rpc_auth but no secret key is configured", name))?;
+                serde_json::from_slice(&key.open(&sealed)?)?
+            }
+            None => RpcAuth::None,
+        };
+
+        Ok(ChainConfig {
+            name: row.get("name"),
+            rpc_urls: row.get("rpc_urls"),
+            chain_type,
+            xpub: row.get("xpub"),
+            native_symbol: row.get("native_symbol"),
+            decimals: row.get::<i16, _>("decimals") as u8,
+            last_processed_block: row.get::<i64, _>("last_processed_block") as u64,
+            block_lag: row.get::<i16, _>("block_lag") as u8,
+            required_confirmations: row.get::<i64, _>("required_confirmations") as u64,
+            record_unknown_transfers: row.get("record_unknown_transfers"),
+            resolve_smart_account_payers: row.get("resolve_smart_account_payers"),
+            rpc_auth,
+            maintenance_windows: row.get::<Json<Vec<MaintenanceWindow>>, _>("maintenance_windows").0,
+            version: row.get::<i64, _>("version") as u64,
+            test_mode: row.get("test_mode"),
+            max_address_index: row.get::<i64, _>("max_address_index") as u32,
+            heimdall_url: row.get("heimdall_url"),
+            block_tag,
+            chain_id: row.get::<Option<i64>, _>("chain_id").map(|id| id as u64),
+            account_xpubs: row.get::<Json<BTreeMap<u32, String>>, _>("account_xpubs").0,
+            watch_addresses: Arc::new(RwLock::new(HashSet::new())),
+            tokens: Arc::new(RwLock::new(HashSet::new())),
+            generation: Default::default(),
+        })
+    }
+
+    // patches the cached chain with a partial update written as `new_version`, false when the
+    // cache isn't at the version right before it and has to be reloaded instead
+    fn apply_chain_update(&self, chain_name: &str, chain_update: &PartialChainUpdate, new_version: u64)
+                          -> anyhow::Result<bool>
+    {
+        let mut guard = self.chains_cache.write().unwrap();
+        let Some(blockchain) = guard.get(chain_name) else {
+            return Ok(false);
+        };
+
+        let config_lock = blockchain.config();
+        let mut chain_config = config_lock.read().unwrap().clone();
+
+        if chain_config.version + 1 != new_version {
+            return Ok(false);
+        }
+        chain_config.version = new_version;
+
+        if let Some(xpub) = &chain_update.xpub {
+            chain_config.xpub = xpub.to_owned();
+        }
+
+        if let Some(rpc_urls) = &chain_update.rpc_urls {
+            chain_config.rpc_urls = rpc_urls.to_owned();
+        }
+
+        if let Some(last_processed_block) = chain_update.last_processed_block {
+            chain_config.last_processed_block = last_processed_block;
+        }
+
+        if let Some(block_lag) = chain_update.block_lag {
+            chain_config.block_lag = block_lag;
+        }
+
+        if let Some(required_confirmations) = chain_update.required_confirmations {
+            chain_config.required_confirmations = required_confirmations;
+        }
+
+        if let Some(record_unknown_transfers) = chain_update.record_unknown_transfers {
+            chain_config.record_unknown_transfers = record_unknown_transfers;
+        }
+
+        if let Some(resolve) = chain_update.resolve_smart_account_payers {
+            chain_config.resolve_smart_account_payers = resolve;
+        }
+
+        if let Some(rpc_auth) = &chain_update.rpc_auth {
+            chain_config.rpc_auth = rpc_auth.clone();
+        }
+
+        if let Some(maintenance_windows) = &chain_update.maintenance_windows {
+            chain_config.maintenance_windows = maintenance_windows.clone();
+        }
+
+        if let Some(max_index) = chain_update.max_address_index {
+            chain_config.max_address_index = max_index;
+        }
+
+        let new_blockchain = Arc::new(Blockchain::new(chain_config)?);
+        // clones share the counter, so listeners still on the replaced config re-snapshot too
+        new_blockchain.config().read().unwrap().bump_generation();
+
+        guard.insert(chain_name.to_owned(), new_blockchain);
+
+        Ok(true)
+    }
+
+    // NULL for RpcAuth::None, so chains without credentials don't need a secret key
+    fn seal_rpc_auth(&self, auth: &RpcAuth) -> anyhow::Result<Option<String>> {
+        if auth.is_none() {
+            return Ok(None);
+        }
+
+        let key = self.secret_key.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("rpc_auth can't be stored without a secret key"))?;
+        Ok(Some(key.seal(&serde_json::to_vec(auth)?)?))
+    }
+
+    // see AccountUsage, live address reservations count as pending invoices created now
+    async fn account_usage<'e>(executor: impl sqlx::PgExecutor<'e>, account_id: u32, since: DateTime<Utc>)
+        -> anyhow::Result<AccountUsage>
+    {
+        let row = sqlx::query(
+            r#"WITH counted AS (
+                   SELECT network, status = 'Pending' AS pending, created_at >= $2 AS recent
+                   FROM invoices
+                   WHERE account_id = $1 AND (status = 'Pending' OR created_at >= $2)
+                   UNION ALL
+                   SELECT network, TRUE, TRUE FROM address_reservations
+                   WHERE account_id = $1 AND reserved_until > NOW()
+               )
+               SELECT COUNT(*) FILTER (WHERE pending) AS pending_invoices,
+                      COUNT(*) FILTER (WHERE recent) AS invoices_last_day,
+                      COALESCE(ARRAY_AGG(DISTINCT network) FILTER (WHERE pending), '{}')
+                          AS pending_networks
+                   FROM counted"#
+        )
+            .bind(account_id as i32)
+            .bind(since)
+            .fetch_one(executor)
+            .traced("get_account_usage")
+            .await?;
+
+        Ok(AccountUsage {
+            pending_invoices: row.get::<i64, _>("pending_invoices") as u64,
+            invoices_last_day: row.get::<i64, _>("invoices_last_day") as u64,
+            pending_networks: row.get("pending_networks"),
+        })
+    }
+
+    fn parse_min_amount(row: &PgRow) -> anyhow::Result<Option<U256>> {
+        row.get::<Option<String>, _>("min_amount")
+            .map(|s| U256::from_str(&s)
+                .map_err(|e| anyhow::anyhow!("Failed to parse min_amount: {}", e)))
+            .transpose()
+    }
+
+    fn map_row_to_webhook_destination(row: PgRow) -> WebhookDestination {
+        WebhookDestination {
+            url: row.get("url"),
+            failure_streak: row.get::<i32, _>("failure_streak") as u32,
+            failing_since: row.get("failing_since"),
+            last_error: row.get("last_error"),
+            suspended_at: row.get("suspended_at"),
+        }
+    }
+
     fn map_row_to_invoice(
         row: PgRow
     ) -> anyhow::Result<Invoice> {
@@ -145,6 +330,8 @@ impl Postgres {
             .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?;
         let paid_raw = U256::from_str(&paid_str)
             .map_err(|e| anyhow::anyhow!("Failed to parse paid_raw: {}", e))?;
+        let refunded_raw = U256::from_str(row.get::<&str, _>("refunded_raw"))
+            .map_err(|e| anyhow::anyhow!("Failed to parse refunded_raw: {}", e))?;
 
         let network: String = row.get("network");
         let token: String = row.get("token");
@@ -157,11 +344,14 @@ impl Postgres {
         Ok(Invoice {
             id: row.get::<uuid::Uuid, _>("id").to_string(),
             address: row.get("address"),
-            address_index: row.get::<i32, _>("address_index") as u32,
+            account_id: row.get::<i32, _>("account_id") as u32,
+            address_index: row.get::<i64, _>("address_index") as u32,
             network,
             token,
+            token_contract: row.get("token_contract"),
             amount_raw,
             paid_raw,
+            refunded_raw,
             amount: amount_human,
             paid: paid_human,
             status,
@@ -170,6 +360,21 @@ impl Postgres {
             webhook_secret: row.get("webhook_secret"),
             created_at: row.get("created_at"),
             expires_at: row.get("expires_at"),
+            paid_at: row.get("paid_at"),
+            expiry_warning_secs: row.get::<Option<i64>, _>("expiry_warning_secs").map(|x| x as u64),
+            grace_period_secs: row.get::<Option<i64>, _>("grace_period_secs").map(|x| x as u64),
+            deadline_policy: row.get::<&str, _>("deadline_policy").parse()
+                .map_err(|e| anyhow::anyhow!("Invalid deadline policy: {}", e))?,
+            finality_mode: row.get::<&str, _>("finality_mode").parse()
+                .map_err(|e| anyhow::anyhow!("Invalid finality mode: {}", e))?,
+            test_mode: row.get("test_mode"),
+            split_schedule: row.get::<Option<Json<Vec<SplitShare>>>, _>("split_schedule")
+                .map(|j| j.0),
+            locale: row.get("locale"),
+            display_currency: row.get("display_currency"),
+            tags: row.get("tags"),
+            webhook_events: row.get("webhook_events"),
+            reissued_from: row.get::<Option<uuid::Uuid>, _>("reissued_from").map(|u| u.to_string()),
         })
     }
 
@@ -180,6 +385,7 @@ impl Postgres {
         let status = match status_str.as_str() {
             "Confirming" => PaymentStatus::Confirming,
             "Confirmed" => PaymentStatus::Confirmed,
+            "Rejected" => PaymentStatus::Rejected,
             _ => anyhow::bail!("Unknown payment status in DB: {}", status_str),
         };
 
@@ -192,13 +398,16 @@ impl Postgres {
             invoice_id: row.get::<uuid::Uuid, _>("invoice_id").to_string(),
             from: row.get("from"),
             to: row.get("to"),
+            payer: row.get("payer"),
             network: row.get("network"),
             tx_hash: row.get("tx_hash"),
             amount_raw,
             block_number: row.get::<i64, _>("block_number") as u64,
+            block_timestamp: row.get("block_timestamp"),
             status,
             created_at: row.get("created_at"),
-            log_index: row.get::<i64, _>("log_index") as u64,
+            confirmed_at: row.get("confirmed_at"),
+            log_index: u64::try_from(row.get::<i64, _>("log_index")).ok(), // -1 for native transfers
         })
     }
 }
@@ -220,6 +429,7 @@ impl DatabaseAdapter for Postgres {
         let row = sqlx::query("SELECT name FROM chains WHERE id = $1")
             .bind(id as i32)
             .fetch_optional(&self.pool)
+            .traced("get_chain_by_id")
             .await?;
 
         if let Some(r) = row {
@@ -232,12 +442,14 @@ impl DatabaseAdapter for Postgres {
 
     async fn add_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<()> {
         sqlx::query(
-            r#"INSERT INTO chains (name, rpc_url, chain_type, xpub, native_symbol, decimals,
-                    last_processed_block, block_lag, required_confirmations)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            r#"INSERT INTO chains (name, rpc_urls, chain_type, xpub, native_symbol, decimals,
+                    last_processed_block, block_lag, required_confirmations,
+                    record_unknown_transfers, maintenance_windows, resolve_smart_account_payers,
+                    rpc_auth, test_mode, max_address_index, heimdall_url, block_tag, chain_id, account_xpubs)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
         )
             .bind(&chain_config.name)
-            .bind(&chain_config.rpc_url)
+            .bind(&chain_config.rpc_urls)
             .bind(chain_config.chain_type.to_string())
             .bind(&chain_config.xpub)
             .bind(&chain_config.native_symbol)
@@ -245,24 +457,96 @@ impl DatabaseAdapter for Postgres {
             .bind(chain_config.last_processed_block as i64)
             .bind(chain_config.block_lag as i16)
             .bind(chain_config.required_confirmations as i64)
+            .bind(chain_config.record_unknown_transfers)
+            .bind(Json(&chain_config.maintenance_windows))
+            .bind(chain_config.resolve_smart_account_payers)
+            .bind(self.seal_rpc_auth(&chain_config.rpc_auth)?)
+            .bind(chain_config.test_mode)
+            .bind(chain_config.max_address_index as i64)
+            .bind(&chain_config.heimdall_url)
+            .bind(chain_config.block_tag.map(|t| t.to_string()))
+            .bind(chain_config.chain_id.map(|id| id as i64))
+            .bind(Json(&chain_config.account_xpubs))
             .execute(&self.pool)
+            .traced("add_chain")
             .await?;
 
         let blockchain = Blockchain::new(chain_config.clone())?;
 
         self.chains_cache.write().unwrap().insert(chain_config.name.clone(), Arc::new(blockchain));
 
-        self._insert_token_decimals(&chain_config.name, &chain_config.native_symbol,
-                                    chain_config.decimals)?;
+        self._insert_token_decimals(&chain_config.name, NATIVE_CONTRACT, chain_config.decimals)?;
 
         Ok(())
     }
 
+    async fn upsert_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<bool> {
+        // last_processed_block is only used for new rows, an existing chain keeps its progress
+        let row = sqlx::query(
+            r#"INSERT INTO chains (name, rpc_urls, chain_type, xpub, native_symbol, decimals,
+                    last_processed_block, block_lag, required_confirmations,
+                    record_unknown_transfers, maintenance_windows, resolve_smart_account_payers,
+                    rpc_auth, test_mode, max_address_index, heimdall_url, block_tag, chain_id, account_xpubs)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
+                    ON CONFLICT (name) DO UPDATE SET
+                        rpc_urls = excluded.rpc_urls,
+                        xpub = excluded.xpub,
+                        block_lag = excluded.block_lag,
+                        required_confirmations = excluded.required_confirmations,
+                        record_unknown_transfers = excluded.record_unknown_transfers,
+                        maintenance_windows = excluded.maintenance_windows,
+                        resolve_smart_account_payers = excluded.resolve_smart_account_payers,
+                        rpc_auth = excluded.rpc_auth,
+                        max_address_index = excluded.max_address_index,
+                        heimdall_url = excluded.heimdall_url,
+                        block_tag = excluded.block_tag,
+                        chain_id = excluded.chain_id,
+                        account_xpubs = excluded.account_xpubs
+                    WHERE chains.chain_type = excluded.chain_type
+                        AND chains.native_symbol = excluded.native_symbol
+                        AND chains.decimals = excluded.decimals
+                        AND chains.test_mode = excluded.test_mode
+                    RETURNING (xmax = 0) AS inserted"#,
+        )
+            .bind(&chain_config.name)
+            .bind(&chain_config.rpc_urls)
+            .bind(chain_config.chain_type.to_string())
+            .bind(&chain_config.xpub)
+            .bind(&chain_config.native_symbol)
+            .bind(chain_config.decimals as i16)
+            .bind(chain_config.last_processed_block as i64)
+            .bind(chain_config.block_lag as i16)
+            .bind(chain_config.required_confirmations as i64)
+            .bind(chain_config.record_unknown_transfers)
+            .bind(Json(&chain_config.maintenance_windows))
+            .bind(chain_config.resolve_smart_account_payers)
+            .bind(self.seal_rpc_auth(&chain_config.rpc_auth)?)
+            .bind(chain_config.test_mode)
+            .bind(chain_config.max_address_index as i64)
+            .bind(&chain_config.heimdall_url)
+            .bind(chain_config.block_tag.map(|t| t.to_string()))
+            .bind(chain_config.chain_id.map(|id| id as i64))
+            .bind(Json(&chain_config.account_xpubs))
+            .fetch_optional(&self.pool)
+            .traced("upsert_chain")
+            .await?;
+
+        let Some(row) = row else {
+            anyhow::bail!("chain '{}' already exists with a different type, native symbol, \
+                decimals or test mode", chain_config.name);
+        };
+
+        self.reload_chain(&chain_config.name).await?;
+
+        Ok(row.get("inserted"))
+    }
+
     async fn update_chain_block(&self, chain_name: &str, block_num: u64) -> anyhow::Result<()> {
         sqlx::query("UPDATE chains SET last_processed_block = $1 WHERE name = $2")
             .bind(block_num as i64)
             .bind(chain_name)
             .execute(&self.pool)
+            .traced("update_chain_block")
             .await?;
 
         Ok(())
@@ -273,16 +557,16 @@ impl DatabaseAdapter for Postgres {
             .map(|c| c.config().read().unwrap().last_processed_block))
     }
 
-    async fn get_chains_with_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
+    async fn get_chains_with_token(&self, token_contract: &str) -> anyhow::Result<Vec<Arc<Blockchain>>> {
         let guard = self.chains_cache.read().unwrap();
+        let key = contract_key(token_contract);
 
         let result = guard.values()
             .filter(|c| {
-                if c.config().read().unwrap()
-                    .native_symbol == token_symbol { return true; }
+                if key == NATIVE_CONTRACT { return true; }
                 c.config().read().unwrap()
                     .tokens.read().unwrap().iter()
-                    .any(|c| c.symbol == token_symbol)
+                    .any(|t| contract_key(&t.contract) == key)
             })
             .cloned()
             .collect();
@@ -294,6 +578,7 @@ impl DatabaseAdapter for Postgres {
         let result = sqlx::query("DELETE FROM chains WHERE name = $1")
             .bind(chain_name)
             .execute(&self.pool)
+            .traced("remove_chain")
             .await?;
 
         if result.rows_affected() > 0 {
@@ -310,6 +595,7 @@ impl DatabaseAdapter for Postgres {
         )
             .bind(id as i32)
             .fetch_optional(&self.pool)
+            .traced("remove_chain_by_id")
             .await?;
 
         if let Some(name) = name_opt {
@@ -327,56 +613,155 @@ impl DatabaseAdapter for Postgres {
     async fn update_chain_partial(&self, chain_name: &str, chain_update: &PartialChainUpdate)
                                   -> anyhow::Result<()>
     {
-        sqlx::query(
+        for window in chain_update.maintenance_windows.iter().flatten() {
+            window.validate()?;
+        }
+
+        if let Some(max_index) = chain_update.max_address_index {
+            validate_max_address_index(max_index)?;
+        }
+
+        if let Some(rpc_urls) = &chain_update.rpc_urls {
+            let chain_type = self.chains_cache.read().unwrap().get(chain_name)
+                .ok_or_else(|| anyhow::anyhow!("chain '{}' does not exist", chain_name))?
+                .config().read().unwrap().chain_type;
+            validate_rpc_urls(rpc_urls, chain_type)?;
+        }
+
+        let new_version: Option<i64> = sqlx::query_scalar(
             r#"UPDATE chains SET
-                       rpc_url = COALESCE($1, rpc_url),
+                       rpc_urls = COALESCE($1, rpc_urls),
                        last_processed_block = COALESCE($2, last_processed_block),
                        xpub = COALESCE($3, xpub),
                        block_lag = COALESCE($4, block_lag),
-                       required_confirmations = COALESCE($5, required_confirmations)
-                   WHERE name = $6"#
+                       required_confirmations = COALESCE($5, required_confirmations),
+                       record_unknown_transfers = COALESCE($6, record_unknown_transfers),
+                       maintenance_windows = COALESCE($7, maintenance_windows),
+                       resolve_smart_account_payers = COALESCE($10, resolve_smart_account_payers),
+                       rpc_auth = CASE WHEN $11 THEN $12 ELSE rpc_auth END,
+                       max_address_index = COALESCE($13, max_address_index),
+                       version = version + 1
+                   WHERE name = $8 AND ($9::BIGINT IS NULL OR version = $9)
+                   RETURNING version"#
         )
-            .bind(chain_update.rpc_url.to_owned())
+            .bind(chain_update.rpc_urls.to_owned())
             .bind(chain_update.last_processed_block.map(|x| x as i64))
             .bind(chain_update.xpub.to_owned())
             .bind(chain_update.block_lag.map(|x| x as i16))
             .bind(chain_update.required_confirmations.map(|x| x as i16))
+            .bind(chain_update.record_unknown_transfers)
+            .bind(chain_update.maintenance_windows.as_ref().map(Json))
             .bind(chain_name)
-            .execute(&self.pool)
+            .bind(chain_update.expected_version.map(|v| v as i64))
+            .bind(chain_update.resolve_smart_account_payers)
+            .bind(chain_update.rpc_auth.is_some())
+            .bind(chain_update.rpc_auth.as_ref().map(|a| self.seal_rpc_auth(a)).transpose()?.flatten())
+            .bind(chain_update.max_address_index.map(|x| x as i64))
+            .fetch_optional(&self.pool)
+            .traced("update_chain_partial")
             .await?;
 
-        let mut guard = self.chains_cache.write().unwrap();
-        let blockchain = guard.get(chain_name)
-            .ok_or_else(|| anyhow::anyhow!("chain '{}' does not exist", chain_name))?;
-
-        let config_lock = blockchain.config();
-        let mut chain_config = config_lock.read().unwrap().clone();
+        let Some(new_version) = new_version.map(|v| v as u64) else {
+            match chain_update.expected_version {
+                Some(expected) if self.chain_exists(chain_name).await? => {
+                    return Err(ChainVersionConflict {
+                        chain: chain_name.to_owned(),
+                        expected,
+                    }.into());
+                }
+                _ => anyhow::bail!("chain '{}' does not exist", chain_name),
+            }
+        };
 
-        if let Some(xpub) = &chain_update.xpub {
-            chain_config.xpub = xpub.to_owned();
+        if !self.apply_chain_update(chain_name, chain_update, new_version)? {
+            // the cache missed a write, by another instance or a racing update, or already
+            // holds a newer one built without ours. the row has every change
+            self.reload_chain(chain_name).await?;
         }
 
-        if let Some(rpc_url) = &chain_update.rpc_url {
-            chain_config.rpc_url = rpc_url.to_owned();
-        }
+        Ok(())
+    }
 
-        if let Some(last_processed_block) = chain_update.last_processed_block {
-            chain_config.last_processed_block = last_processed_block;
-        }
+    async fn reload_chain(&self, chain_name: &str) -> anyhow::Result<Option<Arc<Blockchain>>> {
+        let row = sqlx::query(
+            r#"SELECT id, name, rpc_urls, chain_type, xpub, native_symbol, decimals,
+                       last_processed_block, block_lag, required_confirmations,
+                       record_unknown_transfers, resolve_smart_account_payers, rpc_auth,
+                       maintenance_windows, version, test_mode, max_address_index, heimdall_url, block_tag, chain_id,
+                       account_xpubs
+                   FROM chains WHERE name = $1"#
+        )
+            .bind(chain_name)
+            .fetch_optional(&self.pool)
+            .traced("reload_chain")
+            .await?;
 
-        if let Some(block_lag) = chain_update.block_lag {
-            chain_config.block_lag = block_lag;
-        }
+        let Some(row) = row else {
+            self.chains_cache.write().unwrap().remove(chain_name);
+            self.token_decimals.write().unwrap().remove(chain_name);
+            return Ok(None);
+        };
 
-        if let Some(required_confirmations) = chain_update.required_confirmations {
-            chain_config.required_confirmations = required_confirmations;
+        let chain_id: i32 = row.get("id");
+        let config = Self::map_row_to_chain_config(&row, self.secret_key.as_ref())?;
+
+        let mut decimals: HashMap<String, u8> = HashMap::new();
+        decimals.insert(NATIVE_CONTRACT.to_owned(), config.decimals);
+
+        for row in sqlx::query(
+            "SELECT symbol, contract_address, decimals, non_standard, min_amount::TEXT
+                 FROM tokens WHERE chain_id = $1"
+        )
+            .bind(chain_id)
+            .fetch_all(&self.pool)
+            .traced("reload_chain")
+            .await?
+        {
+            let token = TokenConfig {
+                symbol: row.get("symbol"),
+                contract: row.get("contract_address"),
+                decimals: row.get::<i16, _>("decimals") as u8,
+                non_standard: row.get("non_standard"),
+                min_amount: Self::parse_min_amount(&row)?,
+            };
+
+            decimals.insert(contract_key(&token.contract), token.decimals);
+            config.update_tokens(|tokens| tokens.insert(token));
         }
 
-        let new_blockchain = Arc::new(Blockchain::new(chain_config)?);
+        let addresses: Vec<String> = sqlx::query_scalar(
+            "SELECT address FROM invoices WHERE network = $1 AND status = 'Pending'"
+        )
+            .bind(chain_name)
+            .fetch_all(&self.pool)
+            .traced("reload_chain")
+            .await?;
 
-        guard.insert(chain_name.to_owned(), new_blockchain);
+        config.update_watch_addresses(|addrs| addrs.extend(addresses));
 
-        Ok(())
+        let blockchain = Arc::new(Blockchain::new(config)?);
+
+        self.chains_cache.write().unwrap().insert(chain_name.to_owned(), blockchain.clone());
+        self.token_decimals.write().unwrap().insert(chain_name.to_owned(), decimals);
+
+        Ok(Some(blockchain))
+    }
+
+    async fn get_stored_chain_config(&self, chain_name: &str) -> anyhow::Result<Option<ChainConfig>> {
+        let row = sqlx::query(
+            r#"SELECT id, name, rpc_urls, chain_type, xpub, native_symbol, decimals,
+                       last_processed_block, block_lag, required_confirmations,
+                       record_unknown_transfers, resolve_smart_account_payers, rpc_auth,
+                       maintenance_windows, version, test_mode, max_address_index, heimdall_url, block_tag, chain_id,
+                       account_xpubs
+                   FROM chains WHERE name = $1"#
+        )
+            .bind(chain_name)
+            .fetch_optional(&self.pool)
+            .traced("get_stored_chain_config")
+            .await?;
+
+        row.map(|row| Self::map_row_to_chain_config(&row, self.secret_key.as_ref())).transpose()
     }
 
     async fn get_watch_addresses(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
@@ -388,10 +773,24 @@ impl DatabaseAdapter for Postgres {
     }
 
     async fn remove_watch_address(&self, chain_name: &str, address: &str) -> anyhow::Result<()> {
+        let still_needed: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM invoices
+                   WHERE network = $1 AND address = $2 AND status = 'Pending')"#
+        )
+            .bind(chain_name)
+            .bind(address)
+            .fetch_one(&self.pool)
+            .traced("remove_watch_address")
+            .await?;
+
+        if still_needed {
+            return Ok(());
+        }
+
         match self.chains_cache.read().unwrap().get(chain_name) {
             Some(c) => {
                 c.config().read().unwrap()
-                    .watch_addresses.write().unwrap().remove(address);
+                    .update_watch_addresses(|addrs| addrs.remove(address));
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name),
         }
@@ -404,15 +803,26 @@ impl DatabaseAdapter for Postgres {
         chain_name: &str,
         addresses: &[String]
     ) -> anyhow::Result<()> {
+        let still_needed: HashSet<String> = sqlx::query_scalar(
+            r#"SELECT DISTINCT address FROM invoices
+                   WHERE network = $1 AND address = ANY($2) AND status = 'Pending'"#
+        )
+            .bind(chain_name)
+            .bind(addresses)
+            .fetch_all(&self.pool)
+            .traced("remove_watch_addresses_bulk")
+            .await?
+            .into_iter()
+            .collect();
+
         match self.chains_cache.read().unwrap().get(chain_name) {
             Some(c) => {
                 let config_lock = c.config();
-                let guard = config_lock.read().unwrap();
-                let mut watch_addresses = guard.watch_addresses.write().unwrap();
-
-                for addr in addresses {
-                    watch_addresses.remove::<String>(addr);
-                }
+                config_lock.read().unwrap().update_watch_addresses(|watch_addresses| {
+                    for addr in addresses.iter().filter(|a| !still_needed.contains(*a)) {
+                        watch_addresses.remove::<String>(addr);
+                    }
+                });
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name)
         }
@@ -424,7 +834,7 @@ impl DatabaseAdapter for Postgres {
         match self.chains_cache.read().unwrap().get(chain_name) {
             Some(c) => {
                 c.config().read().unwrap()
-                    .watch_addresses.write().unwrap().insert(address.to_owned());
+                    .update_watch_addresses(|addrs| addrs.insert(address.to_owned()));
             }
             None => anyhow::bail!("chain '{}' does not exist", chain_name),
         }
@@ -437,10 +847,10 @@ impl DatabaseAdapter for Postgres {
             .map(|c| c.config().read().unwrap().xpub.clone()))
     }
 
-    async fn get_rpc_url(&self, chain_name: &str) -> anyhow::Result<Option<String>> {
+    async fn get_rpc_urls(&self, chain_name: &str) -> anyhow::Result<Option<Vec<String>>> {
         Ok(self.chains_cache.read().unwrap().get(chain_name)
             .map(|c| c.config().read().unwrap()
-                .rpc_url.clone()))
+                .rpc_urls.clone()))
     }
 
     async fn get_block_lag(&self, chain_name: &str) -> anyhow::Result<Option<u8>> {
@@ -481,20 +891,24 @@ impl DatabaseAdapter for Postgres {
         -> anyhow::Result<Option<TokenConfig>>
     {
         let row = sqlx::query(
-            r#"SELECT symbol, contract_address, tokens.decimals FROM tokens
+            r#"SELECT symbol, contract_address, tokens.decimals, non_standard, min_amount::TEXT
+                   FROM tokens
                    JOIN chains ON tokens.chain_id = chains.id
                    WHERE chains.name = $1 AND tokens.id = $2"#
         )
             .bind(chain_name)
             .bind(id as i32)
             .fetch_optional(&self.pool)
+            .traced("get_token_by_id")
             .await?;
 
         if let Some(r) = row {
             Ok(Some(TokenConfig {
                 symbol: r.get("symbol"),
                 contract: r.get("contract_address"),
-                decimals: r.get::<i16, _>("decimals") as u8
+                decimals: r.get::<i16, _>("decimals") as u8,
+                non_standard: r.get("non_standard"),
+                min_amount: Self::parse_min_amount(&r)?,
             }))
         } else { Ok(None) }
     }
@@ -505,7 +919,7 @@ impl DatabaseAdapter for Postgres {
         match self.chains_cache.read().unwrap().get(chain_name) {
             Some(c) => Ok(c.config().read().unwrap()
                 .tokens.read().unwrap().iter()
-                .find(|tc| tc.contract == contract_address)
+                .find(|tc| contract_key(&tc.contract) == contract_key(contract_address))
                 .cloned()),
             None => Ok(None),
         }
@@ -519,41 +933,76 @@ impl DatabaseAdapter for Postgres {
             .bind(token_symbol)
             .bind(chain_name)
             .execute(&self.pool)
+            .traced("remove_token")
             .await?;
 
         if let Some(c) = self.chains_cache.read().unwrap().get(chain_name) {
+            let contract = c.config().read().unwrap().token_contract(token_symbol);
             c.config().read().unwrap()
-                .tokens.write().unwrap().retain(|t| t.symbol != token_symbol);
-        }
+                .update_tokens(|tokens| tokens.retain(|t| t.symbol != token_symbol));
 
-        if let Some(chain_decimals) = self.token_decimals.write().unwrap()
-            .get_mut(chain_name)
-        {
-            chain_decimals.remove(token_symbol);
+            if let Some(contract) = contract {
+                self._remove_token_decimals(chain_name, &contract);
+            }
         }
 
         Ok(())
     }
 
     async fn remove_token_by_id(&self, chain_name: &str, id: u32) -> anyhow::Result<()> {
-        let symbol_opt: Option<String> = sqlx::query_scalar(
-            "DELETE FROM tokens WHERE id = &1 RETURNING symbol"
+        let row = sqlx::query(
+            "DELETE FROM tokens WHERE id = $1 RETURNING symbol, contract_address"
         )
             .bind(id as i32)
             .fetch_optional(&self.pool)
+            .traced("remove_token_by_id")
             .await?;
 
-        if let Some(symbol) = symbol_opt {
+        if let Some(row) = row {
+            let symbol: String = row.get("symbol");
+            let contract: String = row.get("contract_address");
+
             if let Some(c) = self.chains_cache.read().unwrap().get(chain_name) {
                 c.config().read().unwrap()
-                    .tokens.write().unwrap().retain(|t| t.symbol != symbol);
+                    .update_tokens(|tokens| tokens.retain(|t| t.symbol != symbol));
             }
 
-            if let Some(chain_decimals) = self.token_decimals.write().unwrap()
-                .get_mut(chain_name)
-            {
-                chain_decimals.remove(&symbol);
-            }
+            self._remove_token_decimals(chain_name, &contract);
+        }
+
+        Ok(())
+    }
+
+    async fn set_token_min_amount(&self, chain_name: &str, token_symbol: &str,
+                                  min_amount: Option<U256>) -> anyhow::Result<()> {
+        let min_amount_bd = min_amount
+            .map(|a| BigDecimal::from_str(&a.to_string()))
+            .transpose()?;
+
+        let result = sqlx::query(
+            r#"UPDATE tokens SET min_amount = $1
+                   WHERE symbol = $2 AND chain_id = (SELECT id FROM chains WHERE name = $3)"#
+        )
+            .bind(min_amount_bd)
+            .bind(token_symbol)
+            .bind(chain_name)
+            .execute(&self.pool)
+            .traced("set_token_min_amount")
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Token {} not found on chain {}", token_symbol, chain_name)
+        }
+
+        if let Some(c) = self.chains_cache.read().unwrap().get(chain_name) {
+            let config = c.config();
+            config.read().unwrap().update_tokens(|tokens| {
+                if let Some(mut token) = tokens.iter().find(|t| t.symbol == token_symbol).cloned() {
+                    tokens.remove(&token);
+                    token.min_amount = min_amount;
+                    tokens.insert(token);
+                }
+            });
         }
 
         Ok(())
@@ -563,38 +1012,51 @@ impl DatabaseAdapter for Postgres {
         let chain_id: i32 = sqlx::query_scalar("SELECT id FROM chains WHERE name = $1")
             .bind(chain_name)
             .fetch_one(&self.pool)
+            .traced("add_token")
             .await
             .map_err(|_| anyhow::anyhow!("Chain {} not found in DB", chain_name))?;
 
+        if let Some(c) = self.chains_cache.read().unwrap().get(chain_name) {
+            c.config().read().unwrap().check_token_collision(token_config)?;
+        }
+
+        let min_amount_bd = token_config.min_amount
+            .map(|a| BigDecimal::from_str(&a.to_string()))
+            .transpose()?;
+
         sqlx::query(
-            r#"INSERT INTO tokens (chain_id, symbol, contract_address, decimals)
-                   VALUES ($1, $2, $3, $4)"#
+            r#"INSERT INTO tokens (chain_id, symbol, contract_address, decimals, non_standard,
+                                   min_amount)
+                   VALUES ($1, $2, $3, $4, $5, $6)"#
         )
             .bind(chain_id)
             .bind(&token_config.symbol)
             .bind(&token_config.contract)
             .bind(token_config.decimals as i16)
+            .bind(token_config.non_standard)
+            .bind(min_amount_bd)
             .execute(&self.pool)
+            .traced("add_token")
             .await?;
 
         if let Some(c) = self.chains_cache.read().unwrap().get(chain_name) {
             c.config().read().unwrap()
-                .tokens.write().unwrap().insert(token_config.clone());
+                .update_tokens(|tokens| tokens.insert(token_config.clone()));
         }
-        self._insert_token_decimals(chain_name, &token_config.symbol, token_config.decimals)?;
+        self._insert_token_decimals(chain_name, &token_config.contract, token_config.decimals)?;
 
         Ok(())
     }
 
     async fn get_invoices(&self) -> anyhow::Result<Vec<Invoice>> {
-        let rows = sqlx::query(
+        let rows = self.fetch_all_read("get_invoices", || sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices"#
-        )
-            .fetch_all(&self.pool)
-            .await?;
+        )).await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
     }
@@ -602,40 +1064,156 @@ impl DatabaseAdapter for Postgres {
     async fn get_invoices_by_chain(&self, chain_name: &str) -> anyhow::Result<Vec<Invoice>> {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE network = $1"#
         )
             .bind(chain_name)
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_chain")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
     }
 
-    async fn get_invoices_by_token(&self, token_symbol: &str) -> anyhow::Result<Vec<Invoice>> {
+    async fn get_invoices_by_token(&self, token_contract: &str) -> anyhow::Result<Vec<Invoice>> {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
-                   FROM invoices WHERE token = $1"#
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices WHERE token_contract = $1"#
         )
-            .bind(token_symbol)
+            .bind(contract_key(token_contract))
+            .fetch_all(&self.pool)
+            .traced("get_invoices_by_token")
+            .await?;
+
+        rows.into_iter().map(Self::map_row_to_invoice).collect()
+    }
+
+    async fn get_invoices_by_tag(&self, tag: &str) -> anyhow::Result<Vec<Invoice>> {
+        let rows = sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices WHERE tags @> ARRAY[$1]::TEXT[]"#
+        )
+            .bind(tag)
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_tag")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
     }
 
+    fn stream_invoices<'a>(&'a self, filter: &InvoiceFilter) -> BoxStream<'a, anyhow::Result<Invoice>> {
+        sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices
+                   WHERE ($1::TEXT IS NULL OR network = $1)
+                     AND ($2::TEXT IS NULL OR status = $2)
+                     AND ($3::TEXT IS NULL OR tags @> ARRAY[$3]::TEXT[])
+                     AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+                     AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)
+                   ORDER BY created_at"#
+        )
+            .bind(filter.network.clone())
+            .bind(filter.status.map(|s| s.to_string()))
+            .bind(filter.tag.clone())
+            .bind(filter.created_from)
+            .bind(filter.created_to)
+            .fetch(&self.pool)
+            .map(|row| Self::map_row_to_invoice(row?))
+            .boxed()
+    }
+
+    async fn list_invoices(&self, filter: &InvoiceFilter, page: &PageRequest) -> anyhow::Result<Page<Invoice>> {
+        let after = page.cursor.as_deref().map(parse_invoice_cursor).transpose()?;
+        let after_id = after.as_ref().map(|(_, id)| uuid::Uuid::parse_str(id)).transpose()?;
+        let limit = page.limit();
+        let status = filter.status.map(|s| s.to_string());
+
+        let rows = self.fetch_all_read("list_invoices", || sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices
+                   WHERE ($1::TEXT IS NULL OR network = $1)
+                     AND ($2::TEXT IS NULL OR status = $2)
+                     AND ($3::TEXT IS NULL OR tags @> ARRAY[$3]::TEXT[])
+                     AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+                     AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)
+                     AND ($6::TIMESTAMPTZ IS NULL OR (created_at, id) < ($6, $7::UUID))
+                   ORDER BY created_at DESC, id DESC
+                   LIMIT $8"#
+        )
+            .bind(filter.network.clone())
+            .bind(status.clone())
+            .bind(filter.tag.clone())
+            .bind(filter.created_from)
+            .bind(filter.created_to)
+            .bind(after.as_ref().map(|(at, _)| *at))
+            .bind(after_id)
+            .bind(limit as i64 + 1)
+        ).await?;
+
+        let mut items = rows.into_iter().map(Self::map_row_to_invoice).collect::<anyhow::Result<Vec<_>>>()?;
+        let next_cursor = match items.len() > limit as usize {
+            true => {
+                items.truncate(limit as usize);
+                items.last().map(invoice_cursor)
+            }
+            false => None,
+        };
+
+        let total = match page.with_total {
+            true => {
+                let rows = self.fetch_all_read("list_invoices_count", || sqlx::query(
+                    r#"SELECT COUNT(*) AS count FROM invoices
+                       WHERE ($1::TEXT IS NULL OR network = $1)
+                         AND ($2::TEXT IS NULL OR status = $2)
+                         AND ($3::TEXT IS NULL OR tags @> ARRAY[$3]::TEXT[])
+                         AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+                         AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)"#
+                )
+                    .bind(filter.network.clone())
+                    .bind(status.clone())
+                    .bind(filter.tag.clone())
+                    .bind(filter.created_from)
+                    .bind(filter.created_to)
+                ).await?;
+                rows.first().map(|row| row.get::<i64, _>("count") as u64)
+            }
+            false => None,
+        };
+
+        Ok(Page { items, next_cursor, total })
+    }
+
     async fn get_invoices_by_address(&self, address: &str) -> anyhow::Result<Vec<Invoice>> {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE address = $1"#
         )
             .bind(address)
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_address")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
@@ -646,12 +1224,15 @@ impl DatabaseAdapter for Postgres {
 
         let row = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE id = $1"#
         )
             .bind(uuid_parsed)
             .fetch_optional(&self.pool)
+            .traced("get_invoice")
             .await?;
 
         match row {
@@ -660,15 +1241,39 @@ impl DatabaseAdapter for Postgres {
         }
     }
 
+    async fn get_invoices_by_ids(&self, uuids: &[String]) -> anyhow::Result<Vec<Invoice>> {
+        let uuids = uuids.iter()
+            .map(|id| uuid::Uuid::parse_str(id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let rows = sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices WHERE id = ANY($1)"#
+        )
+            .bind(uuids)
+            .fetch_all(&self.pool)
+            .traced("get_invoices_by_ids")
+            .await?;
+
+        rows.into_iter().map(Self::map_row_to_invoice).collect()
+    }
+
     async fn get_invoices_by_status(&self, status: InvoiceStatus) -> anyhow::Result<Vec<Invoice>> {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE status = $1"#
         )
             .bind(status.to_string())
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_status")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
@@ -679,13 +1284,16 @@ impl DatabaseAdapter for Postgres {
     {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE network = $1 AND status = $2"#
         )
             .bind(chain_name)
             .bind(status.to_string())
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_chain_and_status")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
@@ -696,45 +1304,172 @@ impl DatabaseAdapter for Postgres {
     {
         let rows = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, webhook_url, webhook_secret, created_at, expires_at
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE address = $1 AND status = $1"#
         )
             .bind(address)
             .bind(status.to_string())
             .fetch_all(&self.pool)
+            .traced("get_invoices_by_address_and_status")
             .await?;
 
         rows.into_iter().map(Self::map_row_to_invoice).collect()
     }
 
-    async fn get_busy_indexes(&self, chain_name: &str) -> anyhow::Result<Vec<u32>> {
+    // both lookups use the (tx_hash, log_index, network) idempotency indexes
+    async fn find_invoice_by_tx_hash(&self, chain_name: &str, tx_hash: &str) -> anyhow::Result<Vec<Invoice>> {
+        let rows = self.fetch_all_read("find_invoice_by_tx_hash", || sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices
+                   WHERE id IN (
+                       SELECT invoice_id FROM payments WHERE tx_hash = $1 AND network = $2
+                       UNION
+                       SELECT invoice_id FROM payments_archive WHERE tx_hash = $1 AND network = $2
+                   )
+                   ORDER BY created_at"#
+        )
+            .bind(tx_hash)
+            .bind(chain_name)
+        ).await?;
+
+        rows.into_iter().map(Self::map_row_to_invoice).collect()
+    }
+
+    async fn get_busy_indexes(&self, chain_name: &str, account_id: u32) -> anyhow::Result<Vec<u32>> {
         let rows = sqlx::query(
-            "SELECT address_index FROM invoices WHERE network = $1 AND status = 'Pending'"
+            r#"SELECT address_index FROM invoices
+               WHERE network = $1 AND account_id = $2
+                   AND (status = 'Pending' OR (status = 'Expired' AND NOT watch_released))"#
         )
             .bind(chain_name)
+            .bind(account_id as i32)
             .fetch_all(&self.pool)
+            .traced("get_busy_indexes")
             .await?;
 
         Ok(rows.iter()
-            .map(|r| r.get::<i32, _>("address_index") as u32)
+            .map(|r| r.get::<i64, _>("address_index") as u32)
             .collect())
     }
 
-    async fn add_invoice(&self, invoice: &Invoice) -> anyhow::Result<()> {
+    async fn acquire_free_slot(&self, chain_name: &str, account_id: u32, quota: Option<&Quota>)
+        -> anyhow::Result<u32>
+    {
+        let max_index = self.chains_cache.read().unwrap().get(chain_name)
+            .map(|chain| chain.config().read().unwrap().max_address_index)
+            .unwrap_or(MAX_NON_HARDENED_INDEX);
+
+        let mut tx = self.pool.begin().await?;
+
+        // the quota spans all chains, so acquisitions of the account on any chain wait for this one.
+        // always taken before the per chain lock below
+        if let Some(quota) = quota {
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtext('account_quota:' || $1))")
+                .bind(account_id.to_string())
+                .execute(&mut *tx)
+                .traced("acquire_free_slot")
+                .await?;
+
+            let usage = Self::account_usage(&mut *tx, account_id, Utc::now() - chrono::Duration::days(1)).await?;
+            quota.check(account_id, chain_name, &usage)?;
+        }
+
+        // serializes concurrent acquisitions on the same account until commit
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('address_slot:' || $1 || ':' || $2))")
+            .bind(chain_name)
+            .bind(account_id.to_string())
+            .execute(&mut *tx)
+            .traced("acquire_free_slot")
+            .await?;
+
+        let slot: Option<i64> = sqlx::query_scalar(
+            r#"WITH busy AS (
+                   SELECT address_index FROM invoices
+                   WHERE network = $1 AND account_id = $3
+                       AND (status = 'Pending' OR (status = 'Expired' AND NOT watch_released))
+                   UNION
+                   SELECT address_index FROM address_reservations
+                   WHERE network = $1 AND account_id = $3 AND reserved_until > NOW()
+               )
+               INSERT INTO address_reservations (network, account_id, address_index, reserved_until)
+               SELECT $1, $3, MIN(candidate), NOW() + (interval '1 second' * $2)
+               FROM (
+                   SELECT 0 AS candidate
+                   UNION ALL
+                   SELECT address_index + 1 FROM busy
+               ) c
+               WHERE candidate NOT IN (SELECT address_index FROM busy) AND candidate <= $4
+               HAVING MIN(candidate) IS NOT NULL
+               ON CONFLICT (network, account_id, address_index)
+               DO UPDATE SET reserved_until = excluded.reserved_until
+               RETURNING address_index"#
+        )
+            .bind(chain_name)
+            .bind(SLOT_RESERVATION_TTL.as_secs_f64())
+            .bind(account_id as i32)
+            .bind(max_index as i64)
+            .fetch_optional(&mut *tx)
+            .traced("acquire_free_slot")
+            .await?;
+
+        tx.commit().await?;
+
+        let Some(slot) = slot else {
+            return Err(AddressIndexExhausted {
+                chain: chain_name.to_owned(),
+                account_id,
+                max_index,
+            }.into());
+        };
+
+        Ok(slot as u32)
+    }
+
+    async fn add_invoice(&self, invoice: &Invoice) -> anyhow::Result<()> {
+        if let Some(schedule) = &invoice.split_schedule {
+            validate_split_schedule(schedule)?;
+        }
+
+        if let Some(events) = &invoice.webhook_events {
+            validate_webhook_events(events)?;
+        }
+
+        if let Some(secs) = invoice.expiry_warning_secs {
+            validate_expiry_warning(secs)?;
+        }
+
+        let token_contract = match self.chains_cache.read().unwrap().get(&invoice.network) {
+            Some(chain) => validate_invoice_chain(invoice, chain)?,
+            None => contract_key(&invoice.token_contract),
+        };
+
         let uuid = uuid::Uuid::parse_str(&invoice.id)?;
+        let reissued_from = invoice.reissued_from.as_deref()
+            .map(uuid::Uuid::parse_str)
+            .transpose()?;
         let amount_bd = BigDecimal::from_str(&invoice.amount_raw.to_string())?;
         let paid_bd = BigDecimal::from_str(&invoice.paid_raw.to_string())?;
 
         sqlx::query(
             r#"INSERT INTO invoices
                    (id, address, address_index, network, token, amount_raw, paid_raw, status,
-                    created_at, expires_at, decimals, webhook_url, webhook_secret)
-                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"#
+                    created_at, expires_at, decimals, webhook_url, webhook_secret,
+                    expiry_warning_secs, split_schedule, locale, display_currency, tags,
+                    webhook_events, account_id, reissued_from, token_contract, grace_period_secs,
+                    deadline_policy, test_mode, finality_mode)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
+                           $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26)"#
         )
             .bind(uuid)
             .bind(&invoice.address)
-            .bind(invoice.address_index as i32)
+            .bind(invoice.address_index as i64)
             .bind(&invoice.network)
             .bind(&invoice.token)
             .bind(&amount_bd)
@@ -745,7 +1480,32 @@ impl DatabaseAdapter for Postgres {
             .bind(invoice.decimals as i16)
             .bind(&invoice.webhook_url)
             .bind(&invoice.webhook_secret)
+            .bind(invoice.expiry_warning_secs.map(|x| x as i64))
+            .bind(invoice.split_schedule.as_ref().map(Json))
+            .bind(&invoice.locale)
+            .bind(&invoice.display_currency)
+            .bind(&invoice.tags)
+            .bind(&invoice.webhook_events)
+            .bind(invoice.account_id as i32)
+            .bind(reissued_from)
+            .bind(token_contract)
+            .bind(invoice.grace_period_secs.map(|x| x as i64))
+            .bind(invoice.deadline_policy.to_string())
+            .bind(invoice.test_mode)
+            .bind(invoice.finality_mode.to_string())
+            .execute(&self.pool)
+            .traced("add_invoice")
+            .await?;
+
+        sqlx::query(
+            r#"DELETE FROM address_reservations
+               WHERE network = $1 AND account_id = $2 AND address_index = $3"#
+        )
+            .bind(&invoice.network)
+            .bind(invoice.account_id as i32)
+            .bind(invoice.address_index as i64)
             .execute(&self.pool)
+            .traced("add_invoice")
             .await?;
 
         Ok(())
@@ -758,6 +1518,24 @@ impl DatabaseAdapter for Postgres {
             .bind(status.to_string())
             .bind(uuid_parsed)
             .execute(&self.pool)
+            .traced("set_invoice_status")
+            .await?;
+
+        if result.rows_affected() == 0 {
+            anyhow::bail!("Invoice {} not found", uuid)
+        }
+
+        Ok(())
+    }
+
+    async fn set_invoice_address(&self, uuid: &str, address: &str) -> anyhow::Result<()> {
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
+
+        let result = sqlx::query("UPDATE invoices SET address = $1 WHERE id = $2")
+            .bind(address)
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("set_invoice_address")
             .await?;
 
         if result.rows_affected() == 0 {
@@ -767,8 +1545,40 @@ impl DatabaseAdapter for Postgres {
         Ok(())
     }
 
+    async fn settle_invoice(&self, uuid: &str, paid_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
+
+        let result = sqlx::query(
+            r#"UPDATE invoices SET status = 'Paid', paid_at = $1
+                   WHERE id = $2 AND status <> 'Paid'"#
+        )
+            .bind(paid_at)
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("settle_invoice")
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn expire_invoice(&self, uuid: &str) -> anyhow::Result<bool> {
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
+
+        let result = sqlx::query(
+            // the caller stops watching right away, there is no grace period
+            r#"UPDATE invoices SET status = 'Expired', watch_released = TRUE
+                   WHERE id = $1 AND status = 'Pending'"#
+        )
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("expire_invoice")
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     // async fn add_payment(&self, uuid: &str, amount_raw: U256) -> anyhow::Result<(U256, String)> {
-    //     let uuid_parsed = uuid::Uuid::parse_str(&uuid)?;
+    //     let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
     //     let added_amount_bd = BigDecimal::from_str(&amount_raw.to_string())?;
     //
     //     let row = sqlx::query(
@@ -801,13 +1611,16 @@ impl DatabaseAdapter for Postgres {
     {
         let row = sqlx::query(
             r#"SELECT
-                       id, address, address_index, network, token, amount_raw::TEXT, paid_raw::TEXT,
-                       status, decimals, created_at, expires_at, webhook_url, webhook_secret
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
                    FROM invoices WHERE network = $1 AND address = $2 AND status = 'Pending'"#
         )
             .bind(chain_name)
             .bind(address)
             .fetch_optional(&self.pool)
+            .traced("get_pending_invoice_by_address")
             .await?;
 
         match row {
@@ -824,6 +1637,7 @@ impl DatabaseAdapter for Postgres {
                    RETURNING id, network, address"#
         )
             .fetch_all(&self.pool)
+            .traced("expire_old_invoices")
             .await?;
 
         let mut expired = Vec::new();
@@ -838,68 +1652,189 @@ impl DatabaseAdapter for Postgres {
         Ok(expired)
     }
 
+    async fn release_expired_addresses(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let rows = sqlx::query(
+            r#"UPDATE invoices
+                   SET watch_released = TRUE
+                   WHERE status = 'Expired' AND NOT watch_released
+                       AND expires_at + (interval '1 second' * COALESCE(grace_period_secs, 0)) <= now()
+                   RETURNING network, address"#
+        )
+            .fetch_all(&self.pool)
+            .traced("release_expired_addresses")
+            .await?;
+
+        Ok(rows.iter()
+            .map(|r| (r.get("network"), r.get("address")))
+            .collect())
+    }
+
+    async fn revive_invoice_in_grace(&self, chain_name: &str, address: &str) -> anyhow::Result<Option<Invoice>> {
+        let row = sqlx::query(
+            r#"UPDATE invoices
+                   SET status = 'Pending',
+                       expires_at = expires_at + (interval '1 second' * grace_period_secs),
+                       grace_period_secs = NULL
+                   WHERE id = (
+                       SELECT id FROM invoices i
+                       WHERE network = $1 AND address = $2 AND status = 'Expired'
+                           AND NOT watch_released AND grace_period_secs IS NOT NULL
+                           AND expires_at + (interval '1 second' * grace_period_secs) > now()
+                           AND NOT EXISTS (SELECT 1 FROM invoices r WHERE r.reissued_from = i.id)
+                       ORDER BY expires_at DESC
+                       LIMIT 1
+                       FOR UPDATE
+                   )
+                   RETURNING
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from"#
+        )
+            .bind(chain_name)
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .traced("revive_invoice_in_grace")
+            .await?;
+
+        row.map(Self::map_row_to_invoice).transpose()
+    }
+
+    async fn get_expiring_invoices(&self) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+        let rows = sqlx::query(
+            r#"SELECT id, expires_at FROM invoices
+                   WHERE status = 'Pending'
+                       AND expiry_warning_secs IS NOT NULL
+                       AND NOT expiry_warning_sent
+                       AND expires_at > now()
+                       AND expires_at - (interval '1 second' * expiry_warning_secs) <= now()"#
+        )
+            .fetch_all(&self.pool)
+            .traced("get_expiring_invoices")
+            .await?;
+
+        Ok(rows.iter()
+            .map(|r| (r.get::<uuid::Uuid, _>("id").to_string(), r.get("expires_at")))
+            .collect())
+    }
+
+    async fn mark_expiry_warned(&self, uuid: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE invoices SET expiry_warning_sent = TRUE WHERE id = $1")
+            .bind(uuid::Uuid::parse_str(uuid)?)
+            .execute(&self.pool)
+            .traced("mark_expiry_warned")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_stale_invoices(&self, older_than: Duration) -> anyhow::Result<Vec<Invoice>> {
+        let rows = sqlx::query(
+            r#"SELECT
+                       id, account_id, address, address_index, network, token, token_contract, amount_raw::TEXT, paid_raw::TEXT, refunded_raw::TEXT,
+                       status, decimals, webhook_url, webhook_secret, created_at, expires_at,
+                       paid_at, expiry_warning_secs, grace_period_secs, deadline_policy, finality_mode, test_mode, split_schedule, locale, display_currency, tags,
+                       webhook_events, reissued_from
+                   FROM invoices
+                   WHERE status = 'Pending'
+                       AND NOT stale_notified
+                       AND created_at <= now() - (interval '1 second' * $1)
+                       AND paid_raw = 0
+                       AND NOT EXISTS (SELECT 1 FROM payments WHERE payments.invoice_id = invoices.id)"#
+        )
+            .bind(older_than.as_secs() as i64)
+            .fetch_all(&self.pool)
+            .traced("get_stale_invoices")
+            .await?;
+
+        rows.into_iter().map(Self::map_row_to_invoice).collect()
+    }
+
+    async fn mark_invoice_stale(&self, uuid: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE invoices SET stale_notified = TRUE WHERE id = $1")
+            .bind(uuid::Uuid::parse_str(uuid)?)
+            .execute(&self.pool)
+            .traced("mark_invoice_stale")
+            .await?;
+
+        Ok(())
+    }
+
     async fn is_invoice_expired(&self, uuid: &str) -> anyhow::Result<Option<bool>> {
-        let uuid_parsed = uuid::Uuid::parse_str(&uuid)?;
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
 
         let status: Option<String> = sqlx::query_scalar(
             "SELECT status FROM invoices WHERE id = $1"
         )
             .bind(uuid_parsed)
             .fetch_optional(&self.pool)
+            .traced("is_invoice_expired")
             .await?;
 
         Ok(status.map(|s| s == InvoiceStatus::Expired.to_string()))
     }
 
     async fn is_invoice_paid(&self, uuid: &str) -> anyhow::Result<Option<bool>> {
-        let uuid_parsed = uuid::Uuid::parse_str(&uuid)?;
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
 
         let status: Option<String> = sqlx::query_scalar(
             "SELECT status FROM invoices WHERE id = $1"
         )
             .bind(uuid_parsed)
             .fetch_optional(&self.pool)
+            .traced("is_invoice_paid")
             .await?;
 
         Ok(status.map(|s| s == InvoiceStatus::Paid.to_string()))
     }
 
     async fn is_invoice_pending(&self, uuid: &str) -> anyhow::Result<Option<bool>> {
-        let uuid_parsed = uuid::Uuid::parse_str(&uuid)?;
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
 
         let status: Option<String> = sqlx::query_scalar(
             "SELECT status FROM invoices WHERE id = $1"
         )
             .bind(uuid_parsed)
             .fetch_optional(&self.pool)
+            .traced("is_invoice_pending")
             .await?;
 
         Ok(status.map(|s| s == InvoiceStatus::Pending.to_string()))
     }
 
     async fn remove_invoice(&self, uuid: &str) -> anyhow::Result<()> {
-        let uuid_parsed = uuid::Uuid::parse_str(&uuid)?;
+        let uuid_parsed = uuid::Uuid::parse_str(uuid)?;
 
         sqlx::query("DELETE FROM invoices WHERE id = $1")
             .bind(uuid_parsed)
             .execute(&self.pool)
+            .traced("remove_invoice")
             .await?;
 
         Ok(())
     }
 
-    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str, tx_hash: &str,
-                                 amount_raw: U256, block_number: u64, network: &str,
-                                 log_index: Option<u64>) -> anyhow::Result<()> {
+    async fn add_payment_attempt(&self, invoice_id: &str, from: &str, to: &str,
+                                 payer: Option<&str>, tx_hash: &str, amount_raw: U256,
+                                 block_number: u64, block_timestamp: Option<DateTime<Utc>>,
+                                 network: &str, log_index: Option<u64>) -> anyhow::Result<()> {
         let invoice_uuid_parsed = uuid::Uuid::parse_str(invoice_id)?;
         let amount_bd = BigDecimal::from_str(&amount_raw.to_string())?;
 
+        // an archived payment was already credited, seeing it again (rescan) must be a no-op
         sqlx::query(
             r#"INSERT INTO payments (invoice_id, "from", "to", network, tx_hash, amount_raw,
-                      block_number, status, log_index)
-                   VALUES ($1, $2, $3, $4, $5, $6, $7, 'Confirming', $8)
+                      block_number, status, log_index, payer, block_timestamp)
+                   SELECT $1, $2, $3, $4, $5, $6, $7, 'Confirming', $8, $9, $10
+                   WHERE NOT EXISTS (
+                       SELECT 1 FROM payments_archive
+                       WHERE tx_hash = $5 AND log_index = $8 AND network = $4
+                   )
                    ON CONFLICT (tx_hash, log_index, network)
-                   DO UPDATE SET block_number = excluded.block_number"#
+                   DO UPDATE SET block_number = excluded.block_number,
+                                 block_timestamp = COALESCE(excluded.block_timestamp,
+                                                            payments.block_timestamp),
+                                 payer = COALESCE(excluded.payer, payments.payer)"#
         )
             .bind(invoice_uuid_parsed)
             .bind(from)
@@ -908,214 +1843,1433 @@ impl DatabaseAdapter for Postgres {
             .bind(tx_hash)
             .bind(amount_bd)
             .bind(block_number as i64)
-            .bind(log_index.map(|x| x as i64))
+            .bind(log_index.map_or(-1, |x| x as i64)) // NULLs would never conflict
+            .bind(payer)
+            .bind(block_timestamp)
             .execute(&self.pool)
+            .traced("add_payment_attempt")
             .await?;
 
         Ok(())
     }
 
-    async fn get_confirming_payments(&self) -> anyhow::Result<Vec<Payment>> {
-        let rows = sqlx::query(
-            r#"SELECT id, invoice_id, "from", "to", network, tx_hash,
-                       amount_raw::TEXT, block_number, status, created_at, log_index
-                   FROM payments WHERE status = 'Confirming'"#)
-            .fetch_all(&self.pool)
-            .await?;
-
-        rows.into_iter().map(Self::map_row_to_payment).collect()
+    // on the primary, a lagging replica would let a burst of invoices past the quota
+    async fn get_account_usage(&self, account_id: u32, since: DateTime<Utc>) -> anyhow::Result<AccountUsage> {
+        Self::account_usage(&self.pool, account_id, since).await
     }
 
-    async fn finalize_payment(&self, payment_id: &str) -> anyhow::Result<bool> {
-        let pay_uuid_parsed = uuid::Uuid::parse_str(&payment_id)?;
-
-        let mut tx = self.pool.begin().await?;
-
-        let row = sqlx::query(
-            "UPDATE payments SET status = 'Confirmed' WHERE id = $1
-                                         RETURNING invoice_id, amount_raw::TEXT"
+    async fn get_invoice_totals(&self, account_id: u32, from: DateTime<Utc>, to: DateTime<Utc>)
+        -> anyhow::Result<Vec<InvoiceTotals>>
+    {
+        let rows = self.fetch_all_read("get_invoice_totals", || sqlx::query(
+            r#"SELECT network, token, decimals, status, COUNT(*) AS count,
+                      SUM(CASE WHEN status = 'Paid' THEN paid_raw ELSE amount_raw END)::TEXT AS amount_raw
+                   FROM invoices
+                   WHERE account_id = $1 AND NOT test_mode
+                     AND ((status = 'Paid' AND COALESCE(paid_at, expires_at) >= $2
+                               AND COALESCE(paid_at, expires_at) < $3)
+                          OR (status = 'Expired' AND expires_at >= $2 AND expires_at < $3))
+                   GROUP BY network, token, decimals, status
+                   ORDER BY status, network, token"#
         )
-            .bind(pay_uuid_parsed)
-            .fetch_one(&mut *tx)
-            .await?;
+            .bind(account_id as i32)
+            .bind(from)
+            .bind(to)
+        ).await?;
 
-        let inv_id: uuid::Uuid = row.get("invoice_id");
+        rows.into_iter()
+            .map(|row| Ok(InvoiceTotals {
+                network: row.get("network"),
+                token: row.get("token"),
+                decimals: row.get::<i16, _>("decimals") as u8,
+                status: InvoiceStatus::from_str(row.get("status"))?,
+                count: row.get::<i64, _>("count") as u64,
+                amount_raw: U256::from_str(row.get::<&str, _>("amount_raw"))?,
+            }))
+            .collect()
+    }
 
-        let pay_amount_str: String = row.get("amount_raw");
-        let pay_amount_bd = BigDecimal::from_str(&pay_amount_str)?;
+    async fn get_payment_analytics(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<PaymentAnalytics>> {
+        let rows = self.fetch_all_read("get_payment_analytics", || sqlx::query(
+            r#"WITH confirmations AS (
+                   SELECT i.network, i.token, COUNT(*) AS payments_count,
+                          AVG(EXTRACT(EPOCH FROM (p.confirmed_at - p.created_at)))::FLOAT8 AS avg_confirmation_secs
+                   FROM (
+                       SELECT invoice_id, status, created_at, confirmed_at FROM payments
+                       UNION ALL
+                       SELECT invoice_id, status, created_at, confirmed_at FROM payments_archive
+                   ) p JOIN invoices i ON i.id = p.invoice_id
+                   WHERE p.status = 'Confirmed' AND p.confirmed_at >= $1 AND p.confirmed_at < $2
+                   GROUP BY i.network, i.token
+               ), deviations AS (
+                   SELECT network, token,
+                          AVG((paid_raw - amount_raw) / amount_raw * 100)::FLOAT8 AS avg_amount_deviation_pct
+                   FROM invoices
+                   WHERE status <> 'Pending' AND paid_raw > 0 AND amount_raw > 0
+                     AND COALESCE(paid_at, expires_at) >= $1 AND COALESCE(paid_at, expires_at) < $2
+                   GROUP BY network, token
+               )
+               SELECT COALESCE(c.network, d.network) AS network, COALESCE(c.token, d.token) AS token,
+                      COALESCE(c.payments_count, 0) AS payments_count,
+                      c.avg_confirmation_secs, d.avg_amount_deviation_pct
+               FROM confirmations c
+               FULL OUTER JOIN deviations d ON d.network = c.network AND d.token = c.token
+               ORDER BY network, token"#
+        )
+            .bind(from)
+            .bind(to)
+        ).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| PaymentAnalytics {
+                network: row.get("network"),
+                token: row.get("token"),
+                payments_count: row.get::<i64, _>("payments_count") as u64,
+                avg_confirmation_secs: row.get("avg_confirmation_secs"),
+                avg_amount_deviation_pct: row.get("avg_amount_deviation_pct"),
+            })
+            .collect())
+    }
 
-        let inv = sqlx::query(
-            r#"UPDATE invoices SET paid_raw = paid_raw + $1 WHERE id = $2
-                   RETURNING paid_raw::TEXT, amount_raw::TEXT"#
+    async fn record_settlement_latency(&self, invoice_id: &str, delivered_at: DateTime<Utc>) -> anyhow::Result<Option<u64>> {
+        let latency_ms: Option<i64> = sqlx::query_scalar(
+            r#"INSERT INTO settlement_latencies (invoice_id, payment_id, network, token,
+                      latency_ms, delivered_at)
+                   SELECT i.id, p.id, i.network, i.token,
+                          GREATEST(EXTRACT(EPOCH FROM ($2 - p.block_timestamp)) * 1000, 0)::BIGINT, $2
+                   FROM (
+                       SELECT id, invoice_id, block_timestamp, confirmed_at FROM payments
+                       WHERE status = 'Confirmed'
+                       UNION ALL
+                       SELECT id, invoice_id, block_timestamp, confirmed_at FROM payments_archive
+                   ) p JOIN invoices i ON i.id = p.invoice_id
+                   WHERE i.id = $1 AND p.block_timestamp IS NOT NULL
+                   ORDER BY p.confirmed_at DESC
+                   LIMIT 1
+                   ON CONFLICT (invoice_id) DO NOTHING
+                   RETURNING latency_ms"#
         )
-            .bind(pay_amount_bd)
-            .bind(inv_id)
-            .fetch_one(&mut *tx)
+            .bind(uuid::Uuid::parse_str(invoice_id)?)
+            .bind(delivered_at)
+            .fetch_optional(&self.pool)
+            .traced("record_settlement_latency")
             .await?;
 
-        let inv_paid_str: String = inv.get("paid_raw");
-        let inv_amount_str: String = inv.get("amount_raw");
-
-        let inv_paid_raw = U256::from_str(&inv_paid_str)
-            .map_err(|e| anyhow::anyhow!("Failed to parse paid_raw: {}", e))?;
-        let inv_amount_raw = U256::from_str(&inv_amount_str)
-            .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?;
+        Ok(latency_ms.map(|ms| ms as u64))
+    }
 
-        let is_fully_paid = inv_paid_raw >= inv_amount_raw;
-        if is_fully_paid {
-            sqlx::query("UPDATE invoices SET status = 'Paid' WHERE id = $1")
-                .bind(inv_id)
-                .execute(&mut *tx)
-                .await?;
-        }
+    async fn get_latency_percentiles(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<LatencyPercentiles>> {
+        let rows = self.fetch_all_read("get_latency_percentiles", || sqlx::query(
+            r#"SELECT network, token, COUNT(*) AS payments_count,
+                      percentile_disc(0.5) WITHIN GROUP (ORDER BY latency_ms) AS p50_ms,
+                      percentile_disc(0.9) WITHIN GROUP (ORDER BY latency_ms) AS p90_ms,
+                      percentile_disc(0.99) WITHIN GROUP (ORDER BY latency_ms) AS p99_ms,
+                      MAX(latency_ms) AS max_ms
+               FROM settlement_latencies
+               WHERE delivered_at >= $1 AND delivered_at < $2
+               GROUP BY network, token
+               ORDER BY network, token"#
+        )
+            .bind(from)
+            .bind(to)
+        ).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| LatencyPercentiles {
+                network: row.get("network"),
+                token: row.get("token"),
+                payments_count: row.get::<i64, _>("payments_count") as u64,
+                p50_ms: row.get::<i64, _>("p50_ms") as u64,
+                p90_ms: row.get::<i64, _>("p90_ms") as u64,
+                p99_ms: row.get::<i64, _>("p99_ms") as u64,
+                max_ms: row.get::<i64, _>("max_ms") as u64,
+            })
+            .collect())
+    }
 
-        tx.commit().await?;
+    async fn archive_finalized_payments(&self, confirmed_before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let res = sqlx::query(
+            r#"WITH moved AS (
+                   DELETE FROM payments
+                   WHERE status = 'Confirmed' AND confirmed_at < $1
+                   RETURNING id, invoice_id, "from", "to", network, tx_hash, amount_raw,
+                             block_number, status, created_at, confirmed_at, log_index, payer,
+                             block_timestamp
+               )
+               INSERT INTO payments_archive (id, invoice_id, "from", "to", network, tx_hash,
+                   amount_raw, block_number, status, created_at, confirmed_at, log_index, payer,
+                   block_timestamp)
+               SELECT id, invoice_id, "from", "to", network, tx_hash, amount_raw,
+                   block_number, status, created_at, confirmed_at, log_index, payer,
+                   block_timestamp
+               FROM moved"#
+        )
+            .bind(confirmed_before)
+            .execute(&self.pool)
+            .traced("archive_finalized_payments")
+            .await?;
 
-        Ok(is_fully_paid)
+        Ok(res.rows_affected())
     }
 
-    async fn update_payment_block(&self, payment_id: &str, block_num: u64) -> anyhow::Result<()> {
-        let uuid_parsed = uuid::Uuid::parse_str(payment_id)?;
+    async fn add_unknown_transfer(&self, transfer: &UnknownTransfer) -> anyhow::Result<()> {
+        let amount_bd = BigDecimal::from_str(&transfer.amount_raw.to_string())?;
 
-        sqlx::query("UPDATE payments SET block_number = $1 WHERE id = $2")
-            .bind(block_num as i64)
-            .bind(uuid_parsed)
+        sqlx::query(
+            r#"INSERT INTO unknown_transfers (network, contract, tx_hash, "from", "to",
+                      amount_raw, block_number, log_index, created_at)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                   ON CONFLICT (network, tx_hash, log_index) DO NOTHING"#
+        )
+            .bind(&transfer.network)
+            .bind(&transfer.contract)
+            .bind(&transfer.tx_hash)
+            .bind(&transfer.from)
+            .bind(&transfer.to)
+            .bind(amount_bd)
+            .bind(transfer.block_number as i64)
+            .bind(transfer.log_index as i64)
+            .bind(transfer.created_at)
             .execute(&self.pool)
+            .traced("add_unknown_transfer")
             .await?;
 
         Ok(())
     }
 
-    async fn select_webhooks_job(&self) -> anyhow::Result<Vec<WebhookJob>> {
-        let mut tx = self.pool.begin().await?;
+    async fn add_misdirected_payment(&self, payment: &MisdirectedPayment) -> anyhow::Result<bool> {
+        let amount_bd = BigDecimal::from_str(&payment.amount_raw.to_string())?;
 
-        let res = sqlx::query_as::<_, WebhookJob>(
-            r#"UPDATE webhooks w
-                       SET status = 'Processing'
-                       FROM invoices i
-                       WHERE w.invoice_id = i.id
-                           AND w.id IN (
-                               SELECT id FROM webhooks
-                               WHERE status = 'Pending' AND next_retry <= NOW()
-                               LIMIT 50
-                               FOR UPDATE SKIP LOCKED
-                           )
-                       RETURNING w.id, w.url, w.payload, w.max_retries, w.attempts,
-                           COALESCE(i.webhook_secret, 'default_secret') as secret_key"#
+        let result = sqlx::query(
+            r#"INSERT INTO misdirected_payments (id, invoice_id, network, token, token_contract,
+                      tx_hash, "from", "to", amount, amount_raw, block_number, log_index,
+                      status, created_at)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                   ON CONFLICT (network, tx_hash, log_index) DO NOTHING"#
         )
-            .fetch_all(&mut *tx)
-            .await;
+            .bind(uuid::Uuid::parse_str(&payment.id)?)
+            .bind(uuid::Uuid::parse_str(&payment.invoice_id)?)
+            .bind(&payment.token.chain)
+            .bind(&payment.token.symbol)
+            .bind(&payment.token.contract)
+            .bind(&payment.tx_hash)
+            .bind(&payment.from)
+            .bind(&payment.to)
+            .bind(&payment.amount)
+            .bind(amount_bd)
+            .bind(payment.block_number as i64)
+            .bind(payment.log_index.map_or(-1, |x| x as i64))
+            .bind(payment.status.to_string())
+            .bind(payment.created_at)
+            .execute(&self.pool)
+            .traced("add_misdirected_payment")
+            .await?;
 
-        match res {
-            Ok(jobs) => {
-                tx.commit().await?;
-                Ok(jobs)
-            },
-            Err(e) => Err(e.into())
-        }
+        Ok(result.rows_affected() > 0)
     }
 
-    async fn set_webhook_status(&self, id: &str, status: WebhookStatus) -> anyhow::Result<()> {
-        let uuid_parsed = uuid::Uuid::parse_str(id)?;
-        sqlx::query(
-            "UPDATE webhooks SET status = $1 WHERE id = $2"
+    async fn get_misdirected_payments(&self, invoice_id: &str) -> anyhow::Result<Vec<MisdirectedPayment>> {
+        let rows = sqlx::query(
+            r#"SELECT id::TEXT, invoice_id::TEXT, network, token, token_contract, tx_hash,
+                       "from", "to", amount, amount_raw::TEXT, block_number, log_index, status,
+                       created_at, resolved_at
+                   FROM misdirected_payments WHERE invoice_id = $1
+                   ORDER BY created_at"#
         )
-            .bind(status.to_string())
-            .bind(uuid_parsed)
-            .execute(&self.pool)
+            .bind(uuid::Uuid::parse_str(invoice_id)?)
+            .fetch_all(&self.pool)
+            .traced("get_misdirected_payments")
             .await?;
 
-        Ok(())
+        rows.into_iter()
+            .map(|row| {
+                let amount_str: String = row.get("amount_raw");
+                let status_str: String = row.get("status");
+
+                Ok(MisdirectedPayment {
+                    id: row.get("id"),
+                    invoice_id: row.get("invoice_id"),
+                    token: TokenRef::new(row.get::<&str, _>("network"), row.get::<&str, _>("token"),
+                                         row.get::<&str, _>("token_contract")),
+                    tx_hash: row.get("tx_hash"),
+                    from: row.get("from"),
+                    to: row.get("to"),
+                    amount: row.get("amount"),
+                    amount_raw: U256::from_str(&amount_str)
+                        .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?,
+                    block_number: row.get::<i64, _>("block_number") as u64,
+                    log_index: u64::try_from(row.get::<i64, _>("log_index")).ok(),
+                    status: status_str.parse()
+                        .map_err(|e| anyhow::anyhow!("Invalid misdirected payment status: {}", e))?,
+                    created_at: row.get("created_at"),
+                    resolved_at: row.get("resolved_at"),
+                })
+            })
+            .collect()
     }
 
-    async fn schedule_webhook_retry(&self, id: &str, attempts: i32, next_retry_in_secs: f64) -> anyhow::Result<()> {
-        let uuid_parsed = uuid::Uuid::parse_str(id)?;
-
-        sqlx::query(
-            r#"UPDATE webhooks SET status = 'Pending', attempts = $1,
-                       next_retry = NOW() + (interval '1 second' * $2) WHERE id = $3"#
+    async fn set_misdirected_payment_status(&self, id: &str, status: MisdirectedStatus) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"UPDATE misdirected_payments
+                   SET status = $2,
+                       resolved_at = CASE WHEN $2 = 'Open' THEN NULL ELSE now() END
+                   WHERE id = $1"#
         )
-            .bind(attempts)
-            .bind(next_retry_in_secs)
-            .bind(uuid_parsed)
+            .bind(uuid::Uuid::parse_str(id)?)
+            .bind(status.to_string())
             .execute(&self.pool)
+            .traced("set_misdirected_payment_status")
             .await?;
 
+        if result.rows_affected() == 0 {
+            anyhow::bail!("misdirected payment '{}' not found", id);
+        }
+
         Ok(())
     }
 
-    async fn add_webhook_job(&self, invoice_id: &str, event: &WebhookEvent) -> anyhow::Result<()> {
-        let uuid_parsed = uuid::Uuid::parse_str(&invoice_id)?;
+    async fn add_refund(&self, refund: &Refund) -> anyhow::Result<U256> {
+        let payment_uuid = uuid::Uuid::parse_str(&refund.payment_id)?;
+        let invoice_uuid = uuid::Uuid::parse_str(&refund.invoice_id)?;
+
+        let mut tx = self.pool.begin().await?;
+
+        // serializes refunds of the same invoice
+        sqlx::query("SELECT 1 FROM invoices WHERE id = $1 FOR UPDATE")
+            .bind(invoice_uuid)
+            .fetch_optional(&mut *tx)
+            .traced("add_refund")
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Invoice {} not found", refund.invoice_id))?;
 
-        let url_opt: Option<String> = sqlx::query_scalar(
-            "SELECT webhook_url FROM invoices WHERE id = $1"
+        let payment = sqlx::query(
+            r#"SELECT amount_raw::TEXT FROM payments
+                   WHERE id = $1 AND invoice_id = $2 AND status = 'Confirmed'
+               UNION ALL
+               SELECT amount_raw::TEXT FROM payments_archive WHERE id = $1 AND invoice_id = $2"#
         )
-            .bind(uuid_parsed)
-            .fetch_optional(&self.pool)
+            .bind(payment_uuid)
+            .bind(invoice_uuid)
+            .fetch_optional(&mut *tx)
+            .traced("add_refund")
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Confirmed payment {} of invoice {} not found",
+                refund.payment_id, refund.invoice_id))?;
+
+        let refunded = sqlx::query(
+            "SELECT COALESCE(SUM(amount_raw), 0)::TEXT AS refunded FROM refunds WHERE payment_id = $1"
+        )
+            .bind(payment_uuid)
+            .fetch_one(&mut *tx)
+            .traced("add_refund")
             .await?;
 
-        let Some(url) = url_opt else {
-            anyhow::bail!("Invoice {} not found", invoice_id);
-        };
+        let payment_amount = U256::from_str(payment.get::<&str, _>("amount_raw"))
+            .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?;
+        let already_refunded = U256::from_str(refunded.get::<&str, _>("refunded"))
+            .map_err(|e| anyhow::anyhow!("Failed to parse refunded amount: {}", e))?;
+
+        let refundable = payment_amount.saturating_sub(already_refunded);
+        if refund.amount_raw > refundable {
+            return Err(RefundExceedsPayment {
+                payment_id: refund.payment_id.clone(),
+                refundable,
+                requested: refund.amount_raw,
+            }.into());
+        }
 
-        let event_type = event.as_ref();
-        let payload = serde_json::to_value(event)?;
+        let amount_bd = BigDecimal::from_str(&refund.amount_raw.to_string())?;
 
         sqlx::query(
-            r#"INSERT INTO webhooks (invoice_id, event_type, url, payload)
-                       VALUES ($1, $2, $3, $4)"#
+            r#"INSERT INTO refunds (id, invoice_id, payment_id, amount_raw, tx_hash, reason, created_at)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7)"#
         )
-            .bind(uuid_parsed)
-            .bind(event_type)
-            .bind(url)
-            .bind(payload)
-            .execute(&self.pool)
+            .bind(uuid::Uuid::parse_str(&refund.id)?)
+            .bind(invoice_uuid)
+            .bind(payment_uuid)
+            .bind(&amount_bd)
+            .bind(&refund.tx_hash)
+            .bind(&refund.reason)
+            .bind(refund.created_at)
+            .execute(&mut *tx)
+            .traced("add_refund")
             .await?;
 
-        Ok(())
+        let inv = sqlx::query(
+            r#"UPDATE invoices SET refunded_raw = refunded_raw + $1 WHERE id = $2
+                   RETURNING refunded_raw::TEXT"#
+        )
+            .bind(&amount_bd)
+            .bind(invoice_uuid)
+            .fetch_one(&mut *tx)
+            .traced("add_refund")
+            .await?;
+
+        tx.commit().await?;
+
+        U256::from_str(inv.get::<&str, _>("refunded_raw"))
+            .map_err(|e| anyhow::anyhow!("Failed to parse refunded_raw: {}", e))
+    }
+
+    async fn get_refunds(&self, invoice_id: &str) -> anyhow::Result<Vec<Refund>> {
+        let rows = sqlx::query(
+            r#"SELECT id::TEXT, invoice_id::TEXT, payment_id::TEXT, amount_raw::TEXT, tx_hash,
+                       reason, created_at
+                   FROM refunds WHERE invoice_id = $1
+                   ORDER BY created_at"#
+        )
+            .bind(uuid::Uuid::parse_str(invoice_id)?)
+            .fetch_all(&self.pool)
+            .traced("get_refunds")
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let amount_str: String = row.get("amount_raw");
+
+                Ok(Refund {
+                    id: row.get("id"),
+                    invoice_id: row.get("invoice_id"),
+                    payment_id: row.get("payment_id"),
+                    amount_raw: U256::from_str(&amount_str)
+                        .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?,
+                    tx_hash: row.get("tx_hash"),
+                    reason: row.get("reason"),
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect()
+    }
+
+    async fn get_unknown_transfers(&self, chain_name: &str) -> anyhow::Result<Vec<UnknownTransfer>> {
+        let rows = self.fetch_all_read("get_unknown_transfers", || sqlx::query(
+            r#"SELECT network, contract, tx_hash, "from", "to", amount_raw::TEXT,
+                       block_number, log_index, created_at
+                   FROM unknown_transfers WHERE network = $1
+                   ORDER BY block_number, log_index"#
+        )
+            .bind(chain_name)
+        ).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let amount_str: String = row.get("amount_raw");
+
+                Ok(UnknownTransfer {
+                    network: row.get("network"),
+                    contract: row.get("contract"),
+                    tx_hash: row.get("tx_hash"),
+                    from: row.get("from"),
+                    to: row.get("to"),
+                    amount_raw: U256::from_str(&amount_str)
+                        .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?,
+                    block_number: row.get::<i64, _>("block_number") as u64,
+                    log_index: row.get::<i64, _>("log_index") as u64,
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect()
+    }
+
+    async fn get_confirming_payments(&self) -> anyhow::Result<Vec<Payment>> {
+        let rows = sqlx::query(
+            r#"SELECT id, invoice_id, "from", "to", network, tx_hash,
+                       amount_raw::TEXT, block_number, status, created_at, confirmed_at, log_index,
+                       payer, block_timestamp
+                   FROM payments WHERE status = 'Confirming'"#)
+            .fetch_all(&self.pool)
+            .traced("get_confirming_payments")
+            .await?;
+
+        rows.into_iter().map(Self::map_row_to_payment).collect()
+    }
+
+    async fn get_payment(&self, payment_id: &str) -> anyhow::Result<Option<Payment>> {
+        let uuid_parsed = uuid::Uuid::parse_str(payment_id)?;
+
+        let row = sqlx::query(
+            r#"SELECT id, invoice_id, "from", "to", network, tx_hash,
+                       amount_raw::TEXT, block_number, status, created_at, confirmed_at, log_index,
+                       payer, block_timestamp
+                   FROM payments WHERE id = $1"#)
+            .bind(uuid_parsed)
+            .fetch_optional(&self.pool)
+            .traced("get_payment")
+            .await?;
+
+        row.map(Self::map_row_to_payment).transpose()
+    }
+
+    async fn finalize_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        let pay_uuid_parsed = uuid::Uuid::parse_str(payment_id)?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(
+            "UPDATE payments SET status = 'Confirmed', confirmed_at = $2
+                   WHERE id = $1 AND status = 'Confirming'
+                   RETURNING invoice_id, amount_raw::TEXT"
+        )
+            .bind(pay_uuid_parsed)
+            .bind(confirmed_at)
+            .fetch_optional(&mut *tx)
+            .traced("finalize_payment")
+            .await?;
+
+        let Some(row) = row else {
+            let status: Option<String> = sqlx::query_scalar("SELECT status FROM payments WHERE id = $1")
+                .bind(pay_uuid_parsed)
+                .fetch_optional(&mut *tx)
+                .traced("finalize_payment")
+                .await?;
+
+            return match status {
+                Some(status) => Err(PaymentAlreadyFinalized {
+                    payment_id: payment_id.to_owned(),
+                    status: PaymentStatus::from_str(&status)?,
+                }.into()),
+                None => Err(anyhow::anyhow!("Payment {} not found", payment_id)),
+            }
+        };
+
+        let inv_id: uuid::Uuid = row.get("invoice_id");
+
+        let pay_amount_str: String = row.get("amount_raw");
+        let pay_amount_bd = BigDecimal::from_str(&pay_amount_str)?;
+
+        let inv = sqlx::query(
+            r#"UPDATE invoices SET paid_raw = paid_raw + $1 WHERE id = $2
+                   RETURNING paid_raw::TEXT, amount_raw::TEXT"#
+        )
+            .bind(pay_amount_bd)
+            .bind(inv_id)
+            .fetch_one(&mut *tx)
+            .traced("finalize_payment")
+            .await?;
+
+        let inv_paid_str: String = inv.get("paid_raw");
+        let inv_amount_str: String = inv.get("amount_raw");
+
+        let inv_paid_raw = U256::from_str(&inv_paid_str)
+            .map_err(|e| anyhow::anyhow!("Failed to parse paid_raw: {}", e))?;
+        let inv_amount_raw = U256::from_str(&inv_amount_str)
+            .map_err(|e| anyhow::anyhow!("Failed to parse amount_raw: {}", e))?;
+
+        let is_fully_paid = inv_paid_raw >= inv_amount_raw;
+        if is_fully_paid {
+            sqlx::query("UPDATE invoices SET status = 'Paid', paid_at = $2 WHERE id = $1")
+                .bind(inv_id)
+                .bind(confirmed_at)
+                .execute(&mut *tx)
+                .traced("finalize_payment")
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(is_fully_paid)
+    }
+
+    async fn reject_payment(&self, payment_id: &str, confirmed_at: DateTime<Utc>) -> anyhow::Result<bool> {
+        let uuid_parsed = uuid::Uuid::parse_str(payment_id)?;
+
+        let result = sqlx::query(
+            "UPDATE payments SET status = 'Rejected', confirmed_at = $2 WHERE id = $1 AND status = 'Confirming'"
+        )
+            .bind(uuid_parsed)
+            .bind(confirmed_at)
+            .execute(&self.pool)
+            .traced("reject_payment")
+            .await?;
+
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        let exists = sqlx::query("SELECT 1 FROM payments WHERE id = $1")
+            .bind(uuid_parsed)
+            .fetch_optional(&self.pool)
+            .traced("reject_payment_exists")
+            .await?;
+
+        match exists {
+            Some(_) => Ok(false),
+            None => anyhow::bail!("Payment {} not found", payment_id),
+        }
+    }
+
+    async fn update_payment_block(&self, payment_id: &str, block_num: u64) -> anyhow::Result<()> {
+        let uuid_parsed = uuid::Uuid::parse_str(payment_id)?;
+
+        sqlx::query("UPDATE payments SET block_number = $1 WHERE id = $2")
+            .bind(block_num as i64)
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("update_payment_block")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_chain_error(&self, error: &ChainError) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"INSERT INTO chain_errors (network, kind, message, created_at)
+                   VALUES ($1, $2, $3, $4)"#
+        )
+            .bind(&error.network)
+            .bind(error.kind.as_ref())
+            .bind(&error.message)
+            .bind(error.created_at)
+            .execute(&mut *tx)
+            .traced("add_chain_error")
+            .await?;
+
+        sqlx::query(
+            r#"DELETE FROM chain_errors
+                   WHERE network = $1 AND id NOT IN (
+                       SELECT id FROM chain_errors WHERE network = $1
+                       ORDER BY id DESC LIMIT $2
+                   )"#
+        )
+            .bind(&error.network)
+            .bind(CHAIN_ERRORS_CAP as i64)
+            .execute(&mut *tx)
+            .traced("add_chain_error")
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_chain_errors(&self, chain_name: &str) -> anyhow::Result<Vec<ChainError>> {
+        let rows = sqlx::query(
+            r#"SELECT network, kind, message, created_at FROM chain_errors
+                   WHERE network = $1 ORDER BY id DESC"#
+        )
+            .bind(chain_name)
+            .fetch_all(&self.pool)
+            .traced("get_chain_errors")
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let kind_str: String = row.get("kind");
+
+                Ok(ChainError {
+                    network: row.get("network"),
+                    kind: ChainErrorKind::from_str(&kind_str)
+                        .map_err(|_| anyhow::anyhow!("Unknown chain error kind in DB: {}", kind_str))?,
+                    message: row.get("message"),
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect()
+    }
+
+    async fn add_chain_stats(&self, chain_name: &str, delta: &ChainStatsDelta) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO chain_stats
+                   (network, blocks_processed, events_emitted, processing_ms_total, last_event_at)
+                   VALUES ($1, $2, $3, $4, $5)
+                   ON CONFLICT (network) DO UPDATE SET
+                       blocks_processed = chain_stats.blocks_processed + excluded.blocks_processed,
+                       events_emitted = chain_stats.events_emitted + excluded.events_emitted,
+                       processing_ms_total = chain_stats.processing_ms_total
+                           + excluded.processing_ms_total,
+                       last_event_at = COALESCE(excluded.last_event_at, chain_stats.last_event_at),
+                       updated_at = now()"#
+        )
+            .bind(chain_name)
+            .bind(delta.blocks as i64)
+            .bind(delta.events as i64)
+            .bind(delta.processing_ms as i64)
+            .bind(delta.last_event_at)
+            .execute(&self.pool)
+            .traced("add_chain_stats")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_chain_stats(&self, chain_name: &str) -> anyhow::Result<Option<ChainStats>> {
+        let row = sqlx::query(
+            r#"SELECT network, blocks_processed, events_emitted, processing_ms_total, last_event_at,
+                      updated_at
+                   FROM chain_stats WHERE network = $1"#
+        )
+            .bind(chain_name)
+            .fetch_optional(&self.pool)
+            .traced("get_chain_stats")
+            .await?;
+
+        Ok(row.map(|row| {
+            let blocks_processed = row.get::<i64, _>("blocks_processed") as u64;
+            let processing_ms_total = row.get::<i64, _>("processing_ms_total") as u64;
+
+            ChainStats {
+                network: row.get("network"),
+                blocks_processed,
+                events_emitted: row.get::<i64, _>("events_emitted") as u64,
+                avg_block_ms: ChainStats::avg_block_ms(processing_ms_total, blocks_processed),
+                last_event_at: row.get("last_event_at"),
+                updated_at: row.get("updated_at"),
+            }
+        }))
+    }
+
+    async fn get_deep_link_templates(&self) -> anyhow::Result<Vec<DeepLinkTemplate>> {
+        let rows = sqlx::query("SELECT wallet, template FROM deep_link_templates ORDER BY wallet")
+            .fetch_all(&self.pool)
+            .traced("get_deep_link_templates")
+            .await?;
+
+        Ok(rows.into_iter()
+            .map(|row| DeepLinkTemplate {
+                wallet: row.get("wallet"),
+                template: row.get("template"),
+            })
+            .collect())
+    }
+
+    async fn set_deep_link_template(&self, template: &DeepLinkTemplate) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO deep_link_templates (wallet, template) VALUES ($1, $2)
+                   ON CONFLICT (wallet) DO UPDATE SET template = excluded.template"#
+        )
+            .bind(&template.wallet)
+            .bind(&template.template)
+            .execute(&self.pool)
+            .traced("set_deep_link_template")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_deep_link_template(&self, wallet: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM deep_link_templates WHERE wallet = $1")
+            .bind(wallet)
+            .execute(&self.pool)
+            .traced("remove_deep_link_template")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_outbound_freeze(&self) -> anyhow::Result<OutboundFreeze> {
+        let row = sqlx::query("SELECT frozen, reason, changed_at FROM outbound_freeze")
+            .fetch_optional(&self.pool)
+            .traced("get_outbound_freeze")
+            .await?;
+
+        Ok(row.map(|row| OutboundFreeze {
+            frozen: row.get("frozen"),
+            reason: row.get("reason"),
+            changed_at: row.get("changed_at"),
+        }).unwrap_or_default())
+    }
+
+    async fn set_outbound_freeze(&self, freeze: &OutboundFreeze) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO outbound_freeze (id, frozen, reason, changed_at) VALUES (TRUE, $1, $2, $3)
+                   ON CONFLICT (id) DO UPDATE
+                   SET frozen = excluded.frozen, reason = excluded.reason,
+                       changed_at = excluded.changed_at"#
+        )
+            .bind(freeze.frozen)
+            .bind(&freeze.reason)
+            .bind(freeze.changed_at)
+            .execute(&self.pool)
+            .traced("set_outbound_freeze")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_account_settings(&self, kind: AccountSettingKind) -> anyhow::Result<Vec<(u32, serde_json::Value)>> {
+        let rows = sqlx::query("SELECT account_id, value FROM account_settings WHERE kind = $1")
+            .bind(kind.as_ref())
+            .fetch_all(&self.pool)
+            .traced("get_account_settings")
+            .await?;
+
+        Ok(rows.iter()
+            .map(|row| (row.get::<i32, _>("account_id") as u32, row.get("value")))
+            .collect())
+    }
+
+    async fn set_account_setting(&self, account_id: u32, kind: AccountSettingKind,
+                                 value: Option<&serde_json::Value>) -> anyhow::Result<()> {
+        match value {
+            Some(value) => sqlx::query(
+                r#"INSERT INTO account_settings (account_id, kind, value, updated_at) VALUES ($1, $2, $3, NOW())
+                       ON CONFLICT (account_id, kind) DO UPDATE
+                       SET value = excluded.value, updated_at = excluded.updated_at"#
+            )
+                .bind(account_id as i32)
+                .bind(kind.as_ref())
+                .bind(value),
+            None => sqlx::query("DELETE FROM account_settings WHERE account_id = $1 AND kind = $2")
+                .bind(account_id as i32)
+                .bind(kind.as_ref()),
+        }
+            .execute(&self.pool)
+            .traced("set_account_setting")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_api_keys(&self) -> anyhow::Result<Vec<(String, Role)>> {
+        let rows = sqlx::query("SELECT key_hash, role FROM api_keys")
+            .fetch_all(&self.pool)
+            .traced("get_api_keys")
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let role: String = row.get("role");
+                let role = Role::from_str(&role).map_err(|_| anyhow::anyhow!("unknown role {}", role))?;
+                Ok((row.get("key_hash"), role))
+            })
+            .collect()
+    }
+
+    async fn set_api_key(&self, key_hash: &str, role: Option<Role>) -> anyhow::Result<()> {
+        match role {
+            Some(role) => sqlx::query(
+                r#"INSERT INTO api_keys (key_hash, role) VALUES ($1, $2)
+                       ON CONFLICT (key_hash) DO UPDATE SET role = excluded.role"#
+            )
+                .bind(key_hash)
+                .bind(role.to_string()),
+            None => sqlx::query("DELETE FROM api_keys WHERE key_hash = $1")
+                .bind(key_hash),
+        }
+            .execute(&self.pool)
+            .traced("set_api_key")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn select_webhooks_job(&self, limit: u32) -> anyhow::Result<Vec<WebhookJob>> {
+        let mut tx = self.pool.begin().await?;
+
+        let res = sqlx::query_as::<_, WebhookJob>(
+            r#"UPDATE webhooks w
+                       SET status = 'Processing', processing_started_at = NOW()
+                       WHERE w.id IN (
+                           SELECT id FROM webhooks
+                           WHERE status = 'Pending' AND next_retry <= NOW()
+                           LIMIT $1
+                           FOR UPDATE SKIP LOCKED
+                       )
+                       RETURNING w.id, w.delivery_token, w.sequence, w.url, w.payload, w.max_retries, w.attempts,
+                           COALESCE(w.account_id,
+                               (SELECT account_id FROM invoices WHERE id = w.invoice_id), 0) as account_id,
+                           COALESCE(w.secret_key,
+                               (SELECT webhook_secret FROM invoices WHERE id = w.invoice_id),
+                               'default_secret') as secret_key"#
+        )
+            .bind(limit as i64)
+            .fetch_all(&mut *tx)
+            .traced("select_webhooks_job")
+            .await;
+
+        match res {
+            Ok(jobs) => {
+                tx.commit().await?;
+                Ok(jobs)
+            },
+            Err(e) => Err(e.into())
+        }
+    }
+
+    async fn set_webhook_status(&self, id: &str, status: WebhookStatus) -> anyhow::Result<()> {
+        let uuid_parsed = uuid::Uuid::parse_str(id)?;
+        sqlx::query(
+            "UPDATE webhooks SET status = $1 WHERE id = $2"
+        )
+            .bind(status.to_string())
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("set_webhook_status")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn schedule_webhook_retry(&self, id: &str, attempts: i32, next_retry_in_secs: f64) -> anyhow::Result<()> {
+        let uuid_parsed = uuid::Uuid::parse_str(id)?;
+
+        sqlx::query(
+            r#"UPDATE webhooks SET status = 'Pending', attempts = $1,
+                       next_retry = NOW() + (interval '1 second' * $2) WHERE id = $3"#
+        )
+            .bind(attempts)
+            .bind(next_retry_in_secs)
+            .bind(uuid_parsed)
+            .execute(&self.pool)
+            .traced("schedule_webhook_retry")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn requeue_stuck_webhooks(&self, visibility_timeout: Duration) -> anyhow::Result<u64> {
+        let res = sqlx::query(
+            r#"UPDATE webhooks SET status = 'Pending'
+                   WHERE status = 'Processing'
+                     AND processing_started_at < NOW() - (interval '1 second' * $1)"#
+        )
+            .bind(visibility_timeout.as_secs_f64())
+            .execute(&self.pool)
+            .traced("requeue_stuck_webhooks")
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn add_webhook_job(&self, invoice_id: &str, event: &WebhookEvent) -> anyhow::Result<()> {
+        let uuid_parsed = uuid::Uuid::parse_str(invoice_id)?;
+
+        let row = sqlx::query(
+            "SELECT webhook_url, webhook_events FROM invoices WHERE id = $1"
+        )
+            .bind(uuid_parsed)
+            .fetch_optional(&self.pool)
+            .traced("add_webhook_job")
+            .await?;
+
+        let Some(row) = row else {
+            anyhow::bail!("Invoice {} not found", invoice_id);
+        };
+
+        let Some(url) = row.get::<Option<String>, _>("webhook_url") else {
+            return Ok(());
+        };
+
+        let webhook_events: Option<Vec<String>> = row.get("webhook_events");
+        if !event.is_test()
+            && webhook_events.is_some_and(|events| !events.iter().any(|e| e == event.as_ref()))
+        {
+            return Ok(());
+        }
+
+        let event_type = event.as_ref();
+        let payload = serde_json::to_value(event)?;
+        let window_secs = event.suppression_window().map(|w| w.as_secs_f64()).unwrap_or(0.0);
+
+        let mut tx = self.pool.begin().await?;
+
+        // duplicates are silently dropped, see WebhookEvent::dedupe_key
+        let inserted: Option<uuid::Uuid> = sqlx::query_scalar(
+            r#"INSERT INTO webhooks (invoice_id, event_type, url, payload, dedupe_key, repeating, sequence, status)
+                       SELECT $1, $2, $3, $4, $5, $6, 0, CASE WHEN EXISTS (
+                           SELECT 1 FROM webhook_destinations WHERE url = $3 AND suspended_at IS NOT NULL
+                       ) THEN 'Suppressed' ELSE 'Pending' END
+                       WHERE NOT $6 OR NOT EXISTS (
+                           SELECT 1 FROM webhooks
+                           WHERE invoice_id = $1 AND event_type = $2 AND dedupe_key = $5
+                             AND created_at > NOW() - (interval '1 second' * $7)
+                       )
+                       ON CONFLICT (invoice_id, event_type, dedupe_key) WHERE NOT repeating
+                       DO NOTHING
+                       RETURNING id"#
+        )
+            .bind(uuid_parsed)
+            .bind(event_type)
+            .bind(url)
+            .bind(payload)
+            .bind(event.dedupe_key())
+            .bind(event.is_repeating())
+            .bind(window_secs)
+            .fetch_optional(&mut *tx)
+            .traced("add_webhook_job")
+            .await?;
+
+        // numbered only once actually enqueued, so dropped duplicates don't leave gaps
+        if let Some(job_id) = inserted {
+            sqlx::query(
+                r#"WITH seq AS (
+                       UPDATE invoices SET webhook_sequence = webhook_sequence + 1
+                       WHERE id = $1
+                       RETURNING webhook_sequence
+                   )
+                   UPDATE webhooks SET sequence = (SELECT webhook_sequence FROM seq)
+                   WHERE id = $2"#
+            )
+                .bind(uuid_parsed)
+                .bind(job_id)
+                .execute(&mut *tx)
+                .traced("add_webhook_job")
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_account_webhook_job(&self, account_id: u32, url: &str, secret: &str, event: &WebhookEvent)
+        -> anyhow::Result<bool>
+    {
+        let inserted = sqlx::query(
+            r#"INSERT INTO webhooks (account_id, secret_key, event_type, url, payload, dedupe_key,
+                                     repeating, sequence, status)
+                   SELECT $1, $2, $3, $4, $5, $6, FALSE, 0, CASE WHEN EXISTS (
+                       SELECT 1 FROM webhook_destinations WHERE url = $4 AND suspended_at IS NOT NULL
+                   ) THEN 'Suppressed' ELSE 'Pending' END
+                   ON CONFLICT (account_id, event_type, dedupe_key) WHERE invoice_id IS NULL
+                   DO NOTHING"#
+        )
+            .bind(account_id as i32)
+            .bind(secret)
+            .bind(event.as_ref())
+            .bind(url)
+            .bind(serde_json::to_value(event)?)
+            .bind(event.dedupe_key())
+            .execute(&self.pool)
+            .traced("add_account_webhook_job")
+            .await?;
+
+        Ok(inserted.rows_affected() > 0)
+    }
+
+    async fn get_pending_webhooks(&self) -> anyhow::Result<Vec<PendingWebhook>> {
+        let rows = sqlx::query(
+            r#"SELECT invoice_id, payload, attempts FROM webhooks
+                   WHERE status IN ('Pending', 'Processing') AND invoice_id IS NOT NULL
+                   ORDER BY created_at"#
+        )
+            .fetch_all(&self.pool)
+            .traced("get_pending_webhooks")
+            .await?;
+
+        Ok(rows.into_iter()
+            .map(|row| PendingWebhook {
+                invoice_id: row.get::<uuid::Uuid, _>("invoice_id").to_string(),
+                event: row.get::<Json<WebhookEvent>, _>("payload").0,
+                attempts: row.get::<i32, _>("attempts") as u32,
+            })
+            .collect())
+    }
+
+    async fn record_webhook_failure(&self, url: &str, error: &str) -> anyhow::Result<WebhookDestination> {
+        let row = sqlx::query(
+            r#"INSERT INTO webhook_destinations (url, failure_streak, failing_since, last_error)
+                   VALUES ($1, 1, NOW(), $2)
+                   ON CONFLICT (url) DO UPDATE
+                   SET failure_streak = webhook_destinations.failure_streak + 1, last_error = $2
+                   RETURNING url, failure_streak, failing_since, last_error, suspended_at"#
+        )
+            .bind(url)
+            .bind(error)
+            .fetch_one(&self.pool)
+            .traced("record_webhook_failure")
+            .await?;
+
+        Ok(Self::map_row_to_webhook_destination(row))
+    }
+
+    async fn clear_webhook_failures(&self, url: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM webhook_destinations WHERE url = $1 AND suspended_at IS NULL")
+            .bind(url)
+            .execute(&self.pool)
+            .traced("clear_webhook_failures")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn suspend_webhook_destination(&self, url: &str) -> anyhow::Result<Option<u64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE webhook_destinations SET suspended_at = NOW() WHERE url = $1 AND suspended_at IS NULL"
+        )
+            .bind(url)
+            .execute(&mut *tx)
+            .traced("suspend_webhook_destination")
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let suppressed = sqlx::query(
+            "UPDATE webhooks SET status = 'Suppressed' WHERE url = $1 AND status = 'Pending'"
+        )
+            .bind(url)
+            .execute(&mut *tx)
+            .traced("suspend_webhook_destination")
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(suppressed.rows_affected()))
+    }
+
+    async fn resume_webhook_destination(&self, url: &str, redeliver: bool) -> anyhow::Result<Option<u64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "DELETE FROM webhook_destinations WHERE url = $1 AND suspended_at IS NOT NULL"
+        )
+            .bind(url)
+            .execute(&mut *tx)
+            .traced("resume_webhook_destination")
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let mut redelivered = 0;
+        if redeliver {
+            redelivered = sqlx::query(
+                r#"UPDATE webhooks SET status = 'Pending', next_retry = NOW()
+                       WHERE url = $1 AND status = 'Suppressed'"#
+            )
+                .bind(url)
+                .execute(&mut *tx)
+                .traced("resume_webhook_destination")
+                .await?
+                .rows_affected();
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(redelivered))
+    }
+
+    async fn get_webhook_destinations(&self) -> anyhow::Result<Vec<WebhookDestination>> {
+        let rows = sqlx::query(
+            r#"SELECT url, failure_streak, failing_since, last_error, suspended_at
+                   FROM webhook_destinations ORDER BY url"#
+        )
+            .fetch_all(&self.pool)
+            .traced("get_webhook_destinations")
+            .await?;
+
+        Ok(rows.into_iter().map(Self::map_row_to_webhook_destination).collect())
     }
 
     async fn get_token_decimals(&self, chain_name: &str, token_symbol: &str) -> anyhow::Result<Option<u8>> {
-        if let Some(d) = self._get_token_decimals_cached(chain_name, token_symbol) {
+        let contract = match self.chains_cache.read().unwrap().get(chain_name) {
+            Some(bc) => bc.config().read().unwrap().token_contract(token_symbol),
+            None => None,
+        };
+
+        match contract {
+            Some(contract) => self.get_token_decimals_by_contract(chain_name, &contract).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn get_token_decimals_by_contract(&self, chain_name: &str, contract: &str)
+        -> anyhow::Result<Option<u8>>
+    {
+        if let Some(d) = self._get_token_decimals_cached(chain_name, contract) {
             return Ok(Some(d));
         }
 
-        if let Some(bc) = self.chains_cache.read().unwrap().get(chain_name) {
-            let lock = bc.config();
-            let c = lock.read().unwrap();
-            if c.native_symbol == token_symbol {
-                self._insert_token_decimals(chain_name, token_symbol, c.decimals)?;
-                return Ok(Some(c.decimals));
-            }
+        let decimals = match self.chains_cache.read().unwrap().get(chain_name) {
+            Some(bc) => bc.config().read().unwrap().decimals_by_contract(contract),
+            None => None,
+        };
 
-            if let Some(tc) = c.tokens.read().unwrap().iter()
-                .find(|tc| tc.symbol == token_symbol)
-            {
-                self._insert_token_decimals(chain_name, token_symbol, tc.decimals)?;
-                return Ok(Some(tc.decimals));
-            }
+        if let Some(d) = decimals {
+            self._insert_token_decimals(chain_name, contract, d)?;
         }
 
-        Ok(None)
+        Ok(decimals)
     }
 
 }
 
+struct Replica {
+    pool: PgPool,
+    down_until: Mutex<Option<Instant>>,
+}
+
+// connection trouble, as opposed to errors the primary would return just the same
+fn is_unavailable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed => true,
+        // class 57, e.g. a standby shutting down or cancelling queries on recovery conflicts
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| code.starts_with("57")),
+        _ => false,
+    }
+}
+
 impl Postgres {
-    fn _insert_token_decimals(&self, chain_name: &str, token_symbol: &str, decimals: u8) -> anyhow::Result<()> {
+    pub fn with_replica(mut self, replica: Option<PgPool>) -> Self {
+        self.replica = replica.map(|pool| Replica { pool, down_until: Mutex::new(None) });
+        self
+    }
+
+    // heavy reads that tolerate replication lag. falls back to the primary while the replica
+    // is unavailable, `query` builds the same query again for the retry
+    async fn fetch_all_read<'q>(&self, name: &'static str,
+                                query: impl Fn() -> Query<'q, sqlx::Postgres, PgArguments>)
+        -> anyhow::Result<Vec<PgRow>>
+    {
+        if let Some(replica) = &self.replica
+            && replica.down_until.lock().unwrap().is_none_or(|until| Instant::now() >= until)
+        {
+            match query().fetch_all(&replica.pool).traced(name).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) if is_unavailable(&e) => {
+                    warn!(error = %e, "Read replica unavailable, falling back to the primary");
+                    *replica.down_until.lock().unwrap() = Some(Instant::now() + REPLICA_RETRY_AFTER);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(query().fetch_all(&self.pool).traced(name).await?)
+    }
+
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        let size = self.pool.size();
+        let idle = self.pool.num_idle() as u32;
+
+        PoolMetrics {
+            size,
+            idle,
+            in_use: size.saturating_sub(idle),
+            max_connections: self.pool.options().get_max_connections(),
+        }
+    }
+
+    fn _insert_token_decimals(&self, chain_name: &str, contract: &str, decimals: u8) -> anyhow::Result<()> {
         let mut write_guard = self.token_decimals.write().unwrap();
         let inner_map = write_guard
             .entry(chain_name.to_string())
-            .or_insert_with(HashMap::new);
+            .or_default();
 
-        inner_map.insert(token_symbol.to_string(), decimals);
+        inner_map.insert(contract_key(contract), decimals);
 
         Ok(())
     }
 
-    fn _get_token_decimals_cached(&self, chain_name: &str, token_symbol: &str) -> Option<u8> {
+    fn _remove_token_decimals(&self, chain_name: &str, contract: &str) {
+        if let Some(chain_decimals) = self.token_decimals.write().unwrap().get_mut(chain_name) {
+            chain_decimals.remove(&contract_key(contract));
+        }
+    }
+
+    fn _get_token_decimals_cached(&self, chain_name: &str, contract: &str) -> Option<u8> {
         self.token_decimals.read().unwrap()
             .get(chain_name)
-            .and_then(|c| c.get(token_symbol).cloned())
+            .and_then(|c| c.get(&contract_key(contract)).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgConnectOptions;
+
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+    const USDT: &str = "0x3333333333333333333333333333333333333333";
+
+    // a scratch database on the server NECKO3_TEST_DATABASE_URL points to, with a "testnet"
+    // chain that has USDT. without the variable the Postgres tests pass without running
+    async fn postgres() -> Option<Postgres> {
+        let url = std::env::var("NECKO3_TEST_DATABASE_URL").ok()?;
+        let server = PgPool::connect(&url).await.unwrap();
+        let name = format!("necko3_test_{}", uuid::Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE DATABASE {}", name)).execute(&server).await.unwrap();
+
+        let options = PgConnectOptions::from_str(&url).unwrap().database(&name);
+        let pool = PgPool::connect_with(options).await.unwrap();
+        sqlx::migrate!("./migrations/postgres").run(&pool).await.unwrap();
+
+        let db = Postgres::init(pool, None).await.unwrap();
+        let usdt = TokenConfig::builder().symbol("USDT").contract(USDT).decimals(6).build().unwrap();
+        // BIP32 test vector 1, chain m/0'/1
+        db.add_chain(&ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .build()
+            .unwrap()).await.unwrap();
+        db.add_token("testnet", &usdt).await.unwrap();
+
+        Some(db)
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_refunds_are_capped_by_the_payment() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        let invoice = Invoice::for_test();
+        db.add_invoice(&invoice).await.unwrap();
+        db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, "0xabc",
+                               U256::from(1_000_000), 42, None, "testnet", Some(0)).await.unwrap();
+        let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+        db.finalize_payment(&payment_id, Utc::now()).await.unwrap();
+
+        let refund = |amount: u64| Refund {
+            id: uuid::Uuid::new_v4().to_string(),
+            invoice_id: invoice.id.clone(),
+            payment_id: payment_id.clone(),
+            amount_raw: U256::from(amount),
+            tx_hash: None,
+            reason: None,
+            created_at: Utc::now(),
+        };
+
+        assert_eq!(db.add_refund(&refund(400_000)).await.unwrap(), U256::from(400_000));
+
+        // each refund fits on its own, together they'd exceed the payment
+        let err = db.add_refund(&refund(700_000)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<RefundExceedsPayment>(), Some(&RefundExceedsPayment {
+            payment_id: payment_id.clone(),
+            refundable: U256::from(600_000),
+            requested: U256::from(700_000),
+        }));
+
+        assert_eq!(db.add_refund(&refund(600_000)).await.unwrap(), U256::from(1_000_000));
+        assert_eq!(db.get_refunds(&invoice.id).await.unwrap().len(), 2);
+        assert_eq!(db.get_invoice(&invoice.id).await.unwrap().unwrap().refunded_raw, U256::from(1_000_000));
+
+        let unknown = Refund { payment_id: uuid::Uuid::new_v4().to_string(), ..refund(1) };
+        let err = db.add_refund(&unknown).await.unwrap_err();
+        assert!(err.downcast_ref::<RefundExceedsPayment>().is_none());
+        assert_eq!(db.get_refunds(&invoice.id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_misdirected_payments_are_recorded_once() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        let invoice = Invoice::for_test();
+        db.add_invoice(&invoice).await.unwrap();
+
+        // a bridged token with a symbol longer than the ones we register
+        let token = TokenRef::new("testnet", "USDC.e-BRIDGED", "0x4444444444444444444444444444444444444444");
+        let payment = MisdirectedPayment {
+            id: uuid::Uuid::new_v4().to_string(),
+            invoice_id: invoice.id.clone(),
+            token: token.clone(),
+            tx_hash: "0xabc".to_owned(),
+            from: ADDRESS.to_owned(),
+            to: ADDRESS.to_owned(),
+            amount: "1.5".to_owned(),
+            amount_raw: U256::from(1_500_000),
+            block_number: 42,
+            log_index: Some(3),
+            status: MisdirectedStatus::Open,
+            created_at: Utc::now(),
+            resolved_at: None,
+        };
+
+        assert!(db.add_misdirected_payment(&payment).await.unwrap());
+        let rescanned = MisdirectedPayment { id: uuid::Uuid::new_v4().to_string(), ..payment.clone() };
+        assert!(!db.add_misdirected_payment(&rescanned).await.unwrap());
+
+        let stored = db.get_misdirected_payments(&invoice.id).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((&stored[0].id, &stored[0].token, stored[0].log_index), (&payment.id, &token, Some(3)));
+        assert_eq!(stored[0].amount_raw, U256::from(1_500_000));
+    }
+
+    #[tokio::test]
+    async fn test_payment_analytics_count_archived_payments() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        let invoice = Invoice::for_test();
+        db.add_invoice(&invoice).await.unwrap();
+        db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, "0xabc",
+                               U256::from(2_000_000), 42, None, "testnet", Some(0)).await.unwrap();
+        let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+        let confirmed_at = Utc::now() - chrono::Duration::hours(10);
+        db.finalize_payment(&payment_id, confirmed_at).await.unwrap();
+        assert_eq!(db.archive_finalized_payments(Utc::now()).await.unwrap(), 1);
+
+        let analytics = db.get_payment_analytics(confirmed_at - chrono::Duration::hours(1), Utc::now()).await.unwrap();
+        assert_eq!(analytics.iter().map(|a| a.payments_count).sum::<u64>(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_min_amount_of_unknown_token_is_an_error() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        db.set_token_min_amount("testnet", "USDT", Some(U256::from(1_000))).await.unwrap();
+        assert!(db.set_token_min_amount("testnet", "USDC", None).await.is_err());
+        assert!(db.set_token_min_amount("mainnet", "USDT", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unversioned_chain_updates_refresh_the_cache() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        // another instance updated the row, this one's cache is a version behind
+        sqlx::query("UPDATE chains SET block_lag = 7, version = version + 1 WHERE name = 'testnet'")
+            .execute(&db.pool).await.unwrap();
+
+        let update: PartialChainUpdate = serde_json::from_value(
+            serde_json::json!({ "required_confirmations": 3 })).unwrap();
+        db.update_chain_partial("testnet", &update).await.unwrap();
+
+        let stored = db.get_stored_chain_config("testnet").await.unwrap().unwrap();
+        let cached = db.get_chain("testnet").await.unwrap().unwrap().config().read().unwrap().clone();
+        assert_eq!(cached.version, stored.version);
+        assert_eq!((cached.block_lag, cached.required_confirmations), (7, 3));
+
+        // so a versioned update against what's cached goes through
+        let update: PartialChainUpdate = serde_json::from_value(
+            serde_json::json!({ "block_lag": 2, "expected_version": cached.version })).unwrap();
+        db.update_chain_partial("testnet", &update).await.unwrap();
+        assert!(db.update_chain_partial("missing", &update).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_invoices_pages_newest_first() {
+        let Some(db) = postgres().await else {
+            return
+        };
+
+        let created_at = Utc::now();
+        for i in 0..5 {
+            let mut invoice = Invoice::for_test();
+            invoice.address_index = i as u32;
+            invoice.address = format!("0x{:040x}", i);
+            // two share a timestamp, the id breaks the tie
+            invoice.created_at = created_at + chrono::Duration::seconds(i.min(3));
+            db.add_invoice(&invoice).await.unwrap();
+        }
+
+        let mut page = PageRequest { limit: Some(2), with_total: true, ..Default::default() };
+        let mut listed = vec![];
+        loop {
+            let result = db.list_invoices(&InvoiceFilter::default(), &page).await.unwrap();
+            assert_eq!(result.total, page.with_total.then_some(5));
+            listed.extend(result.items.into_iter().map(|i| (i.created_at, i.id)));
+
+            match result.next_cursor {
+                Some(cursor) => page = PageRequest { cursor: Some(cursor), limit: Some(2), with_total: false },
+                None => break,
+            }
+        }
+
+        assert_eq!(listed.len(), 5);
+        assert!(listed.windows(2).all(|w| w[0] > w[1]));
+    }
+}
@@ -0,0 +1,76 @@
+use sqlx::postgres::{PgQueryResult, PgRow};
+#[cfg(feature = "db-tracing")]
+use std::time::Instant;
+#[cfg(feature = "db-tracing")]
+use tracing::{field, Instrument, Span};
+
+// rows a statement returned or touched, recorded on its span
+pub trait RowCount {
+    #[cfg_attr(not(feature = "db-tracing"), allow(dead_code))]
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for PgQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+// fetch_one of a row or a scalar
+macro_rules! single_row {
+    ($($ty:ty),*) => {
+        $(impl RowCount for $ty {
+            fn row_count(&self) -> u64 {
+                1
+            }
+        })*
+    };
+}
+
+single_row!(PgRow, bool, i32, i64, String, uuid::Uuid);
+
+// statement-level spans ("db.query" with query, duration_ms and rows) for slow-query hunting
+// without Postgres' own statement logging. compiles to nothing without the db-tracing feature
+pub trait Traced<T: RowCount>: Future<Output = sqlx::Result<T>> + Sized {
+    #[cfg(feature = "db-tracing")]
+    fn traced(self, query: &'static str) -> impl Future<Output = sqlx::Result<T>> + Send
+    where
+        Self: Send,
+        T: Send,
+    {
+        let span = tracing::debug_span!("db.query", query, duration_ms = field::Empty,
+            rows = field::Empty);
+
+        async move {
+            let started = Instant::now();
+            let result = self.await;
+
+            let span = Span::current();
+            span.record("duration_ms", started.elapsed().as_millis() as u64);
+            if let Ok(value) = &result {
+                span.record("rows", value.row_count());
+            }
+
+            result
+        }.instrument(span)
+    }
+
+    #[cfg(not(feature = "db-tracing"))]
+    fn traced(self, _query: &'static str) -> Self {
+        self
+    }
+}
+
+impl<T: RowCount, F: Future<Output = sqlx::Result<T>>> Traced<T> for F {}
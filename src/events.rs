@@ -0,0 +1,375 @@
+use crate::model::{ReportPeriod, TokenRef, TokenTotal, WebhookEvent};
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TOP_TOKENS: usize = 3;
+
+// constructors take what every delivery of the event carries, the with_* setters fill in the
+// optional fields and leave variants without that field untouched
+impl WebhookEvent {
+    pub fn tx_detected(invoice_id: impl Into<String>, tx_hash: impl Into<String>,
+                       amount: impl Into<String>, currency: impl Into<String>) -> Self {
+        WebhookEvent::TxDetected {
+            invoice_id: invoice_id.into(),
+            tx_hash: tx_hash.into(),
+            amount: amount.into(),
+            currency: currency.into(),
+            token: None,
+            log_index: None,
+            eta_secs: None,
+            locale: None,
+            display_currency: None,
+        }
+    }
+
+    pub fn tx_confirmed(invoice_id: impl Into<String>, tx_hash: impl Into<String>,
+                        confirmations: u64, confirmed_at: DateTime<Utc>) -> Self {
+        WebhookEvent::TxConfirmed {
+            invoice_id: invoice_id.into(),
+            tx_hash: tx_hash.into(),
+            log_index: None,
+            confirmations,
+            confirmed_at,
+        }
+    }
+
+    pub fn invoice_paid(invoice_id: impl Into<String>, paid_amount: impl Into<String>,
+                        paid_at: DateTime<Utc>) -> Self {
+        WebhookEvent::InvoicePaid {
+            invoice_id: invoice_id.into(),
+            paid_amount: paid_amount.into(),
+            paid_at,
+            accepted_shortfall: None,
+            locale: None,
+            display_currency: None,
+        }
+    }
+
+    pub fn invoice_expired(invoice_id: impl Into<String>) -> Self {
+        WebhookEvent::InvoiceExpired {
+            invoice_id: invoice_id.into(),
+            locale: None,
+            display_currency: None,
+        }
+    }
+
+    pub fn invoice_expiring_soon(invoice_id: impl Into<String>, expires_at: DateTime<Utc>) -> Self {
+        WebhookEvent::InvoiceExpiringSoon {
+            invoice_id: invoice_id.into(),
+            expires_at,
+            locale: None,
+            display_currency: None,
+        }
+    }
+
+    pub fn invoice_stale(invoice_id: impl Into<String>, created_at: DateTime<Utc>, expires_at: DateTime<Utc>) -> Self {
+        WebhookEvent::InvoiceStale {
+            invoice_id: invoice_id.into(),
+            created_at,
+            expires_at,
+            locale: None,
+            display_currency: None,
+        }
+    }
+
+    pub fn wrong_asset_received(invoice_id: impl Into<String>, tx_hash: impl Into<String>,
+                                amount: impl Into<String>, received: TokenRef, expected: TokenRef) -> Self {
+        WebhookEvent::WrongAssetReceived {
+            invoice_id: invoice_id.into(),
+            tx_hash: tx_hash.into(),
+            log_index: None,
+            amount: amount.into(),
+            received,
+            expected,
+        }
+    }
+
+    pub fn tx_confirmed_late(invoice_id: impl Into<String>, tx_hash: impl Into<String>,
+                             deadline: DateTime<Utc>, confirmed_at: DateTime<Utc>) -> Self {
+        WebhookEvent::TxConfirmedLate {
+            invoice_id: invoice_id.into(),
+            tx_hash: tx_hash.into(),
+            log_index: None,
+            deadline,
+            confirmed_at,
+        }
+    }
+
+    pub fn tx_rejected_late(invoice_id: impl Into<String>, tx_hash: impl Into<String>,
+                            amount: impl Into<String>, deadline: DateTime<Utc>,
+                            confirmed_at: DateTime<Utc>) -> Self {
+        WebhookEvent::TxRejectedLate {
+            invoice_id: invoice_id.into(),
+            tx_hash: tx_hash.into(),
+            log_index: None,
+            amount: amount.into(),
+            deadline,
+            confirmed_at,
+        }
+    }
+
+    pub fn delivery_test(invoice_id: impl Into<String>, test_id: impl Into<String>,
+                         sent_at: DateTime<Utc>) -> Self {
+        WebhookEvent::DeliveryTest {
+            invoice_id: invoice_id.into(),
+            test_id: test_id.into(),
+            sent_at,
+        }
+    }
+
+    // counts and top tokens are derived from the totals
+    pub fn period_summary(account_id: u32, period: ReportPeriod, from: DateTime<Utc>, to: DateTime<Utc>,
+                          paid: Vec<TokenTotal>, expired: Vec<TokenTotal>) -> Self {
+        let mut by_symbol: HashMap<&str, u64> = HashMap::new();
+        for t in &paid {
+            *by_symbol.entry(&t.token).or_default() += t.count;
+        }
+        let mut top_tokens: Vec<(&str, u64)> = by_symbol.into_iter().collect();
+        top_tokens.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let top_tokens = top_tokens.into_iter()
+            .take(TOP_TOKENS)
+            .map(|(symbol, _)| symbol.to_owned())
+            .collect();
+
+        WebhookEvent::PeriodSummary {
+            account_id,
+            period,
+            from,
+            to,
+            paid_count: paid.iter().map(|t| t.count).sum(),
+            expired_count: expired.iter().map(|t| t.count).sum(),
+            paid,
+            expired,
+            top_tokens,
+        }
+    }
+
+    pub fn with_log_index(mut self, index: u64) -> Self {
+        match &mut self {
+            WebhookEvent::TxDetected { log_index, .. }
+            | WebhookEvent::TxConfirmed { log_index, .. }
+            | WebhookEvent::WrongAssetReceived { log_index, .. }
+            | WebhookEvent::TxConfirmedLate { log_index, .. }
+            | WebhookEvent::TxRejectedLate { log_index, .. } => *log_index = Some(index),
+            _ => {}
+        }
+        self
+    }
+
+    pub fn with_token(mut self, token_ref: TokenRef) -> Self {
+        if let WebhookEvent::TxDetected { token, .. } = &mut self {
+            *token = Some(token_ref);
+        }
+        self
+    }
+
+    pub fn with_eta_secs(mut self, secs: u64) -> Self {
+        if let WebhookEvent::TxDetected { eta_secs, .. } = &mut self {
+            *eta_secs = Some(secs);
+        }
+        self
+    }
+
+    pub fn with_accepted_shortfall(mut self, shortfall: impl Into<String>) -> Self {
+        if let WebhookEvent::InvoicePaid { accepted_shortfall, .. } = &mut self {
+            *accepted_shortfall = Some(shortfall.into());
+        }
+        self
+    }
+
+    pub fn with_locale(mut self, value: impl Into<String>) -> Self {
+        match &mut self {
+            WebhookEvent::TxDetected { locale, .. }
+            | WebhookEvent::InvoicePaid { locale, .. }
+            | WebhookEvent::InvoiceExpired { locale, .. }
+            | WebhookEvent::InvoiceExpiringSoon { locale, .. }
+            | WebhookEvent::InvoiceStale { locale, .. } => *locale = Some(value.into()),
+            _ => {}
+        }
+        self
+    }
+
+    pub fn with_display_currency(mut self, value: impl Into<String>) -> Self {
+        match &mut self {
+            WebhookEvent::TxDetected { display_currency, .. }
+            | WebhookEvent::InvoicePaid { display_currency, .. }
+            | WebhookEvent::InvoiceExpired { display_currency, .. }
+            | WebhookEvent::InvoiceExpiringSoon { display_currency, .. }
+            | WebhookEvent::InvoiceStale { display_currency, .. } =>
+                *display_currency = Some(value.into()),
+            _ => {}
+        }
+        self
+    }
+
+    // the invoice's own hints, None leaves the field as it is
+    pub fn with_display_hints(self, locale: Option<String>, display_currency: Option<String>) -> Self {
+        let event = match locale {
+            Some(locale) => self.with_locale(locale),
+            None => self,
+        };
+
+        match display_currency {
+            Some(currency) => event.with_display_currency(currency),
+            None => event,
+        }
+    }
+
+    // event_type as it appears in the payload
+    pub fn event_type(&self) -> String {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map.get("event_type")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            _ => String::new(),
+        }
+    }
+}
+
+// canonical payloads, pretty-printed. the same files back the golden tests below, so a renamed
+// field or a changed envelope fails the build before it reaches a merchant
+pub const EXAMPLE_PAYLOADS: &[(&str, &str)] = &[
+    ("tx_detected", include_str!("../golden/webhook_events/tx_detected.json")),
+    ("tx_confirmed", include_str!("../golden/webhook_events/tx_confirmed.json")),
+    ("invoice_paid", include_str!("../golden/webhook_events/invoice_paid.json")),
+    ("invoice_expired", include_str!("../golden/webhook_events/invoice_expired.json")),
+    ("invoice_expiring_soon", include_str!("../golden/webhook_events/invoice_expiring_soon.json")),
+    ("invoice_stale", include_str!("../golden/webhook_events/invoice_stale.json")),
+    ("wrong_asset_received", include_str!("../golden/webhook_events/wrong_asset_received.json")),
+    ("tx_confirmed_late", include_str!("../golden/webhook_events/tx_confirmed_late.json")),
+    ("tx_rejected_late", include_str!("../golden/webhook_events/tx_rejected_late.json")),
+    ("delivery_test", include_str!("../golden/webhook_events/delivery_test.json")),
+    ("period_summary", include_str!("../golden/webhook_events/period_summary.json")),
+];
+
+pub fn example_payload(event_type: &str) -> Option<&'static str> {
+    EXAMPLE_PAYLOADS.iter()
+        .find(|(name, _)| *name == event_type)
+        .map(|(_, payload)| *payload)
+}
+
+// the events EXAMPLE_PAYLOADS were generated from, every optional field set
+pub fn examples() -> Vec<WebhookEvent> {
+    const INVOICE: &str = "0b7e4d2c-5f1a-4c3e-9a8b-1d2e3f4a5b6c";
+    const TX: &str = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060";
+    let at = |hour| Utc.with_ymd_and_hms(2026, 3, 1, hour, 0, 0).unwrap();
+    let usdt = TokenRef::new("ethereum", "USDT", "0xdAC17F958D2ee523a2206206994597C13D831ec7");
+
+    vec![
+        WebhookEvent::tx_detected(INVOICE, TX, "10.5", "USDT")
+            .with_token(usdt.clone())
+            .with_log_index(3)
+            .with_eta_secs(36)
+            .with_locale("en-US")
+            .with_display_currency("USD"),
+        WebhookEvent::tx_confirmed(INVOICE, TX, 12, at(12)).with_log_index(3),
+        WebhookEvent::invoice_paid(INVOICE, "10.5", at(12))
+            .with_accepted_shortfall("0.01")
+            .with_locale("en-US")
+            .with_display_currency("USD"),
+        WebhookEvent::invoice_expired(INVOICE)
+            .with_locale("en-US")
+            .with_display_currency("USD"),
+        WebhookEvent::invoice_expiring_soon(INVOICE, at(13))
+            .with_locale("en-US")
+            .with_display_currency("USD"),
+        WebhookEvent::invoice_stale(INVOICE, at(0), at(23))
+            .with_locale("en-US")
+            .with_display_currency("USD"),
+        WebhookEvent::wrong_asset_received(INVOICE, TX, "10.5",
+            TokenRef::new("ethereum", "USDC", "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"), usdt)
+            .with_log_index(3),
+        WebhookEvent::tx_confirmed_late(INVOICE, TX, at(11), at(12)).with_log_index(3),
+        WebhookEvent::tx_rejected_late(INVOICE, TX, "10.5", at(11), at(12)).with_log_index(3),
+        WebhookEvent::delivery_test(INVOICE, "a3f1c9e2-7b4d-4e8a-b6c5-2d1e0f9a8b7c", at(12)),
+        WebhookEvent::period_summary(7, ReportPeriod::Daily, at(0), at(0) + chrono::Duration::days(1),
+            vec![
+                TokenTotal { network: "ethereum".into(), token: "USDC".into(), count: 1, amount: "25.000000".into() },
+                TokenTotal { network: "ethereum".into(), token: "USDT".into(), count: 4, amount: "120.500000".into() },
+            ],
+            vec![TokenTotal { network: "ethereum".into(), token: "USDT".into(), count: 2, amount: "30.000000".into() }]),
+    ]
+}
+
+// strict decoding for merchant-side tests: serde alone skips unknown fields and defaults
+// missing optional ones, so a renamed field would go unnoticed. this fails unless the body
+// is exactly what the decoded event serializes back to. redacted payloads won't pass
+pub fn verify(body: &[u8]) -> anyhow::Result<WebhookEvent> {
+    let value: Value = serde_json::from_slice(body)?;
+
+    let Value::Object(envelope) = &value else {
+        anyhow::bail!("payload is not a JSON object");
+    };
+    if let Some(key) = envelope.keys().find(|k| *k != "event_type" && *k != "data") {
+        anyhow::bail!("unexpected top-level field '{}'", key);
+    }
+
+    let event: WebhookEvent = serde_json::from_value(value.clone())?;
+
+    let reencoded = serde_json::to_value(&event)?;
+    if reencoded != value {
+        let (Some(Value::Object(expected)), Some(Value::Object(got))) = (reencoded.get("data"), value.get("data")) else {
+            anyhow::bail!("payload doesn't round-trip");
+        };
+
+        let mut fields: Vec<&String> = expected.keys().chain(got.keys())
+            .filter(|k| expected.get(*k) != got.get(*k))
+            .collect();
+        fields.sort();
+        fields.dedup();
+
+        anyhow::bail!("{} fields don't round-trip: {:?}", event.event_type(), fields);
+    }
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use strum::VariantNames;
+
+    // UPDATE_GOLDEN=1 cargo test rewrites the files after an intended change to the payloads
+    #[test]
+    fn test_examples_match_golden_payloads() {
+        let examples = examples();
+        let types: HashSet<String> = examples.iter().map(WebhookEvent::event_type).collect();
+        assert_eq!(types.len(), WebhookEvent::VARIANTS.len(), "every variant needs an example");
+        assert_eq!(EXAMPLE_PAYLOADS.len(), examples.len());
+
+        for event in examples {
+            let event_type = event.event_type();
+            let rendered = serde_json::to_string_pretty(&event).unwrap() + "\n";
+
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                let path = format!("{}/golden/webhook_events/{}.json", env!("CARGO_MANIFEST_DIR"), event_type);
+                std::fs::write(path, &rendered).unwrap();
+                continue;
+            }
+
+            let golden = example_payload(&event_type)
+                .unwrap_or_else(|| panic!("no golden payload for {}", event_type));
+            assert_eq!(rendered, golden, "{} drifted from its golden payload", event_type);
+            assert_eq!(verify(golden.as_bytes()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn test_verify_rejects_drifted_payloads() {
+        let renamed = r#"{"event_type":"invoice_expired","data":{"invoiceId":"x"}}"#;
+        assert!(verify(renamed.as_bytes()).is_err());
+
+        let unknown = r#"{"event_type":"invoice_expired","data":{"invoice_id":"x","reason":"timeout"}}"#;
+        let err = verify(unknown.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("reason"), "{}", err);
+
+        let flattened = r#"{"event_type":"invoice_expired","invoice_id":"x"}"#;
+        assert!(verify(flattened.as_bytes()).is_err());
+
+        let minimal = r#"{"event_type":"invoice_expired","data":{"invoice_id":"x"}}"#;
+        assert_eq!(verify(minimal.as_bytes()).unwrap(), WebhookEvent::invoice_expired("x"));
+    }
+}
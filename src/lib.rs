@@ -1,6 +1,20 @@
 pub mod model;
+pub mod events;
 pub mod state;
 pub mod db;
 pub mod chain;
+pub mod notify;
+pub mod checkout;
+pub mod client;
+pub mod rates;
+pub mod settlement;
+pub mod signature;
+pub mod verify;
+pub mod secrets;
+pub mod token_registry;
+pub mod runtime;
+pub mod logging;
+pub mod prelude;
 
-pub use state::AppState;
\ No newline at end of file
+pub use state::AppState;
+pub use signature::verify_webhook_signature;
\ No newline at end of file
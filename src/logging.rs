@@ -0,0 +1,213 @@
+// per-subsystem verbosity for the embedding app's subscriber. the crate never installs one itself,
+// LogFilter goes in as a per-layer filter, e.g. fmt::layer().with_filter(state.log_filter.clone())
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+use strum::{Display, EnumString};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+// the background services, told apart by the root span each of them runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "PascalCase")]
+pub enum Subsystem {
+    Watcher,
+    Confirmator,
+    Janitor,
+    Webhook,
+    Listener,
+}
+
+impl Subsystem {
+    fn of_span(name: &str) -> Option<Self> {
+        match name {
+            "invoice_watcher_loop" => Some(Subsystem::Watcher),
+            "confirmator_service" => Some(Subsystem::Confirmator),
+            "janitor_service" => Some(Subsystem::Janitor),
+            "webhook_service" => Some(Subsystem::Webhook),
+            "chain_listener" => Some(Subsystem::Listener),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    pub default: LevelFilter, // outside the services and for those without an override
+    pub subsystems: HashMap<Subsystem, LevelFilter>,
+    pub chains: HashMap<String, LevelFilter>, // the chain's listener and watcher, wins over `subsystems`
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::INFO,
+            subsystems: HashMap::new(),
+            chains: HashMap::new(),
+        }
+    }
+}
+
+impl LogLevels {
+    fn level_of(&self, origin: &Origin) -> LevelFilter {
+        origin.chain.as_ref().and_then(|chain| self.chains.get(chain))
+            .or_else(|| self.subsystems.get(&origin.subsystem))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+// stored on the root span of a service
+struct Origin {
+    subsystem: Subsystem,
+    chain: Option<String>,
+}
+
+struct ChainField(Option<String>);
+
+impl Visit for ChainField {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "chain" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "chain" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+// levels changed through AppState apply to the next event. audit events always pass, turning a
+// service down mustn't hide what operators did
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    levels: Arc<RwLock<LogLevels>>,
+}
+
+impl LogFilter {
+    pub fn new(levels: LogLevels) -> Self {
+        Self { levels: Arc::new(RwLock::new(levels)) }
+    }
+
+    pub fn levels(&self) -> LogLevels {
+        self.levels.read().unwrap().clone()
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut LogLevels)) {
+        f(&mut self.levels.write().unwrap());
+    }
+}
+
+impl<S> Filter<S> for LogFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if meta.target() == "audit" {
+            return true;
+        }
+
+        // always recorded, or events below them couldn't be attributed
+        if meta.is_span() && Subsystem::of_span(meta.name()).is_some() {
+            return true;
+        }
+
+        let levels = self.levels.read().unwrap();
+        let max = cx.lookup_current()
+            .and_then(|span| span.scope()
+                .find_map(|s| s.extensions().get::<Origin>().map(|origin| levels.level_of(origin))))
+            .unwrap_or(levels.default);
+
+        *meta.level() <= max
+    }
+
+    // the answer changes with the levels, so nothing may be cached per callsite
+    fn callsite_enabled(&self, _meta: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, cx: Context<'_, S>) {
+        let Some(subsystem) = Subsystem::of_span(attrs.metadata().name()) else {
+            return;
+        };
+        let Some(span) = cx.span(id) else {
+            return;
+        };
+
+        let mut chain = ChainField(None);
+        attrs.record(&mut chain);
+
+        // another layer with a clone of this filter may have been first
+        let mut extensions = span.extensions_mut();
+        if extensions.get_mut::<Origin>().is_none() {
+            extensions.insert(Origin { subsystem, chain: chain.0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing::{debug, info, info_span};
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
+
+    #[derive(Clone, Default)]
+    struct Counter(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Counter {
+        fn on_event(&self, _event: &tracing::Event<'_>, _cx: tracing_subscriber::layer::Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_levels_per_subsystem_and_chain() {
+        let filter = LogFilter::default();
+        let counter = Counter::default();
+        let subscriber = || tracing_subscriber::registry()
+            .with(counter.clone().with_filter(filter.clone()));
+
+        let emit = || {
+            debug!("outside");
+            info_span!("confirmator_service").in_scope(|| {
+                info_span!("verify_payment").in_scope(|| debug!("confirmator"));
+            });
+            for chain in ["polygon", "base"] {
+                info_span!("chain_listener", chain = %chain).in_scope(|| debug!("listener"));
+            }
+            info_span!("janitor_service").in_scope(|| info!(target: "audit", "audited"));
+        };
+        let count = |levels: LogLevels| {
+            filter.update(|l| *l = levels);
+            counter.0.store(0, Ordering::Relaxed);
+            tracing::subscriber::with_default(subscriber(), emit);
+            counter.0.load(Ordering::Relaxed)
+        };
+
+        assert_eq!(count(LogLevels::default()), 1); // the audit event
+
+        let mut levels = LogLevels::default();
+        levels.subsystems.insert(Subsystem::Listener, LevelFilter::DEBUG);
+        levels.chains.insert("base".to_owned(), LevelFilter::WARN);
+        assert_eq!(count(levels.clone()), 2);
+
+        levels.subsystems.insert(Subsystem::Confirmator, LevelFilter::TRACE);
+        assert_eq!(count(levels), 3);
+
+        let everything = LogLevels { default: LevelFilter::DEBUG, ..Default::default() };
+        assert_eq!(count(everything), 5);
+
+        let silent = LogLevels { default: LevelFilter::OFF, ..Default::default() };
+        assert_eq!(count(silent), 1);
+    }
+}
@@ -1,10 +1,17 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use chrono::{DateTime, Utc};
-use alloy::primitives::{TxHash, U256};
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use chrono::{DateTime, Datelike, Utc};
+use alloy::primitives::{Address, Signature, TxHash, U256};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use coins_bip32::prelude::XPub;
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::types::Json;
-use strum::{AsRefStr, Display, EnumString};
+use strum::{AsRefStr, Display, EnumString, VariantNames};
+use url::Url;
 use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq, Deserialize, Serialize, ToSchema)]
@@ -12,12 +19,19 @@ pub struct TokenConfig {
     pub symbol: String,
     pub contract: String,
     pub decimals: u8,
+    #[serde(default)]
+    pub non_standard: bool, // Transfer events that don't decode as ERC-20 (indexed value, odd data), see decode_transfer
+    #[serde(default)]
+    #[schema(value_type = Option<String>, example = "1000000")]
+    pub min_amount: Option<U256>, // raw units, smaller invoices are rejected as dust
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ChainConfig {
     pub name: String,
-    pub rpc_url: String,
+    // by priority, later ones take over while the ones before them fail. EVM only past the first
+    #[serde(alias = "rpc_url", deserialize_with = "one_or_many")]
+    pub rpc_urls: Vec<String>,
     pub chain_type: ChainType,
     pub xpub: String,
     pub native_symbol: String,
@@ -25,14 +39,702 @@ pub struct ChainConfig {
     pub last_processed_block: u64,
     pub block_lag: u8,
     pub required_confirmations: u64,
+    #[serde(default)]
+    pub record_unknown_transfers: bool,
+    #[serde(default)]
+    pub resolve_smart_account_payers: bool, // one extra receipt lookup per detected payment
+    // stored encrypted, see crate::secrets. never serialized, so it doesn't leak through exports and APIs
+    #[serde(default, skip_serializing)]
+    pub rpc_auth: RpcAuth,
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    pub version: u64, // see PartialChainUpdate::expected_version
+    #[serde(default)]
+    pub test_mode: bool, // simulated instead of an RPC, fixed once the chain exists
+    #[serde(default = "default_max_address_index")]
+    pub max_address_index: u32, // invoices stop getting addresses past this, see AddressIndexExhausted
+    #[serde(default)]
+    pub heimdall_url: Option<String>, // polygon pos only, enables FinalityMode::Checkpoint
+    #[serde(default)]
+    pub block_tag: Option<BlockTag>, // EVM only, replaces block_lag and enables FinalityMode::FinalizedTag
+    #[serde(default)]
+    pub chain_id: Option<u64>, // EVM only, wallets pick the network from it in checkout links
+    // xpubs exported at each merchant's hardened account' level, `xpub` serves DEFAULT_ACCOUNT
+    #[serde(default)]
+    pub account_xpubs: BTreeMap<u32, String>,
 
     #[schema(ignore)]
     #[serde(skip)]
-    pub watch_addresses: Arc<RwLock<HashSet<String>>>,
+    pub(crate) watch_addresses: Arc<RwLock<HashSet<String>>>,
 
     #[schema(ignore)]
     #[serde(skip)]
-    pub tokens: Arc<RwLock<HashSet<TokenConfig>>>,
+    pub(crate) tokens: Arc<RwLock<HashSet<TokenConfig>>>,
+
+    // bumped after every change of watch_addresses or tokens, listeners re-snapshot when it moves
+    #[schema(ignore)]
+    #[serde(skip)]
+    pub(crate) generation: Arc<AtomicU64>,
+}
+
+impl ChainConfig {
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // the xpub addresses of `account` are derived from, see crate::chain::address::derive_pubkey
+    pub fn account_xpub(&self, account: u32) -> anyhow::Result<&str> {
+        if account == DEFAULT_ACCOUNT {
+            return Ok(&self.xpub);
+        }
+
+        self.account_xpubs.get(&account)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow::anyhow!("chain '{}' has no xpub for account {}", self.name, account))
+    }
+
+    pub fn watch_addresses(&self) -> Vec<String> {
+        self.watch_addresses.read().unwrap().iter().cloned().collect()
+    }
+
+    pub fn is_watching(&self, address: &str) -> bool {
+        self.watch_addresses.read().unwrap().contains(address)
+    }
+
+    pub fn tokens(&self) -> Vec<TokenConfig> {
+        self.tokens.read().unwrap().iter().cloned().collect()
+    }
+
+    pub fn update_watch_addresses<R>(&self, f: impl FnOnce(&mut HashSet<String>) -> R) -> R {
+        let result = f(&mut self.watch_addresses.write().unwrap());
+        self.bump_generation();
+        result
+    }
+
+    pub fn update_tokens<R>(&self, f: impl FnOnce(&mut HashSet<TokenConfig>) -> R) -> R {
+        let result = f(&mut self.tokens.write().unwrap());
+        self.bump_generation();
+        result
+    }
+
+    // for changes to the rest of the config, e.g. a partial chain update
+    pub(crate) fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // contract key of the token invoices call `symbol`, NATIVE_CONTRACT for the native coin
+    pub fn token_contract(&self, symbol: &str) -> Option<String> {
+        if symbol == self.native_symbol {
+            return Some(NATIVE_CONTRACT.to_owned());
+        }
+
+        self.tokens.read().unwrap().iter()
+            .find(|t| t.symbol == symbol)
+            .map(|t| contract_key(&t.contract))
+    }
+
+    pub fn token_by_contract(&self, contract: &str) -> Option<TokenConfig> {
+        let key = contract_key(contract);
+        self.tokens.read().unwrap().iter()
+            .find(|t| contract_key(&t.contract) == key)
+            .cloned()
+    }
+
+    pub fn decimals_by_contract(&self, contract: &str) -> Option<u8> {
+        let key = contract_key(contract);
+        if key == NATIVE_CONTRACT {
+            return Some(self.decimals);
+        }
+
+        self.tokens.read().unwrap().iter()
+            .find(|t| contract_key(&t.contract) == key)
+            .map(|t| t.decimals)
+    }
+
+    // settings the listener reads that differ from `stored`, by field name. the cursor,
+    // version, watch addresses and tokens are left out, they move on their own
+    pub fn drift_from(&self, stored: &ChainConfig) -> Vec<String> {
+        let mut fields = vec![];
+        let mut check = |name: &str, drifted: bool| if drifted {
+            fields.push(name.to_owned());
+        };
+
+        check("rpc_urls", self.rpc_urls != stored.rpc_urls);
+        check("xpub", self.xpub != stored.xpub);
+        check("account_xpubs", self.account_xpubs != stored.account_xpubs);
+        check("block_lag", self.block_lag != stored.block_lag);
+        check("required_confirmations", self.required_confirmations != stored.required_confirmations);
+        check("record_unknown_transfers", self.record_unknown_transfers != stored.record_unknown_transfers);
+        check("resolve_smart_account_payers",
+              self.resolve_smart_account_payers != stored.resolve_smart_account_payers);
+        check("rpc_auth", self.rpc_auth != stored.rpc_auth);
+        check("maintenance_windows", self.maintenance_windows != stored.maintenance_windows);
+        check("max_address_index", self.max_address_index != stored.max_address_index);
+        check("heimdall_url", self.heimdall_url != stored.heimdall_url);
+        check("block_tag", self.block_tag != stored.block_tag);
+        check("chain_id", self.chain_id != stored.chain_id);
+        check("test_mode", self.test_mode != stored.test_mode);
+
+        fields
+    }
+
+    // a contract is registered once per chain. symbols are display-only, native and bridged
+    // USDC may both be "USDC"
+    pub fn check_token_collision(&self, token: &TokenConfig) -> anyhow::Result<()> {
+        let key = contract_key(&token.contract);
+        for existing in self.tokens.read().unwrap().iter() {
+            if contract_key(&existing.contract) == key {
+                anyhow::bail!("contract {} is already registered on {} as '{}'",
+                    token.contract, self.name, existing.symbol);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// a token as payments are matched against it, the symbol is display-only
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct TokenRef {
+    pub chain: String,
+    pub symbol: String,
+    pub contract: String, // contract_key, NATIVE_CONTRACT for the native coin, empty if unknown
+}
+
+impl TokenRef {
+    pub fn new(chain: &str, symbol: &str, contract: &str) -> Self {
+        Self { chain: chain.to_owned(), symbol: symbol.to_owned(), contract: contract_key(contract) }
+    }
+
+    pub fn native(chain: &str, symbol: &str) -> Self {
+        Self::new(chain, symbol, NATIVE_CONTRACT)
+    }
+
+    // strict on contracts, symbols only count when a side doesn't know its contract
+    pub fn matches(&self, other: &TokenRef) -> bool {
+        if self.chain != other.chain {
+            return false;
+        }
+
+        match self.contract.is_empty() || other.contract.is_empty() {
+            true => self.symbol == other.symbol,
+            false => self.contract == other.contract,
+        }
+    }
+}
+
+impl std::fmt::Display for TokenRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on {} ({})", self.symbol, self.chain, self.contract)
+    }
+}
+
+// key of the native coin in per-contract lookups
+pub const NATIVE_CONTRACT: &str = "native";
+
+// EVM addresses differ in checksum casing only
+pub fn contract_key(contract: &str) -> String {
+    contract.trim().to_lowercase()
+}
+
+// where a new chain's listener begins, resolved once against the RPC when the chain is added
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartFrom {
+    #[default]
+    Latest,
+    Block(u64), // first block that gets processed
+    TimeAgo(Duration),
+}
+
+// credentials sent with every RPC request, for providers that don't take them in the URL
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RpcAuth {
+    #[default]
+    None,
+    Bearer { token: String },
+    Basic { username: String, password: String },
+    Headers { headers: BTreeMap<String, String> },
+}
+
+impl RpcAuth {
+    pub fn is_none(&self) -> bool {
+        matches!(self, RpcAuth::None)
+    }
+}
+
+// never prints the credentials
+impl std::fmt::Debug for RpcAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcAuth::None => f.write_str("None"),
+            RpcAuth::Bearer { .. } => f.write_str("Bearer(..)"),
+            RpcAuth::Basic { username, .. } => write!(f, "Basic({}:..)", username),
+            RpcAuth::Headers { headers } => f.debug_tuple("Headers")
+                .field(&headers.keys().collect::<Vec<_>>())
+                .finish(),
+        }
+    }
+}
+
+// listener pauses and confirmations are deferred while a window is active
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceWindow {
+    pub cron: String, // 5 fields, UTC, e.g. "0 2 * * 0" for sundays at 02:00
+    pub duration_secs: u64,
+}
+
+// 10^77 is the largest power of ten that still fits into U256
+pub const MAX_DECIMALS: u8 = 77;
+const MAX_SYMBOL_LEN: usize = 10;
+const MAX_RPC_URLS: usize = 8;
+
+fn validate_symbol(symbol: &str) -> anyhow::Result<()> {
+    if symbol.is_empty() || symbol.len() > MAX_SYMBOL_LEN {
+        anyhow::bail!("symbol '{}' must be 1 to {} characters long", symbol, MAX_SYMBOL_LEN);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_max_address_index(max_index: u32) -> anyhow::Result<()> {
+    if max_index > MAX_NON_HARDENED_INDEX {
+        anyhow::bail!("max_address_index must be at most {}, got {}", MAX_NON_HARDENED_INDEX, max_index);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_rpc_urls(urls: &[String], chain_type: ChainType) -> anyhow::Result<()> {
+    if urls.is_empty() {
+        anyhow::bail!("at least one rpc_url is required");
+    }
+    if urls.len() > MAX_RPC_URLS {
+        anyhow::bail!("at most {} rpc urls are supported, got {}", MAX_RPC_URLS, urls.len());
+    }
+    if urls.len() > 1 && chain_type != ChainType::EVM {
+        anyhow::bail!("fallback rpc urls are only supported on EVM chains");
+    }
+
+    for (i, url) in urls.iter().enumerate() {
+        Url::parse(url)
+            .map_err(|e| anyhow::anyhow!("invalid rpc_url '{}': {}", url, e))?;
+        if urls[..i].contains(url) {
+            anyhow::bail!("rpc_url '{}' is specified twice", url);
+        }
+    }
+
+    Ok(())
+}
+
+// a single string is still accepted where a list of urls used to be one
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(url) => vec![url],
+            OneOrMany::Many(urls) => urls,
+        }
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    OneOrMany::deserialize(deserializer).map(Into::into)
+}
+
+fn one_or_many_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    Option::<OneOrMany>::deserialize(deserializer).map(|v| v.map(Into::into))
+}
+
+pub fn validate_account_xpubs(account_xpubs: &BTreeMap<u32, String>) -> anyhow::Result<()> {
+    for (account, xpub) in account_xpubs {
+        if *account == DEFAULT_ACCOUNT {
+            anyhow::bail!("account {} uses the chain's xpub", DEFAULT_ACCOUNT);
+        }
+        XPub::from_str(xpub)
+            .map_err(|e| anyhow::anyhow!("invalid xpub of account {}: {}", account, e))?;
+    }
+
+    Ok(())
+}
+
+fn default_max_address_index() -> u32 {
+    MAX_NON_HARDENED_INDEX
+}
+
+fn validate_decimals(decimals: u8) -> anyhow::Result<()> {
+    if decimals > MAX_DECIMALS {
+        anyhow::bail!("decimals must be at most {}, got {}", MAX_DECIMALS, decimals);
+    }
+
+    Ok(())
+}
+
+impl TokenConfig {
+    pub fn builder() -> TokenConfigBuilder {
+        TokenConfigBuilder::default()
+    }
+
+    pub fn check_min_amount(&self, network: &str, amount_raw: U256) -> Result<(), InvoiceAmountError> {
+        match self.min_amount {
+            Some(minimum) if amount_raw < minimum => Err(InvoiceAmountError::BelowMinimum {
+                network: network.to_owned(),
+                token: self.symbol.clone(),
+                minimum,
+                amount: amount_raw,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvoiceAmountError {
+    Zero,
+    BelowMinimum { network: String, token: String, minimum: U256, amount: U256 },
+}
+
+impl std::fmt::Display for InvoiceAmountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvoiceAmountError::Zero => write!(f, "invoice amount must be greater than 0"),
+            InvoiceAmountError::BelowMinimum { network, token, minimum, amount } =>
+                write!(f, "invoice amount {} is below the minimum of {} for {} on {}",
+                    amount, minimum, token, network),
+        }
+    }
+}
+
+impl std::error::Error for InvoiceAmountError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct TokenConfigBuilder {
+    symbol: Option<String>,
+    contract: Option<String>,
+    decimals: Option<u8>,
+    non_standard: bool,
+    min_amount: Option<U256>,
+}
+
+impl TokenConfigBuilder {
+    pub fn symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_owned());
+        self
+    }
+
+    pub fn contract(mut self, contract: &str) -> Self {
+        self.contract = Some(contract.to_owned());
+        self
+    }
+
+    pub fn decimals(mut self, decimals: u8) -> Self {
+        self.decimals = Some(decimals);
+        self
+    }
+
+    pub fn non_standard(mut self, non_standard: bool) -> Self {
+        self.non_standard = non_standard;
+        self
+    }
+
+    pub fn min_amount(mut self, min_amount: U256) -> Self {
+        self.min_amount = Some(min_amount);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<TokenConfig> {
+        let symbol = self.symbol.ok_or_else(|| anyhow::anyhow!("token symbol is required"))?;
+        let contract = self.contract.ok_or_else(|| anyhow::anyhow!("token contract is required"))?;
+        let decimals = self.decimals.ok_or_else(|| anyhow::anyhow!("token decimals are required"))?;
+
+        validate_symbol(&symbol)?;
+        validate_decimals(decimals)?;
+
+        let contract = Address::from_str(&contract)
+            .map_err(|e| anyhow::anyhow!("invalid contract address '{}': {}", contract, e))?;
+
+        Ok(TokenConfig {
+            symbol,
+            contract: contract.to_string(),
+            decimals,
+            non_standard: self.non_standard,
+            min_amount: self.min_amount,
+        })
+    }
+}
+
+// what the chain says about a token before we start watching it
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct TokenPreflightReport {
+    pub contract_exists: bool,
+    pub emits_transfer: bool, // Transfer topic in the bytecode or seen in recent logs
+    pub onchain_decimals: Option<u8>, // None if decimals() reverted or isn't implemented
+    pub issues: Vec<String>,
+}
+
+impl TokenPreflightReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl ChainConfig {
+    pub fn builder() -> ChainConfigBuilder {
+        ChainConfigBuilder::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainConfigBuilder {
+    name: Option<String>,
+    rpc_urls: Vec<String>,
+    chain_type: ChainType,
+    xpub: Option<String>,
+    native_symbol: Option<String>,
+    decimals: u8,
+    last_processed_block: u64,
+    block_lag: u8,
+    required_confirmations: u64,
+    record_unknown_transfers: bool,
+    resolve_smart_account_payers: bool,
+    rpc_auth: RpcAuth,
+    maintenance_windows: Vec<MaintenanceWindow>,
+    test_mode: bool,
+    max_address_index: u32,
+    heimdall_url: Option<String>,
+    block_tag: Option<BlockTag>,
+    chain_id: Option<u64>,
+    account_xpubs: BTreeMap<u32, String>,
+    tokens: Vec<TokenConfig>,
+}
+
+impl Default for ChainConfigBuilder {
+    fn default() -> Self {
+        Self {
+            name: None,
+            rpc_urls: Vec::new(),
+            chain_type: ChainType::EVM,
+            xpub: None,
+            native_symbol: None,
+            decimals: 18,
+            last_processed_block: 0,
+            block_lag: 0,
+            required_confirmations: 1,
+            record_unknown_transfers: false,
+            resolve_smart_account_payers: false,
+            rpc_auth: RpcAuth::None,
+            maintenance_windows: Vec::new(),
+            test_mode: false,
+            max_address_index: MAX_NON_HARDENED_INDEX,
+            heimdall_url: None,
+            block_tag: None,
+            chain_id: None,
+            account_xpubs: BTreeMap::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+impl ChainConfigBuilder {
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    // called again for fallbacks, in order of priority
+    pub fn rpc_url(mut self, rpc_url: &str) -> Self {
+        self.rpc_urls.push(rpc_url.to_owned());
+        self
+    }
+
+    pub fn chain_type(mut self, chain_type: ChainType) -> Self {
+        self.chain_type = chain_type;
+        self
+    }
+
+    pub fn xpub(mut self, xpub: &str) -> Self {
+        self.xpub = Some(xpub.to_owned());
+        self
+    }
+
+    pub fn native_symbol(mut self, native_symbol: &str) -> Self {
+        self.native_symbol = Some(native_symbol.to_owned());
+        self
+    }
+
+    pub fn decimals(mut self, decimals: u8) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    pub fn last_processed_block(mut self, block: u64) -> Self {
+        self.last_processed_block = block;
+        self
+    }
+
+    pub fn block_lag(mut self, block_lag: u8) -> Self {
+        self.block_lag = block_lag;
+        self
+    }
+
+    pub fn required_confirmations(mut self, confirmations: u64) -> Self {
+        self.required_confirmations = confirmations;
+        self
+    }
+
+    pub fn record_unknown_transfers(mut self, record: bool) -> Self {
+        self.record_unknown_transfers = record;
+        self
+    }
+
+    pub fn resolve_smart_account_payers(mut self, resolve: bool) -> Self {
+        self.resolve_smart_account_payers = resolve;
+        self
+    }
+
+    pub fn rpc_auth(mut self, auth: RpcAuth) -> Self {
+        self.rpc_auth = auth;
+        self
+    }
+
+    pub fn maintenance_window(mut self, cron: &str, duration: Duration) -> Self {
+        self.maintenance_windows.push(MaintenanceWindow {
+            cron: cron.to_owned(),
+            duration_secs: duration.as_secs(),
+        });
+        self
+    }
+
+    pub fn test_mode(mut self, test_mode: bool) -> Self {
+        self.test_mode = test_mode;
+        self
+    }
+
+    pub fn max_address_index(mut self, max_index: u32) -> Self {
+        self.max_address_index = max_index;
+        self
+    }
+
+    // REST endpoint of a heimdall node, e.g. https://heimdall-api.polygon.technology
+    pub fn heimdall_url(mut self, url: &str) -> Self {
+        self.heimdall_url = Some(url.to_owned());
+        self
+    }
+
+    // for rollups like arbitrum, optimism or base, whose latest blocks the sequencer can still drop
+    pub fn block_tag(mut self, tag: BlockTag) -> Self {
+        self.block_tag = Some(tag);
+        self
+    }
+
+    // EIP-155 id, e.g. 1 for ethereum or 137 for polygon
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    // exported at the merchant's hardened level, e.g. m/44'/60'/account'
+    pub fn account_xpub(mut self, account: u32, xpub: &str) -> Self {
+        self.account_xpubs.insert(account, xpub.to_owned());
+        self
+    }
+
+    pub fn token(mut self, token: TokenConfig) -> Self {
+        self.tokens.push(token);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<ChainConfig> {
+        let name = self.name.ok_or_else(|| anyhow::anyhow!("chain name is required"))?;
+        let xpub = self.xpub.ok_or_else(|| anyhow::anyhow!("xpub is required"))?;
+        let native_symbol = self.native_symbol
+            .ok_or_else(|| anyhow::anyhow!("native_symbol is required"))?;
+
+        if name.is_empty() || name.len() > 50 {
+            anyhow::bail!("chain name must be 1 to 50 characters long");
+        }
+
+        validate_rpc_urls(&self.rpc_urls, self.chain_type)?;
+
+        XPub::from_str(&xpub)
+            .map_err(|e| anyhow::anyhow!("invalid xpub: {}", e))?;
+        validate_account_xpubs(&self.account_xpubs)?;
+
+        validate_symbol(&native_symbol)?;
+        validate_decimals(self.decimals)?;
+        validate_max_address_index(self.max_address_index)?;
+
+        if let Some(url) = &self.heimdall_url {
+            if self.chain_type != ChainType::EVM {
+                anyhow::bail!("heimdall_url is only supported on EVM chains");
+            }
+            Url::parse(url)
+                .map_err(|e| anyhow::anyhow!("invalid heimdall_url '{}': {}", url, e))?;
+        }
+
+        if self.block_tag.is_some() {
+            if self.chain_type != ChainType::EVM {
+                anyhow::bail!("block_tag is only supported on EVM chains");
+            }
+            if self.block_lag != 0 {
+                anyhow::bail!("block_lag can't be combined with block_tag");
+            }
+            // one source of finality per chain
+            if self.heimdall_url.is_some() {
+                anyhow::bail!("block_tag can't be combined with heimdall_url");
+            }
+        }
+
+        if self.chain_id.is_some() && self.chain_type != ChainType::EVM {
+            anyhow::bail!("chain_id is only supported on EVM chains");
+        }
+
+        for window in &self.maintenance_windows {
+            window.validate()?;
+        }
+
+        let config = ChainConfig {
+            name,
+            rpc_urls: self.rpc_urls,
+            chain_type: self.chain_type,
+            xpub,
+            native_symbol,
+            decimals: self.decimals,
+            last_processed_block: self.last_processed_block,
+            block_lag: self.block_lag,
+            required_confirmations: self.required_confirmations,
+            record_unknown_transfers: self.record_unknown_transfers,
+            resolve_smart_account_payers: self.resolve_smart_account_payers,
+            rpc_auth: self.rpc_auth,
+            maintenance_windows: self.maintenance_windows,
+            version: 0,
+            test_mode: self.test_mode,
+            max_address_index: self.max_address_index,
+            heimdall_url: self.heimdall_url,
+            block_tag: self.block_tag,
+            chain_id: self.chain_id,
+            account_xpubs: self.account_xpubs,
+            watch_addresses: Arc::new(RwLock::new(HashSet::new())),
+            tokens: Arc::new(RwLock::new(HashSet::new())),
+            generation: Default::default(),
+        };
+
+        // same rules as adding them one by one later, a contract or symbol is registered once
+        for token in self.tokens {
+            config.check_token_collision(&token)?;
+            config.tokens.write().unwrap().insert(token);
+        }
+
+        Ok(config)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -41,21 +743,365 @@ pub struct Payment {
     pub invoice_id: String,
     pub from: String,
     pub to: String,
+    pub payer: Option<String>, // smart account behind `from`, if it was resolved
     pub network: String,
     pub tx_hash: String,
     #[schema(value_type = String, example = "1000000000000000000")]
     pub amount_raw: U256,
     pub block_number: u64,
-    pub log_index: u64,
+    #[serde(default)]
+    pub block_timestamp: Option<DateTime<Utc>>, // unknown for payments detected before it was stored
+    pub log_index: Option<u64>, // position of the transfer log in its block, None for native transfers. output index on UTXO chains
     pub status: PaymentStatus,
     pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UnknownTransfer {
+    pub network: String,
+    pub contract: String,
+    pub tx_hash: String,
+    pub from: String,
+    pub to: String,
+    #[schema(value_type = String, example = "1000000000000000000")]
+    pub amount_raw: U256,
+    pub block_number: u64,
+    pub log_index: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+// a transfer to an invoice address in a token the invoice doesn't accept, kept until someone
+// refunds or dismisses it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct MisdirectedPayment {
+    pub id: String,
+    pub invoice_id: String,
+    pub token: TokenRef, // what was received
+    pub tx_hash: String,
+    pub from: String,
+    pub to: String,
+    pub amount: String,
+    #[schema(value_type = String, example = "1000000000000000000")]
+    pub amount_raw: U256,
+    pub block_number: u64,
+    pub log_index: Option<u64>,
+    pub status: MisdirectedStatus,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+// part of a confirmed payment sent back to the payer, a payment can be refunded in several parts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Refund {
+    pub id: String,
+    pub invoice_id: String,
+    pub payment_id: String,
+    #[schema(value_type = String, example = "1000000000000000000")]
+    pub amount_raw: U256,
+    pub tx_hash: Option<String>, // the outbound transfer, if it was sent on-chain
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefundExceedsPayment {
+    pub payment_id: String,
+    pub refundable: U256, // payment amount minus earlier refunds
+    pub requested: U256,
+}
+
+impl std::fmt::Display for RefundExceedsPayment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "refund of {} exceeds the {} still refundable on payment '{}'",
+            self.requested, self.refundable, self.payment_id)
+    }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, ToSchema,
+impl std::error::Error for RefundExceedsPayment {}
+
+// finalize_payment lost the race, another confirmator (or an earlier try of this one)
+// already moved the payment out of Confirming
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentAlreadyFinalized {
+    pub payment_id: String,
+    pub status: PaymentStatus,
+}
+
+impl std::fmt::Display for PaymentAlreadyFinalized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "payment '{}' is already {}", self.payment_id, self.status)
+    }
+}
+
+impl std::error::Error for PaymentAlreadyFinalized {}
+
+// every index up to ChainConfig::max_address_index is held by an open invoice or a reservation
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressIndexExhausted {
+    pub chain: String,
+    pub account_id: u32,
+    pub max_index: u32,
+}
+
+impl std::fmt::Display for AddressIndexExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chain '{}' has no free address index up to {} on account {}",
+            self.chain, self.max_index, self.account_id)
+    }
+}
+
+impl std::error::Error for AddressIndexExhausted {}
+
+// what one merchant may have at once on a shared deployment, None = unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Quota {
+    #[serde(default)]
+    pub max_pending_invoices: Option<u64>,
+    #[serde(default)]
+    pub max_invoices_per_day: Option<u64>, // created in the last 24 hours
+    #[serde(default)]
+    pub max_chains: Option<u64>, // chains with pending invoices of the merchant
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, Display)]
+pub enum QuotaKind {
+    PendingInvoices,
+    InvoicesPerDay,
+    Chains,
+}
+
+// the merchant's invoices as far as Quota counts them, test mode ones included. a live address
+// reservation counts as a pending invoice created now, it's about to become one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountUsage {
+    pub pending_invoices: u64,
+    pub invoices_last_day: u64,
+    pub pending_networks: Vec<String>,
+}
+
+impl Quota {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if [self.max_pending_invoices, self.max_invoices_per_day, self.max_chains].contains(&Some(0)) {
+            anyhow::bail!("quota limits must be at least 1, leave them out for no limit");
+        }
+
+        Ok(())
+    }
+
+    // whether one more invoice on `network` still fits
+    pub fn check(&self, account_id: u32, network: &str, usage: &AccountUsage) -> Result<(), QuotaExceeded> {
+        let new_network = !usage.pending_networks.iter().any(|n| n == network);
+
+        for (kind, limit, used) in [
+            (QuotaKind::PendingInvoices, self.max_pending_invoices, usage.pending_invoices),
+            (QuotaKind::InvoicesPerDay, self.max_invoices_per_day, usage.invoices_last_day),
+            (QuotaKind::Chains, self.max_chains.filter(|_| new_network), usage.pending_networks.len() as u64),
+        ] {
+            if let Some(limit) = limit && used >= limit {
+                return Err(QuotaExceeded { account_id, kind, limit });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    pub account_id: u32,
+    pub kind: QuotaKind,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "account {} is at its {} quota of {}", self.account_id, self.kind, self.limit)
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+// attached to API keys, each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Role {
+    Viewer, // reads
+    Operator, // invoices, settlements, refunds
+    Admin, // chains, tokens, service config and API keys
+}
+
+// per-merchant settings stored as JSON, one row per (account, kind)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum AccountSettingKind {
+    WebhookHostLimits,
+    Quota,
+    SettlementPreference,
+    RedactionPolicy,
+    ReportSubscription,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forbidden {
+    pub required: Role,
+    pub role: Option<Role>, // None = the key is unknown
+}
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.role {
+            Some(role) => write!(f, "this needs the {} role, the API key is {}", self.required, role),
+            None => write!(f, "unknown API key"),
+        }
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema,
+    Display, EnumString, AsRefStr)]
+pub enum MisdirectedStatus {
+    Open,
+    Refunded,
+    Dismissed,
+}
+
+// block timestamp of the payment that completed an invoice to the successful InvoicePaid
+// delivery, aggregated per chain/token like PaymentAnalytics
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct LatencyPercentiles {
+    pub network: String,
+    pub token: String,
+    pub payments_count: u64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+// one status of one merchant's invoices per chain/token, what a PeriodSummary is made of.
+// paid invoices count what was received, expired ones what was asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceTotals {
+    pub network: String,
+    pub token: String,
+    pub decimals: u8,
+    pub status: InvoiceStatus,
+    pub count: u64,
+    pub amount_raw: U256,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct TokenTotal {
+    pub network: String,
+    pub token: String,
+    pub count: u64,
+    pub amount: String,
+}
+
+// aggregated per chain/token, never carries invoice ids or addresses
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PaymentAnalytics {
+    pub network: String,
+    pub token: String,
+    pub payments_count: u64,
+    pub avg_confirmation_secs: Option<f64>,
+    pub avg_amount_deviation_pct: Option<f64>, // > 0 overpaid, < 0 underpaid
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema,
     Display, EnumString, AsRefStr)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum ChainType {
-    EVM
+    EVM,
+    LTC, // native segwit addresses
+    DOGE, // legacy P2PKH addresses
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[strum(serialize_all = "PascalCase")]
+pub enum FinalityMode {
+    #[default]
+    Confirmations, // final after min_confirmations blocks on top
+    Checkpoint, // final once a checkpoint on the parent chain covers the block, see ChainConfig::heimdall_url
+    FinalizedTag, // final once the node tags the block finalized, see ChainConfig::block_tag
+}
+
+// what an EVM listener treats as the head instead of `latest`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[strum(serialize_all = "PascalCase")]
+pub enum BlockTag {
+    Safe, // posted to the parent chain, reorgs only with it
+    Finalized,
+}
+
+// what a chain adapter can do, checked up front instead of failing inside the listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ChainCapabilities {
+    pub supports_tokens: bool,
+    pub supports_memo: bool,
+    pub supports_ws: bool,
+    pub finality_mode: FinalityMode,
+    pub min_confirmations: u64,
+}
+
+// how webhook deliveries reach merchants, for their allow-lists
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EgressInfo {
+    pub proxy: Option<String>, // without credentials
+    pub proxy_from_env: bool, // picked up from HTTPS_PROXY and friends instead of configured
+    pub source_addresses: Vec<String>, // empty if none are configured, the host's routing decides
+}
+
+// a running listener whose config no longer matches the chain's DB row
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ConfigDrift {
+    pub chain: String,
+    pub fields: Vec<String>, // see ChainConfig::drift_from
+    pub listener_version: u64,
+    pub stored_version: u64,
+}
+
+// live view of a chain for dashboards and checkout pages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ChainStatus {
+    pub network: String,
+    pub listening: bool,
+    pub in_maintenance: bool,
+    pub last_processed_block: u64,
+    pub required_confirmations: u64,
+    pub avg_block_time_ms: Option<u64>, // None until the listener has seen two blocks
+    #[serde(default)]
+    pub rpc_endpoints: Vec<RpcEndpointHealth>, // by priority, empty where the adapter doesn't track them
+}
+
+// one of ChainConfig::rpc_urls as the chain's requests see it. only the host is shown, providers
+// put API keys in the url
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RpcEndpointHealth {
+    pub host: String,
+    pub priority: usize, // index in rpc_urls
+    pub active: bool, // answered the last request
+    pub healthy: bool, // false while backing off after failures
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+impl ChainCapabilities {
+    pub fn ensure_token(&self, token: &str, native_symbol: &str) -> anyhow::Result<()> {
+        if token != native_symbol && !self.supports_tokens {
+            anyhow::bail!("chain doesn't support tokens, only {}", native_symbol);
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -64,11 +1110,13 @@ pub struct PaymentEvent {
     pub tx_hash: TxHash,
     pub from: String,
     pub to: String,
-    pub token: String,
+    pub payer: Option<String>,
+    pub token: TokenRef,
     pub amount: String,
     pub amount_raw: U256,
     pub decimals: u8,
     pub block_number: u64,
+    pub block_timestamp: Option<DateTime<Utc>>, // None if the node's block had no usable timestamp
     pub log_index: Option<u64>,
 }
 
@@ -87,11 +1135,24 @@ pub enum InvoiceStatus {
 pub enum PaymentStatus {
     Confirming,
     Confirmed,
+    Rejected, // confirmed after the deadline of a DeadlinePolicy::MustConfirmBeforeExpiry invoice
+}
+
+// what happens to a payment whose block was mined after the invoice's deadline (expires_at plus
+// the grace period). measured by the block timestamp, not by when the confirmator gets to it
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema,
+    Display, EnumString, AsRefStr)]
+pub enum DeadlinePolicy {
+    #[default]
+    HonorDetectedBeforeExpiry,
+    MustConfirmBeforeExpiry, // late confirmations are rejected, the funds need a refund
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct Invoice {
     pub id: String,
+    #[serde(default)]
+    pub account_id: u32, // HD branch of the merchant, see BlockchainAdapter::derive_address
     pub address_index: u32,
     pub address: String,
     pub amount: String,
@@ -100,28 +1161,344 @@ pub struct Invoice {
     pub paid: String,
     #[schema(value_type = String, example = "0")]
     pub paid_raw: U256,
-    pub token: String,
+    #[serde(default)]
+    #[schema(value_type = String, example = "0")]
+    pub refunded_raw: U256, // sum of the recorded refunds, paid_raw stays what was received
+    pub token: String, // symbol, display-only like TokenRef::symbol
+    #[serde(default)]
+    pub token_contract: String, // see TokenRef::contract, resolved from the symbol on insert if empty
     pub network: String,
     pub decimals: u8,
     pub webhook_url: Option<String>,
     pub webhook_secret: Option<String>,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
     pub status: InvoiceStatus,
+    pub expiry_warning_secs: Option<u64>,
+    // payments up to this long after expires_at still count and revive the invoice
+    #[serde(default)]
+    pub grace_period_secs: Option<u64>,
+    #[serde(default)]
+    pub deadline_policy: DeadlinePolicy,
+    // Checkpoint for high-value invoices, the chain has to support it
+    #[serde(default)]
+    pub finality_mode: FinalityMode,
+    pub split_schedule: Option<Vec<SplitShare>>,
+    pub locale: Option<String>, // BCP 47, e.g. "en-US"
+    pub display_currency: Option<String>, // ISO 4217, e.g. "EUR"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub webhook_events: Option<Vec<String>>, // event types to deliver, None = all of them
+    #[serde(default)]
+    pub reissued_from: Option<String>, // expired invoice this one replaces
+    #[serde(default)]
+    pub test_mode: bool, // must match the chain's, paid through AppState::simulate_payment
+}
+
+// every set field must match, an empty filter selects all invoices
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct InvoiceFilter {
+    pub network: Option<String>,
+    pub status: Option<InvoiceStatus>,
+    pub tag: Option<String>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+}
+
+impl InvoiceFilter {
+    pub fn matches(&self, invoice: &Invoice) -> bool {
+        self.network.as_ref().is_none_or(|n| *n == invoice.network)
+            && self.status.is_none_or(|s| s == invoice.status)
+            && self.tag.as_ref().is_none_or(|t| invoice.tags.contains(t))
+            && self.created_from.is_none_or(|from| invoice.created_at >= from)
+            && self.created_to.is_none_or(|to| invoice.created_at < to)
+    }
+}
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+// one page of a list API. next_cursor is None on the last page, total is only counted when
+// PageRequest::with_total asks for it since it scans every matching row
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PageRequest {
+    pub cursor: Option<String>, // next_cursor of the previous page, opaque
+    pub limit: Option<u32>, // DEFAULT_PAGE_SIZE if unset, at most MAX_PAGE_SIZE
+    #[serde(default)]
+    pub with_total: bool,
+}
+
+impl PageRequest {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+// position after `invoice` in a newest first listing. created_at alone isn't unique
+pub(crate) fn invoice_cursor(invoice: &Invoice) -> String {
+    format!("{}:{}", invoice.created_at.timestamp_micros(), invoice.id)
+}
+
+pub(crate) fn parse_invoice_cursor(cursor: &str) -> anyhow::Result<(DateTime<Utc>, String)> {
+    let parsed = cursor.split_once(':').and_then(|(micros, id)| {
+        let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+        let id = uuid::Uuid::parse_str(id).ok()?;
+        Some((created_at, id.to_string()))
+    });
+
+    parsed.ok_or_else(|| anyhow::anyhow!("invalid page cursor '{}'", cursor))
+}
+
+// invoices created before per-merchant accounts all live here
+pub const DEFAULT_ACCOUNT: u32 = 0;
+
+// indexes from 2^31 up are hardened and can't be derived from an xpub
+pub const MAX_NON_HARDENED_INDEX: u32 = (1 << 31) - 1;
+
+impl Invoice {
+    pub fn token_ref(&self) -> TokenRef {
+        TokenRef::new(&self.network, &self.token, &self.token_contract)
+    }
+
+    pub fn payment_deadline(&self) -> DateTime<Utc> {
+        self.expires_at + chrono::Duration::seconds(self.grace_period_secs.unwrap_or(0) as i64)
+    }
+
+    // what the merchant keeps after refunds
+    pub fn net_paid(&self) -> U256 {
+        self.paid_raw.saturating_sub(self.refunded_raw)
+    }
+
+    pub fn wants_webhook(&self, event: &WebhookEvent) -> bool {
+        event.is_test() || self.webhook_events.as_ref()
+            .is_none_or(|events| events.iter().any(|e| e == event.as_ref()))
+    }
+}
+
+#[cfg(test)]
+impl Invoice {
+    // pending 2 USDT on "testnet", tests override the rest with ..Invoice::for_test()
+    pub(crate) fn for_test() -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            account_id: DEFAULT_ACCOUNT,
+            address_index: 0,
+            address: "0x1111111111111111111111111111111111111111".to_owned(),
+            amount: "2.000000".to_owned(),
+            amount_raw: U256::from(2_000_000),
+            paid: "0".to_owned(),
+            paid_raw: U256::ZERO,
+            refunded_raw: U256::ZERO,
+            token: "USDT".to_owned(),
+            token_contract: String::new(),
+            network: "testnet".to_owned(),
+            decimals: 6,
+            webhook_url: None,
+            webhook_secret: None,
+            created_at: Utc::now(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+            paid_at: None,
+            status: InvoiceStatus::Pending,
+            expiry_warning_secs: None,
+            grace_period_secs: None,
+            deadline_policy: Default::default(),
+            finality_mode: Default::default(),
+            split_schedule: None,
+            locale: None,
+            display_currency: None,
+            tags: vec![],
+            webhook_events: None,
+            reissued_from: None,
+            test_mode: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct DeepLinkTemplate {
+    pub wallet: String,
+    pub template: String, // see checkout::render_template for placeholders
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct DeepLink {
+    pub wallet: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct CheckoutPayload {
+    pub invoice_id: String,
+    pub address: String,
+    pub network: String,
+    pub token: String,
+    pub contract: Option<String>, // None for the native coin
+    pub amount_due: String,
+    pub amount_due_raw: String,
+    pub expires_at: DateTime<Utc>,
+    pub locale: Option<String>,
+    pub display_currency: Option<String>,
+    pub deep_links: Vec<DeepLink>,
+}
+
+// incident response switch, sweeps/refunds/payouts must check it before sending anything
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct OutboundFreeze {
+    pub frozen: bool,
+    pub reason: Option<String>,
+    pub changed_at: Option<DateTime<Utc>>, // None if it was never toggled
+}
+
+// lets an auditor re-derive a deposit address from the merchant's xpub without our DB.
+// signed with a secp256k1 key whose address is published, so checking a proof needs no secret
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct AddressOwnershipProof {
+    pub invoice_id: String,
+    pub network: String,
+    pub address: String,
+    pub xpub_fingerprint: String, // BIP32 fingerprint of the account's xpub, hex
+    pub derivation_path: String, // relative to that xpub, e.g. "m/15"
+    pub issued_at: DateTime<Utc>,
+    pub signer: String, // address of the attestation key
+    pub signature: String, // hex EIP-191 signature of statement(), recovers to `signer`
+}
+
+impl AddressOwnershipProof {
+    pub fn statement(&self) -> String {
+        format!("necko-address-proof:v1\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.invoice_id, self.network, self.address, self.xpub_fingerprint,
+            self.derivation_path, self.issued_at.to_rfc3339())
+    }
+
+    pub fn sign(mut self, key: &PrivateKeySigner) -> anyhow::Result<Self> {
+        let signature = key.sign_message_sync(self.statement().as_bytes())?;
+        self.signer = key.address().to_string();
+        self.signature = hex::encode(signature.as_bytes());
+
+        Ok(self)
+    }
+
+    // `signer` comes from the proof itself, callers pin it to the published attestation address
+    pub fn verify(&self, signer: &Address) -> bool {
+        let Ok(signature) = Signature::from_str(&self.signature) else {
+            return false;
+        };
+
+        Address::from_str(&self.signer).ok() == Some(*signer)
+            && signature.recover_address_from_msg(self.statement()).ok() == Some(*signer)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct SplitShare {
+    pub address: String,
+    pub bps: u16, // basis points, shares of one schedule must sum up to 10000
+}
+
+pub const SPLIT_TOTAL_BPS: u32 = 10_000;
+
+pub fn validate_webhook_events(events: &[String]) -> anyhow::Result<()> {
+    if let Some(unknown) = events.iter().find(|e| !WebhookEvent::VARIANTS.contains(&e.as_str())) {
+        anyhow::bail!("unknown webhook event type '{}', expected one of {:?}",
+            unknown, WebhookEvent::VARIANTS);
+    }
+
+    Ok(())
+}
+
+// stored as BIGINT seconds and subtracted from expires_at, a warning can't be longer than this
+pub const MAX_EXPIRY_WARNING_SECS: u64 = 30 * 24 * 60 * 60;
+
+pub fn validate_expiry_warning(secs: u64) -> anyhow::Result<()> {
+    if secs == 0 || secs > MAX_EXPIRY_WARNING_SECS {
+        anyhow::bail!("expiry warning must be between 1 and {} seconds, got {}", MAX_EXPIRY_WARNING_SECS, secs);
+    }
+
+    Ok(())
+}
+
+pub fn validate_split_schedule(schedule: &[SplitShare]) -> anyhow::Result<()> {
+    if schedule.is_empty() {
+        anyhow::bail!("split schedule must contain at least one recipient");
+    }
+
+    if let Some(share) = schedule.iter().find(|s| s.bps == 0) {
+        anyhow::bail!("split share for {} must be greater than 0", share.address);
+    }
+
+    let total: u32 = schedule.iter().map(|s| s.bps as u32).sum();
+    if total != SPLIT_TOTAL_BPS {
+        anyhow::bail!("split shares must sum up to {} bps, got {}", SPLIT_TOTAL_BPS, total);
+    }
+
+    Ok(())
+}
+
+// rounding dust goes to the first recipient so the legs always add up to `amount`
+pub fn split_amount(schedule: &[SplitShare], amount: U256) -> anyhow::Result<Vec<(String, U256)>> {
+    validate_split_schedule(schedule)?;
+
+    let mut legs: Vec<(String, U256)> = schedule.iter()
+        .map(|s| (s.address.clone(), amount * U256::from(s.bps) / U256::from(SPLIT_TOTAL_BPS)))
+        .collect();
+
+    let distributed = legs.iter().fold(U256::ZERO, |acc, (_, a)| acc + *a);
+    legs[0].1 += amount - distributed;
+
+    Ok(legs)
 }
 
 #[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
 pub struct PartialChainUpdate {
-    pub rpc_url: Option<String>,
+    #[serde(default, alias = "rpc_url", deserialize_with = "one_or_many_opt")]
+    pub rpc_urls: Option<Vec<String>>, // replaces the whole list
     pub last_processed_block: Option<u64>,
     pub xpub: Option<String>,
     pub block_lag: Option<u8>,
     pub required_confirmations: Option<u64>,
+    pub record_unknown_transfers: Option<bool>,
+    #[serde(default)]
+    pub resolve_smart_account_payers: Option<bool>,
+    #[serde(default)]
+    pub rpc_auth: Option<RpcAuth>, // Some(RpcAuth::None) removes the credentials
+    pub maintenance_windows: Option<Vec<MaintenanceWindow>>,
+    #[serde(default)]
+    pub max_address_index: Option<u32>,
+    // compare-and-set against ChainConfig::version, None writes unconditionally
+    #[serde(default)]
+    pub expected_version: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainVersionConflict {
+    pub chain: String,
+    pub expected: u64,
 }
 
+impl std::fmt::Display for ChainVersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chain '{}' was modified concurrently, it is no longer at version {}",
+            self.chain, self.expected)
+    }
+}
+
+impl std::error::Error for ChainVersionConflict {}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct WebhookJob {
     pub id: uuid::Uuid,
+    pub delivery_token: uuid::Uuid, // stays the same across retries of one job
+    pub account_id: i32,
+    pub sequence: i64, // per invoice, starts at 1, lets consumers spot gaps and reorder
     pub url: String,
     pub secret_key: String,
     pub payload: Json<WebhookEvent>,
@@ -129,8 +1506,45 @@ pub struct WebhookJob {
     pub max_retries: i32,
 }
 
+// an undelivered webhook, without claiming it like select_webhooks_job does
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PendingWebhook {
+    pub invoice_id: String,
+    pub event: WebhookEvent,
+    pub attempts: u32,
+}
+
+pub const STATE_SNAPSHOT_VERSION: u32 = 1;
+
+// what export_state writes, enough to resume payment processing in another environment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub chains: Vec<ChainSnapshot>,
+    pub invoices: Vec<Invoice>, // pending only
+    pub payments: Vec<Payment>, // confirming only
+    pub webhooks: Vec<PendingWebhook>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainSnapshot {
+    pub config: ChainConfig,
+    pub tokens: Vec<TokenConfig>,
+}
+
+// rows created by import_state, anything that already existed is left alone
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub chains: u64,
+    pub tokens: u64,
+    pub invoices: u64,
+    pub payments: u64,
+    pub webhooks: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema,
-    Display, EnumString, AsRefStr)]
+    Display, EnumString, AsRefStr, VariantNames)]
 #[serde(tag = "event_type", content = "data", rename_all = "snake_case")]
 pub enum WebhookEvent {
     TxDetected {
@@ -138,21 +1552,275 @@ pub enum WebhookEvent {
         tx_hash: String,
         amount: String,
         currency: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<TokenRef>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        log_index: Option<u64>,
+        // until TxConfirmed, estimated from the average block time of the chain
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        eta_secs: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display_currency: Option<String>,
     },
     TxConfirmed {
         invoice_id: String,
         tx_hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        log_index: Option<u64>,
         confirmations: u64,
+        confirmed_at: DateTime<Utc>,
     },
     InvoicePaid {
         invoice_id: String,
         paid_amount: String,
+        paid_at: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        accepted_shortfall: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display_currency: Option<String>,
     },
     InvoiceExpired {
         invoice_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display_currency: Option<String>,
+    },
+    InvoiceExpiringSoon {
+        invoice_id: String,
+        expires_at: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display_currency: Option<String>,
+    },
+    // still Pending without a detected payment long after creation, see ServicesConfig::stale_invoice_age
+    InvoiceStale {
+        invoice_id: String,
+        created_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display_currency: Option<String>,
+    },
+    // funds in another token reached the invoice address, they are not credited
+    WrongAssetReceived {
+        invoice_id: String,
+        tx_hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        log_index: Option<u64>,
+        amount: String,
+        received: TokenRef,
+        expected: TokenRef,
+    },
+    // a payment confirmed after the invoice's deadline that was still credited
+    TxConfirmedLate {
+        invoice_id: String,
+        tx_hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        log_index: Option<u64>,
+        deadline: DateTime<Utc>,
+        confirmed_at: DateTime<Utc>,
+    },
+    // same, but the invoice uses DeadlinePolicy::MustConfirmBeforeExpiry and the payment
+    // was not credited
+    TxRejectedLate {
+        invoice_id: String,
+        tx_hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        log_index: Option<u64>,
+        amount: String,
+        deadline: DateTime<Utc>,
+        confirmed_at: DateTime<Utc>,
+    },
+    // sent on request to check the endpoint and its signature verification, never filtered out
+    DeliveryTest {
+        invoice_id: String,
+        test_id: String,
+        sent_at: DateTime<Utc>,
+    },
+    // to the merchant's ReportSubscription instead of an invoice's webhook_url. test mode
+    // invoices are left out
+    PeriodSummary {
+        account_id: u32,
+        period: ReportPeriod,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        paid_count: u64,
+        expired_count: u64,
+        paid: Vec<TokenTotal>,
+        expired: Vec<TokenTotal>,
+        top_tokens: Vec<String>, // symbols by paid invoices, most first
     },
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorCode {
+    WebhookRejected, // endpoint answered with a 4xx
+    WebhookUnavailable, // 5xx, 408 or 429
+    WebhookUnreachable, // network error or timeout
+    DatabaseUnavailable,
+    ListenerFailed,
+}
+
+impl ErrorCode {
+    pub fn retryable(&self) -> bool {
+        !matches!(self, ErrorCode::WebhookRejected)
+    }
+}
+
+// machine-readable error for operator-facing events, branch on `code` instead of `message`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ErrorEnvelope {
+    pub code: ErrorCode,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl ErrorEnvelope {
+    pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+        Self { code, message: message.to_string(), retryable: code.retryable() }
+    }
+
+    pub fn webhook_status(status: u16) -> Self {
+        let code = match status {
+            408 | 429 | 500.. => ErrorCode::WebhookUnavailable,
+            _ => ErrorCode::WebhookRejected,
+        };
+
+        Self::new(code, format!("HTTP Status {}", status))
+    }
+}
+
+impl std::fmt::Display for ErrorEnvelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+// applied to the event `data` right before a webhook is signed and sent
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct RedactionPolicy {
+    pub allowed_fields: Option<Vec<String>>, // None = keep everything, invoice_id is always kept
+    #[serde(default)]
+    pub hashed_fields: Vec<String>, // replaced by an HMAC keyed with the webhook secret
+}
+
+// the token a merchant ultimately wants to hold, whatever the customer paid with
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct SettlementPreference {
+    pub currency: String, // token symbol, e.g. "USDC"
+    pub network: Option<String>, // None = stay on the network the invoice was paid on
+}
+
+impl SettlementPreference {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        validate_symbol(&self.currency)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ReportPeriod {
+    #[default]
+    Daily,
+    Weekly, // monday to monday
+}
+
+impl ReportPeriod {
+    // the last period that ended at or before `now`, boundaries are midnight UTC
+    pub fn last_complete(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let midnight = now.date_naive().and_time(chrono::NaiveTime::MIN).and_utc();
+
+        let (to, length) = match self {
+            ReportPeriod::Daily => (midnight, chrono::Duration::days(1)),
+            ReportPeriod::Weekly => (
+                midnight - chrono::Duration::days(now.weekday().num_days_from_monday() as i64),
+                chrono::Duration::weeks(1),
+            ),
+        };
+
+        (to - length, to)
+    }
+}
+
+// where a merchant gets its WebhookEvent::PeriodSummary, invoices keep their own webhook_url
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct ReportSubscription {
+    pub period: ReportPeriod,
+    pub url: String,
+    pub secret: String, // signs the deliveries like an invoice's webhook_secret
+}
+
+impl ReportSubscription {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let url = Url::parse(&self.url)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("report url must be http(s), got {}", url.scheme());
+        }
+
+        if self.secret.is_empty() {
+            anyhow::bail!("report secret must not be empty");
+        }
+
+        Ok(())
+    }
+}
+
+const WEBHOOK_SUPPRESSION_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+impl WebhookEvent {
+    // tx events may legitimately repeat per invoice (several payments), the rest happen once
+    pub fn is_repeating(&self) -> bool {
+        matches!(self, WebhookEvent::TxDetected { .. } | WebhookEvent::TxConfirmed { .. }
+            | WebhookEvent::TxConfirmedLate { .. } | WebhookEvent::TxRejectedLate { .. }
+            | WebhookEvent::WrongAssetReceived { .. } | WebhookEvent::DeliveryTest { .. })
+    }
+
+    pub fn is_test(&self) -> bool {
+        matches!(self, WebhookEvent::DeliveryTest { .. })
+    }
+
+    pub fn dedupe_key(&self) -> String {
+        match self {
+            // one transaction can carry several transfers to the same invoice
+            WebhookEvent::TxDetected { tx_hash, log_index, .. }
+            | WebhookEvent::TxConfirmed { tx_hash, log_index, .. }
+            | WebhookEvent::TxConfirmedLate { tx_hash, log_index, .. }
+            | WebhookEvent::TxRejectedLate { tx_hash, log_index, .. }
+            | WebhookEvent::WrongAssetReceived { tx_hash, log_index, .. } => match log_index {
+                Some(log_index) => format!("{}:{}", tx_hash, log_index),
+                None => tx_hash.clone(),
+            },
+            // one-shot per transition, an invoice paid again after a reorg or whose expiry was
+            // moved gets a new key
+            WebhookEvent::InvoicePaid { paid_at, .. } => format!("{}:{}", self.as_ref(), paid_at.timestamp_micros()),
+            WebhookEvent::InvoiceExpiringSoon { expires_at, .. }
+            | WebhookEvent::InvoiceStale { expires_at, .. } => {
+                format!("{}:{}", self.as_ref(), expires_at.timestamp_micros())
+            }
+            WebhookEvent::DeliveryTest { test_id, .. } => test_id.clone(),
+            WebhookEvent::PeriodSummary { period, from, .. } => format!("{}:{}", period, from.timestamp()),
+            _ => self.as_ref().to_owned(),
+        }
+    }
+
+    // same repeating event with the same key is dropped if enqueued again within this window
+    pub fn suppression_window(&self) -> Option<Duration> {
+        self.is_repeating().then_some(WEBHOOK_SUPPRESSION_WINDOW)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema,
     Display, EnumString, AsRefStr)]
 #[strum(serialize_all = "PascalCase")]
@@ -160,5 +1828,176 @@ pub enum WebhookStatus {
     Pending,
     Processing,
     Sent,
-    Failed
-}
\ No newline at end of file
+    Failed,
+    Suppressed, // its url was suspended, see WebhookDestination
+}
+
+// a webhook url with failed deliveries since its last success. once suspended, its jobs are
+// Suppressed instead of sent until an operator resumes it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct WebhookDestination {
+    pub url: String,
+    pub failure_streak: u32,
+    pub failing_since: DateTime<Utc>,
+    pub last_error: String,
+    pub suspended_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema,
+    Display, EnumString, AsRefStr)]
+#[strum(serialize_all = "PascalCase")]
+pub enum ChainErrorKind {
+    Rpc,
+    Processing,
+    Listener,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChainError {
+    pub network: String,
+    pub kind: ChainErrorKind,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// increments the listener accumulated since its last flush
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChainStatsDelta {
+    pub blocks: u64,
+    pub events: u64,
+    pub processing_ms: u64,
+    pub last_event_at: Option<DateTime<Utc>>,
+}
+
+impl ChainStatsDelta {
+    pub fn is_empty(&self) -> bool {
+        self.blocks == 0 && self.events == 0
+    }
+
+    pub fn merge(&mut self, other: &ChainStatsDelta) {
+        self.blocks += other.blocks;
+        self.events += other.events;
+        self.processing_ms += other.processing_ms;
+        self.last_event_at = self.last_event_at.max(other.last_event_at);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ChainStats {
+    pub network: String,
+    pub blocks_processed: u64,
+    pub events_emitted: u64,
+    pub avg_block_ms: f64,
+    pub last_event_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ChainStats {
+    pub fn avg_block_ms(processing_ms_total: u64, blocks_processed: u64) -> f64 {
+        if blocks_processed == 0 {
+            return 0.0;
+        }
+
+        processing_ms_total as f64 / blocks_processed as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PoolMetrics {
+    pub size: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub max_connections: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const USDT: &str = "0x3333333333333333333333333333333333333333";
+
+    fn token(symbol: &str, contract: &str) -> TokenConfig {
+        TokenConfig::builder().symbol(symbol).contract(contract).decimals(6).build().unwrap()
+    }
+
+    fn chain() -> ChainConfigBuilder {
+        ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+    }
+
+    #[test]
+    fn test_token_config_builder() {
+        let usdt = token("USDT", USDT);
+        assert_eq!((usdt.decimals, usdt.non_standard, usdt.min_amount), (6, false, None));
+
+        assert!(TokenConfig::builder().symbol("USDT").decimals(6).build().is_err());
+        assert!(TokenConfig::builder().symbol("USDT").contract("0x1234").decimals(6).build().is_err());
+        assert!(TokenConfig::builder().symbol("").contract(USDT).decimals(6).build().is_err());
+        assert!(TokenConfig::builder().symbol("USDT").contract(USDT).decimals(MAX_DECIMALS + 1).build().is_err());
+    }
+
+    #[test]
+    fn test_chain_config_builder() {
+        let config = chain().token(token("USDT", USDT)).build().unwrap();
+        assert_eq!(config.tokens.read().unwrap().len(), 1);
+        assert!(config.watch_addresses.read().unwrap().is_empty());
+
+        assert!(chain().xpub("xpub-nope").build().is_err());
+        assert!(chain().name("").build().is_err());
+        assert!(ChainConfig::builder().name("testnet").xpub(XPUB).native_symbol("ETH").rpc_url("not a url").build().is_err());
+    }
+
+    #[test]
+    fn test_chain_config_builder_rejects_duplicate_tokens() {
+        let usdt = token("USDT", USDT);
+
+        // same contract under another symbol, or with other settings
+        let renamed = token("USDT0", USDT);
+        assert!(chain().token(usdt.clone()).token(renamed).build().is_err());
+        let lowercase = TokenConfig { decimals: 18, contract: USDT.to_lowercase(), ..usdt.clone() };
+        assert!(chain().token(usdt.clone()).token(lowercase).build().is_err());
+
+        let usdc = token("USDC", "0x4444444444444444444444444444444444444444");
+        assert_eq!(chain().token(usdt).token(usdc).build().unwrap().tokens.read().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_chain_config_builder_accepts_same_symbol_on_other_contracts() {
+        let native = token("USDC", USDT);
+        let bridged = token("USDC", "0x4444444444444444444444444444444444444444");
+
+        let config = chain().token(native).token(bridged).build().unwrap();
+        assert_eq!(config.tokens.read().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_address_ownership_proof_verifies_against_the_signer() {
+        let key = PrivateKeySigner::from_str("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap();
+        let proof = AddressOwnershipProof {
+            invoice_id: "inv".to_owned(),
+            network: "testnet".to_owned(),
+            address: USDT.to_owned(),
+            xpub_fingerprint: "bef5a2f9".to_owned(),
+            derivation_path: "m/5".to_owned(),
+            issued_at: Utc::now(),
+            signer: String::new(),
+            signature: String::new(),
+        }.sign(&key).unwrap();
+
+        assert_eq!(proof.signer, key.address().to_string());
+        assert!(proof.verify(&key.address()));
+        assert!(!proof.verify(&PrivateKeySigner::random().address()));
+
+        let tampered = AddressOwnershipProof { derivation_path: "m/6".to_owned(), ..proof.clone() };
+        assert!(!tampered.verify(&key.address()));
+        // claiming another signer doesn't help, the signature recovers to the real one
+        let other = PrivateKeySigner::random().address();
+        let relabeled = AddressOwnershipProof { signer: other.to_string(), ..proof };
+        assert!(!relabeled.verify(&other));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::notify::{Alert, NotifierAdapter};
+use reqwest::Client;
+use serde_json::json;
+use std::time::Duration;
+
+// sends through an HTTP mail API (bearer-authenticated JSON endpoint),
+// we don't speak SMTP ourselves
+#[derive(Debug, Clone)]
+pub struct EmailNotifier {
+    client: Client,
+    api_url: String,
+    api_key: String,
+    from: String,
+    to: Vec<String>,
+}
+
+impl EmailNotifier {
+    pub fn new(api_url: &str, api_key: &str, from: &str, to: &[String]) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: api_url.to_owned(),
+            api_key: api_key.to_owned(),
+            from: from.to_owned(),
+            to: to.to_vec(),
+        }
+    }
+}
+
+impl NotifierAdapter for EmailNotifier {
+    async fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        self.client
+            .post(&self.api_url)
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "from": self.from,
+                "to": self.to,
+                "subject": format!("[necko3] {} {}", alert.severity(), alert),
+                "text": alert.message(),
+            }))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
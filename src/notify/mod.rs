@@ -0,0 +1,203 @@
+use crate::model::ErrorEnvelope;
+use crate::notify::email::EmailNotifier;
+use crate::notify::slack::SlackNotifier;
+use crate::notify::telegram::TelegramNotifier;
+use crate::notify::webhook::WebhookNotifier;
+use serde::Serialize;
+use std::future::Future;
+use strum::{AsRefStr, Display};
+
+pub mod slack;
+pub mod telegram;
+pub mod email;
+pub mod webhook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Display, AsRefStr)]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum AlertSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+// operator-facing alerts, never sent to merchants
+#[derive(Debug, Clone, Serialize, Display, AsRefStr)]
+#[serde(tag = "alert_type", content = "data", rename_all = "snake_case")]
+pub enum Alert {
+    ChainStalled {
+        chain: String,
+        last_processed_block: u64,
+        stalled_for_secs: u64,
+    },
+    ChainListenerStarted {
+        chain: String,
+        from_block: u64,
+    },
+    ChainListenerDied {
+        chain: String,
+        error: ErrorEnvelope,
+    },
+    ChainListenerRestarted {
+        chain: String,
+        from_block: u64,
+    },
+    ChainResynced {
+        chain: String,
+        previous_block: u64,
+        from_block: u64,
+    },
+    WebhookDeadLettered {
+        job_id: String,
+        url: String,
+        attempts: i32,
+        error: ErrorEnvelope,
+    },
+    DatabaseDegraded {
+        service: String,
+        error: ErrorEnvelope,
+    },
+    PaidVolumeAnomaly {
+        chain: String,
+        spike: bool, // false = drop
+        last_hour_count: u64,
+        baseline_hourly: f64,
+    },
+    RateSourceDegraded {
+        pair: String,
+        sources: Vec<String>,
+        deviation_pct: f64,
+    },
+    AddressIndexExhaustion {
+        chain: String,
+        account_id: u32,
+        index: u32, // the index just handed out, or max_index once none is left
+        max_index: u32,
+    },
+    ChainConfigDrift {
+        chain: String,
+        fields: Vec<String>, // differing between the DB row and the running listener
+        reconciled: bool,
+    },
+    WebhookDestinationSuspended {
+        url: String,
+        failures: u32,
+        failing_for_secs: u64,
+        suppressed: u64, // queued jobs that won't be sent
+    },
+}
+
+impl Alert {
+    pub fn severity(&self) -> AlertSeverity {
+        match self {
+            Alert::ChainStalled { .. } => AlertSeverity::Warning,
+            Alert::ChainListenerStarted { .. } => AlertSeverity::Info,
+            Alert::ChainListenerDied { .. } => AlertSeverity::Critical,
+            Alert::ChainListenerRestarted { .. } => AlertSeverity::Info,
+            Alert::ChainResynced { .. } => AlertSeverity::Warning,
+            Alert::WebhookDeadLettered { .. } => AlertSeverity::Warning,
+            Alert::WebhookDestinationSuspended { .. } => AlertSeverity::Warning,
+            Alert::DatabaseDegraded { .. } => AlertSeverity::Critical,
+            Alert::PaidVolumeAnomaly { .. } => AlertSeverity::Warning,
+            Alert::RateSourceDegraded { .. } => AlertSeverity::Critical,
+            Alert::AddressIndexExhaustion { index, max_index, .. } if index >= max_index =>
+                AlertSeverity::Critical,
+            Alert::AddressIndexExhaustion { .. } => AlertSeverity::Warning,
+            Alert::ChainConfigDrift { .. } => AlertSeverity::Warning,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let text = match self {
+            Alert::ChainStalled { chain, last_processed_block, stalled_for_secs } =>
+                format!("Chain '{}' is stuck at block {} for {}s",
+                        chain, last_processed_block, stalled_for_secs),
+            Alert::ChainListenerStarted { chain, from_block } =>
+                format!("Listener for chain '{}' started from block {}", chain, from_block),
+            Alert::ChainListenerDied { chain, error } =>
+                format!("Listener for chain '{}' died: {}", chain, error),
+            Alert::ChainListenerRestarted { chain, from_block } =>
+                format!("Listener for chain '{}' restarted from block {}", chain, from_block),
+            Alert::ChainResynced { chain, previous_block, from_block } =>
+                format!("Chain '{}' was resynced from block {}, it was at block {}",
+                        chain, from_block, previous_block),
+            Alert::WebhookDeadLettered { job_id, url, attempts, error } =>
+                format!("Webhook {} to {} gave up after {} attempts: {}",
+                        job_id, url, attempts, error),
+            Alert::WebhookDestinationSuspended { url, failures, failing_for_secs, suppressed } =>
+                format!("Webhooks to {} are suspended after {} failures in {}s, {} queued jobs suppressed",
+                        url, failures, failing_for_secs, suppressed),
+            Alert::DatabaseDegraded { service, error } =>
+                format!("Database errors in {}: {}", service, error),
+            Alert::PaidVolumeAnomaly { chain, spike, last_hour_count, baseline_hourly } =>
+                format!("Paid volume on '{}' {}: {} payments in the last hour, usually {:.1}",
+                        chain, if *spike { "spiked" } else { "dropped" },
+                        last_hour_count, baseline_hourly),
+            Alert::RateSourceDegraded { pair, sources, deviation_pct } =>
+                format!("Rate sources {} disagree on {} by {:.2}%, not locking rates",
+                        sources.join(", "), pair, deviation_pct),
+            Alert::AddressIndexExhaustion { chain, account_id, index, max_index } =>
+                format!("Chain '{}' account {} is at address index {} of {}, {} left",
+                        chain, account_id, index, max_index, max_index - index),
+            Alert::ChainConfigDrift { chain, fields, reconciled } =>
+                format!("Listener for chain '{}' runs with a stale {}, {}",
+                        chain, fields.join(", "),
+                        if *reconciled { "reloaded it" } else { "reload the chain to apply the DB config" }),
+        };
+
+        format!("[{}] {}", self.severity(), text)
+    }
+
+    pub fn error(&self) -> Option<&ErrorEnvelope> {
+        match self {
+            Alert::ChainListenerDied { error, .. }
+            | Alert::WebhookDeadLettered { error, .. }
+            | Alert::DatabaseDegraded { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    // alerts with the same key are rate limited together, None = lifecycle events
+    // that monitoring needs to see every time
+    pub fn dedup_key(&self) -> Option<String> {
+        let key = match self {
+            Alert::ChainStalled { chain, .. } => format!("{}:{}", self, chain),
+            Alert::ChainListenerDied { chain, .. } => format!("{}:{}", self, chain),
+            Alert::WebhookDeadLettered { url, .. } => format!("{}:{}", self, url),
+            Alert::WebhookDestinationSuspended { url, .. } => format!("{}:{}", self, url),
+            Alert::DatabaseDegraded { service, .. } => format!("{}:{}", self, service),
+            Alert::PaidVolumeAnomaly { chain, spike, .. } => format!("{}:{}:{}", self, chain, spike),
+            Alert::RateSourceDegraded { pair, .. } => format!("{}:{}", self, pair),
+            Alert::AddressIndexExhaustion { chain, account_id, .. } =>
+                format!("{}:{}:{}", self, chain, account_id),
+            Alert::ChainConfigDrift { chain, .. } => format!("{}:{}", self, chain),
+            Alert::ChainListenerStarted { .. }
+            | Alert::ChainListenerRestarted { .. }
+            | Alert::ChainResynced { .. } => return None,
+        };
+
+        Some(key)
+    }
+}
+
+pub trait NotifierAdapter: Send + Sync {
+    fn notify(&self, alert: &Alert) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub enum Notifier {
+    Slack(SlackNotifier),
+    Telegram(TelegramNotifier),
+    Email(EmailNotifier),
+    Webhook(WebhookNotifier),
+}
+
+impl NotifierAdapter for Notifier {
+    async fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        match self {
+            Notifier::Slack(n) => n.notify(alert).await,
+            Notifier::Telegram(n) => n.notify(alert).await,
+            Notifier::Email(n) => n.notify(alert).await,
+            Notifier::Webhook(n) => n.notify(alert).await,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::notify::{Alert, NotifierAdapter};
+use reqwest::Client;
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SlackNotifier {
+    client: Client,
+    webhook_url: String,
+}
+
+impl SlackNotifier {
+    pub fn new(webhook_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            webhook_url: webhook_url.to_owned(),
+        }
+    }
+}
+
+impl NotifierAdapter for SlackNotifier {
+    async fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        self.client
+            .post(&self.webhook_url)
+            .json(&json!({ "text": alert.message() }))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use crate::notify::{Alert, NotifierAdapter};
+use reqwest::Client;
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct TelegramNotifier {
+    client: Client,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(bot_token: &str, chat_id: &str) -> Self {
+        Self {
+            client: Client::new(),
+            bot_token: bot_token.to_owned(),
+            chat_id: chat_id.to_owned(),
+        }
+    }
+}
+
+impl NotifierAdapter for TelegramNotifier {
+    async fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+
+        self.client
+            .post(url)
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": alert.message(),
+            }))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
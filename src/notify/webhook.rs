@@ -0,0 +1,74 @@
+use crate::notify::{Alert, NotifierAdapter};
+use crate::signature;
+use chrono::Utc;
+use reqwest::Client;
+use std::time::Duration;
+
+// posts the alert as JSON for external monitoring, signed like merchant webhooks
+// so the receiver can check it with WebhookVerifier
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+    secret: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, secret: &str) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.to_owned(),
+            secret: secret.to_owned(),
+        }
+    }
+}
+
+impl NotifierAdapter for WebhookNotifier {
+    async fn notify(&self, alert: &Alert) -> anyhow::Result<()> {
+        let body = serde_json::to_string(alert)?;
+        let now = Utc::now().timestamp().to_string();
+
+        self.client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header(signature::HEADER_ID, uuid::Uuid::new_v4().to_string())
+            .header(signature::HEADER_TIMESTAMP, &now)
+            .header(signature::HEADER_SIGNATURE, signature::sign(&now, body.as_bytes(), &self.secret))
+            .header(signature::HEADER_SIGNATURE_VERSION, signature::SIGNATURE_VERSION)
+            .body(body)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::WebhookVerifier;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_lifecycle_alert_is_signed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let alert = Alert::ChainListenerStarted { chain: "polygon".to_owned(), from_block: 42 };
+        WebhookNotifier::new(&server.uri(), "secret").notify(&alert).await.unwrap();
+
+        let request = &server.received_requests().await.unwrap()[0];
+        assert_eq!(WebhookVerifier::new("secret").verify(&request.headers, &request.body), Ok(()));
+
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["alert_type"], "chain_listener_started");
+        assert_eq!(body["data"]["from_block"], 42);
+    }
+}
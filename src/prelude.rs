@@ -0,0 +1,18 @@
+// the part of the crate downstream code should depend on, anything not re-exported here may change
+// between minor versions
+
+pub use crate::model::{
+    BlockTag, ChainConfig, ChainType, ErrorCode, ErrorEnvelope, Forbidden, Invoice, InvoiceFilter, InvoiceStatus, Page, PageRequest, Payment,
+    PaymentEvent, PaymentStatus, Quota, QuotaExceeded, QuotaKind, ReportPeriod, ReportSubscription, Role, RpcAuth, StartFrom, TokenConfig, TokenRef, WebhookDestination, WebhookEvent, WebhookStatus,
+};
+
+pub use crate::chain::{Blockchain, BlockchainAdapter};
+pub use crate::events::{self, example_payload};
+pub use crate::verify;
+pub use crate::db::{Database, DatabaseAdapter};
+pub use crate::logging::{LogFilter, LogLevels, Subsystem};
+pub use crate::notify::{Alert, Notifier, NotifierAdapter};
+pub use crate::settlement::{Converter, ConverterAdapter};
+pub use crate::signature::{verify_webhook_signature, SignatureError, WebhookVerifier};
+pub use crate::state::Authorized;
+pub use crate::AppState;
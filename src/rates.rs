@@ -0,0 +1,147 @@
+use crate::notify::Alert;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// sanity checks for fiat-denominated invoices: a rate is only locked when
+// independent price sources agree on it
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct RateQuote {
+    pub source: String,
+    pub pair: String, // e.g. "ETH/EUR"
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct RateCheckPolicy {
+    pub min_sources: usize,
+    pub max_deviation_pct: f64, // between the lowest and the highest quote
+}
+
+impl Default for RateCheckPolicy {
+    fn default() -> Self {
+        Self {
+            min_sources: 2,
+            max_deviation_pct: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateCheckError {
+    NotEnoughSources { pair: String, got: usize, needed: usize },
+    InvalidQuote { source: String, rate: f64 },
+    PairMismatch { source: String, expected: String, got: String },
+    Disagreement { pair: String, sources: Vec<String>, deviation_pct: f64 },
+}
+
+impl std::fmt::Display for RateCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateCheckError::NotEnoughSources { pair, got, needed } =>
+                write!(f, "only {} of {} rate sources answered for {}", got, needed, pair),
+            RateCheckError::InvalidQuote { source, rate } =>
+                write!(f, "{} returned an invalid rate {}", source, rate),
+            RateCheckError::PairMismatch { source, expected, got } =>
+                write!(f, "{} quoted {} instead of {}", source, got, expected),
+            RateCheckError::Disagreement { pair, deviation_pct, .. } =>
+                write!(f, "rate sources disagree on {} by {:.2}%", pair, deviation_pct),
+        }
+    }
+}
+
+impl std::error::Error for RateCheckError {}
+
+impl RateCheckError {
+    // only a disagreement means a source is glitching, the rest is plain unavailability
+    pub fn to_alert(&self) -> Option<Alert> {
+        match self {
+            RateCheckError::Disagreement { pair, sources, deviation_pct } =>
+                Some(Alert::RateSourceDegraded {
+                    pair: pair.clone(),
+                    sources: sources.clone(),
+                    deviation_pct: *deviation_pct,
+                }),
+            _ => None,
+        }
+    }
+}
+
+// returns the median of the quotes once they are within the allowed spread
+pub fn cross_check(pair: &str, quotes: &[RateQuote], policy: &RateCheckPolicy)
+    -> Result<f64, RateCheckError>
+{
+    if let Some(q) = quotes.iter().find(|q| q.pair != pair) {
+        return Err(RateCheckError::PairMismatch {
+            source: q.source.clone(),
+            expected: pair.to_owned(),
+            got: q.pair.clone(),
+        });
+    }
+
+    if let Some(q) = quotes.iter().find(|q| !q.rate.is_finite() || q.rate <= 0.0) {
+        return Err(RateCheckError::InvalidQuote { source: q.source.clone(), rate: q.rate });
+    }
+
+    if quotes.len() < policy.min_sources.max(1) {
+        return Err(RateCheckError::NotEnoughSources {
+            pair: pair.to_owned(),
+            got: quotes.len(),
+            needed: policy.min_sources.max(1),
+        });
+    }
+
+    let mut rates: Vec<f64> = quotes.iter().map(|q| q.rate).collect();
+    rates.sort_by(f64::total_cmp);
+
+    let (low, high) = (rates[0], rates[rates.len() - 1]);
+    let deviation_pct = (high - low) / low * 100.0;
+
+    if deviation_pct > policy.max_deviation_pct {
+        return Err(RateCheckError::Disagreement {
+            pair: pair.to_owned(),
+            sources: quotes.iter().map(|q| q.source.clone()).collect(),
+            deviation_pct,
+        });
+    }
+
+    let mid = rates.len() / 2;
+    Ok(if rates.len().is_multiple_of(2) { (rates[mid - 1] + rates[mid]) / 2.0 } else { rates[mid] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(source: &str, rate: f64) -> RateQuote {
+        RateQuote { source: source.to_owned(), pair: "ETH/EUR".to_owned(), rate }
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let policy = RateCheckPolicy::default();
+
+        let rate = cross_check("ETH/EUR", &[quote("a", 2000.0), quote("b", 2010.0)], &policy);
+        assert_eq!(rate, Ok(2005.0));
+
+        let err = cross_check("ETH/EUR", &[quote("a", 2000.0), quote("b", 2100.0)], &policy)
+            .unwrap_err();
+        assert!(matches!(err.to_alert(), Some(Alert::RateSourceDegraded { .. })));
+
+        let err = cross_check("ETH/EUR", &[quote("a", 2000.0)], &policy).unwrap_err();
+        assert!(err.to_alert().is_none());
+
+        let err = cross_check("ETH/EUR", &[quote("a", 2000.0), quote("b", 0.0)], &policy)
+            .unwrap_err();
+        assert!(matches!(err, RateCheckError::InvalidQuote { .. }));
+
+        let usd = RateQuote { pair: "ETH/USD".to_owned(), ..quote("b", 2010.0) };
+        let err = cross_check("ETH/EUR", &[quote("a", 2000.0), usd], &policy).unwrap_err();
+        assert_eq!(err, RateCheckError::PairMismatch {
+            source: "b".to_owned(),
+            expected: "ETH/EUR".to_owned(),
+            got: "ETH/USD".to_owned(),
+        });
+        assert!(err.to_alert().is_none());
+    }
+}
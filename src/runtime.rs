@@ -0,0 +1,171 @@
+use futures::channel::oneshot;
+use futures::future::{AbortHandle, Abortable, BoxFuture};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// the few executor primitives the services use. tokio by default (the `tokio-runtime`
+// feature), other hosts implement Runtime and install it before starting AppState.
+// postgres and the RPC/webhook HTTP clients still need a tokio reactor of their own
+pub trait Runtime: Send + Sync + 'static {
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    // the clock sleep follows, deadlines passed to sleep_until are taken from it
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(feature = "tokio-runtime")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "tokio-runtime")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::spawn(task);
+    }
+
+    // tokio's clock, so tokio::time::pause works in tests
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
+
+static RUNTIME: OnceLock<Arc<dyn Runtime>> = OnceLock::new();
+
+// only the first call wins, and only if nothing was spawned yet
+pub fn set_runtime(runtime: impl Runtime) -> anyhow::Result<()> {
+    RUNTIME.set(Arc::new(runtime))
+        .map_err(|_| anyhow::anyhow!("the async runtime is already set"))
+}
+
+fn runtime() -> &'static Arc<dyn Runtime> {
+    RUNTIME.get_or_init(|| {
+        #[cfg(feature = "tokio-runtime")]
+        return Arc::new(TokioRuntime);
+
+        #[cfg(not(feature = "tokio-runtime"))]
+        panic!("no async runtime, call runtime::set_runtime first or enable `tokio-runtime`");
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinError; // the task was aborted or panicked
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task was aborted or panicked")
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// dropping it detaches the task, like tokio's JoinHandle
+pub struct JoinHandle<T> {
+    abort: AbortHandle,
+    result: oneshot::Receiver<T>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.result).poll(cx).map(|r| r.map_err(|_| JoinError))
+    }
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (abort, registration) = AbortHandle::new_pair();
+    let (tx, result) = oneshot::channel();
+
+    runtime().spawn(Box::pin(async move {
+        if let Ok(output) = Abortable::new(future, registration).await {
+            let _ = tx.send(output);
+        }
+    }));
+
+    JoinHandle { abort, result }
+}
+
+pub async fn sleep(duration: Duration) {
+    runtime().sleep(duration).await
+}
+
+pub fn now() -> Instant {
+    runtime().now()
+}
+
+pub async fn sleep_until(deadline: Instant) {
+    sleep(deadline.saturating_duration_since(now())).await
+}
+
+// a tick more than a period late fires once, not once per missed period
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    pub async fn tick(&mut self) {
+        sleep_until(self.next).await;
+        self.next = (self.next + self.period).max(now());
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+// first tick completes right away
+pub fn interval(period: Duration) -> Interval {
+    interval_at(now(), period)
+}
+
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    Interval { next: start, period }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spawn_and_abort() {
+        assert_eq!(spawn(async { 42 }).await, Ok(42));
+
+        let handle = spawn(async {
+            sleep(Duration::from_secs(60)).await;
+        });
+        handle.abort();
+        assert_eq!(handle.await, Err(JoinError));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval_follows_the_paused_clock() {
+        let start = now();
+        let mut interval = interval(Duration::from_secs(10));
+        for _ in 0..3 {
+            interval.tick().await;
+        }
+
+        assert_eq!(now() - start, Duration::from_secs(20));
+    }
+}
@@ -0,0 +1,72 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+
+// bump when the sealed format changes, older values stay readable by their prefix
+const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+// encrypts credentials before they are persisted, e.g. ChainConfig::rpc_auth
+#[derive(Clone)]
+pub struct SecretKey(Aes256Gcm);
+
+impl SecretKey {
+    // 32 bytes, hex encoded
+    pub fn from_hex(key: &str) -> anyhow::Result<Self> {
+        let bytes = hex::decode(key.trim())?;
+        if bytes.len() != 32 {
+            anyhow::bail!("secret key must be 32 bytes, got {}", bytes.len());
+        }
+
+        Ok(Self(Aes256Gcm::new_from_slice(&bytes)?))
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> anyhow::Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.0.encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow::anyhow!("failed to encrypt secret"))?;
+
+        Ok(format!("{}{}{}", SEALED_PREFIX, hex::encode(nonce), hex::encode(ciphertext)))
+    }
+
+    pub fn open(&self, sealed: &str) -> anyhow::Result<Vec<u8>> {
+        let Some(sealed) = sealed.strip_prefix(SEALED_PREFIX) else {
+            anyhow::bail!("unknown sealed secret format");
+        };
+
+        let bytes = hex::decode(sealed)?;
+        if bytes.len() < NONCE_LEN {
+            anyhow::bail!("sealed secret is too short");
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+
+        self.0.decrypt(&Nonce::from(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("failed to decrypt secret, wrong key or corrupted value"))
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = SecretKey::from_hex(&"11".repeat(32)).unwrap();
+        let other = SecretKey::from_hex(&"22".repeat(32)).unwrap();
+
+        let sealed = key.seal(b"token").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert_ne!(sealed, key.seal(b"token").unwrap()); // fresh nonce every time
+
+        assert_eq!(key.open(&sealed).unwrap(), b"token");
+        assert!(other.open(&sealed).is_err());
+        assert!(key.open("token").is_err());
+        assert!(SecretKey::from_hex("1234").is_err());
+    }
+}
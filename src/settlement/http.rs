@@ -0,0 +1,92 @@
+use crate::settlement::{ConversionReceipt, ConversionRequest, ConverterAdapter};
+use reqwest::Client;
+use std::time::Duration;
+
+// POSTs the ConversionRequest as JSON and expects a ConversionReceipt back
+#[derive(Debug, Clone)]
+pub struct HttpConverter {
+    client: Client,
+    endpoint: String,
+    api_key: String,
+}
+
+impl HttpConverter {
+    pub fn new(endpoint: &str, api_key: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.to_owned(),
+            api_key: api_key.to_owned(),
+        }
+    }
+}
+
+impl ConverterAdapter for HttpConverter {
+    async fn convert(&self, request: &ConversionRequest) -> anyhow::Result<ConversionReceipt> {
+        let receipt = self.client
+            .post(&self.endpoint)
+            .bearer_auth(&self.api_key)
+            .json(request)
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::U256;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request() -> ConversionRequest {
+        ConversionRequest {
+            reference: "inv-1".to_owned(),
+            account_id: 7,
+            from_network: "polygon".to_owned(),
+            from_token: "USDT".to_owned(),
+            amount_raw: U256::from(5_000_000),
+            to_network: "ethereum".to_owned(),
+            to_currency: "USDC".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_posts_the_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/convert"))
+            .and(header("authorization", "Bearer conv-key"))
+            .and(body_json(request()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(ConversionReceipt {
+                external_id: "swap-42".to_owned(),
+                deposit_address: "0x1111111111111111111111111111111111111111".to_owned(),
+                expected_amount: Some("4.99".to_owned()),
+            }))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let converter = HttpConverter::new(&format!("{}/convert", server.uri()), "conv-key");
+        let receipt = converter.convert(&request()).await.unwrap();
+        assert_eq!(receipt.external_id, "swap-42");
+        assert_eq!(receipt.expected_amount.as_deref(), Some("4.99"));
+    }
+
+    #[tokio::test]
+    async fn test_convert_fails_on_error_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let converter = HttpConverter::new(&server.uri(), "conv-key");
+        assert!(converter.convert(&request()).await.is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::settlement::http::HttpConverter;
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use utoipa::ToSchema;
+
+pub mod http;
+
+// what the sweeper should do with the funds of one paid invoice. swept funds are tagged with
+// settlement_currency so they can be told apart before and after the conversion
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct SettlementInstruction {
+    pub invoice_id: String,
+    pub account_id: u32,
+    pub network: String,
+    pub token: String,
+    #[schema(value_type = String)]
+    pub amount_raw: U256,
+    pub settlement_currency: String,
+    pub settlement_network: String,
+    pub needs_conversion: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ConversionRequest {
+    pub reference: String, // invoice id, lets the service deduplicate retries
+    pub account_id: u32,
+    pub from_network: String,
+    pub from_token: String,
+    #[schema(value_type = String)]
+    pub amount_raw: U256,
+    pub to_network: String,
+    pub to_currency: String,
+}
+
+impl From<&SettlementInstruction> for ConversionRequest {
+    fn from(instruction: &SettlementInstruction) -> Self {
+        Self {
+            reference: instruction.invoice_id.clone(),
+            account_id: instruction.account_id,
+            from_network: instruction.network.clone(),
+            from_token: instruction.token.clone(),
+            amount_raw: instruction.amount_raw,
+            to_network: instruction.settlement_network.clone(),
+            to_currency: instruction.settlement_currency.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ConversionReceipt {
+    pub external_id: String,
+    pub deposit_address: String, // where the sweeper has to send from_token
+    pub expected_amount: Option<String>, // in to_currency, if the service quotes one
+}
+
+// external conversion service, swapping or bridging is never done by necko itself
+pub trait ConverterAdapter: Send + Sync {
+    fn convert(&self, request: &ConversionRequest)
+        -> impl Future<Output = anyhow::Result<ConversionReceipt>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub enum Converter {
+    Http(HttpConverter),
+}
+
+impl ConverterAdapter for Converter {
+    async fn convert(&self, request: &ConversionRequest) -> anyhow::Result<ConversionReceipt> {
+        match self {
+            Converter::Http(c) => c.convert(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::mock::MockDatabase;
+    use crate::db::Database;
+    use crate::model::{Invoice, InvoiceStatus, SettlementPreference};
+    use crate::AppState;
+    use alloy::primitives::U256;
+    use chrono::Utc;
+
+    fn paid_invoice() -> Invoice {
+        Invoice {
+            account_id: 7,
+            amount: "5".to_owned(),
+            amount_raw: U256::from(5_000_000),
+            paid: "5".to_owned(),
+            paid_raw: U256::from(5_000_000),
+            network: "polygon".to_owned(),
+            paid_at: Some(Utc::now()),
+            status: InvoiceStatus::Paid,
+            ..Invoice::for_test()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_needs_conversion_follows_the_preference() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let invoice = paid_invoice();
+        let prefer = |currency: &str, network: Option<&str>| Some(SettlementPreference {
+            currency: currency.to_owned(),
+            network: network.map(str::to_owned),
+        });
+
+        // no preference, kept as paid
+        let instruction = state.settlement_instruction(&invoice).await;
+        assert!(!instruction.needs_conversion);
+        assert_eq!((instruction.settlement_currency.as_str(), instruction.settlement_network.as_str()),
+                   ("USDT", "polygon"));
+
+        state.set_settlement_preference(7, prefer("USDT", None)).await.unwrap();
+        assert!(!state.settlement_instruction(&invoice).await.needs_conversion);
+
+        state.set_settlement_preference(7, prefer("USDT", Some("ethereum"))).await.unwrap();
+        assert!(state.settlement_instruction(&invoice).await.needs_conversion);
+
+        state.set_settlement_preference(7, prefer("USDC", None)).await.unwrap();
+        let instruction = state.settlement_instruction(&invoice).await;
+        assert!(instruction.needs_conversion);
+        assert_eq!(instruction.settlement_network, "polygon");
+
+        // survives a restart
+        state.settlement_preferences.write().await.clear();
+        state.load_account_settings().await.unwrap();
+        assert_eq!(state.settlement_preferences.read().await.get(&7), prefer("USDC", None).as_ref());
+    }
+}
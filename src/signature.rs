@@ -0,0 +1,155 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::header::HeaderMap;
+use sha2::Sha256;
+use std::time::Duration;
+
+pub const HEADER_ID: &str = "X-Webhook-Id";
+pub const HEADER_TIMESTAMP: &str = "X-Webhook-Timestamp";
+pub const HEADER_SIGNATURE: &str = "X-Webhook-Signature";
+pub const HEADER_SIGNATURE_VERSION: &str = "X-Webhook-Signature-Version";
+
+// hex HMAC-SHA256 of "{timestamp}.{body}" with the invoice's webhook secret
+pub const SIGNATURE_VERSION: &str = "v1";
+
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    MissingHeader(&'static str),
+    UnsupportedVersion(String),
+    MalformedTimestamp(String),
+    OutsideWindow { skew_secs: i64 }, // positive if the timestamp is in the past
+    Mismatch,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingHeader(name) => write!(f, "missing or non-ascii header {}", name),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported signature version '{}'", v),
+            Self::MalformedTimestamp(ts) => write!(f, "malformed timestamp '{}'", ts),
+            Self::OutsideWindow { skew_secs } =>
+                write!(f, "timestamp is {}s off, outside the allowed window", skew_secs),
+            Self::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+fn mac(timestamp: &str, body: &[u8], secret: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("hmac accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+pub fn sign(timestamp: &str, body: &[u8], secret: &str) -> String {
+    hex::encode(mac(timestamp, body, secret).finalize().into_bytes())
+}
+
+// checks deliveries on the merchant side, the window covers replays and clock skew in both directions
+#[derive(Debug, Clone)]
+pub struct WebhookVerifier {
+    secret: String,
+    tolerance: Duration,
+}
+
+impl WebhookVerifier {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self { secret: secret.into(), tolerance: DEFAULT_TOLERANCE }
+    }
+
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), SignatureError> {
+        self.verify_at(headers, body, Utc::now().timestamp())
+    }
+
+    fn verify_at(&self, headers: &HeaderMap, body: &[u8], now: i64) -> Result<(), SignatureError> {
+        let header = |name: &'static str| headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(SignatureError::MissingHeader(name));
+
+        // deliveries from before the version header was added are v1
+        let version = headers.get(HEADER_SIGNATURE_VERSION)
+            .map(|v| v.to_str().unwrap_or_default())
+            .unwrap_or(SIGNATURE_VERSION);
+        if version != SIGNATURE_VERSION {
+            return Err(SignatureError::UnsupportedVersion(version.to_owned()));
+        }
+
+        let timestamp = header(HEADER_TIMESTAMP)?;
+        let signature = header(HEADER_SIGNATURE)?;
+
+        let sent_at: i64 = timestamp.parse()
+            .map_err(|_| SignatureError::MalformedTimestamp(timestamp.to_owned()))?;
+        let skew_secs = now.saturating_sub(sent_at);
+        if skew_secs.unsigned_abs() > self.tolerance.as_secs() {
+            return Err(SignatureError::OutsideWindow { skew_secs });
+        }
+
+        let signature = hex::decode(signature).map_err(|_| SignatureError::Mismatch)?;
+        mac(timestamp, body, &self.secret).verify_slice(&signature)
+            .map_err(|_| SignatureError::Mismatch)
+    }
+}
+
+pub fn verify_webhook_signature(headers: &HeaderMap, body: &[u8], secret: &str) -> Result<(), SignatureError> {
+    WebhookVerifier::new(secret).verify(headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn signed_headers(timestamp: i64, body: &[u8], secret: &str) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_TIMESTAMP, HeaderValue::from_str(&timestamp).unwrap());
+        headers.insert(HEADER_SIGNATURE, HeaderValue::from_str(&sign(&timestamp, body, secret)).unwrap());
+        headers.insert(HEADER_SIGNATURE_VERSION, HeaderValue::from_static(SIGNATURE_VERSION));
+        headers
+    }
+
+    #[test]
+    fn test_verify_webhook_signature() {
+        let body = br#"{"type":"invoice_paid"}"#;
+        let now = 1_700_000_000;
+        let verifier = WebhookVerifier::new("secret").tolerance(Duration::from_secs(60));
+
+        let headers = signed_headers(now - 30, body, "secret");
+        assert_eq!(verifier.verify_at(&headers, body, now), Ok(()));
+        assert_eq!(verifier.verify_at(&headers, b"{}", now), Err(SignatureError::Mismatch));
+        assert_eq!(WebhookVerifier::new("other").verify_at(&headers, body, now),
+                   Err(SignatureError::Mismatch));
+
+        let stale = signed_headers(now - 61, body, "secret");
+        assert_eq!(verifier.verify_at(&stale, body, now),
+                   Err(SignatureError::OutsideWindow { skew_secs: 61 }));
+        let ahead = signed_headers(now + 61, body, "secret");
+        assert_eq!(verifier.verify_at(&ahead, body, now),
+                   Err(SignatureError::OutsideWindow { skew_secs: -61 }));
+
+        let mut unversioned = signed_headers(now, body, "secret");
+        unversioned.remove(HEADER_SIGNATURE_VERSION);
+        assert_eq!(verifier.verify_at(&unversioned, body, now), Ok(()));
+
+        let mut future = signed_headers(now, body, "secret");
+        future.insert(HEADER_SIGNATURE_VERSION, HeaderValue::from_static("v2"));
+        assert_eq!(verifier.verify_at(&future, body, now),
+                   Err(SignatureError::UnsupportedVersion("v2".to_owned())));
+
+        let mut unsigned = signed_headers(now, body, "secret");
+        unsigned.remove(HEADER_SIGNATURE);
+        assert_eq!(verifier.verify_at(&unsigned, body, now),
+                   Err(SignatureError::MissingHeader(HEADER_SIGNATURE)));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::db::DatabaseAdapter;
+use crate::model::{AddressOwnershipProof, ChainCapabilities, ChainConfig, ChainStatus, CheckoutPayload, ConfigDrift, EgressInfo, Forbidden, ImportReport, Invoice, InvoiceFilter, MisdirectedStatus, Page, PageRequest, Quota, RedactionPolicy, Refund, ReportSubscription, Role, SettlementPreference, StartFrom, TokenConfig, TokenPreflightReport, WebhookDestination};
+use crate::logging::{LogLevels, Subsystem};
+use crate::notify::Notifier;
+use crate::settlement::Converter;
+use crate::state::{EgressConfig, HostLimits, ServicesConfig, SettlementPolicy};
+use crate::AppState;
+use alloy::primitives::{Address, U256};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use tracing::level_filters::LevelFilter;
+
+use tracing::{debug, info, warn};
+
+// keys are only kept hashed, a heap dump doesn't hand out admin access
+pub(crate) fn key_hash(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+impl AppState {
+    // a key added again just gets the new role. only reachable through Authorized::add_api_key
+    pub(crate) async fn add_api_key(&self, api_key: &str, role: Role) -> anyhow::Result<()> {
+        let hash = key_hash(api_key);
+        let mut keys = self.api_keys.write().await;
+        self.db.set_api_key(&hash, Some(role)).await?;
+        keys.insert(hash, role);
+        info!(target: "audit", action = "add_api_key", %role, "API key added");
+
+        Ok(())
+    }
+
+    pub(crate) async fn revoke_api_key(&self, api_key: &str) -> anyhow::Result<bool> {
+        let hash = key_hash(api_key);
+        let mut keys = self.api_keys.write().await;
+        self.db.set_api_key(&hash, None).await?;
+        let revoked = keys.remove(&hash).is_some();
+        if revoked {
+            info!(target: "audit", action = "revoke_api_key", "API key revoked");
+        }
+
+        Ok(revoked)
+    }
+
+    // the keys added at runtime, the one AppState was created with stays Admin
+    pub(crate) async fn load_api_keys(&self) -> anyhow::Result<()> {
+        let mut keys = self.api_keys.write().await;
+        for (hash, role) in self.db.get_api_keys().await? {
+            keys.entry(hash).or_insert(role);
+        }
+
+        debug!(count = keys.len(), "Loaded API keys");
+        Ok(())
+    }
+
+    pub(crate) async fn role_of(&self, api_key: &str) -> Option<Role> {
+        self.api_keys.read().await.get(&key_hash(api_key)).copied()
+    }
+
+    // what the API layer calls the administrative methods through, Forbidden maps to a 403.
+    // the services keep calling AppState directly
+    pub async fn authorize(self: &Arc<Self>, api_key: &str) -> Result<Authorized, Forbidden> {
+        match self.role_of(api_key).await {
+            Some(role) => Ok(Authorized { state: self.clone(), role }),
+            None => {
+                warn!("Rejected unknown API key");
+                Err(Forbidden { required: Role::Viewer, role: None })
+            }
+        }
+    }
+}
+
+// AppState as seen by one API key. every method checks the key's role before delegating:
+// reads need Viewer, invoice and payment handling Operator, chains and config Admin
+pub struct Authorized {
+    state: Arc<AppState>,
+    role: Role,
+}
+
+impl Authorized {
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn require(&self, required: Role) -> Result<(), Forbidden> {
+        if self.role < required {
+            warn!(role = %self.role, %required, "Rejected API call without the required role");
+            return Err(Forbidden { required, role: Some(self.role) });
+        }
+
+        Ok(())
+    }
+
+    // viewer
+
+    pub async fn chain_status(&self, chain_name: &str) -> anyhow::Result<ChainStatus> {
+        self.require(Role::Viewer)?;
+        self.state.chain_status(chain_name).await
+    }
+
+    pub async fn chain_capabilities(&self, chain_name: &str) -> anyhow::Result<ChainCapabilities> {
+        self.require(Role::Viewer)?;
+        self.state.chain_capabilities(chain_name).await
+    }
+
+    pub async fn checkout_payload(&self, uuid: &str) -> anyhow::Result<CheckoutPayload> {
+        self.require(Role::Viewer)?;
+        self.state.checkout_payload(uuid).await
+    }
+
+    pub async fn find_invoice_by_tx_hash(&self, chain_name: &str, tx_hash: &str) -> anyhow::Result<Vec<Invoice>> {
+        self.require(Role::Viewer)?;
+        self.state.find_invoice_by_tx_hash(chain_name, tx_hash).await
+    }
+
+    pub async fn list_invoices(&self, filter: &InvoiceFilter, page: &PageRequest) -> anyhow::Result<Page<Invoice>> {
+        self.require(Role::Viewer)?;
+        self.state.list_invoices(filter, page).await
+    }
+
+    pub async fn address_ownership_proof(&self, uuid: &str) -> anyhow::Result<AddressOwnershipProof> {
+        self.require(Role::Viewer)?;
+        self.state.address_ownership_proof(uuid).await
+    }
+
+    pub async fn egress_info(&self) -> anyhow::Result<EgressInfo> {
+        self.require(Role::Viewer)?;
+        Ok(self.state.egress_info().await)
+    }
+
+    pub async fn config_drift(&self) -> anyhow::Result<Vec<ConfigDrift>> {
+        self.require(Role::Viewer)?;
+        self.state.config_drift().await
+    }
+
+    pub fn log_levels(&self) -> anyhow::Result<LogLevels> {
+        self.require(Role::Viewer)?;
+        Ok(self.state.log_levels())
+    }
+
+    pub async fn webhook_destinations(&self) -> anyhow::Result<Vec<WebhookDestination>> {
+        self.require(Role::Viewer)?;
+        self.state.webhook_destinations().await
+    }
+
+    // operator
+
+    // reserves the address index a new invoice is created with
+    pub async fn get_free_slot(&self, chain_name: &str, account_id: u32) -> anyhow::Result<Option<u32>> {
+        self.require(Role::Operator)?;
+        self.state.reserve_slot(chain_name, account_id).await
+    }
+
+    pub async fn reissue_invoice(&self, uuid: &str) -> anyhow::Result<Invoice> {
+        self.require(Role::Operator)?;
+        self.state.reissue_invoice(uuid).await
+    }
+
+    pub async fn settle_invoice(&self, uuid: &str, accept_shortfall: bool) -> anyhow::Result<()> {
+        self.require(Role::Operator)?;
+        self.state.settle_invoice(uuid, accept_shortfall).await
+    }
+
+    pub async fn send_test_webhook(&self, uuid: &str) -> anyhow::Result<String> {
+        self.require(Role::Operator)?;
+        self.state.send_test_webhook(uuid).await
+    }
+
+    pub async fn resume_webhook_destination(&self, url: &str, redeliver: bool) -> anyhow::Result<u64> {
+        self.require(Role::Operator)?;
+        self.state.resume_webhook_destination(url, redeliver).await
+    }
+
+    pub async fn simulate_payment(&self, uuid: &str, amount_raw: Option<U256>) -> anyhow::Result<String> {
+        self.require(Role::Operator)?;
+        self.state.simulate_payment(uuid, amount_raw).await
+    }
+
+    pub async fn resolve_misdirected_payment(&self, id: &str, status: MisdirectedStatus, reason: &str)
+        -> anyhow::Result<()>
+    {
+        self.require(Role::Operator)?;
+        self.state.resolve_misdirected_payment(id, status, reason).await
+    }
+
+    pub async fn record_refund(&self, invoice_id: &str, payment_id: &str, amount_raw: U256,
+                               tx_hash: Option<&str>, reason: Option<&str>) -> anyhow::Result<Refund> {
+        self.require(Role::Operator)?;
+        self.state.record_refund(invoice_id, payment_id, amount_raw, tx_hash, reason).await
+    }
+
+    // admin
+
+    pub async fn add_chain(&self, chain_config: &ChainConfig, start_from: StartFrom) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.add_chain(chain_config, start_from).await
+    }
+
+    pub async fn add_token(&self, chain_name: &str, token: &TokenConfig) -> anyhow::Result<TokenPreflightReport> {
+        self.require(Role::Admin)?;
+        self.state.add_token(chain_name, token).await
+    }
+
+    pub async fn install_standard_tokens(&self, chain_name: &str)
+        -> anyhow::Result<Vec<(TokenConfig, TokenPreflightReport)>>
+    {
+        self.require(Role::Admin)?;
+        self.state.install_standard_tokens(chain_name).await
+    }
+
+    pub async fn remove_chain(&self, chain_name: &str, force: bool) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.clone().remove_chain(chain_name, force).await
+    }
+
+    pub async fn upsert_chain(&self, chain_config: &ChainConfig) -> anyhow::Result<bool> {
+        self.require(Role::Admin)?;
+        self.state.clone().upsert_chain(chain_config).await
+    }
+
+    pub async fn reload_chain(&self, chain_name: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.clone().reload_chain(chain_name).await
+    }
+
+    pub async fn resync_chain(&self, chain_name: &str, start_from: StartFrom) -> anyhow::Result<u64> {
+        self.require(Role::Admin)?;
+        self.state.clone().resync_chain(chain_name, start_from).await
+    }
+
+    pub async fn start_listening(&self, chain_name: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.clone().start_listening(chain_name).await
+    }
+
+    pub async fn stop_listening(&self, chain_name: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.stop_listening(chain_name).await
+    }
+
+    pub async fn repair_invoice_addresses(&self, chain_name: &str) -> anyhow::Result<u64> {
+        self.require(Role::Admin)?;
+        self.state.repair_invoice_addresses(chain_name).await
+    }
+
+    pub async fn force_finalize_payment(&self, payment_id: &str, reason: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.force_finalize_payment(payment_id, reason).await
+    }
+
+    pub async fn force_expire_invoice(&self, uuid: &str, reason: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.force_expire_invoice(uuid, reason).await
+    }
+
+    pub async fn freeze_outbound(&self, reason: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.freeze_outbound(reason).await
+    }
+
+    pub async fn unfreeze_outbound(&self, reason: &str) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.unfreeze_outbound(reason).await
+    }
+
+    pub async fn set_report_subscription(&self, account_id: u32, subscription: Option<ReportSubscription>)
+        -> anyhow::Result<()>
+    {
+        self.require(Role::Admin)?;
+        self.state.set_report_subscription(account_id, subscription).await
+    }
+
+    pub async fn set_quota(&self, account_id: u32, quota: Option<Quota>) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_quota(account_id, quota).await
+    }
+
+    pub async fn set_redaction_policy(&self, account_id: u32, policy: Option<RedactionPolicy>)
+        -> anyhow::Result<()>
+    {
+        self.require(Role::Admin)?;
+        self.state.set_redaction_policy(account_id, policy).await
+    }
+
+    pub async fn set_webhook_host_limits(&self, account_id: u32, limits: Option<HostLimits>)
+        -> anyhow::Result<()>
+    {
+        self.require(Role::Admin)?;
+        self.state.set_webhook_host_limits(account_id, limits).await
+    }
+
+    pub async fn set_settlement_preference(&self, account_id: u32, preference: Option<SettlementPreference>)
+        -> anyhow::Result<()>
+    {
+        self.require(Role::Admin)?;
+        self.state.set_settlement_preference(account_id, preference).await
+    }
+
+    pub async fn set_settlement_policy(&self, policy: SettlementPolicy) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_settlement_policy(policy).await;
+        Ok(())
+    }
+
+    pub async fn set_converter(&self, converter: Option<Converter>) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_converter(converter).await;
+        Ok(())
+    }
+
+    pub async fn set_attestation_key(&self, key: Option<String>) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_attestation_key(key).await
+    }
+
+    pub async fn attestation_address(&self) -> anyhow::Result<Option<Address>> {
+        self.require(Role::Viewer)?;
+        Ok(self.state.attestation_address().await)
+    }
+
+    pub async fn add_notifier(&self, notifier: Notifier) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.add_notifier(notifier).await;
+        Ok(())
+    }
+
+    pub async fn export_state(&self, writer: impl Write) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.export_state(writer).await
+    }
+
+    pub async fn import_state(&self, reader: impl Read) -> anyhow::Result<ImportReport> {
+        self.require(Role::Admin)?;
+        self.state.import_state(reader).await
+    }
+
+    pub async fn set_services_config(&self, config: ServicesConfig) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_services_config(config).await
+    }
+
+    pub async fn set_egress_config(&self, config: EgressConfig) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_egress_config(config).await
+    }
+
+    pub fn set_default_log_level(&self, level: LevelFilter) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_default_log_level(level);
+        Ok(())
+    }
+
+    pub fn set_log_level(&self, subsystem: Subsystem, level: Option<LevelFilter>) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_log_level(subsystem, level);
+        Ok(())
+    }
+
+    pub fn set_chain_log_level(&self, chain: &str, level: Option<LevelFilter>) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.set_chain_log_level(chain, level);
+        Ok(())
+    }
+
+    pub async fn add_api_key(&self, api_key: &str, role: Role) -> anyhow::Result<()> {
+        self.require(Role::Admin)?;
+        self.state.add_api_key(api_key, role).await
+    }
+
+    pub async fn revoke_api_key(&self, api_key: &str) -> anyhow::Result<bool> {
+        self.require(Role::Admin)?;
+        self.state.revoke_api_key(api_key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::db::Database;
+
+    const ATTESTATION_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[tokio::test]
+    async fn test_roles_are_enforced() {
+        let state = Arc::new(AppState::new(Database::Mock(MockDatabase::new()), "root-key"));
+        assert_eq!(state.role_of("root-key").await, Some(Role::Admin));
+
+        state.add_api_key("viewer-key", Role::Viewer).await.unwrap();
+        state.add_api_key("operator-key", Role::Operator).await.unwrap();
+
+        let viewer = state.authorize("viewer-key").await.unwrap();
+        let err = viewer.reissue_invoice("missing").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<Forbidden>(),
+                   Some(&Forbidden { required: Role::Operator, role: Some(Role::Viewer) }));
+        // allowed, fails on the lookup instead
+        let err = viewer.chain_status("missing").await.unwrap_err();
+        assert!(err.downcast_ref::<Forbidden>().is_none());
+
+        let operator = state.authorize("operator-key").await.unwrap();
+        assert!(operator.require(Role::Operator).is_ok());
+        let err = operator.remove_chain("missing", false).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<Forbidden>(),
+                   Some(&Forbidden { required: Role::Admin, role: Some(Role::Operator) }));
+        let err = operator.export_state(Vec::new()).await.unwrap_err();
+        assert!(err.downcast_ref::<Forbidden>().is_some());
+        let err = operator.set_attestation_key(None).await.unwrap_err();
+        assert!(err.downcast_ref::<Forbidden>().is_some());
+
+        let admin = state.authorize("root-key").await.unwrap();
+        assert!(admin.set_attestation_key(Some("attest".to_owned())).await.is_err());
+        admin.set_attestation_key(Some(ATTESTATION_KEY.to_owned())).await.unwrap();
+        assert!(admin.attestation_address().await.unwrap().is_some());
+
+        assert!(state.revoke_api_key("operator-key").await.unwrap());
+        assert_eq!(state.authorize("operator-key").await.err(),
+                   Some(Forbidden { required: Role::Viewer, role: None }));
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_are_persisted_hashed() {
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "root-key");
+        let state = AppState { db: db.clone(), ..state };
+        state.add_api_key("viewer-key", Role::Viewer).await.unwrap();
+        state.add_api_key("gone-key", Role::Operator).await.unwrap();
+        assert!(state.revoke_api_key("gone-key").await.unwrap());
+
+        // the root key isn't stored, the others only as their hash
+        assert_eq!(db.get_api_keys().await.unwrap(), vec![(key_hash("viewer-key"), Role::Viewer)]);
+
+        let restarted = AppState::new(Database::Mock(MockDatabase::new()), "root-key");
+        let restarted = AppState { db, ..restarted };
+        restarted.load_api_keys().await.unwrap();
+        assert_eq!(restarted.role_of("viewer-key").await, Some(Role::Viewer));
+        assert_eq!(restarted.role_of("root-key").await, Some(Role::Admin));
+        assert_eq!(restarted.role_of("gone-key").await, None);
+    }
+}
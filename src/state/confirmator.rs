@@ -1,170 +1,665 @@
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::task::JoinHandle;
+use crate::runtime::{self, JoinHandle};
 use crate::AppState;
-use crate::chain::BlockchainAdapter;
-use crate::db::DatabaseAdapter;
-use crate::model::WebhookEvent;
+use crate::chain::{Blockchain, BlockchainAdapter};
+use crate::db::{Database, DatabaseAdapter};
+use crate::model::{DeadlinePolicy, ErrorCode, ErrorEnvelope, FinalityMode, Invoice, Payment, PaymentAlreadyFinalized, WebhookEvent};
+use crate::notify::Alert;
+use alloy::primitives::utils::format_units;
+use chrono::{DateTime, Utc};
 
 use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
+const INVOICE_CACHE_CAPACITY: usize = 1024;
+
+// taken once per tick so thousands of confirming payments don't each hit the chain config lock
+struct ChainSnapshot {
+    blockchain: Arc<Blockchain>,
+    last_processed: u64,
+    required_confirmations: u64,
+    in_maintenance: bool,
+    finality_mode: FinalityMode, // the chain's, see finalized_block
+}
+
+fn snapshot_chains(chains: HashMap<String, Arc<Blockchain>>) -> HashMap<String, ChainSnapshot> {
+    let now = Utc::now();
+
+    chains.into_iter()
+        .map(|(name, blockchain)| {
+            let (last_processed, required_confirmations, in_maintenance) = {
+                let chain_config_lock = blockchain.config();
+                let guard = chain_config_lock.read().unwrap();
+                (guard.last_processed_block, guard.required_confirmations, guard.in_maintenance(now))
+            };
+
+            let finality_mode = blockchain.capabilities().finality_mode;
+
+            (name, ChainSnapshot { blockchain, last_processed, required_confirmations, in_maintenance, finality_mode })
+        })
+        .collect()
+}
+
+// invoices of confirming payments, kept across ticks so a burst of payments or a payment waiting
+// for its confirmations doesn't read the same invoice over and over. only fields fixed at creation
+// may be used from it, the confirmator drops an invoice once it changed its status and rereads it
+struct InvoiceCache {
+    capacity: usize,
+    invoices: HashMap<String, (Invoice, u64)>, // key = id, (invoice, last use)
+    by_use: BTreeMap<u64, String>, // last use -> id, least recently used first
+    clock: u64,
+}
+
+impl InvoiceCache {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, invoices: HashMap::new(), by_use: BTreeMap::new(), clock: 0 }
+    }
+
+    async fn get(&mut self, db: &Database, id: &str) -> anyhow::Result<Option<Invoice>> {
+        self.clock += 1;
+
+        if let Some((invoice, last_use)) = self.invoices.get_mut(id) {
+            self.by_use.remove(last_use);
+            *last_use = self.clock;
+            self.by_use.insert(self.clock, id.to_owned());
+            return Ok(Some(invoice.clone()));
+        }
+
+        let Some(invoice) = db.get_invoice(id).await? else {
+            return Ok(None);
+        };
+
+        if self.invoices.len() >= self.capacity
+            && let Some((_, oldest)) = self.by_use.pop_first()
+        {
+            self.invoices.remove(&oldest);
+        }
+        self.invoices.insert(id.to_owned(), (invoice.clone(), self.clock));
+        self.by_use.insert(self.clock, id.to_owned());
+
+        Ok(Some(invoice))
+    }
+
+    fn invalidate(&mut self, id: &str) {
+        if let Some((_, last_use)) = self.invoices.remove(id) {
+            self.by_use.remove(&last_use);
+        }
+    }
+}
+
+// MustConfirmBeforeExpiry: the payment stays on record as Rejected so it can be refunded
+async fn reject_late_payment(state: &AppState, payment: &Payment, invoice: &Invoice,
+                             confirmed_at: DateTime<Utc>) {
+    let deadline = invoice.payment_deadline();
+    warn!(%deadline, "Payment confirmed after the invoice deadline, rejecting it");
+
+    match state.db.reject_payment(&payment.id, confirmed_at).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("Payment was finalized concurrently, not rejecting it");
+            return;
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to reject late payment");
+            return; // retried on the next tick
+        }
+    }
+
+    let webhook_event = WebhookEvent::TxRejectedLate {
+        invoice_id: invoice.id.clone(),
+        tx_hash: payment.tx_hash.clone(),
+        log_index: payment.log_index,
+        amount: format_units(payment.amount_raw, invoice.decimals)
+            .unwrap_or_else(|_| payment.amount_raw.to_string()),
+        deadline,
+        confirmed_at,
+    };
+
+    if let Err(e) = state.db.add_webhook_job(&invoice.id, &webhook_event).await {
+        error!(error = %e, "Failed to add TxRejectedLate webhook job");
+    }
+}
+
 #[instrument(skip(state))]
-pub fn start_confirmator(state: Arc<AppState>, interval: Duration) -> JoinHandle<()> {
-    info!(?interval, "Starting payment confirmator service");
+pub fn start_confirmator(state: Arc<AppState>) -> JoinHandle<()> {
+    info!("Starting payment confirmator service");
 
     let span = tracing::info_span!(parent: None, "confirmator_service");
 
-    tokio::spawn(async move {
-        let mut interval_timer = tokio::time::interval(interval);
+    runtime::spawn(async move {
+        let mut interval_timer = runtime::interval(
+            state.services_config.read().await.confirmator_interval);
+        let mut invoices = InvoiceCache::new(INVOICE_CACHE_CAPACITY);
 
         loop {
             interval_timer.tick().await;
 
-            trace!("Scanning for confirming payments...");
+            let interval = state.services_config.read().await.confirmator_interval;
+            if interval != interval_timer.period() {
+                info!(?interval, "Confirmator interval changed");
+                interval_timer = runtime::interval_at(
+                    runtime::now() + interval, interval);
+            }
 
-            let payments = match state.db.get_confirming_payments().await {
-                Ok(p) => p,
-                Err(e) => {
-                    error!(error = %e, "Failed to fetch confirming payments from DB");
-                    continue;
-                }
+            confirm_payments(&state, &mut invoices).await;
+        }
+    }.instrument(span))
+}
+
+// one pass over the confirming payments
+async fn confirm_payments(state: &AppState, invoices: &mut InvoiceCache) {
+    trace!("Scanning for confirming payments...");
+
+    let payments = match state.db.get_confirming_payments().await {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch confirming payments from DB");
+            state.alert(Alert::DatabaseDegraded {
+                service: "confirmator".to_owned(),
+                error: ErrorEnvelope::new(ErrorCode::DatabaseUnavailable, e),
+            }).await;
+            return;
+        }
+    };
+
+    if payments.is_empty() {
+        return;
+    }
+
+    debug!(count = payments.len(), "Processing confirming payments batch");
+
+    let snapshots = match state.db.get_chains_map().await {
+        Ok(chains) => snapshot_chains(chains),
+        Err(e) => {
+            error!(error = %e, "DB error while fetching chain adapters");
+            return;
+        }
+    };
+    // chain -> finalized block, asked for at most once per tick
+    let mut finalized: HashMap<String, Option<u64>> = HashMap::new();
+
+    for payment in payments {
+        let verify_span = tracing::info_span!(
+            "verify_payment",
+            id = %payment.id,
+            tx = %payment.tx_hash,
+            net = %payment.network
+        );
+
+        async {
+            let Some(snapshot) = snapshots.get(&payment.network) else {
+                error!("Blockchain adapter not found for active payment");
+                return;
             };
 
-            if !payments.is_empty() {
-                debug!(count = payments.len(), "Processing confirming payments batch");
+            if snapshot.in_maintenance {
+                trace!("Chain is in a maintenance window, deferring");
+                return;
             }
 
-            for payment in payments {
-                let verify_span = tracing::info_span!(
-                    "verify_payment",
-                    id = %payment.id,
-                    tx = %payment.tx_hash,
-                    net = %payment.network
+            let blockchain = &snapshot.blockchain;
+            let last_processed = snapshot.last_processed;
+            let required = snapshot.required_confirmations;
+
+            let target_block = payment.block_number + required;
+
+            // checkpoints only for invoices asking for them, the finalized tag for all
+            let by_finality = match snapshot.finality_mode {
+                FinalityMode::Confirmations => false,
+                FinalityMode::FinalizedTag => true,
+                FinalityMode::Checkpoint => match invoices.get(&state.db, &payment.invoice_id).await {
+                    Ok(invoice) => invoice.is_some_and(|i| i.finality_mode == FinalityMode::Checkpoint),
+                    Err(e) => {
+                        error!(inv_id = %payment.invoice_id, error = %e, "DB error getting invoice");
+                        return;
+                    }
+                },
+            };
+
+            if by_finality {
+                if !finalized.contains_key(&payment.network) {
+                    let block = blockchain.finalized_block().await.unwrap_or_else(|e| {
+                        warn!(error = %e, "Failed to fetch the finalized block, will retry");
+                        None
+                    });
+                    finalized.insert(payment.network.clone(), block);
+                }
+
+                let final_block = finalized[&payment.network];
+                if final_block.is_none_or(|block| block < payment.block_number) {
+                    trace!(?final_block, mode = %snapshot.finality_mode, "Block isn't final yet");
+                    return;
+                }
+            } else if last_processed < target_block {
+                trace!(
+                    current = last_processed,
+                    needed = target_block,
+                    confirmations = required,
+                    "Not enough confirmations yet"
                 );
+                return;
+            }
+
+            // payments final by checkpoint or tag can have fewer blocks on top
+            let confirmations = match by_finality {
+                true => last_processed.saturating_sub(payment.block_number),
+                false => required,
+            };
+
+            debug!("Threshold reached, verifying transaction on-chain...");
+
+            match blockchain.get_tx_block_number(&payment.tx_hash).await {
+                Ok(Some(actual_block)) => {
+                    if actual_block != payment.block_number {
+                        warn!(
+                            old_block = payment.block_number,
+                            new_block = actual_block,
+                            "Transaction moved to a different block (Chain Reorg). \
+                            Updating DB..."
+                        );
+
+                        if let Err(e) = state.db.update_payment_block(&payment.id,
+                                                                      actual_block).await {
+                            error!(error = %e, "Failed to update payment block after reorg");
+                        }
+
+                        return;
+                    }
+
+                    info!(confirmations, by_finality,
+                        "Payment confirmed and verified on-chain. Finalizing...");
+
+                    let confirmed_at = Utc::now();
 
-                async {
-                    let blockchain = match state.db.get_chain(&payment.network).await {
-                        Ok(Some(bc)) => bc,
+                    let invoice = match invoices.get(&state.db, &payment.invoice_id).await {
+                        Ok(Some(invoice)) => invoice,
                         Ok(None) => {
-                            error!("Blockchain adapter not found for active payment");
+                            error!(inv_id = %payment.invoice_id, "Invoice of a confirming \
+                            payment doesn't exist");
                             return;
                         }
                         Err(e) => {
-                            error!(error = %e, "DB error while fetching chain adapter");
+                            error!(inv_id = %payment.invoice_id, error = %e,
+                                "DB error getting invoice");
                             return;
                         }
                     };
+                    let deadline = invoice.payment_deadline();
+                    // by the payment's block, a slow tick or downtime here doesn't make it late
+                    let mined_at = payment.block_timestamp.unwrap_or(payment.created_at);
+                    let late = mined_at > deadline;
 
-                    let (last_processed, required) = {
-                        let chain_config_lock = blockchain.config();
-                        let guard = chain_config_lock.read().unwrap();
-                        (guard.last_processed_block, guard.required_confirmations)
-                    };
+                    if late && invoice.deadline_policy == DeadlinePolicy::MustConfirmBeforeExpiry {
+                        reject_late_payment(state, &payment, &invoice, confirmed_at).await;
+                        invoices.invalidate(&invoice.id);
+                        return;
+                    }
 
-                    let target_block = payment.block_number + required;
+                    let finalized = state.db.finalize_payment(&payment.id, confirmed_at).await;
+                    invoices.invalidate(&invoice.id);
 
-                    if last_processed < target_block {
-                        trace!(
-                            current = last_processed,
-                            needed = target_block,
-                            confirmations = required,
-                            "Not enough confirmations yet"
-                        );
-                        return;
+                    if late && finalized.is_ok() {
+                        warn!(%deadline, "Payment confirmed after the invoice deadline, \
+                            credited anyway");
+
+                        let webhook_event = WebhookEvent::TxConfirmedLate {
+                            invoice_id: payment.invoice_id.clone(),
+                            tx_hash: payment.tx_hash.clone(),
+                            log_index: payment.log_index,
+                            deadline,
+                            confirmed_at,
+                        };
+
+                        if let Err(e) = state.db.add_webhook_job(&payment.invoice_id,
+                                                                 &webhook_event).await {
+                            error!(error = %e, "Failed to add TxConfirmedLate webhook job");
+                        }
                     }
 
-                    debug!("Threshold reached, verifying transaction on-chain...");
-
-                    match blockchain.get_tx_block_number(&payment.tx_hash).await {
-                        Ok(Some(actual_block)) => {
-                            if actual_block != payment.block_number {
-                                warn!(
-                                    old_block = payment.block_number,
-                                    new_block = actual_block,
-                                    "Transaction moved to a different block (Chain Reorg). \
-                                    Updating DB..."
-                                );
-
-                                if let Err(e) = state.db.update_payment_block(&payment.id,
-                                                                              actual_block).await {
-                                    error!(error = %e, "Failed to update payment block after reorg");
+                    match finalized {
+                        Ok(true) => {
+                            info!("Invoice fully paid!");
+
+                            let invoice = match state.db.get_invoice(
+                                &payment.invoice_id).await
+                            {
+                                Ok(Some(invoice)) => invoice,
+                                Ok(None) => {
+                                    error!(inv_id = %payment.invoice_id, "Invoice \
+                                    disappeared from DB before finalization (???)");
+                                    return;
+                                }
+                                Err(e) => {
+                                    error!(inv_id = %payment.invoice_id, error = %e,
+                                        "DB error getting invoice");
+                                    return;
                                 }
+                            };
+
+                            let webhook_event = WebhookEvent::InvoicePaid {
+                                invoice_id: payment.invoice_id.clone(),
+                                paid_amount: invoice.paid,
+                                paid_at: invoice.paid_at.unwrap_or(confirmed_at),
+                                accepted_shortfall: None,
+                                locale: invoice.locale,
+                                display_currency: invoice.display_currency,
+                            };
 
-                                return;
+                            if let Err(e) = state.db.add_webhook_job(&payment.invoice_id,
+                                                                     &webhook_event).await {
+                                error!(error = %e, "Failed to add InvoicePaid webhook job");
                             }
 
-                            info!(confirmations = required,
-                                "Payment confirmed and verified on-chain. Finalizing...");
-
-                            match state.db.finalize_payment(&payment.id).await {
-                                Ok(true) => {
-                                    info!("Invoice fully paid!");
-
-                                    let invoice = match state.db.get_invoice(
-                                        &payment.invoice_id).await
-                                    {
-                                        Ok(Some(invoice)) => invoice,
-                                        Ok(None) => {
-                                            error!(inv_id = %payment.invoice_id, "Invoice \
-                                            disappeared from DB before finalization (???)");
-                                            return;
-                                        }
-                                        Err(e) => {
-                                            error!(inv_id = %payment.invoice_id, error = %e,
-                                                "DB error getting invoice");
-                                            return;
-                                        }
-                                    };
-
-                                    let webhook_event = WebhookEvent::InvoicePaid {
-                                        invoice_id: payment.invoice_id.clone(),
-                                        paid_amount: invoice.paid,
-                                    };
-
-                                    if let Err(e) = state.db.add_webhook_job(&payment.invoice_id,
-                                                                             &webhook_event).await {
-                                        error!(error = %e, "Failed to add InvoicePaid webhook job");
-                                    }
-
-                                    debug!(address = %payment.to, "Removing address from watcher");
-
-                                    if let Err(e) = state.db.remove_watch_address(
-                                        &payment.network, &payment.to).await
-                                    {
-                                        error!(error = %e, "Failed to remove address from watcher");
-                                    }
-                                }
-                                Ok(false) => {
-                                    info!("Invoice isn't fully paid");
-
-                                    let webhook_event = WebhookEvent::TxConfirmed {
-                                        invoice_id: payment.invoice_id.clone(),
-                                        tx_hash: payment.tx_hash,
-                                        confirmations: required,
-                                    };
-
-                                    if let Err(e) = state.db.add_webhook_job(&payment.invoice_id,
-                                                                             &webhook_event).await {
-                                        error!(error = %e, "Failed to add TxConfirmed webhook job");
-                                    }
-                                },
-                                Err(e) => {
-                                    error!(error = %e,
-                                        "CRITICAL: DB error during payment finalization")
-                                },
+                            debug!(address = %payment.to, "Removing address from watcher");
+
+                            if let Err(e) = state.db.remove_watch_address(
+                                &payment.network, &payment.to).await
+                            {
+                                error!(error = %e, "Failed to remove address from watcher");
                             }
                         }
-                        Ok(None) => {
-                            warn!("Transaction cannot be found in chain (possible deep reorg or \
-                            dropped tx). Waiting...");
-                        }
+                        Ok(false) => {
+                            info!("Invoice isn't fully paid");
+
+                            let webhook_event = WebhookEvent::TxConfirmed {
+                                invoice_id: payment.invoice_id.clone(),
+                                tx_hash: payment.tx_hash,
+                                log_index: payment.log_index,
+                                confirmations,
+                                confirmed_at,
+                            };
+
+                            if let Err(e) = state.db.add_webhook_job(&payment.invoice_id,
+                                                                     &webhook_event).await {
+                                error!(error = %e, "Failed to add TxConfirmed webhook job");
+                            }
+                        },
+                        Err(e) if e.is::<PaymentAlreadyFinalized>() => {
+                            debug!(error = %e, "Payment finalized concurrently, skipping");
+                        },
                         Err(e) => {
-                            warn!(error = %e, "RPC error while verifying transaction status. Will \
-                            retry.");
+                            error!(error = %e,
+                                "CRITICAL: DB error during payment finalization");
+                            state.alert(Alert::DatabaseDegraded {
+                                service: "confirmator".to_owned(),
+                                error: ErrorEnvelope::new(ErrorCode::DatabaseUnavailable, e),
+                            }).await;
                         },
                     }
-                }.instrument(verify_span).await;
+                }
+                Ok(None) => {
+                    warn!("Transaction cannot be found in chain (possible deep reorg or \
+                    dropped tx). Waiting...");
+                }
+                Err(e) => {
+                    warn!(error = %e, "RPC error while verifying transaction status. Will \
+                    retry.");
+                },
             }
+        }.instrument(verify_span).await;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::model::{BlockTag, ChainConfig, DeadlinePolicy, FinalityMode, PaymentStatus};
+    use alloy::primitives::U256;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const TX_HASH: &str = "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const PAYMENT_BLOCK: u64 = 100;
+
+    fn word(n: u64) -> String {
+        format!("0x{:064x}", n)
+    }
+
+    fn rpc_receipt(block: u64) -> Value {
+        json!({
+            "type": "0x2", "status": "0x1", "cumulativeGasUsed": "0x5208", "logs": [],
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "transactionHash": TX_HASH, "transactionIndex": "0x0",
+            "blockHash": word(block), "blockNumber": format!("{:#x}", block),
+            "gasUsed": "0x5208", "effectiveGasPrice": "0x1",
+            "from": "0x2222222222222222222222222222222222222222",
+            "to": "0x1111111111111111111111111111111111111111",
+            "contractAddress": null
+        })
+    }
+
+    fn rpc_block(number: u64) -> Value {
+        json!({
+            "hash": word(number), "parentHash": word(number.saturating_sub(1)),
+            "sha3Uncles": word(0), "miner": "0x0000000000000000000000000000000000000000",
+            "stateRoot": word(0), "transactionsRoot": word(0), "receiptsRoot": word(0),
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "difficulty": "0x0", "number": format!("{:#x}", number), "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0", "timestamp": format!("{:#x}", 1_700_000_000 + number * 12),
+            "extraData": "0x", "mixHash": word(0), "nonce": "0x0000000000000000",
+            "baseFeePerGas": "0x1", "uncles": [], "transactions": []
+        })
+    }
+
+    // JSON-RPC node that mined TX_HASH in PAYMENT_BLOCK. the finalized tag and the latest
+    // heimdall checkpoint both report `final_block`
+    async fn rpc_node(final_block: Arc<AtomicU64>) -> MockServer {
+        let server = MockServer::start().await;
+        let checkpoint = final_block.clone();
+
+        Mock::given(method("GET"))
+            .respond_with(move |_: &Request| ResponseTemplate::new(200).set_body_json(json!({
+                "checkpoint": {"end_block": checkpoint.load(Ordering::SeqCst).to_string()}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let call: Value = serde_json::from_slice(&request.body).unwrap();
+                let result = match call["method"].as_str().unwrap() {
+                    "eth_getTransactionReceipt" => rpc_receipt(PAYMENT_BLOCK),
+                    "eth_getBlockByNumber" => rpc_block(final_block.load(Ordering::SeqCst)),
+                    other => panic!("unexpected RPC call {}", other),
+                };
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+            })
+            .mount(&server)
+            .await;
+
+        server
+    }
+
+    // a chain at block 200 with one confirming payment for `invoice` in PAYMENT_BLOCK
+    async fn confirming(config: ChainConfig, invoice: &Invoice, mined_at: DateTime<Utc>) -> (AppState, String) {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        state.db.add_chain(&config).await.unwrap();
+        state.db.add_invoice(invoice).await.unwrap();
+        state.db.add_payment_attempt(&invoice.id, "0x2222222222222222222222222222222222222222", &invoice.address,
+            None, TX_HASH, invoice.amount_raw, PAYMENT_BLOCK, Some(mined_at), &invoice.network, Some(0)).await.unwrap();
+
+        let payment_id = state.db.get_confirming_payments().await.unwrap()[0].id.clone();
+        (state, payment_id)
+    }
+
+    fn chain(server: &MockServer) -> crate::model::ChainConfigBuilder {
+        ChainConfig::builder()
+            .name("ethereum")
+            .rpc_url(&server.uri())
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .last_processed_block(200)
+    }
+
+    async fn payment_status(state: &AppState, payment_id: &str) -> PaymentStatus {
+        state.db.get_payment(payment_id).await.unwrap().unwrap().status
+    }
+
+    async fn webhook_events(state: &AppState) -> Vec<WebhookEvent> {
+        state.db.select_webhooks_job(10).await.unwrap().into_iter().map(|job| job.payload.0).collect()
+    }
+
+    fn invoice() -> Invoice {
+        Invoice {
+            address: format!("0x{}", uuid::Uuid::new_v4().simple()),
+            amount: "1.0".to_owned(),
+            amount_raw: U256::from(1_000_000),
+            network: "ethereum".to_owned(),
+            ..Invoice::for_test()
         }
-    }.instrument(span))
-}
\ No newline at end of file
+    }
+
+    #[tokio::test]
+    async fn test_invoice_cache_evicts_least_recently_used() {
+        let db = Database::Mock(MockDatabase::new());
+        let (a, b, c) = (invoice(), invoice(), invoice());
+        for inv in [&a, &b, &c] {
+            db.add_invoice(inv).await.unwrap();
+        }
+
+        let mut cache = InvoiceCache::new(2);
+        cache.get(&db, &a.id).await.unwrap();
+        cache.get(&db, &b.id).await.unwrap();
+        cache.get(&db, &a.id).await.unwrap();
+        cache.get(&db, &c.id).await.unwrap(); // evicts b
+
+        // whatever is still cached is answered without the DB
+        for inv in [&a, &b, &c] {
+            db.remove_invoice(&inv.id).await.unwrap();
+        }
+        assert_eq!(cache.get(&db, &a.id).await.unwrap().map(|i| i.id), Some(a.id.clone()));
+        assert!(cache.get(&db, &c.id).await.unwrap().is_some());
+        assert!(cache.get(&db, &b.id).await.unwrap().is_none());
+
+        cache.invalidate(&a.id);
+        assert!(cache.get(&db, &a.id).await.unwrap().is_none());
+        assert_eq!((cache.invoices.len(), cache.by_use.len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_late_payment_is_rejected_by_block_time() {
+        let server = rpc_node(Arc::new(AtomicU64::new(0))).await;
+        let invoice = Invoice {
+            network: "ethereum".to_owned(),
+            token: "ETH".to_owned(),
+            webhook_url: Some("https://merchant.example/hook".to_owned()),
+            webhook_secret: Some("s3cret".to_owned()),
+            expires_at: Utc::now() - chrono::Duration::minutes(10),
+            deadline_policy: DeadlinePolicy::MustConfirmBeforeExpiry,
+            ..invoice()
+        };
+        let (state, payment_id) = confirming(chain(&server).build().unwrap(), &invoice, Utc::now() - chrono::Duration::minutes(5)).await;
+
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Rejected);
+        let events = webhook_events(&state).await;
+        assert!(matches!(&events[..], [WebhookEvent::TxRejectedLate { amount, .. }] if amount == "1.000000"));
+        assert_eq!(state.db.get_invoice(&invoice.id).await.unwrap().unwrap().paid_raw, U256::ZERO);
+
+        // rejecting again is a no-op, and a finalized payment can't be rejected afterwards
+        assert!(!state.db.reject_payment(&payment_id, Utc::now()).await.unwrap());
+        assert!(state.db.reject_payment(&uuid::Uuid::new_v4().to_string(), Utc::now()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_payment_mined_before_the_deadline_is_not_late() {
+        let server = rpc_node(Arc::new(AtomicU64::new(0))).await;
+        let invoice = Invoice {
+            network: "ethereum".to_owned(),
+            token: "ETH".to_owned(),
+            webhook_url: Some("https://merchant.example/hook".to_owned()),
+            webhook_secret: Some("s3cret".to_owned()),
+            expires_at: Utc::now() - chrono::Duration::minutes(10),
+            deadline_policy: DeadlinePolicy::MustConfirmBeforeExpiry,
+            ..invoice()
+        };
+        // confirmed long after the deadline, e.g. after downtime, but mined in time
+        let (state, payment_id) = confirming(chain(&server).build().unwrap(), &invoice, Utc::now() - chrono::Duration::minutes(15)).await;
+
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirmed);
+        assert!(matches!(&webhook_events(&state).await[..], [WebhookEvent::InvoicePaid { .. }]));
+        assert!(!state.db.reject_payment(&payment_id, Utc::now()).await.unwrap());
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn test_late_payment_is_credited_when_honored() {
+        let server = rpc_node(Arc::new(AtomicU64::new(0))).await;
+        let invoice = Invoice {
+            network: "ethereum".to_owned(),
+            token: "ETH".to_owned(),
+            webhook_url: Some("https://merchant.example/hook".to_owned()),
+            webhook_secret: Some("s3cret".to_owned()),
+            expires_at: Utc::now() - chrono::Duration::minutes(10),
+            deadline_policy: DeadlinePolicy::HonorDetectedBeforeExpiry,
+            ..invoice()
+        };
+        let (state, payment_id) = confirming(chain(&server).build().unwrap(), &invoice, Utc::now() - chrono::Duration::minutes(5)).await;
+
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirmed);
+        let events = webhook_events(&state).await;
+        assert!(events.iter().any(|e| matches!(e, WebhookEvent::TxConfirmedLate { .. })));
+        assert!(events.iter().any(|e| matches!(e, WebhookEvent::InvoicePaid { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_finalized_tag_payment_waits_for_the_finalized_block() {
+        let final_block = Arc::new(AtomicU64::new(PAYMENT_BLOCK - 1));
+        let server = rpc_node(final_block.clone()).await;
+        let invoice = Invoice { token: "ETH".to_owned(), ..invoice() };
+        // 100 blocks on top already, plenty by confirmations
+        let config = chain(&server).block_tag(BlockTag::Finalized).build().unwrap();
+        let (state, payment_id) = confirming(config, &invoice, Utc::now()).await;
+
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirming);
+
+        final_block.store(PAYMENT_BLOCK, Ordering::SeqCst);
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn test_finalized_tag_payment_waits_while_the_tag_is_unavailable() {
+        let server = rpc_node(Arc::new(AtomicU64::new(200))).await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "eth_getBlockByNumber"})))
+            .respond_with(move |request: &Request| {
+                let call: Value = serde_json::from_slice(&request.body).unwrap();
+                ResponseTemplate::new(200).set_body_json(json!({"jsonrpc": "2.0", "id": call["id"],
+                    "error": {"code": -32601, "message": "finalized tag not supported"}}))
+            })
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let invoice = Invoice { token: "ETH".to_owned(), ..invoice() };
+        let config = chain(&server).block_tag(BlockTag::Finalized).build().unwrap();
+        let (state, payment_id) = confirming(config, &invoice, Utc::now()).await;
+
+        // no fallback to counting confirmations, the payment is retried next tick
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirming);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_payment_waits_for_the_checkpoint() {
+        let final_block = Arc::new(AtomicU64::new(PAYMENT_BLOCK - 1));
+        let server = rpc_node(final_block.clone()).await;
+        let invoice = Invoice { token: "ETH".to_owned(), finality_mode: FinalityMode::Checkpoint, ..invoice() };
+        let config = chain(&server).heimdall_url(&server.uri()).build().unwrap();
+        let (state, payment_id) = confirming(config, &invoice, Utc::now()).await;
+
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirming);
+
+        final_block.store(PAYMENT_BLOCK, Ordering::SeqCst);
+        confirm_payments(&state, &mut InvoiceCache::new(8)).await;
+        assert_eq!(payment_status(&state, &payment_id).await, PaymentStatus::Confirmed);
+    }
+}
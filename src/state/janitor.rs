@@ -1,42 +1,102 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::task::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::runtime::{self, JoinHandle};
 use crate::AppState;
-use crate::db::DatabaseAdapter;
-use crate::model::WebhookEvent;
+use crate::chain::BlockchainAdapter;
+use crate::db::{Database, DatabaseAdapter};
+use crate::model::{ConfigDrift, ErrorCode, ErrorEnvelope, PaymentAnalytics, WebhookEvent};
+use crate::notify::Alert;
+use chrono::{DateTime, Utc};
 
 use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
+const CHAIN_STALL_THRESHOLD: Duration = Duration::from_secs(5 * 60);
+const PAYMENT_ARCHIVE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// last hour of confirmed payments per chain vs the hourly average of the day before it
+const VOLUME_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const VOLUME_BASELINE_HOURS: i64 = 24;
+const VOLUME_DROP_RATIO: f64 = 0.2;
+const VOLUME_SPIKE_RATIO: f64 = 5.0;
+const VOLUME_MIN_BASELINE: f64 = 5.0; // quiet chains are too noisy to judge a drop
+const VOLUME_MIN_SPIKE: u64 = 20;
+
+const CONFIG_DRIFT_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const REPORT_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 #[instrument(skip(state))]
-pub fn start_janitor(state: Arc<AppState>, interval: Duration) -> JoinHandle<()> {
-    info!(?interval, "Starting janitor service");
+pub fn start_janitor(state: Arc<AppState>) -> JoinHandle<()> {
+    info!("Starting janitor service");
 
     let span = tracing::info_span!(parent: None, "janitor_service");
 
-    tokio::spawn(async move {
-        let mut interval_timer = tokio::time::interval(interval);
+    runtime::spawn(async move {
+        let mut interval_timer = runtime::interval(
+            state.services_config.read().await.janitor_interval);
+        let mut chain_progress: HashMap<String, (u64, Instant)> = HashMap::new();
+        let mut last_volume_check: Option<Instant> = None;
+        let mut last_drift_check: Option<Instant> = None;
+        let mut last_report_check: Option<Instant> = None;
+        let mut sent_summaries = HashMap::new(); // account_id -> start of the last queued period
 
         loop {
             interval_timer.tick().await;
 
+            let interval = state.services_config.read().await.janitor_interval;
+            if interval != interval_timer.period() {
+                info!(?interval, "Janitor interval changed");
+                interval_timer = runtime::interval_at(
+                    runtime::now() + interval, interval);
+            }
+
+            check_stalled_chains(&state, &mut chain_progress).await;
+
+            if last_volume_check.is_none_or(|t| runtime::now().duration_since(t) >= VOLUME_CHECK_INTERVAL) {
+                last_volume_check = Some(runtime::now());
+                check_paid_volume(&state).await;
+            }
+
+            if last_drift_check.is_none_or(|t| runtime::now().duration_since(t) >= CONFIG_DRIFT_CHECK_INTERVAL) {
+                last_drift_check = Some(runtime::now());
+                check_config_drift(&state).await;
+            }
+
+            if last_report_check.is_none_or(|t| runtime::now().duration_since(t) >= REPORT_CHECK_INTERVAL) {
+                last_report_check = Some(runtime::now());
+                state.enqueue_period_summaries(Utc::now(), &mut sent_summaries).await;
+            }
+
+            archive_payments(&state).await;
+
+            warn_expiring_invoices(&state).await;
+
+            let stale_invoice_age = state.services_config.read().await.stale_invoice_age;
+            if let Some(age) = stale_invoice_age {
+                notify_stale_invoices(&state, age).await;
+            }
+
             debug!("Checking for expired invoices...");
 
-            let expired_addresses = state.db.expire_old_invoices().await
-                .unwrap_or_else(|e| {
+            let expired_addresses = match state.db.expire_old_invoices().await {
+                Ok(expired) => expired,
+                Err(e) => {
                     error!(error = %e, "Failed to fetch/expire old invoices from DB");
+                    state.alert(Alert::DatabaseDegraded {
+                        service: "janitor".to_owned(),
+                        error: ErrorEnvelope::new(ErrorCode::DatabaseUnavailable, e),
+                    }).await;
                     vec![]
-                });
+                }
+            };
 
             if expired_addresses.is_empty() {
                 trace!("No expired invoices found");
-                continue;
+            } else {
+                info!(count = expired_addresses.len(), "Found expired invoices, processing cleanup");
             }
 
-            info!(count = expired_addresses.len(), "Found expired invoices, processing cleanup");
-
-            let mut to_remove: HashMap<String, Vec<String>> = HashMap::new();
-
+            let mut hints = display_hints(&state, expired_addresses.iter().map(|(id, _, _)| id.clone())).await;
             for (invoice_id, network, address) in expired_addresses {
                 let expire_span = tracing::info_span!("expire_invoice", id = %invoice_id, net = %network);
 
@@ -46,21 +106,35 @@ pub fn start_janitor(state: Arc<AppState>, interval: Duration) -> JoinHandle<()>
                         "Marking invoice as expired"
                     );
 
+                    let (locale, display_currency) = hints.remove(&invoice_id).unwrap_or_default();
+
                     let webhook_job = WebhookEvent::InvoiceExpired {
                         invoice_id: invoice_id.clone(),
+                        locale,
+                        display_currency,
                     };
 
                     if let Err(e) = state.db.add_webhook_job(&invoice_id,
                                                                   &webhook_job).await {
                         error!(error = %e, "Failed to add InvoiceExpired webhook job");
                     }
-
-                    to_remove.entry(network)
-                        .or_insert_with(Vec::new)
-                        .push(address);
                 }.instrument(expire_span).await;
             }
 
+            // addresses stay watched through the grace period, a late payment revives the invoice
+            let released = state.db.release_expired_addresses().await
+                .unwrap_or_else(|e| {
+                    error!(error = %e, "Failed to fetch addresses past their grace period");
+                    vec![]
+                });
+
+            let mut to_remove: HashMap<String, Vec<String>> = HashMap::new();
+            for (network, address) in released {
+                to_remove.entry(network)
+                    .or_default()
+                    .push(address);
+            }
+
             for (network, addresses) in to_remove {
                 debug!(network = %network, count = addresses.len(),
                     "Removing addresses from watcher");
@@ -78,4 +152,475 @@ pub fn start_janitor(state: Arc<AppState>, interval: Duration) -> JoinHandle<()>
             }
         }
     }.instrument(span))
-}
\ No newline at end of file
+}
+
+// locale and display_currency by invoice id, fetched in one go for a whole tick
+async fn display_hints(state: &AppState, invoice_ids: impl Iterator<Item = String>)
+    -> HashMap<String, (Option<String>, Option<String>)>
+{
+    let invoice_ids: Vec<String> = invoice_ids.collect();
+    if invoice_ids.is_empty() {
+        return HashMap::new();
+    }
+
+    match state.db.get_invoices_by_ids(&invoice_ids).await {
+        Ok(invoices) => invoices.into_iter()
+            .map(|invoice| (invoice.id, (invoice.locale, invoice.display_currency)))
+            .collect(),
+        Err(e) => {
+            warn!(count = invoice_ids.len(), error = %e, "Failed to load invoice display hints");
+            HashMap::new()
+        }
+    }
+}
+
+async fn warn_expiring_invoices(state: &AppState) {
+    trace!("Checking for invoices about to expire...");
+
+    let expiring = state.db.get_expiring_invoices().await
+        .unwrap_or_else(|e| {
+            error!(error = %e, "Failed to fetch expiring invoices from DB");
+            vec![]
+        });
+
+    let mut hints = display_hints(state, expiring.iter().map(|(id, _)| id.clone())).await;
+    for (invoice_id, expires_at) in expiring {
+        info!(id = %invoice_id, %expires_at, "Invoice is about to expire, sending warning");
+
+        let (locale, display_currency) = hints.remove(&invoice_id).unwrap_or_default();
+        let webhook_event = WebhookEvent::invoice_expiring_soon(&invoice_id, expires_at)
+            .with_display_hints(locale, display_currency);
+
+        // unmarked it comes up again next pass
+        if let Err(e) = state.db.add_webhook_job(&invoice_id, &webhook_event).await {
+            error!(id = %invoice_id, error = %e, "Failed to add InvoiceExpiringSoon webhook job");
+            continue;
+        }
+
+        if let Err(e) = state.db.mark_expiry_warned(&invoice_id).await {
+            error!(id = %invoice_id, error = %e, "Failed to mark invoice as warned");
+        }
+    }
+}
+
+// abandoned checkouts with a long expiry, the merchant decides whether to nudge or cancel
+async fn notify_stale_invoices(state: &AppState, age: Duration) {
+    let stale = state.db.get_stale_invoices(age).await
+        .unwrap_or_else(|e| {
+            error!(error = %e, "Failed to fetch stale invoices from DB");
+            vec![]
+        });
+
+    for invoice in stale {
+        info!(id = %invoice.id, created_at = %invoice.created_at, "Invoice is stale, notifying merchant");
+
+        let webhook_event = WebhookEvent::invoice_stale(&invoice.id, invoice.created_at, invoice.expires_at)
+            .with_display_hints(invoice.locale, invoice.display_currency);
+
+        // unmarked it comes up again next pass
+        if let Err(e) = state.db.add_webhook_job(&invoice.id, &webhook_event).await {
+            error!(id = %invoice.id, error = %e, "Failed to add InvoiceStale webhook job");
+            continue;
+        }
+
+        if let Err(e) = state.db.mark_invoice_stale(&invoice.id).await {
+            error!(id = %invoice.id, error = %e, "Failed to mark invoice as stale");
+        }
+    }
+}
+
+async fn archive_payments(state: &AppState) {
+    let confirmed_before = Utc::now() - PAYMENT_ARCHIVE_AGE;
+
+    match state.db.archive_finalized_payments(confirmed_before).await {
+        Ok(0) => trace!("No finalized payments to archive"),
+        Ok(count) => info!(count, "Moved finalized payments to archive"),
+        Err(e) => error!(error = %e, "Failed to archive finalized payments"),
+    }
+}
+
+// Some(true) = spike, Some(false) = drop
+fn classify_volume(last_hour: u64, baseline_hourly: f64) -> Option<bool> {
+    if baseline_hourly >= VOLUME_MIN_BASELINE
+        && (last_hour as f64) < baseline_hourly * VOLUME_DROP_RATIO
+    {
+        return Some(false);
+    }
+
+    if last_hour >= VOLUME_MIN_SPIKE
+        && (last_hour as f64) > baseline_hourly.max(1.0) * VOLUME_SPIKE_RATIO
+    {
+        return Some(true);
+    }
+
+    None
+}
+
+fn payments_per_chain(analytics: Vec<PaymentAnalytics>) -> HashMap<String, u64> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for a in analytics {
+        *counts.entry(a.network).or_default() += a.payments_count;
+    }
+    counts
+}
+
+// confirmed payments per chain in the last hour and in the baseline before it. the baseline
+// reaches past PAYMENT_ARCHIVE_AGE, so archived payments have to count as well
+async fn paid_volumes(db: &Database, now: DateTime<Utc>)
+    -> anyhow::Result<(HashMap<String, u64>, HashMap<String, u64>)>
+{
+    let hour_ago = now - chrono::Duration::hours(1);
+    let baseline_from = hour_ago - chrono::Duration::hours(VOLUME_BASELINE_HOURS);
+
+    let (current, baseline) = tokio::try_join!(
+        db.get_payment_analytics(hour_ago, now),
+        db.get_payment_analytics(baseline_from, hour_ago),
+    )?;
+
+    Ok((payments_per_chain(current), payments_per_chain(baseline)))
+}
+
+async fn check_paid_volume(state: &AppState) {
+    let (current, baseline) = match paid_volumes(&state.db, Utc::now()).await {
+        Ok(volumes) => volumes,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch payment analytics for volume check");
+            return;
+        }
+    };
+
+    let active: Vec<String> = state.active_chains.read().await.keys().cloned().collect();
+
+    for chain in active {
+        let last_hour_count = current.get(&chain).copied().unwrap_or(0);
+        let baseline_hourly = baseline.get(&chain).copied().unwrap_or(0) as f64
+            / VOLUME_BASELINE_HOURS as f64;
+
+        trace!(chain = %chain, last_hour_count, baseline_hourly, "Paid volume check");
+
+        if let Some(spike) = classify_volume(last_hour_count, baseline_hourly) {
+            warn!(chain = %chain, spike, last_hour_count, baseline_hourly, "Paid volume anomaly");
+
+            state.alert(Alert::PaidVolumeAnomaly {
+                chain,
+                spike,
+                last_hour_count,
+                baseline_hourly,
+            }).await;
+        }
+    }
+}
+
+async fn check_config_drift(state: &Arc<AppState>) {
+    let drift = match state.config_drift().await {
+        Ok(drift) => drift,
+        Err(e) => {
+            error!(error = %e, "Failed to compare listener configs with DB");
+            return;
+        }
+    };
+
+    let reconcile = state.services_config.read().await.reconcile_config_drift;
+
+    for ConfigDrift { chain, fields, listener_version, stored_version } in drift {
+        warn!(chain = %chain, ?fields, listener_version, stored_version,
+            "Listener runs with a config that differs from DB");
+
+        let reconciled = reconcile && match state.clone().reload_chain(&chain).await {
+            Ok(()) => true,
+            Err(e) => {
+                error!(chain = %chain, error = %e, "Failed to reload drifted chain");
+                false
+            }
+        };
+
+        state.alert(Alert::ChainConfigDrift { chain, fields, reconciled }).await;
+    }
+}
+
+async fn check_stalled_chains(state: &AppState, progress: &mut HashMap<String, (u64, Instant)>) {
+    let active: Vec<String> = state.active_chains.read().await.keys().cloned().collect();
+    progress.retain(|chain, _| active.contains(chain));
+
+    for chain in active {
+        let Ok(Some(blockchain)) = state.db.get_chain(&chain).await else {
+            continue
+        };
+
+        let (block, in_maintenance) = {
+            let config_lock = blockchain.config();
+            let config = config_lock.read().unwrap();
+            (config.last_processed_block, config.in_maintenance(Utc::now()))
+        };
+
+        // a paused listener is expected to stand still, start counting again afterwards
+        if in_maintenance {
+            progress.remove(&chain);
+            continue;
+        }
+
+        let entry = progress.entry(chain.clone()).or_insert((block, runtime::now()));
+        if entry.0 != block {
+            *entry = (block, runtime::now());
+            continue;
+        }
+
+        let stalled_for = runtime::now().duration_since(entry.1);
+        if stalled_for >= CHAIN_STALL_THRESHOLD {
+            warn!(chain = %chain, block, ?stalled_for, "Chain listener made no progress");
+
+            state.alert(Alert::ChainStalled {
+                chain,
+                last_processed_block: block,
+                stalled_for_secs: stalled_for.as_secs(),
+            }).await;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_volume() {
+        assert_eq!(classify_volume(0, 10.0), Some(false));
+        assert_eq!(classify_volume(8, 10.0), None);
+        assert_eq!(classify_volume(0, 1.0), None); // too quiet to call it a drop
+        assert_eq!(classify_volume(60, 10.0), Some(true));
+        assert_eq!(classify_volume(10, 0.0), None); // below the spike floor
+        assert_eq!(classify_volume(25, 0.0), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_grace_period_keeps_address_watched() {
+        use crate::db::mock::MockDatabase;
+        use crate::model::{Invoice, InvoiceStatus};
+        use alloy::primitives::U256;
+
+        let db = MockDatabase::new();
+        let expired = |address: &str, grace_period_secs: Option<u64>| Invoice {
+            address: address.to_owned(),
+            amount: "1".to_owned(),
+            amount_raw: U256::from(1),
+            token: "ETH".to_owned(),
+            decimals: 0,
+            created_at: Utc::now() - chrono::Duration::hours(1),
+            expires_at: Utc::now() - chrono::Duration::minutes(1),
+            grace_period_secs,
+            ..Invoice::for_test()
+        };
+
+        let graced = expired("0xaaaa", Some(10 * 60));
+        db.add_invoice(&graced).await.unwrap();
+        db.add_invoice(&expired("0xbbbb", None)).await.unwrap();
+
+        assert_eq!(db.expire_old_invoices().await.unwrap().len(), 2);
+        assert_eq!(db.release_expired_addresses().await.unwrap(),
+                   vec![("testnet".to_owned(), "0xbbbb".to_owned())]);
+        assert!(db.release_expired_addresses().await.unwrap().is_empty());
+
+        assert!(db.revive_invoice_in_grace("testnet", "0xbbbb").await.unwrap().is_none());
+        let revived = db.revive_invoice_in_grace("testnet", "0xaaaa").await.unwrap().unwrap();
+        assert_eq!(revived.status, InvoiceStatus::Pending);
+        assert_eq!(revived.expires_at, graced.expires_at + chrono::Duration::minutes(10));
+        assert_eq!(revived.grace_period_secs, None); // used up
+
+        // reissued, the payment stays with neither of them
+        let replaced = expired("0xcccc", Some(10 * 60));
+        db.add_invoice(&replaced).await.unwrap();
+        db.expire_old_invoices().await.unwrap();
+        db.add_invoice(&Invoice {
+            reissued_from: Some(replaced.id.clone()),
+            ..expired("0xdddd", None)
+        }).await.unwrap();
+        assert!(db.revive_invoice_in_grace("testnet", "0xcccc").await.unwrap().is_none());
+
+        let state = AppState::new(crate::db::Database::Mock(db), "key");
+        let graced = expired("0xeeee", Some(10 * 60));
+        state.db.add_invoice(&graced).await.unwrap();
+        state.db.expire_old_invoices().await.unwrap();
+        let err = state.reissue_invoice(&graced.id).await.unwrap_err();
+        assert!(err.to_string().contains("grace period"));
+
+        // hints for a whole batch in one lookup, unknown ids are skipped
+        let german = Invoice {
+            locale: Some("de-DE".to_owned()),
+            display_currency: Some("EUR".to_owned()),
+            ..expired("0xffff", None)
+        };
+        state.db.add_invoice(&german).await.unwrap();
+        let missing = uuid::Uuid::new_v4().to_string();
+        let hints = display_hints(&state, [german.id.clone(), graced.id.clone(), missing].into_iter()).await;
+        assert_eq!(hints, HashMap::from([
+            (german.id, (Some("de-DE".to_owned()), Some("EUR".to_owned()))),
+            (graced.id, (None, None)),
+        ]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_config_drift_is_detected_and_reconciled() {
+        use crate::db::mock::MockDatabase;
+        use crate::db::Database;
+        use crate::model::{ChainConfig, PartialChainUpdate, StartFrom};
+
+        let state = Arc::new(AppState::new(Database::Mock(MockDatabase::new()), "key"));
+        let config = ChainConfig::builder()
+            .name("sandbox")
+            .rpc_url("http://localhost:8545")
+            .xpub("xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ")
+            .native_symbol("ETH")
+            .required_confirmations(12)
+            .test_mode(true)
+            .build()
+            .unwrap();
+        state.add_chain(&config, StartFrom::Latest).await.unwrap();
+        state.clone().start_listening("sandbox").await.unwrap();
+        assert!(state.config_drift().await.unwrap().is_empty());
+
+        let update: PartialChainUpdate = serde_json::from_value(
+            serde_json::json!({ "required_confirmations": 20 })).unwrap();
+        state.db.update_chain_partial("sandbox", &update).await.unwrap();
+
+        let drift = state.config_drift().await.unwrap();
+        assert_eq!(drift, vec![ConfigDrift {
+            chain: "sandbox".to_owned(),
+            fields: vec!["required_confirmations".to_owned()],
+            listener_version: 0,
+            stored_version: 1,
+        }]);
+
+        // only reported unless reconciling is turned on
+        check_config_drift(&state).await;
+        assert_eq!(state.config_drift().await.unwrap().len(), 1);
+
+        state.services_config.write().await.reconcile_config_drift = true;
+        check_config_drift(&state).await;
+        assert!(state.config_drift().await.unwrap().is_empty());
+        assert!(state.active_chains.read().await.contains_key("sandbox"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_invoices_are_flagged_once() {
+        use crate::db::mock::MockDatabase;
+        use crate::model::Invoice;
+        use alloy::primitives::U256;
+
+        let state = AppState::new(crate::db::Database::Mock(MockDatabase::new()), "key");
+        let db = &state.db;
+        let pending = |address: &str, age: chrono::Duration| Invoice {
+            address: address.to_owned(),
+            amount: "1".to_owned(),
+            amount_raw: U256::from(1),
+            token: "ETH".to_owned(),
+            decimals: 0,
+            webhook_url: Some("https://merchant.example/hook".to_owned()),
+            webhook_secret: Some("s3cret".to_owned()),
+            created_at: Utc::now() - age,
+            expires_at: Utc::now() + chrono::Duration::days(1),
+            ..Invoice::for_test()
+        };
+
+        let abandoned = pending("0xaaaa", chrono::Duration::hours(3));
+        let confirming = pending("0xbbbb", chrono::Duration::hours(3));
+        for invoice in [&abandoned, &confirming, &pending("0xcccc", chrono::Duration::minutes(10))] {
+            db.add_invoice(invoice).await.unwrap();
+        }
+        db.add_payment_attempt(&confirming.id, "0xpayer", "0xbbbb", None, "0xtx", U256::from(1),
+                               1, None, "testnet", None).await.unwrap();
+
+        // its job can't be enqueued, the id is no uuid
+        let broken = Invoice { id: "not-a-uuid".to_owned(), ..pending("0xdddd", chrono::Duration::hours(3)) };
+        db.add_invoice(&broken).await.unwrap();
+
+        let age = Duration::from_secs(60 * 60);
+        let mut stale: Vec<_> = db.get_stale_invoices(age).await.unwrap().into_iter().map(|inv| inv.id).collect();
+        stale.sort();
+        let mut expected = vec![abandoned.id.clone(), broken.id.clone()];
+        expected.sort();
+        assert_eq!(stale, expected);
+
+        notify_stale_invoices(&state, age).await;
+        let jobs = db.select_webhooks_job(10).await.unwrap();
+        assert!(matches!(&jobs[..], [job] if matches!(&job.payload.0,
+            WebhookEvent::InvoiceStale { invoice_id, .. } if *invoice_id == abandoned.id)));
+
+        // only the notified one is marked, the other is tried again
+        let stale: Vec<_> = db.get_stale_invoices(age).await.unwrap().into_iter().map(|inv| inv.id).collect();
+        assert_eq!(stale, vec![broken.id.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_warning_is_marked_once_enqueued() {
+        use crate::db::mock::MockDatabase;
+        use crate::model::Invoice;
+        use alloy::primitives::U256;
+
+        let state = AppState::new(crate::db::Database::Mock(MockDatabase::new()), "key");
+        let expiring = |id: String| Invoice {
+            id,
+            address: "0xaaaa".to_owned(),
+            amount: "1".to_owned(),
+            amount_raw: U256::from(1),
+            token: "ETH".to_owned(),
+            decimals: 0,
+            webhook_url: Some("https://merchant.example/hook".to_owned()),
+            webhook_secret: Some("s3cret".to_owned()),
+            expires_at: Utc::now() + chrono::Duration::minutes(5),
+            expiry_warning_secs: Some(600),
+            locale: Some("de-DE".to_owned()),
+            ..Invoice::for_test()
+        };
+
+        let warned = expiring(uuid::Uuid::new_v4().to_string());
+        // its job can't be enqueued, the id is no uuid
+        let broken = expiring("not-a-uuid".to_owned());
+        state.db.add_invoice(&warned).await.unwrap();
+        state.db.add_invoice(&broken).await.unwrap();
+
+        warn_expiring_invoices(&state).await;
+        let jobs = state.db.select_webhooks_job(10).await.unwrap();
+        assert!(matches!(&jobs[..], [job] if matches!(&job.payload.0,
+            WebhookEvent::InvoiceExpiringSoon { invoice_id, locale: Some(locale), .. }
+                if *invoice_id == warned.id && locale == "de-DE")));
+
+        let pending: Vec<_> = state.db.get_expiring_invoices().await.unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(pending, vec![broken.id.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_paid_volume_counts_archived_payments() {
+        use crate::db::mock::MockDatabase;
+        use crate::model::Invoice;
+        use alloy::primitives::U256;
+
+        let db = Database::Mock(MockDatabase::new());
+        let now = Utc::now();
+        for (i, confirmed_at) in [now - chrono::Duration::hours(10), now - chrono::Duration::minutes(10)]
+            .into_iter().enumerate()
+        {
+            let invoice = Invoice {
+                address_index: i as u32,
+                address: format!("0x{:040x}", i),
+                amount: "1".to_owned(),
+                amount_raw: U256::from(1),
+                token: "ETH".to_owned(),
+                decimals: 0,
+                created_at: now - chrono::Duration::hours(11),
+                expires_at: now + chrono::Duration::hours(1),
+                ..Invoice::for_test()
+            };
+            db.add_invoice(&invoice).await.unwrap();
+            db.add_payment_attempt(&invoice.id, "", &invoice.address, None, &format!("0x{}", i),
+                                   U256::from(1), 1, None, "testnet", None).await.unwrap();
+            let payment_id = db.get_confirming_payments().await.unwrap()[0].id.clone();
+            db.finalize_payment(&payment_id, confirmed_at).await.unwrap();
+        }
+
+        // the older payment is archived, the recent one isn't
+        assert_eq!(db.archive_finalized_payments(now - chrono::Duration::hours(1)).await.unwrap(), 1);
+
+        let (current, baseline) = paid_volumes(&db, now).await.unwrap();
+        assert_eq!(current.get("testnet"), Some(&1));
+        assert_eq!(baseline.get("testnet"), Some(&1));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::db::DatabaseAdapter;
+use crate::model::{AccountSettingKind, InvoiceStatus, ReportPeriod, ReportSubscription, TokenTotal, WebhookEvent};
+use crate::AppState;
+use alloy::primitives::utils::format_units;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use tracing::{debug, error, info, instrument};
+
+impl AppState {
+    pub async fn set_report_subscription(&self, account_id: u32, subscription: Option<ReportSubscription>)
+        -> anyhow::Result<()>
+    {
+        let mut subscriptions = self.report_subscriptions.write().await;
+        if let Some(s) = &subscription {
+            s.validate()?;
+        }
+
+        self.save_account_setting(account_id, AccountSettingKind::ReportSubscription, subscription.as_ref()).await?;
+        match subscription {
+            Some(s) => {
+                info!(target: "audit", action = "set_report_subscription", account_id, period = %s.period,
+                    "Report subscription set");
+                subscriptions.insert(account_id, s);
+            }
+            None => {
+                subscriptions.remove(&account_id);
+            }
+        };
+
+        Ok(())
+    }
+
+    // the merchant's paid and expired invoices in [from, to)
+    #[instrument(skip(self), err)]
+    pub async fn period_summary(&self, account_id: u32, period: ReportPeriod,
+                                from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<WebhookEvent>
+    {
+        let totals = self.db.get_invoice_totals(account_id, from, to).await?;
+
+        let mut paid = vec![];
+        let mut expired = vec![];
+
+        for t in totals {
+            let total = TokenTotal {
+                network: t.network,
+                token: t.token,
+                count: t.count,
+                amount: format_units(t.amount_raw, t.decimals)?,
+            };
+
+            match t.status {
+                InvoiceStatus::Paid => paid.push(total),
+                InvoiceStatus::Expired => expired.push(total),
+                InvoiceStatus::Pending => {}
+            }
+        }
+
+        Ok(WebhookEvent::period_summary(account_id, period, from, to, paid, expired))
+    }
+
+    // queues the last complete period of every subscribed merchant that isn't in `sent` yet.
+    // the DB drops a period queued twice, `sent` only saves the aggregation
+    pub(crate) async fn enqueue_period_summaries(&self, now: DateTime<Utc>,
+                                                 sent: &mut HashMap<u32, DateTime<Utc>>) {
+        let subscriptions: Vec<(u32, ReportSubscription)> = self.report_subscriptions.read().await
+            .iter()
+            .map(|(account_id, s)| (*account_id, s.clone()))
+            .collect();
+        sent.retain(|account_id, _| subscriptions.iter().any(|(id, _)| id == account_id));
+
+        for (account_id, subscription) in subscriptions {
+            let (from, to) = subscription.period.last_complete(now);
+            if sent.get(&account_id) == Some(&from) {
+                continue;
+            }
+
+            let queued = match self.period_summary(account_id, subscription.period, from, to).await {
+                Ok(event) => self.db.add_account_webhook_job(account_id, &subscription.url,
+                                                             &subscription.secret, &event).await,
+                Err(e) => Err(e),
+            };
+
+            match queued {
+                Ok(true) => info!(account_id, period = %subscription.period, %from, "Queued period summary"),
+                Ok(false) => debug!(account_id, %from, "Period summary was already queued"),
+                Err(e) => {
+                    error!(account_id, error = %e, "Failed to queue period summary");
+                    continue;
+                }
+            }
+
+            sent.insert(account_id, from);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::db::Database;
+    use crate::model::Invoice;
+    use alloy::primitives::U256;
+    use chrono::TimeZone;
+
+    fn invoice(token: &str, status: InvoiceStatus, paid_at: Option<DateTime<Utc>>, test_mode: bool) -> Invoice {
+        let at = Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap();
+        Invoice {
+            account_id: 7,
+            address: format!("0x{}", uuid::Uuid::new_v4().simple()),
+            amount: "1.5".to_owned(),
+            amount_raw: U256::from(1_500_000),
+            paid: "1.5".to_owned(),
+            paid_raw: U256::from(1_500_000),
+            token: token.to_owned(),
+            network: "ethereum".to_owned(),
+            created_at: at - chrono::Duration::hours(1),
+            expires_at: at,
+            paid_at,
+            status,
+            test_mode,
+            ..Invoice::for_test()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_period_summary_is_queued_once() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let paid_at = Some(Utc.with_ymd_and_hms(2026, 3, 2, 11, 0, 0).unwrap());
+        for inv in [
+            invoice("USDT", InvoiceStatus::Paid, paid_at, false),
+            invoice("USDT", InvoiceStatus::Paid, paid_at, false),
+            invoice("USDC", InvoiceStatus::Paid, paid_at, false),
+            invoice("USDC", InvoiceStatus::Expired, None, false),
+            invoice("USDT", InvoiceStatus::Paid, paid_at, true),
+        ] {
+            state.db.add_invoice(&inv).await.unwrap();
+        }
+
+        state.set_report_subscription(7, Some(ReportSubscription {
+            period: ReportPeriod::Daily,
+            url: "https://merchant.example/reports".to_owned(),
+            secret: "s3cret".to_owned(),
+        })).await.unwrap();
+
+        let now = Utc.with_ymd_and_hms(2026, 3, 3, 0, 5, 0).unwrap();
+        let mut sent = HashMap::new();
+        state.enqueue_period_summaries(now, &mut sent).await;
+        sent.clear(); // as after a restart
+        state.enqueue_period_summaries(now, &mut sent).await;
+
+        let jobs = state.db.select_webhooks_job(10).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].account_id, 7);
+        assert_eq!(jobs[0].secret_key, "s3cret");
+        assert_eq!(jobs[0].url, "https://merchant.example/reports");
+
+        let WebhookEvent::PeriodSummary { from, to, paid_count, expired_count, paid, expired, top_tokens, .. }
+            = jobs[0].payload.0.clone() else {
+            panic!("not a period summary");
+        };
+        assert_eq!((from, to), ReportPeriod::Daily.last_complete(now));
+        assert_eq!((paid_count, expired_count), (3, 1));
+        assert_eq!(paid.iter().map(|t| (t.token.as_str(), t.count, t.amount.as_str())).collect::<Vec<_>>(),
+                   vec![("USDC", 1, "1.500000"), ("USDT", 2, "3.000000")]);
+        assert_eq!(expired[0].amount, "1.500000");
+        assert_eq!(top_tokens, vec!["USDT", "USDC"]);
+
+        // not part of resuming payment processing elsewhere
+        assert!(state.db.get_pending_webhooks().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_subscriptions_are_persisted() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let subscription = ReportSubscription {
+            period: ReportPeriod::Weekly,
+            url: "https://merchant.example/reports".to_owned(),
+            secret: "s3cret".to_owned(),
+        };
+
+        state.set_report_subscription(7, Some(subscription.clone())).await.unwrap();
+        assert!(state.set_report_subscription(8, Some(ReportSubscription {
+            url: "ftp://merchant.example".to_owned(),
+            ..subscription.clone()
+        })).await.is_err());
+
+        state.report_subscriptions.write().await.clear();
+        state.load_account_settings().await.unwrap();
+        assert_eq!(*state.report_subscriptions.read().await, HashMap::from([(7, subscription)]));
+
+        state.set_report_subscription(7, None).await.unwrap();
+        state.load_account_settings().await.unwrap();
+        assert!(state.report_subscriptions.read().await.is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::chain::BlockchainAdapter;
+use crate::db::DatabaseAdapter;
+use crate::model::{ChainSnapshot, ImportReport, InvoiceStatus, StateSnapshot, STATE_SNAPSHOT_VERSION};
+use crate::AppState;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use tracing::{info, instrument};
+
+impl AppState {
+    // versioned JSON snapshot for recovery drills and moving between environments.
+    // RPC credentials are left out, set them again on the imported chains
+    #[instrument(skip_all, err)]
+    pub async fn export_state(&self, writer: impl Write) -> anyhow::Result<()> {
+        let mut chains = Vec::new();
+        for blockchain in self.db.get_chains().await? {
+            let config = blockchain.config().read().unwrap().clone();
+            let mut tokens = config.tokens();
+            tokens.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+            chains.push(ChainSnapshot { config, tokens });
+        }
+        chains.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+
+        let snapshot = StateSnapshot {
+            version: STATE_SNAPSHOT_VERSION,
+            exported_at: Utc::now(),
+            chains,
+            invoices: self.db.get_invoices_by_status(InvoiceStatus::Pending).await?,
+            payments: self.db.get_confirming_payments().await?,
+            webhooks: self.db.get_pending_webhooks().await?,
+        };
+
+        serde_json::to_writer(writer, &snapshot)?;
+
+        info!(target: "audit", action = "export_state", chains = snapshot.chains.len(),
+            invoices = snapshot.invoices.len(), payments = snapshot.payments.len(),
+            webhooks = snapshot.webhooks.len(), "State exported");
+        Ok(())
+    }
+
+    // only creates what is missing, so importing the same snapshot twice is harmless.
+    // listeners of imported chains still have to be started
+    #[instrument(skip_all, err)]
+    pub async fn import_state(&self, reader: impl Read) -> anyhow::Result<ImportReport> {
+        let snapshot: StateSnapshot = serde_json::from_reader(reader)?;
+        if snapshot.version > STATE_SNAPSHOT_VERSION {
+            anyhow::bail!("snapshot version {} is newer than the supported version {}",
+                snapshot.version, STATE_SNAPSHOT_VERSION);
+        }
+
+        let mut report = ImportReport::default();
+
+        for chain in &snapshot.chains {
+            let name = &chain.config.name;
+            if !self.db.chain_exists(name).await? {
+                self.db.add_chain(&chain.config).await?;
+                report.chains += 1;
+            }
+
+            for token in &chain.tokens {
+                if self.db.get_token(name, &token.symbol).await?.is_none() {
+                    self.db.add_token(name, token).await?;
+                    report.tokens += 1;
+                }
+            }
+        }
+
+        for invoice in &snapshot.invoices {
+            if self.db.get_invoice(&invoice.id).await?.is_some() {
+                continue;
+            }
+
+            self.db.add_invoice(invoice).await?;
+            if invoice.status == InvoiceStatus::Pending {
+                self.db.add_watch_address(&invoice.network, &invoice.address).await?;
+            }
+            report.invoices += 1;
+        }
+
+        let known: HashSet<_> = self.db.get_confirming_payments().await?.into_iter()
+            .map(|p| (p.network, p.tx_hash, p.log_index))
+            .collect();
+
+        for payment in &snapshot.payments {
+            if known.contains(&(payment.network.clone(), payment.tx_hash.clone(), payment.log_index)) {
+                continue;
+            }
+
+            // snapshots from before log_index was optional stored native transfers as u64::MAX
+            let log_index = payment.log_index.filter(|i| *i != u64::MAX);
+            self.db.add_payment_attempt(&payment.invoice_id, &payment.from, &payment.to,
+                                        payment.payer.as_deref(), &payment.tx_hash,
+                                        payment.amount_raw, payment.block_number,
+                                        payment.block_timestamp, &payment.network,
+                                        log_index).await?;
+            report.payments += 1;
+        }
+
+        // attempts and delivery tokens start over, consumers see these as fresh deliveries
+        let queued = self.db.get_pending_webhooks().await?;
+        for webhook in &snapshot.webhooks {
+            if queued.iter().any(|q| q.invoice_id == webhook.invoice_id && q.event == webhook.event) {
+                continue;
+            }
+
+            self.db.add_webhook_job(&webhook.invoice_id, &webhook.event).await?;
+            report.webhooks += 1;
+        }
+
+        info!(target: "audit", action = "import_state", version = snapshot.version,
+            exported_at = %snapshot.exported_at, ?report, "State imported");
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::db::Database;
+    use crate::model::{ChainConfig, Invoice, RpcAuth, TokenConfig, WebhookEvent};
+    use alloy::primitives::U256;
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+
+    async fn seeded_state() -> AppState {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .rpc_auth(RpcAuth::Bearer { token: "rpc-s3cret".to_owned() })
+            .build()
+            .unwrap();
+        state.db.add_chain(&chain).await.unwrap();
+
+        let token = TokenConfig::builder()
+            .symbol("USDT")
+            .contract("0x3333333333333333333333333333333333333333")
+            .decimals(6)
+            .build()
+            .unwrap();
+        state.db.add_token("testnet", &token).await.unwrap();
+
+        let invoice = Invoice {
+            amount: "1.000000".to_owned(),
+            amount_raw: U256::from(1_000_000),
+            webhook_url: Some("http://localhost:9999/hook".to_owned()),
+            ..Invoice::for_test()
+        };
+        state.db.add_invoice(&invoice).await.unwrap();
+
+        state.db.add_payment_attempt(&invoice.id, ADDRESS, ADDRESS, None, "0xabc",
+                                     U256::from(1_000_000), 42, None, "testnet", Some(3))
+            .await.unwrap();
+
+        state.db.add_webhook_job(&invoice.id, &WebhookEvent::TxDetected {
+            invoice_id: invoice.id.clone(),
+            tx_hash: "0xabc".to_owned(),
+            amount: "1.000000".to_owned(),
+            currency: "USDT".to_owned(),
+            token: None,
+            log_index: None,
+            eta_secs: None,
+            locale: None,
+            display_currency: None,
+        }).await.unwrap();
+
+        state
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import() {
+        let source = seeded_state().await;
+        let mut buf = Vec::new();
+        source.export_state(&mut buf).await.unwrap();
+
+        let target = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let report = target.import_state(buf.as_slice()).await.unwrap();
+        assert_eq!(report, ImportReport { chains: 1, tokens: 1, invoices: 1, payments: 1, webhooks: 1 });
+
+        assert!(!String::from_utf8(buf.clone()).unwrap().contains("rpc-s3cret"));
+        let imported = target.db.get_stored_chain_config("testnet").await.unwrap().unwrap();
+        assert!(imported.rpc_auth.is_none());
+
+        assert_eq!(target.db.get_token_decimals("testnet", "USDT").await.unwrap(), Some(6));
+        assert_eq!(target.db.get_watch_addresses("testnet").await.unwrap(),
+            Some(vec![ADDRESS.to_owned()]));
+
+        // a second import finds everything in place
+        let again = target.import_state(buf.as_slice()).await.unwrap();
+        assert_eq!(again, ImportReport::default());
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_newer_version() {
+        let snapshot = serde_json::json!({
+            "version": STATE_SNAPSHOT_VERSION + 1,
+            "exported_at": Utc::now(),
+            "chains": [], "invoices": [], "payments": [], "webhooks": []
+        });
+
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        assert!(state.import_state(snapshot.to_string().as_bytes()).await.is_err());
+    }
+}
@@ -1,28 +1,196 @@
 use crate::db::DatabaseAdapter;
-use crate::model::{PaymentEvent, WebhookEvent};
+use crate::chain::BlockchainAdapter;
+use crate::model::{Invoice, InvoiceStatus, MisdirectedPayment, MisdirectedStatus, PaymentEvent, WebhookEvent};
+use chrono::Utc;
 use crate::AppState;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc::Receiver;
-use tokio::task::JoinHandle;
+use tokio::sync::oneshot;
+use crate::runtime::{self, JoinHandle};
 
 use tracing::{debug, error, info, instrument, warn, Instrument};
 
-#[instrument(skip_all)]
-pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent>) -> JoinHandle<()> {
+// block timestamps come from the producer's clock and only have second precision
+const BLOCK_CLOCK_SKEW_SECS: i64 = 60;
+
+// the stored address can differ in formatting (case, whitespace) from what the listener
+// reports, so unmatched events are looked up by the normalized stored address and the one
+// derived from the index. only rebuilt when the chain's watch set changes, and an invoice's
+// address is derived once per watcher
+#[derive(Default)]
+struct AddressIndex {
+    version: Option<(usize, u64)>, // (chain, generation), a reloaded chain starts a new generation
+    derived: HashMap<String, String>, // invoice id -> derived address
+    by_address: HashMap<String, String>, // normalized or derived address -> invoice id
+}
+
+impl AddressIndex {
+    async fn find(&mut self, state: &AppState, event: &PaymentEvent) -> Option<Invoice> {
+        let blockchain = state.db.get_chain(&event.network).await.ok().flatten()?;
+        let version = (Arc::as_ptr(&blockchain) as usize, blockchain.config().read().unwrap().generation());
+
+        if self.version != Some(version) {
+            let pending = state.db
+                .get_invoices_by_chain_and_status(&event.network, InvoiceStatus::Pending).await
+                .inspect_err(|e| error!(error = %e, "DB error during address index fallback"))
+                .ok()?;
+
+            let mut derived = HashMap::with_capacity(pending.len());
+            let mut by_address = HashMap::with_capacity(pending.len());
+            for invoice in pending {
+                if let Some(normalized) = blockchain.normalize_address(&invoice.address) {
+                    by_address.insert(normalized, invoice.id.clone());
+                }
+
+                let address = match self.derived.remove(&invoice.id) {
+                    Some(address) => address,
+                    None => match blockchain.derive_address(invoice.account_id, invoice.address_index).await {
+                        Ok(address) => address,
+                        Err(e) => {
+                            debug!(invoice_id = %invoice.id, error = %e, "Failed to derive address");
+                            continue;
+                        }
+                    },
+                };
+                by_address.insert(address.clone(), invoice.id.clone());
+                derived.insert(invoice.id, address);
+            }
+
+            *self = Self { version: Some(version), derived, by_address };
+        }
+
+        let id = self.by_address.get(&event.to)?;
+        state.db.get_invoice(id).await
+            .inspect_err(|e| error!(error = %e, "DB error during address index fallback"))
+            .ok()
+            .flatten()
+            .filter(|invoice| invoice.status == InvoiceStatus::Pending)
+    }
+}
+
+// blocks the confirmator still waits for, times the average block time
+async fn confirmation_eta(state: &AppState, event: &PaymentEvent) -> Option<u64> {
+    let blockchain = state.db.get_chain(&event.network).await.ok().flatten()?;
+    let (last_processed, required) = {
+        let config = blockchain.config();
+        let guard = config.read().unwrap();
+        (guard.last_processed_block, guard.required_confirmations)
+    };
+
+    let remaining = (event.block_number + required).saturating_sub(last_processed);
+    blockchain.block_time().eta(remaining).map(|eta| eta.as_secs())
+}
+
+// a rescan or lookback can go over a transfer meant for an earlier invoice on the same address index
+fn predates(invoice: &Invoice, event: &PaymentEvent) -> bool {
+    event.block_timestamp.is_some_and(|mined_at|
+        mined_at + chrono::Duration::seconds(BLOCK_CLOCK_SKEW_SECS) < invoice.created_at)
+}
+
+// only a transfer the invoice would take revives it. anything else leaves the expired invoice
+// and its unused grace alone, the address holds a single invoice in grace at a time
+async fn revive_in_grace(state: &AppState, event: &PaymentEvent) -> Option<Invoice> {
+    let now = Utc::now();
+    let graced = state.db.get_invoices_by_address_and_status(&event.to, InvoiceStatus::Expired).await
+        .inspect_err(|e| error!(error = %e, "DB error while looking for an invoice in grace period"))
+        .ok()?
+        .into_iter()
+        .find(|inv| inv.network == event.network && inv.grace_period_secs.is_some()
+            && inv.payment_deadline() > now)?;
+
+    if !event.token.matches(&graced.token_ref()) || predates(&graced, event) {
+        debug!(invoice_id = %graced.id, got = %event.token,
+            "Transfer doesn't pay the invoice in grace period, not reviving it");
+        return None;
+    }
+
+    state.db.revive_invoice_in_grace(&event.network, &event.to).await
+        .inspect_err(|e| error!(error = %e, "DB error while reviving invoice in grace period"))
+        .ok()
+        .flatten()
+}
+
+// not credited, the merchant decides whether to refund
+async fn record_misdirected_payment(state: &AppState, invoice: &Invoice, event: &PaymentEvent) {
+    let payment = MisdirectedPayment {
+        id: uuid::Uuid::new_v4().to_string(),
+        invoice_id: invoice.id.clone(),
+        token: event.token.clone(),
+        tx_hash: event.tx_hash.to_string(),
+        from: event.from.clone(),
+        to: event.to.clone(),
+        amount: event.amount.clone(),
+        amount_raw: event.amount_raw,
+        block_number: event.block_number,
+        log_index: event.log_index,
+        status: MisdirectedStatus::Open,
+        created_at: Utc::now(),
+        resolved_at: None,
+    };
+
+    match state.db.add_misdirected_payment(&payment).await {
+        Ok(true) => {}
+        Ok(false) => return, // seen before, e.g. on a rescan
+        Err(e) => {
+            error!(invoice_id = %invoice.id, error = %e, "Failed to record misdirected payment");
+            return;
+        }
+    }
+
+    let webhook_event = WebhookEvent::WrongAssetReceived {
+        invoice_id: invoice.id.clone(),
+        tx_hash: payment.tx_hash,
+        log_index: payment.log_index,
+        amount: payment.amount,
+        received: payment.token,
+        expected: invoice.token_ref(),
+    };
+
+    if let Err(e) = state.db.add_webhook_job(&invoice.id, &webhook_event).await {
+        error!(invoice_id = %invoice.id, error = %e, "Failed to add WrongAssetReceived webhook job");
+    }
+}
+
+#[instrument(skip(state, rx))]
+pub fn start_invoice_watcher(
+    state: Arc<AppState>,
+    chain: &str,
+    mut rx: Receiver<PaymentEvent>,
+    mut stop: oneshot::Receiver<()>,
+) -> JoinHandle<()> {
     info!("Starting invoice watcher service");
 
-    let span = tracing::info_span!(parent: None, "invoice_watcher_loop");
+    let span = tracing::info_span!(parent: None, "invoice_watcher_loop", chain = %chain);
 
-    tokio::spawn(async move {
+    runtime::spawn(async move {
         debug!("Invoice watcher loop started, waiting for events...");
 
-        while let Some(event) = rx.recv().await {
+        let mut closed = false;
+        let mut addresses = AddressIndex::default();
+
+        loop {
+            // closing makes the listener wind down, what it already sent is still processed
+            let event = tokio::select! {
+                event = rx.recv() => event,
+                _ = &mut stop, if !closed => {
+                    debug!("Stop requested, draining the event channel");
+                    rx.close();
+                    closed = true;
+                    continue;
+                }
+            };
+
+            let Some(event) = event else {
+                break;
+            };
+
             let process_span = tracing::info_span!(
                 "process_payment",
                 tx_hash = %event.tx_hash,
                 amount = %event.amount,
                 network = %event.network,
-                token = %event.token
+                token = %event.token.symbol
             );
 
             async {
@@ -32,6 +200,16 @@ pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent
                     &event.network, &event.to).await
                 {
                     Ok(Some(inv)) => inv,
+                    Ok(None) if let Some(inv) = revive_in_grace(&state, &event).await => {
+                        info!(invoice_id = %inv.id, expires_at = %inv.expires_at,
+                            "Payment arrived within the grace period, invoice revived");
+                        inv
+                    }
+                    Ok(None) if let Some(inv) = addresses.find(&state, &event).await => {
+                        warn!(invoice_id = %inv.id, stored = %inv.address, to_address = %event.to,
+                            "Matched payment by address index, stored address is not canonical");
+                        inv
+                    }
                     Ok(None) => {
                         warn!(to_address = %event.to,
                             "Received payment to an address with no pending invoice \
@@ -44,14 +222,19 @@ pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent
                     }
                 };
 
-                if event.network != invoice.network || event.token != invoice.token {
+                if !event.token.matches(&invoice.token_ref()) {
                     warn!(
-                        expected_network = %invoice.network,
-                        expected_token = %invoice.token,
-                        got_network = %event.network,
-                        got_token = %event.token,
+                        expected = %invoice.token_ref(),
+                        got = %event.token,
                         "Payment mismatch: received wrong token or network for this invoice"
                     );
+                    record_misdirected_payment(&state, &invoice, &event).await;
+                    return;
+                }
+
+                if predates(&invoice, &event) {
+                    warn!(invoice_id = %invoice.id, mined_at = ?event.block_timestamp,
+                        created_at = %invoice.created_at, "Payment predates the invoice, not crediting it");
                     return;
                 }
 
@@ -59,9 +242,11 @@ pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent
                     &invoice.id,
                     &event.from,
                     &event.to,
+                    event.payer.as_deref(),
                     &event.tx_hash.to_string(),
                     event.amount_raw,
                     event.block_number,
+                    event.block_timestamp,
                     &event.network,
                     event.log_index
                 ).await {
@@ -73,7 +258,12 @@ pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent
                             invoice_id: invoice.id.clone(),
                             tx_hash: event.tx_hash.to_string(),
                             amount: event.amount.clone(),
-                            currency: event.token.clone(),
+                            currency: event.token.symbol.clone(),
+                            token: Some(event.token.clone()),
+                            log_index: event.log_index,
+                            eta_secs: confirmation_eta(&state, &event).await,
+                            locale: invoice.locale.clone(),
+                            display_currency: invoice.display_currency.clone(),
                         };
 
                         if let Err(e) = state.db.add_webhook_job(
@@ -97,6 +287,145 @@ pub fn start_invoice_watcher(state: Arc<AppState>, mut rx: Receiver<PaymentEvent
             }.instrument(process_span).await;
         }
 
-        warn!("Invoice watcher channel closed, service stopping");
+        match closed {
+            true => info!("Invoice watcher drained, service stopping"),
+            false => warn!("Invoice watcher channel closed, service stopping"),
+        }
     }.instrument(span))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mock::MockDatabase;
+    use crate::db::Database;
+    use crate::model::{ChainConfig, TokenRef};
+    use alloy::primitives::{TxHash, U256};
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    fn invoice(address: &str, address_index: u32) -> Invoice {
+        Invoice {
+            address_index,
+            address: address.to_owned(),
+            amount: "1".to_owned(),
+            amount_raw: U256::from(10).pow(U256::from(18)),
+            token: "ETH".to_owned(),
+            decimals: 18,
+            ..Invoice::for_test()
+        }
+    }
+
+    fn event(to: &str) -> PaymentEvent {
+        PaymentEvent {
+            network: "testnet".to_owned(),
+            tx_hash: TxHash::ZERO,
+            from: String::new(),
+            to: to.to_owned(),
+            payer: None,
+            token: TokenRef::native("testnet", "ETH"),
+            amount: "1".to_owned(),
+            amount_raw: U256::from(10).pow(U256::from(18)),
+            decimals: 18,
+            block_number: 1,
+            block_timestamp: None,
+            log_index: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_address_index_matches_non_canonical_addresses() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let chain = ChainConfig::builder()
+            .name("testnet")
+            .rpc_url("http://localhost:8545")
+            .xpub(XPUB)
+            .native_symbol("ETH")
+            .build()
+            .unwrap();
+        state.db.add_chain(&chain).await.unwrap();
+        let blockchain = state.db.get_chain("testnet").await.unwrap().unwrap();
+        let first = blockchain.derive_address(0, 0).await.unwrap();
+        let second = blockchain.derive_address(0, 1).await.unwrap();
+
+        let lowercase = invoice(&first.to_lowercase(), 0);
+        let garbled = invoice("not an address", 1);
+        state.db.add_invoice(&lowercase).await.unwrap();
+        state.db.add_invoice(&garbled).await.unwrap();
+
+        let mut addresses = AddressIndex::default();
+        assert_eq!(addresses.find(&state, &event(&first)).await.unwrap().id, lowercase.id);
+        assert_eq!(addresses.find(&state, &event(&second)).await.unwrap().id, garbled.id);
+        assert_eq!(addresses.derived.len(), 2);
+
+        // no longer pending, the cached entry doesn't match it anymore
+        state.db.set_invoice_status(&garbled.id, InvoiceStatus::Paid).await.unwrap();
+        assert!(addresses.find(&state, &event(&second)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_only_a_matching_transfer_revives_an_invoice_in_grace() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let address = "0x1111111111111111111111111111111111111111";
+        let graced = Invoice {
+            created_at: Utc::now() - chrono::Duration::hours(1),
+            expires_at: Utc::now() - chrono::Duration::minutes(1),
+            grace_period_secs: Some(10 * 60),
+            ..invoice(address, 0)
+        };
+        state.db.add_invoice(&graced).await.unwrap();
+        state.db.expire_old_invoices().await.unwrap();
+
+        let untouched = || async {
+            let inv = state.db.get_invoice(&graced.id).await.unwrap().unwrap();
+            inv.status == InvoiceStatus::Expired && inv.grace_period_secs.is_some()
+        };
+
+        let wrong_token = PaymentEvent {
+            token: TokenRef::new("testnet", "USDT", "0x3333333333333333333333333333333333333333"),
+            ..event(address)
+        };
+        assert!(revive_in_grace(&state, &wrong_token).await.is_none());
+        assert!(untouched().await);
+
+        let before_invoice = PaymentEvent {
+            block_timestamp: Some(Utc::now() - chrono::Duration::hours(2)),
+            ..event(address)
+        };
+        assert!(revive_in_grace(&state, &before_invoice).await.is_none());
+        assert!(untouched().await);
+
+        let revived = revive_in_grace(&state, &event(address)).await.unwrap();
+        assert_eq!((revived.id, revived.status), (graced.id.clone(), InvoiceStatus::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_asset_is_recorded_and_reported_once() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let address = "0x1111111111111111111111111111111111111111";
+        let eth_invoice = Invoice {
+            webhook_url: Some("https://merchant.example/hooks".to_owned()),
+            ..invoice(address, 0)
+        };
+        state.db.add_invoice(&eth_invoice).await.unwrap();
+
+        let usdt = TokenRef::new("testnet", "USDT", "0x3333333333333333333333333333333333333333");
+        let transfer = PaymentEvent { token: usdt.clone(), log_index: Some(2), ..event(address) };
+        record_misdirected_payment(&state, &eth_invoice, &transfer).await;
+        record_misdirected_payment(&state, &eth_invoice, &transfer).await; // e.g. on a rescan
+
+        let misdirected = state.db.get_misdirected_payments(&eth_invoice.id).await.unwrap();
+        assert_eq!(misdirected.len(), 1);
+        assert_eq!((&misdirected[0].token, misdirected[0].status), (&usdt, MisdirectedStatus::Open));
+
+        let jobs = state.db.select_webhooks_job(10).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        let WebhookEvent::WrongAssetReceived { received, expected, log_index, .. } = jobs[0].payload.0.clone() else {
+            panic!("not a WrongAssetReceived event");
+        };
+        assert_eq!((received, expected, log_index), (usdt, eth_invoice.token_ref(), Some(2)));
+
+        // nothing was credited
+        assert_eq!(state.db.get_invoice(&eth_invoice.id).await.unwrap().unwrap().paid_raw, U256::ZERO);
+    }
+}
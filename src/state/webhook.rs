@@ -1,48 +1,214 @@
 use crate::db::{Database, DatabaseAdapter};
-use crate::model::{WebhookJob, WebhookStatus};
+use crate::model::{ErrorCode, ErrorEnvelope, RedactionPolicy, WebhookEvent, WebhookJob, WebhookStatus};
+use crate::notify::Alert;
+use crate::signature;
+use crate::state::HostLimits;
 use crate::AppState;
 use chrono::Utc;
+use dashmap::DashMap;
 use hmac::{Hmac, Mac};
 use reqwest::Client;
+use serde_json::Value;
 use sha2::Sha256;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::task::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use crate::runtime::{self, JoinHandle};
+use url::Url;
 
 use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
-#[instrument(skip(state))]
-pub fn start_webhook_dispatcher(state: Arc<AppState>) -> JoinHandle<()> {
+// well above any allowed request timeout, a job still Processing after this was lost by a crashed dispatcher
+pub(crate) const VISIBILITY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const REQUEUE_INTERVAL: Duration = Duration::from_secs(30);
+// a job that doesn't fit into its host queue goes back to Pending for this long
+const HOST_DEFER_SECS: f64 = 1.0;
+// besides failing for webhook_suspend_after, so a url that got a single job in days isn't suspended
+const SUSPEND_MIN_FAILURES: u32 = 20;
+
+struct HostSlot {
+    in_flight: Arc<Semaphore>,
+    queued: AtomicUsize, // waiting + in flight
+    capacity: usize,
+    interval: Duration, // between two sends, from max_per_second
+    next_send: Mutex<Instant>,
+}
+
+// released when the delivery finishes, whether it was sent or not
+struct HostTicket {
+    slot: Arc<HostSlot>,
+}
+
+impl HostTicket {
+    // waits for a free connection to the host and then for the rate limit
+    async fn ready(&self) -> OwnedSemaphorePermit {
+        let permit = self.slot.in_flight.clone().acquire_owned().await
+            .expect("host semaphore is never closed");
+
+        let send_at = {
+            let mut next_send = self.slot.next_send.lock().await;
+            let send_at = (*next_send).max(runtime::now());
+            *next_send = send_at + self.slot.interval;
+            send_at
+        };
+        runtime::sleep_until(send_at).await;
+
+        permit
+    }
+}
+
+impl Drop for HostTicket {
+    fn drop(&mut self) {
+        self.slot.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// merchants with different overrides get separate buckets even when they share a host
+#[derive(Default)]
+struct HostLimiter {
+    slots: DashMap<(String, HostLimits), Arc<HostSlot>>,
+}
+
+impl HostLimiter {
+    // None once the host already has a full queue
+    fn reserve(&self, host: &str, limits: HostLimits) -> Option<HostTicket> {
+        let slot = self.slots.entry((host.to_owned(), limits))
+            .or_insert_with(|| Arc::new(HostSlot {
+                in_flight: Arc::new(Semaphore::new(limits.max_concurrent)),
+                queued: AtomicUsize::new(0),
+                capacity: limits.max_concurrent + limits.max_queued,
+                interval: Duration::from_secs_f64(1.0 / limits.max_per_second as f64),
+                next_send: Mutex::new(runtime::now()),
+            }))
+            .clone();
+
+        slot.queued.fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+            (queued < slot.capacity).then_some(queued + 1)
+        }).ok()?;
+
+        Some(HostTicket { slot })
+    }
+
+    fn prune_idle(&self) {
+        self.slots.retain(|_, slot| slot.queued.load(Ordering::Acquire) > 0);
+    }
+}
+
+fn destination_host(url: &str) -> String {
+    Url::parse(url).ok()
+        .and_then(|u| Some(format!("{}:{}", u.host_str()?, u.port_or_known_default()?)))
+        .unwrap_or_default()
+}
+
+// a client without the configured proxy or source address would deliver from where merchants
+// don't expect it, so a bad egress config fails the start instead
+#[instrument(skip(state), err)]
+pub async fn start_webhook_dispatcher(state: Arc<AppState>) -> anyhow::Result<JoinHandle<()>> {
     info!("Starting webhook dispatcher service");
 
+    let mut egress = state.egress_config.read().await.clone();
+    let mut client = Arc::new(egress.client()
+        .map_err(|e| anyhow::anyhow!("Invalid egress config: {}", e))?);
+
     let span = tracing::info_span!(parent: None, "webhook_service");
 
-    tokio::spawn(async move {
-        let client = Arc::new(Client::new());
+    Ok(runtime::spawn(async move {
+        let mut last_requeue = runtime::now();
+        let limiter = Arc::new(HostLimiter::default());
+
+        let mut concurrency = state.services_config.read().await.webhook_concurrency;
+        let mut in_flight = Arc::new(Semaphore::new(concurrency));
 
         loop {
-            let jobs_result: anyhow::Result<Vec<WebhookJob>> = state.db.select_webhooks_job().await;
+            let config = *state.services_config.read().await;
+
+            // deliveries holding permits of the old semaphore just finish on their own
+            if config.webhook_concurrency != concurrency {
+                info!(from = concurrency, to = config.webhook_concurrency,
+                    "Webhook concurrency changed");
+                concurrency = config.webhook_concurrency;
+                in_flight = Arc::new(Semaphore::new(concurrency));
+            }
+
+            // in-flight deliveries keep the old client. set_egress_config validates, a config
+            // written around it keeps the previous one
+            let current_egress = state.egress_config.read().await.clone();
+            if current_egress != egress {
+                match current_egress.client() {
+                    Ok(c) => {
+                        info!("Egress config changed, rebuilt the webhook client");
+                        client = Arc::new(c);
+                    }
+                    Err(e) => error!(error = %e, "Invalid egress config, keeping the previous webhook client"),
+                }
+                egress = current_egress;
+            }
+
+            if runtime::now().duration_since(last_requeue) >= REQUEUE_INTERVAL {
+                last_requeue = runtime::now();
+
+                match state.db.requeue_stuck_webhooks(VISIBILITY_TIMEOUT).await {
+                    Ok(0) => {}
+                    Ok(count) => warn!(count, "Requeued webhook jobs stuck in Processing"),
+                    Err(e) => error!(error = %e, "Failed to requeue stuck webhook jobs"),
+                }
+
+                limiter.prune_idle();
+            }
+
+            // only as many jobs are claimed as can be sent right away, a job left waiting in
+            // Processing past VISIBILITY_TIMEOUT would be requeued and delivered twice
+            let mut permits = claim_permits(&in_flight, config.webhook_batch_size as usize).await;
+
+            let jobs_result: anyhow::Result<Vec<WebhookJob>> = state.db.select_webhooks_job(permits.len() as u32).await;
 
             let jobs = match jobs_result {
                 Ok(j) => j,
                 Err(e) => {
                     error!(error = %e, "Failed to select webhook jobs from DB. Retrying in 5s...");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    state.alert(Alert::DatabaseDegraded {
+                        service: "webhook_dispatcher".to_owned(),
+                        error: ErrorEnvelope::new(ErrorCode::DatabaseUnavailable, e),
+                    }).await;
+                    runtime::sleep(Duration::from_secs(5)).await;
                     continue
                 }
             };
 
             if jobs.is_empty() {
-                trace!("No pending webhooks found, sleeping 500ms...");
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                trace!(sleep = ?config.webhook_idle_poll, "No pending webhooks found, sleeping...");
+                runtime::sleep(config.webhook_idle_poll).await;
                 continue;
             }
 
             debug!(count = jobs.len(), "Found pending webhook jobs");
 
+            let pool = state.db.pool_metrics();
+            if pool.max_connections > 0 && pool.in_use >= pool.max_connections {
+                warn!(?pool, "DB connection pool is saturated, webhook jobs may stall on acquire");
+            }
+
             for job in jobs {
+                let limits = state.webhook_host_limits.read().await
+                    .get(&(job.account_id as u32))
+                    .copied()
+                    .unwrap_or(config.webhook_host_limits);
+
+                let host = destination_host(&job.url);
+                let Some(ticket) = limiter.reserve(&host, limits) else {
+                    trace!(job_id = %job.id, %host, "Host queue is full, deferring webhook");
+                    if let Err(e) = state.db.schedule_webhook_retry(
+                        &job.id.to_string(), job.attempts, HOST_DEFER_SECS).await
+                    {
+                        error!(error = %e, "Failed to defer webhook job");
+                    }
+                    continue;
+                };
+
+                let permit = permits.pop().expect("a permit is claimed for every job");
                 let client_clone = client.clone();
-                let db_clone = state.db.clone();
+                let state_clone = state.clone();
 
                 let job_span = tracing::info_span!(
                     "webhook_job",
@@ -51,26 +217,132 @@ pub fn start_webhook_dispatcher(state: Arc<AppState>) -> JoinHandle<()> {
                     attempt = job.attempts
                 );
 
-                tokio::spawn(async move {
-                    if let Err(e) = process_webhook(db_clone, client_clone, job).await {
-                        error!(error = %e, "Failed to process webhook");
+                runtime::spawn(async move {
+                    let (job_id, url) = (job.id.to_string(), job.url.clone());
+                    let redaction = state_clone.redaction_policies.read().await
+                        .get(&(job.account_id as u32))
+                        .cloned();
+
+                    let _permit = permit;
+                    let _host_permit = ticket.ready().await;
+
+                    match process_webhook(state_clone.db.clone(), client_clone, job,
+                                          redaction.as_ref(), config.webhook_timeout).await {
+                        Ok(DeliveryOutcome::Sent) => {
+                            if let Err(e) = state_clone.db.clear_webhook_failures(&url).await {
+                                error!(error = %e, "Failed to clear webhook failure streak");
+                            }
+                        }
+                        Ok(DeliveryOutcome::Retrying { error }) => {
+                            if let Err(e) = track_failure(&state_clone, &job_id, &url, &error, true,
+                                                          config.webhook_suspend_after).await {
+                                error!(error = %e, "Failed to track webhook failure");
+                            }
+                        }
+                        Ok(DeliveryOutcome::DeadLettered { attempts, error }) => {
+                            if let Err(e) = track_failure(&state_clone, &job_id, &url, &error, false,
+                                                          config.webhook_suspend_after).await {
+                                error!(error = %e, "Failed to track webhook failure");
+                            }
+                            state_clone.alert(Alert::WebhookDeadLettered {
+                                job_id, url, attempts, error
+                            }).await;
+                        }
+                        Err(e) => error!(error = %e, "Failed to process webhook"),
                     }
                 }.instrument(job_span));
             }
         }
-    }.instrument(span))
+    }.instrument(span)))
 }
 
-#[instrument(level = "trace", skip(secret, body))] // :)
-fn generate_signature(timestamp: &str, secret: &str, body: &str) -> anyhow::Result<String> {
-    trace!("Generating HMAC signature");
-    let signed_body = format!("{}.{}", timestamp, body);
+// waits for one free delivery slot, then takes whatever else is free up to `max`
+async fn claim_permits(in_flight: &Arc<Semaphore>, max: usize) -> Vec<OwnedSemaphorePermit> {
+    let first = in_flight.clone().acquire_owned().await
+        .expect("delivery semaphore is never closed");
+
+    let mut permits = vec![first];
+    while permits.len() < max {
+        match in_flight.clone().try_acquire_owned() {
+            Ok(permit) => permits.push(permit),
+            Err(_) => break,
+        }
+    }
+
+    permits
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryOutcome {
+    Sent,
+    Retrying { error: ErrorEnvelope },
+    DeadLettered { attempts: i32, error: ErrorEnvelope },
+}
+
+// counts the failure against the url and suspends it once it kept failing for `suspend_after`.
+// `rescheduled` = the job is Pending again and gets suppressed too if the url was suspended meanwhile
+async fn track_failure(state: &AppState, job_id: &str, url: &str, error: &ErrorEnvelope,
+                       rescheduled: bool, suspend_after: Option<Duration>) -> anyhow::Result<()> {
+    let destination = state.db.record_webhook_failure(url, &error.to_string()).await?;
+
+    if destination.suspended_at.is_some() {
+        if rescheduled {
+            state.db.set_webhook_status(job_id, WebhookStatus::Suppressed).await?;
+        }
+        return Ok(());
+    }
+
+    let Some(suspend_after) = suspend_after else {
+        return Ok(());
+    };
+    let failing_for = (Utc::now() - destination.failing_since).to_std().unwrap_or_default();
+    if destination.failure_streak < SUSPEND_MIN_FAILURES || failing_for < suspend_after {
+        return Ok(());
+    }
+
+    // None = another delivery to the url got here first
+    if let Some(suppressed) = state.db.suspend_webhook_destination(url).await? {
+        warn!(url, failures = destination.failure_streak, ?failing_for, suppressed,
+            "Suspending webhook destination");
+        state.alert(Alert::WebhookDestinationSuspended {
+            url: url.to_owned(),
+            failures: destination.failure_streak,
+            failing_for_secs: failing_for.as_secs(),
+            suppressed,
+        }).await;
+    }
+
+    Ok(())
+}
+
+fn hash_field(secret: &str, value: &Value) -> anyhow::Result<String> {
+    let plain = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
 
     let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
-    mac.update(signed_body.as_bytes());
-    let result = mac.finalize();
+    mac.update(plain.as_bytes());
+
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+fn redact_payload(mut payload: Value, policy: &RedactionPolicy, secret: &str) -> anyhow::Result<Value> {
+    let Some(data) = payload.get_mut("data").and_then(Value::as_object_mut) else {
+        return Ok(payload);
+    };
 
-    Ok(hex::encode(result.into_bytes()))
+    if let Some(allowed) = &policy.allowed_fields {
+        data.retain(|k, _| k == "invoice_id" || allowed.contains(k));
+    }
+
+    for field in &policy.hashed_fields {
+        if let Some(value) = data.get_mut(field) {
+            *value = Value::String(hash_field(secret, value)?);
+        }
+    }
+
+    Ok(payload)
 }
 
 #[instrument(skip_all, err)]
@@ -78,15 +350,29 @@ pub async fn process_webhook(
     db: Arc<Database>,
     client: Arc<Client>,
     job: WebhookJob,
-) -> anyhow::Result<()> {
+    redaction: Option<&RedactionPolicy>,
+    timeout: Duration,
+) -> anyhow::Result<DeliveryOutcome> {
     let now = Utc::now().timestamp().to_string();
-    let body_string = serde_json::to_string(&job.payload.0)
+
+    let mut payload = serde_json::to_value(&job.payload.0)?;
+    if let Some(obj) = payload.as_object_mut() {
+        obj.insert("sequence".to_owned(), Value::from(job.sequence));
+    }
+
+    if let Some(policy) = redaction {
+        trace!("Applying redaction policy");
+        payload = redact_payload(payload, policy, &job.secret_key)?;
+    }
+
+    let body_string = serde_json::to_string(&payload)
         .map_err(|e| {
             error!(error = %e, "Failed to serialize webhook payload");
             anyhow::anyhow!(e)
         })?;
 
-    let signature = generate_signature(&now, &job.secret_key, &body_string)?;
+    trace!("Generating HMAC signature");
+    let signature = signature::sign(&now, body_string.as_bytes(), &job.secret_key);
 
     debug!(
         max = job.max_retries,
@@ -96,10 +382,13 @@ pub async fn process_webhook(
     let result = client
         .post(&job.url)
         .header("Content-Type", "application/json")
-        .header("X-Webhook-Timestamp", &now)
-        .header("X-Webhook-Signature", &signature)
+        .header(signature::HEADER_ID, job.id.to_string())
+        .header(signature::HEADER_TIMESTAMP, &now)
+        .header(signature::HEADER_SIGNATURE, &signature)
+        .header(signature::HEADER_SIGNATURE_VERSION, signature::SIGNATURE_VERSION)
+        .header("X-Webhook-Delivery", job.delivery_token.to_string())
         .body(body_string.clone())
-        .timeout(Duration::from_secs(10))
+        .timeout(timeout)
         .send()
         .await;
 
@@ -107,49 +396,64 @@ pub async fn process_webhook(
         Ok(res) if res.status().is_success() => {
             info!(status = %res.status(), "Webhook sent successfully");
             db.set_webhook_status(&job.id.to_string(), WebhookStatus::Sent).await?;
+
+            if let WebhookEvent::InvoicePaid { invoice_id, .. } = &job.payload.0 {
+                match db.record_settlement_latency(invoice_id, Utc::now()).await {
+                    Ok(Some(latency_ms)) => debug!(latency_ms, "Recorded settlement latency"),
+                    Ok(None) => {}
+                    Err(e) => error!(error = %e, "Failed to record settlement latency"),
+                }
+            }
+
+            Ok(DeliveryOutcome::Sent)
         }
         Ok(res) => {
             let status = res.status();
             warn!(status = %status, "Webhook server returned error status");
-            handle_retry(db, job, format!("HTTP Status {}", status)).await?;
+            handle_retry(db, job, ErrorEnvelope::webhook_status(status.as_u16())).await
         }
         Err(e) => {
             warn!(error = %e, "Network error while sending webhook");
-            handle_retry(db, job, e.to_string()).await?;
+            handle_retry(db, job, ErrorEnvelope::new(ErrorCode::WebhookUnreachable, e)).await
         }
     }
-
-    Ok(())
 }
 
 async fn handle_retry(
     db: Arc<Database>,
     job: WebhookJob,
-    reason: String
-) -> anyhow::Result<()> {
+    error: ErrorEnvelope,
+) -> anyhow::Result<DeliveryOutcome> {
     let new_attempts = job.attempts + 1;
 
-    if new_attempts >= job.max_retries {
+    if !error.retryable {
+        error!(reason = %error, attempts = new_attempts, "Webhook was rejected, not retrying");
+        db.set_webhook_status(&job.id.to_string(), WebhookStatus::Failed).await?;
+
+        Ok(DeliveryOutcome::DeadLettered { attempts: new_attempts, error })
+    } else if new_attempts >= job.max_retries {
         error!(
-            reason = %reason,
+            reason = %error,
             attempts = new_attempts,
             "Failed to send webhook after max retries. Giving up."
         );
         db.set_webhook_status(&job.id.to_string(), WebhookStatus::Failed).await?;
+
+        Ok(DeliveryOutcome::DeadLettered { attempts: new_attempts, error })
     } else {
         let wait_time = 2_u64.pow(new_attempts as u32);
 
         warn!(
-            reason = %reason,
+            reason = %error,
             next_attempt_in = %format!("{}s", wait_time),
             attempt = new_attempts,
             "Scheduling webhook retry"
         );
 
         db.schedule_webhook_retry(&job.id.to_string(), new_attempts, wait_time as f64).await?;
-    }
 
-    Ok(())
+        Ok(DeliveryOutcome::Retrying { error })
+    }
 }
 
 
@@ -157,7 +461,9 @@ async fn handle_retry(
 mod tests {
     use super::*;
     use crate::db::mock::MockDatabase;
-    use crate::model::{Invoice, InvoiceStatus, WebhookEvent};
+    use crate::model::{Invoice, WebhookEvent};
+    use futures::FutureExt;
+    use std::collections::HashMap;
     use wiremock::matchers::{header, header_exists, method};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -169,6 +475,9 @@ mod tests {
         Mock::given(method("POST"))
             .and(header("Content-Type", "application/json"))
             .and(header_exists("X-Webhook-Signature"))
+            .and(header_exists("X-Webhook-Delivery"))
+            .and(header_exists("X-Webhook-Id"))
+            .and(header("X-Webhook-Signature-Version", "v1"))
             .respond_with(ResponseTemplate::new(200))
             .mount(&mock_server)
             .await;
@@ -179,34 +488,221 @@ mod tests {
         let event = WebhookEvent::InvoicePaid {
             invoice_id: invoice_uid.clone(),
             paid_amount: "100.0".to_string(),
+            paid_at: Utc::now(),
+            accepted_shortfall: None,
+            locale: None,
+            display_currency: None,
         };
 
         let db = Arc::new(Database::Mock(MockDatabase::new()));
         db.add_invoice(&Invoice {
             id: invoice_uid.clone(),
-            address_index: 0,
-            address: "".to_string(),
-            amount: "".to_string(),
-            amount_raw: Default::default(),
-            paid: "".to_string(),
-            paid_raw: Default::default(),
-            token: "".to_string(),
-            network: "".to_string(),
-            decimals: 0,
             webhook_url: Some(mock_server.uri()),
             webhook_secret: Some(secret.to_string()),
-            created_at: Default::default(),
-            expires_at: Default::default(),
-            status: InvoiceStatus::Pending,
+            ..Invoice::for_test()
         }).await.unwrap();
 
         db.add_webhook_job(&invoice_uid.clone(), &event).await.unwrap();
 
-        let mut jobs = db.select_webhooks_job().await.unwrap();
+        let mut jobs = db.select_webhooks_job(50).await.unwrap();
         assert!(!jobs.is_empty(), "Job was not created in DB");
 
         let job = jobs.remove(0);
+        assert_eq!(job.sequence, 1);
+
+        let outcome = process_webhook(db, client, job, None, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(outcome, DeliveryOutcome::Sent);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        crate::verify_webhook_signature(&requests[0].headers, &requests[0].body, secret).unwrap();
+    }
+
+    #[test]
+    fn test_error_envelope_for_status() {
+        let rejected = ErrorEnvelope::webhook_status(404);
+        assert_eq!(rejected.code, ErrorCode::WebhookRejected);
+        assert!(!rejected.retryable);
+
+        for status in [408, 429, 503] {
+            let unavailable = ErrorEnvelope::webhook_status(status);
+            assert_eq!(unavailable.code, ErrorCode::WebhookUnavailable);
+            assert!(unavailable.retryable);
+        }
+
+        let value = serde_json::to_value(&rejected).unwrap();
+        assert_eq!(value["code"], "webhook_rejected");
+        assert_eq!(value["retryable"], false);
+    }
+
+    #[test]
+    fn test_redact_payload() {
+        let event = WebhookEvent::TxDetected {
+            invoice_id: "inv".to_owned(),
+            tx_hash: "0xabc".to_owned(),
+            amount: "1.0".to_owned(),
+            currency: "USDT".to_owned(),
+            token: None,
+            log_index: None,
+            eta_secs: None,
+            locale: Some("en-US".to_owned()),
+            display_currency: None,
+        };
+
+        let policy = RedactionPolicy {
+            allowed_fields: Some(vec!["tx_hash".to_owned(), "amount".to_owned()]),
+            hashed_fields: vec!["tx_hash".to_owned()],
+        };
+
+        let payload = serde_json::to_value(&event).unwrap();
+        let redacted = redact_payload(payload, &policy, "secret").unwrap();
+        let data = redacted["data"].as_object().unwrap();
+
+        assert_eq!(data.len(), 3);
+        assert_eq!(data["invoice_id"], "inv");
+        assert_eq!(data["amount"], "1.0");
+        assert_eq!(data["tx_hash"], hash_field("secret", &Value::from("0xabc")).unwrap());
+        assert_eq!(redacted["event_type"], "tx_detected");
+    }
+
+    #[test]
+    fn test_host_limiter_queue_bound() {
+        let limiter = HostLimiter::default();
+        let limits = HostLimits { max_concurrent: 1, max_per_second: 100, max_queued: 1 };
+        let host = destination_host("https://shop.example/hooks?x=1");
+        assert_eq!(host, "shop.example:443");
+
+        let first = limiter.reserve(&host, limits).unwrap();
+        let _second = limiter.reserve(&host, limits).unwrap();
+        assert!(limiter.reserve(&host, limits).is_none());
+        assert!(limiter.reserve("other.example:443", limits).is_some());
+
+        drop(first);
+        assert!(limiter.reserve(&host, limits).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_host_limiter_paces_requests() {
+        let limiter = HostLimiter::default();
+        let limits = HostLimits { max_concurrent: 10, max_per_second: 20, max_queued: 0 };
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            let ticket = limiter.reserve("shop.example:443", limits).unwrap();
+            drop(ticket.ready().await);
+        }
 
-        process_webhook(db, client, job).await.unwrap();
+        // the first send goes out right away, then one every 50ms
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_secs(1));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_failing_destination_is_suspended_and_resumed() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let url = "https://merchant.example/hooks";
+        let event = |test_id: &str| WebhookEvent::DeliveryTest {
+            invoice_id: String::new(),
+            test_id: test_id.to_owned(),
+            sent_at: Utc::now(),
+        };
+        let error = ErrorEnvelope::webhook_status(503);
+
+        assert!(state.db.add_account_webhook_job(7, url, "s3cret", &event("queued")).await.unwrap());
+        for _ in 1..SUSPEND_MIN_FAILURES {
+            track_failure(&state, "", url, &error, false, Some(Duration::ZERO)).await.unwrap();
+        }
+        let destinations = state.webhook_destinations().await.unwrap();
+        assert_eq!(destinations[0].failure_streak, SUSPEND_MIN_FAILURES - 1);
+        assert_eq!(destinations[0].suspended_at, None);
+
+        track_failure(&state, "", url, &error, false, Some(Duration::ZERO)).await.unwrap();
+        assert!(state.webhook_destinations().await.unwrap()[0].suspended_at.is_some());
+        assert!(state.db.select_webhooks_job(10).await.unwrap().is_empty());
+
+        // queued while suspended
+        assert!(state.db.add_account_webhook_job(7, url, "s3cret", &event("late")).await.unwrap());
+        assert!(state.db.select_webhooks_job(10).await.unwrap().is_empty());
+
+        assert_eq!(state.resume_webhook_destination(url, true).await.unwrap(), 2);
+        assert!(state.resume_webhook_destination(url, true).await.is_err());
+        assert!(state.webhook_destinations().await.unwrap().is_empty());
+        assert_eq!(state.db.select_webhooks_job(10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_only_claimed_for_free_slots() {
+        let in_flight = Arc::new(Semaphore::new(3));
+        let busy = in_flight.clone().acquire_owned().await.unwrap();
+
+        let permits = claim_permits(&in_flight, 50).await;
+        assert_eq!(permits.len(), 2);
+        assert!(claim_permits(&in_flight, 1).now_or_never().is_none());
+
+        drop(busy);
+        assert_eq!(claim_permits(&in_flight, 50).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_host_limits_are_persisted() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let limits = HostLimits { max_concurrent: 2, max_per_second: 5, max_queued: 4 };
+
+        state.set_webhook_host_limits(7, Some(limits)).await.unwrap();
+        assert!(state.set_webhook_host_limits(8, Some(HostLimits { max_concurrent: 0, ..limits })).await.is_err());
+
+        state.webhook_host_limits.write().await.clear();
+        state.load_account_settings().await.unwrap();
+        assert_eq!(*state.webhook_host_limits.read().await, HashMap::from([(7, limits)]));
+
+        state.set_webhook_host_limits(7, None).await.unwrap();
+        state.load_account_settings().await.unwrap();
+        assert!(state.webhook_host_limits.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_redaction_policies_are_persisted() {
+        let state = AppState::new(Database::Mock(MockDatabase::new()), "key");
+        let policy = RedactionPolicy {
+            allowed_fields: Some(vec!["status".to_owned(), "paid".to_owned()]),
+            hashed_fields: vec!["address".to_owned()],
+        };
+
+        state.set_redaction_policy(7, Some(policy.clone())).await.unwrap();
+        state.redaction_policies.write().await.clear();
+        state.load_account_settings().await.unwrap();
+        assert_eq!(*state.redaction_policies.read().await, HashMap::from([(7, policy)]));
+
+        state.set_redaction_policy(7, None).await.unwrap();
+        state.load_account_settings().await.unwrap();
+        assert!(state.redaction_policies.read().await.is_empty());
+    }
+    #[tokio::test]
+    async fn test_rejected_webhooks_are_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(wiremock::matchers::path("/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(wiremock::matchers::path("/busy"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let db = Arc::new(Database::Mock(MockDatabase::new()));
+        let client = Arc::new(Client::new());
+        let mut outcomes = HashMap::new();
+        for url in ["gone", "busy"] {
+            let event = WebhookEvent::DeliveryTest { invoice_id: String::new(), test_id: url.to_owned(), sent_at: Utc::now() };
+            db.add_account_webhook_job(7, &format!("{}/{}", server.uri(), url), "s3cret", &event).await.unwrap();
+            let job = db.select_webhooks_job(10).await.unwrap().remove(0);
+            let outcome = process_webhook(db.clone(), client.clone(), job, None, Duration::from_secs(5)).await.unwrap();
+            outcomes.insert(url, outcome);
+        }
+
+        assert!(matches!(&outcomes["gone"], DeliveryOutcome::DeadLettered { attempts: 1, error } if !error.retryable));
+        assert!(matches!(&outcomes["busy"], DeliveryOutcome::Retrying { error } if error.retryable));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::model::TokenConfig;
+
+// vetted stablecoin deployments, keyed by the EVM chain id the RPC reports (eth_chainId).
+// bridged variants (USDC.e and friends) are left out on purpose, add those by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandardToken {
+    pub chain_id: u64,
+    pub symbol: &'static str,
+    pub contract: &'static str, // checksummed
+    pub decimals: u8,
+}
+
+const fn token(chain_id: u64, symbol: &'static str, contract: &'static str, decimals: u8) -> StandardToken {
+    StandardToken { chain_id, symbol, contract, decimals }
+}
+
+pub const STANDARD_TOKENS: &[StandardToken] = &[
+    // ethereum. USDT's transfer() returns nothing, but its Transfer events are standard and that's all we read
+    token(1, "USDT", "0xdAC17F958D2ee523a2206206994597C13D831ec7", 6),
+    token(1, "USDC", "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48", 6),
+    token(1, "DAI", "0x6B175474E89094C44Da98b954EedeAC495271d0F", 18),
+    // optimism
+    token(10, "USDT", "0x94b008aA00579c1307B0EF2c499aD98a8ce58e58", 6),
+    token(10, "USDC", "0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85", 6),
+    // bnb smart chain, both use 18 decimals there
+    token(56, "USDT", "0x55d398326f99059fF775485246999027B3197955", 18),
+    token(56, "USDC", "0x8AC76a51cc950d9822D68b83fE1Ad97B32Cd580d", 18),
+    // polygon pos
+    token(137, "USDT", "0xc2132D05D31c914a87C6611C10748AEb04B58e8F", 6),
+    token(137, "USDC", "0x3c499c542cEF5E3811e1192ce70d8cC03d5c3359", 6),
+    // base
+    token(8453, "USDC", "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", 6),
+    // arbitrum one
+    token(42161, "USDT", "0xFd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9", 6),
+    token(42161, "USDC", "0xaf88d065e77c8cC2239327C5EDb3A432268e5831", 6),
+    // avalanche c-chain
+    token(43114, "USDT", "0x9702230A8Ea53601f5cD2dc00fDBc13d4dF4A8c7", 6),
+    token(43114, "USDC", "0xB97EF9Ef8734C71904D8002F8b6Bc66Dd9c48a6E", 6),
+];
+
+impl From<&StandardToken> for TokenConfig {
+    fn from(token: &StandardToken) -> Self {
+        TokenConfig {
+            symbol: token.symbol.to_owned(),
+            contract: token.contract.to_owned(),
+            decimals: token.decimals,
+            non_standard: false, // every listed token emits standard Transfer events
+            min_amount: None,
+        }
+    }
+}
+
+pub fn standard_tokens(chain_id: u64) -> Vec<TokenConfig> {
+    STANDARD_TOKENS.iter()
+        .filter(|t| t.chain_id == chain_id)
+        .map(TokenConfig::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::Address;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_registry_is_consistent() {
+        let mut seen = HashSet::new();
+
+        for token in STANDARD_TOKENS {
+            // a typo in a checksummed address fails the checksum
+            Address::parse_checksummed(token.contract, None)
+                .unwrap_or_else(|e| panic!("{} on {}: {}", token.symbol, token.chain_id, e));
+            assert!(seen.insert((token.chain_id, token.symbol)), "{} listed twice on {}",
+                    token.symbol, token.chain_id);
+        }
+
+        assert_eq!(standard_tokens(1).len(), 3);
+        assert!(standard_tokens(1).iter().any(|t| t.symbol == "USDT" && !t.non_standard));
+        assert!(standard_tokens(31337).is_empty());
+    }
+}
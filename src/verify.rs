@@ -0,0 +1,156 @@
+// recomputes what necko reports from its inputs, for auditors and merchant backends that don't
+// want to take it on trust. everything here is pure: no RPC, no database, no AppState
+
+use crate::chain::address::{derive_pubkey, AddressEncoder, EvmEncoder};
+use crate::chain::utxo::UtxoParams;
+use crate::model::{ChainType, Invoice, Payment, PaymentStatus, Refund};
+use alloy::primitives::{Address, U256};
+use std::str::FromStr;
+
+pub use crate::events::verify as verify_webhook_payload;
+pub use crate::signature::{verify_webhook_signature, SignatureError, WebhookVerifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecomputedTotals {
+    pub paid_raw: U256, // confirmed payments only
+    pub refunded_raw: U256,
+    pub fully_paid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotalsMismatch {
+    pub invoice_id: String,
+    pub field: &'static str,
+    pub reported: U256,
+    pub recomputed: U256,
+}
+
+impl std::fmt::Display for TotalsMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invoice '{}' reports {} = {}, its payments add up to {}",
+            self.invoice_id, self.field, self.reported, self.recomputed)
+    }
+}
+
+impl std::error::Error for TotalsMismatch {}
+
+// payments and refunds of other invoices are skipped, so whole exports can be passed in
+pub fn invoice_totals(invoice: &Invoice, payments: &[Payment], refunds: &[Refund]) -> RecomputedTotals {
+    let paid_raw = payments.iter()
+        .filter(|p| p.invoice_id == invoice.id && p.status == PaymentStatus::Confirmed)
+        .fold(U256::ZERO, |sum, p| sum + p.amount_raw);
+    let refunded_raw = refunds.iter()
+        .filter(|r| r.invoice_id == invoice.id)
+        .fold(U256::ZERO, |sum, r| sum + r.amount_raw);
+
+    RecomputedTotals { paid_raw, refunded_raw, fully_paid: paid_raw >= invoice.amount_raw }
+}
+
+// the status isn't checked, a settled shortfall is Paid with less than amount_raw received
+pub fn check_invoice_totals(invoice: &Invoice, payments: &[Payment], refunds: &[Refund])
+    -> Result<RecomputedTotals, TotalsMismatch>
+{
+    let totals = invoice_totals(invoice, payments, refunds);
+
+    for (field, reported, recomputed) in [
+        ("paid_raw", invoice.paid_raw, totals.paid_raw),
+        ("refunded_raw", invoice.refunded_raw, totals.refunded_raw),
+    ] {
+        if reported != recomputed {
+            return Err(TotalsMismatch { invoice_id: invoice.id.clone(), field, reported, recomputed });
+        }
+    }
+
+    Ok(totals)
+}
+
+// the receive address of `index` below an account's xpub, as necko derives it
+pub fn derive_address(chain_type: ChainType, xpub: &str, index: u32) -> anyhow::Result<String> {
+    let pubkey = derive_pubkey(xpub, index)?;
+
+    match UtxoParams::of(chain_type) {
+        Some(params) => Ok(params.address.encode(&pubkey)),
+        None => Ok(EvmEncoder.encode(&pubkey)),
+    }
+}
+
+// whether the invoice's address really belongs to its index under `xpub`, the xpub of the
+// invoice's account (ChainConfig::account_xpub)
+pub fn check_invoice_address(invoice: &Invoice, chain_type: ChainType, xpub: &str) -> anyhow::Result<bool> {
+    let derived = derive_address(chain_type, xpub, invoice.address_index)?;
+
+    let reported = match UtxoParams::of(chain_type) {
+        Some(params) => params.address.normalize(&invoice.address),
+        None => Address::from_str(invoice.address.trim()).ok().map(|a| a.to_string()),
+    };
+
+    Ok(reported.as_deref() == Some(derived.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::InvoiceStatus;
+    use chrono::Utc;
+
+    // BIP32 test vector 1, chain m/0'/1
+    const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    fn payment(invoice_id: &str, amount: u64, status: PaymentStatus) -> Payment {
+        Payment {
+            id: uuid::Uuid::new_v4().to_string(),
+            invoice_id: invoice_id.to_owned(),
+            from: String::new(),
+            to: String::new(),
+            payer: None,
+            network: "ethereum".to_owned(),
+            tx_hash: String::new(),
+            amount_raw: U256::from(amount),
+            block_number: 1,
+            block_timestamp: None,
+            log_index: None,
+            status,
+            created_at: Utc::now(),
+            confirmed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_invoice_totals_and_address_are_recomputed() {
+        let mut invoice = Invoice {
+            address: String::new(),
+            amount: "0.0001".to_owned(),
+            amount_raw: U256::from(100),
+            paid: "0.0001".to_owned(),
+            paid_raw: U256::from(100),
+            network: "ethereum".to_owned(),
+            expires_at: Utc::now(),
+            paid_at: Some(Utc::now()),
+            status: InvoiceStatus::Paid,
+            ..Invoice::for_test()
+        };
+
+        let payments = [
+            payment(&invoice.id, 60, PaymentStatus::Confirmed),
+            payment(&invoice.id, 40, PaymentStatus::Confirmed),
+            payment(&invoice.id, 500, PaymentStatus::Confirming),
+            payment("other", 7, PaymentStatus::Confirmed),
+        ];
+        let totals = check_invoice_totals(&invoice, &payments, &[]).unwrap();
+        assert_eq!(totals, RecomputedTotals { paid_raw: U256::from(100), refunded_raw: U256::ZERO, fully_paid: true });
+
+        let err = check_invoice_totals(&invoice, &payments[..1], &[]).unwrap_err();
+        assert_eq!((err.field, err.recomputed), ("paid_raw", U256::from(60)));
+
+        invoice.account_id = 3;
+        invoice.address_index = 7;
+        invoice.address = derive_address(ChainType::EVM, XPUB, 7).unwrap().to_lowercase();
+        assert!(check_invoice_address(&invoice, ChainType::EVM, XPUB).unwrap());
+        invoice.address_index = 8;
+        assert!(!check_invoice_address(&invoice, ChainType::EVM, XPUB).unwrap());
+
+        invoice.address = derive_address(ChainType::LTC, XPUB, 8).unwrap();
+        assert!(invoice.address.starts_with("ltc1q"));
+        assert!(check_invoice_address(&invoice, ChainType::LTC, XPUB).unwrap());
+    }
+}